@@ -0,0 +1,544 @@
+use crate::ban::{self, BanList};
+use crate::filter;
+use crate::format::Elapsed;
+use crate::net;
+use crate::output::{eprint_line, print_line};
+use crate::pool;
+use crate::proxy::{self, Destination};
+use crate::reporter::{self, WeakReporterHandle};
+use crate::usage;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+
+/// The commands understood by the admin API.
+const HELP: &str = "commands:
+  set-destination <address> [drain]    forward new connections to the given address;
+                                       `drain` also closes the existing connections
+  add-mapping <bind> <destination> [name]
+                                       start forwarding another bind address, naming the
+                                       mapping after it unless given a name
+  remove-mapping <bind>                stop accepting on a mapping added at runtime
+  list-mappings                        list the mappings added at runtime
+  stats                                show the summary of the connections so far
+  peers                                show the connections per client address
+  connections                          show the open connections and their throughput
+  history [<duration>]                 show what happened every second of the last minute,
+                                       or the given duration
+  backends                             show the backends of the pools
+  set-weight <backend> <weight>        change the weight of a backend in the pools
+  list-bans                            list the banned addresses, and the denied and
+                                       allowed networks
+  add-ban <address> <duration>         refuse the connections of an address for a while
+  remove-ban <address>                 lift the ban of an address
+  add-deny <network>                   refuse the connections of a network, e.g. 10.0.0.0/8
+  remove-deny <network>                stop refusing the connections of a network
+  add-allow <network>                  never refuse nor ban the addresses of a network
+  remove-allow <network>               stop exempting a network
+  help                                 show this help";
+
+/// The admin API. Accepts line-based commands (e.g. through `nc`) to control the proxy at runtime.
+#[derive(Clone)]
+pub struct Admin {
+    /// Used for changing where new connections are forwarded to.
+    destination: Arc<watch::Sender<Destination>>,
+
+    /// Used for reporting connections of mappings added at runtime. Weak, so that admin clients
+    /// don't keep the reporter running while draining.
+    reporter_handle: WeakReporterHandle,
+
+    /// The mappings added at runtime, by bind address.
+    mappings: Arc<Mutex<HashMap<String, Mapping>>>,
+
+    /// The bans, and the denied and allowed networks.
+    bans: BanList,
+}
+
+/// A bind → destination mapping added at runtime.
+struct Mapping {
+    /// The address connections are forwarded to.
+    dest_addr: String,
+
+    /// The name its connections are reported under.
+    name: String,
+
+    /// The task accepting connections for the mapping.
+    task: JoinHandle<()>,
+}
+
+impl Admin {
+    /// Creates a new admin API.
+    pub fn new(
+        destination: Arc<watch::Sender<Destination>>,
+        reporter_handle: WeakReporterHandle,
+    ) -> Self {
+        Self {
+            destination,
+            reporter_handle,
+            mappings: Arc::default(),
+            bans: ban::shared().clone(),
+        }
+    }
+
+    /// Stops accepting connections on all mappings added at runtime. Connections that were
+    /// already accepted keep running until they close.
+    pub fn remove_mappings(&self) {
+        for (_, mapping) in self.mappings.lock().unwrap().drain() {
+            mapping.task.abort();
+        }
+    }
+
+    /// Runs the admin API, serving clients until the listener fails.
+    pub async fn run(self, listener: TcpListener) {
+        while let Ok((stream, socket_addr)) = listener.accept().await {
+            let admin = self.clone();
+            usage::spawn(async move {
+                if let Err(err) = admin.handle_client(stream).await {
+                    eprint_line!("💥️ — admin client {} failed: {}", &socket_addr, err)
+                }
+            });
+        }
+    }
+
+    /// Reads commands from the client and writes back the responses.
+    async fn handle_client(&self, mut stream: TcpStream) -> Result<(), std::io::Error> {
+        let (read, mut write) = stream.split();
+        let mut lines = BufReader::new(read).lines();
+
+        while let Some(line) = lines.next_line().await? {
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let response = self.execute(line.trim()).await;
+            write.write_all(response.as_bytes()).await?;
+            write.write_all(b"\n").await?;
+        }
+
+        Ok(())
+    }
+
+    /// Executes a single command and returns the response.
+    pub async fn execute(&self, command: &str) -> String {
+        let words: Vec<&str> = command.split_whitespace().collect();
+        match words.as_slice() {
+            ["set-destination", addr] => self.set_destination(addr, false),
+            ["set-destination", addr, "drain"] => self.set_destination(addr, true),
+            ["add-mapping", bind_addr, dest_addr] => {
+                self.add_mapping(bind_addr, dest_addr, bind_addr).await
+            }
+            ["add-mapping", bind_addr, dest_addr, name] => {
+                self.add_mapping(bind_addr, dest_addr, name).await
+            }
+            ["remove-mapping", bind_addr] => self.remove_mapping(bind_addr),
+            ["list-mappings"] => self.list_mappings(),
+            ["stats"] => self.stats().await,
+            ["peers"] => self.peers().await,
+            ["connections"] => connections(),
+            ["history"] => self.history("1m").await,
+            ["history", length] => self.history(length).await,
+            ["backends"] => backends(),
+            ["set-weight", addr, weight] => set_weight(addr, weight),
+            ["list-bans"] => list_bans(&self.bans),
+            ["add-ban", ip, duration] => add_ban(&self.bans, ip, duration),
+            ["remove-ban", ip] => remove_ban(&self.bans, ip),
+            ["add-deny", network] => change_network(
+                &self.bans,
+                network,
+                BanList::deny,
+                "denied",
+                "is denied already",
+            ),
+            ["remove-deny", network] => change_network(
+                &self.bans,
+                network,
+                BanList::remove_deny,
+                "no longer denied",
+                "isn't denied",
+            ),
+            ["add-allow", network] => change_network(
+                &self.bans,
+                network,
+                BanList::allow,
+                "allowed",
+                "is allowed already",
+            ),
+            ["remove-allow", network] => change_network(
+                &self.bans,
+                network,
+                BanList::remove_allow,
+                "no longer allowed",
+                "isn't allowed",
+            ),
+            ["help"] => HELP.to_string(),
+            _ => format!("error: unknown command `{}`, try `help`", command),
+        }
+    }
+
+    /// Changes the destination for new connections, optionally draining the existing ones.
+    fn set_destination(&self, addr: &str, drain: bool) -> String {
+        // In port range mode, the destination is a host that gets the connection's port.
+        let validated = match self.destination.borrow().same_port {
+            true => proxy::validate_destination(&net::join_host_port(addr, 1)),
+            false => proxy::validate_destination(addr),
+        };
+        if let Err(err) = validated {
+            return format!("error: {}", err);
+        }
+
+        self.destination.send_modify(|destination| {
+            destination.addr = addr.to_string();
+            destination.overridden = true;
+            if drain {
+                destination.drain_generation += 1;
+            }
+        });
+
+        print_line!("🛠️  destination set to {} via the admin API", addr);
+        "ok".to_string()
+    }
+
+    /// Binds a new listener and forwards its connections to the destination, as a mapping of
+    /// the given name.
+    async fn add_mapping(&self, bind_addr: &str, dest_addr: &str, name: &str) -> String {
+        if let Err(err) = proxy::validate_destination(dest_addr) {
+            return format!("error: {}", err);
+        }
+
+        if let Err(err) = reporter::validate_mapping_name(name) {
+            return format!("error: {}", err);
+        }
+
+        if self.mappings.lock().unwrap().contains_key(bind_addr) {
+            return format!("error: there already is a mapping for {}", bind_addr);
+        }
+
+        let Some(reporter_handle) = self.reporter_handle.upgrade() else {
+            return "error: the reporter has stopped".to_string();
+        };
+
+        let listener = match net::listen(bind_addr, None).await {
+            Ok(listener) => listener,
+            Err(err) => return format!("error: could not bind {}: {}", bind_addr, err),
+        };
+
+        // The reporter shows the name of the mapping with its events.
+        let mapping: Arc<str> = name.into();
+        let (_, destination) = watch::channel(Destination::new(dest_addr.to_string(), false));
+        let task = usage::spawn(async move {
+            let result =
+                proxy::run(&[listener], mapping.clone(), destination, reporter_handle).await;
+            if let Err(err) = result {
+                eprint_line!("💥️ — mapping {} failed: {}", mapping, err)
+            }
+        });
+
+        // Another client may have added the same mapping while we were binding.
+        let mut mappings = self.mappings.lock().unwrap();
+        if mappings.contains_key(bind_addr) {
+            task.abort();
+            return format!("error: there already is a mapping for {}", bind_addr);
+        }
+
+        let dest_addr = dest_addr.to_string();
+        print_line!(
+            "🛠️  forwarding {} -> {} via the admin API",
+            bind_addr,
+            dest_addr
+        );
+        let name = name.to_string();
+        mappings.insert(
+            bind_addr.to_string(),
+            Mapping {
+                dest_addr,
+                name,
+                task,
+            },
+        );
+        "ok".to_string()
+    }
+
+    /// Stops accepting connections on a mapping added at runtime.
+    fn remove_mapping(&self, bind_addr: &str) -> String {
+        match self.mappings.lock().unwrap().remove(bind_addr) {
+            Some(mapping) => {
+                mapping.task.abort();
+                print_line!("🛠️  stopped forwarding {} via the admin API", bind_addr);
+                "ok".to_string()
+            }
+            None => format!("error: there is no mapping for {}", bind_addr),
+        }
+    }
+
+    /// Lists the mappings added at runtime.
+    fn list_mappings(&self) -> String {
+        let mappings = self.mappings.lock().unwrap();
+        if mappings.is_empty() {
+            return "no mappings".to_string();
+        }
+
+        let mut lines: Vec<String> = mappings
+            .iter()
+            .map(|(bind_addr, mapping)| match mapping.name == *bind_addr {
+                true => format!("{} -> {}", bind_addr, mapping.dest_addr),
+                false => format!("{} -> {} ({})", bind_addr, mapping.dest_addr, mapping.name),
+            })
+            .collect();
+        lines.sort();
+        lines.join("\n")
+    }
+
+    /// Returns the summary of the connections so far.
+    async fn stats(&self) -> String {
+        let summary = match self.reporter_handle.upgrade() {
+            Some(reporter_handle) => reporter_handle.summary().await,
+            None => None,
+        };
+
+        summary.unwrap_or_else(|| "error: the reporter has stopped".to_string())
+    }
+
+    /// Lists what happened every second of the given length of recent history, oldest first.
+    async fn history(&self, length: &str) -> String {
+        let length = match filter::parse_duration(length) {
+            Ok(length) => length,
+            Err(err) => return format!("error: {}", err),
+        };
+        let samples = match self.reporter_handle.upgrade() {
+            Some(reporter_handle) => reporter_handle.timeline(length).await,
+            None => None,
+        };
+        let Some(samples) = samples else {
+            return "error: the reporter has stopped".to_string();
+        };
+        if samples.is_empty() {
+            return "no history yet".to_string();
+        }
+
+        let lines: Vec<String> = samples.iter().map(ToString::to_string).collect();
+        lines.join("\n")
+    }
+
+    /// Lists the connections per client address, the ones with the most open first.
+    async fn peers(&self) -> String {
+        let snapshot = match self.reporter_handle.upgrade() {
+            Some(reporter_handle) => reporter_handle.snapshot().await,
+            None => None,
+        };
+        let Some(snapshot) = snapshot else {
+            return "error: the reporter has stopped".to_string();
+        };
+        if snapshot.peers.is_empty() {
+            return "no peers".to_string();
+        }
+
+        let mut peers: Vec<_> = snapshot.peers.into_iter().collect();
+        peers.sort_by(|a, b| (b.1.open, b.1.total, a.0).cmp(&(a.1.open, a.1.total, b.0)));
+        let lines: Vec<String> = peers
+            .into_iter()
+            .map(|(ip, peer)| {
+                format!(
+                    "{}: {} open, {} total, {} closed with an error, connected for {}",
+                    ip,
+                    peer.open,
+                    peer.total,
+                    peer.closed_with_error,
+                    Elapsed(peer.connected)
+                )
+            })
+            .collect();
+        lines.join("\n")
+    }
+}
+
+/// Lists the open connections with their throughput, the fastest first.
+fn connections() -> String {
+    let connections = proxy::describe_connections();
+    match connections.is_empty() {
+        true => "no connections".to_string(),
+        false => connections.join("\n"),
+    }
+}
+
+/// Lists the backends of the pools connected to so far.
+fn backends() -> String {
+    let backends = pool::describe();
+    match backends.is_empty() {
+        true => "no pools".to_string(),
+        false => backends.join("\n"),
+    }
+}
+
+/// Changes the weight of a backend in the pools it's in.
+fn set_weight(addr: &str, weight: &str) -> String {
+    let Ok(weight) = weight.parse() else {
+        return format!("error: `{}` is not a weight", weight);
+    };
+    if pool::set_weight(addr, weight) == 0 {
+        return format!("error: {} is not a backend of any pool", addr);
+    }
+
+    print_line!(
+        "🛠️  weight of backend {} set to {} via the admin API",
+        addr,
+        weight
+    );
+    "ok".to_string()
+}
+
+/// Lists the banned addresses, and the denied and allowed networks.
+fn list_bans(bans: &BanList) -> String {
+    let bans = bans.describe();
+    match bans.is_empty() {
+        true => "no bans".to_string(),
+        false => bans.join("\n"),
+    }
+}
+
+/// Bans an address for a while.
+fn add_ban(bans: &BanList, ip: &str, duration: &str) -> String {
+    let Ok(ip) = ip.parse() else {
+        return format!("error: `{}` is not an IP address", ip);
+    };
+    let duration = match filter::parse_duration(duration) {
+        Ok(duration) if !duration.is_zero() => duration,
+        Ok(_) => return "error: a ban can't be for 0s".to_string(),
+        Err(err) => return format!("error: {}", err),
+    };
+
+    bans.ban(ip, duration);
+    print_line!(
+        "🛠️  {} banned for {} via the admin API",
+        ip,
+        Elapsed(duration)
+    );
+    "ok".to_string()
+}
+
+/// Lifts the ban of an address.
+fn remove_ban(bans: &BanList, ip: &str) -> String {
+    let Ok(ip) = ip.parse() else {
+        return format!("error: `{}` is not an IP address", ip);
+    };
+    let Some(refused) = bans.unban(ip) else {
+        return format!("error: {} is not banned", ip);
+    };
+
+    print_line!(
+        "🛠️  {} unbanned via the admin API, {} connection(s) refused meanwhile",
+        ip,
+        refused
+    );
+    "ok".to_string()
+}
+
+/// Adds a network to, or removes one from, the denied or allowed networks with `change`, which
+/// returns whether that changed anything, saying the network is `changed` if so and
+/// `unchanged` if not.
+fn change_network(
+    bans: &BanList,
+    network: &str,
+    change: fn(&BanList, (IpAddr, u8)) -> bool,
+    changed: &str,
+    unchanged: &str,
+) -> String {
+    let Some(network) = filter::parse_network(network) else {
+        return format!("error: `{}` is not a network or an IP address", network);
+    };
+    let network_name = ban::display_network(network);
+    if !change(bans, network) {
+        return format!("error: {} {}", network_name, unchanged);
+    }
+
+    print_line!("🛠️  {} {} via the admin API", network_name, changed);
+    "ok".to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn set_destination_with_drain() {
+        let (sender, receiver) = watch::channel(Destination::new("a:80".to_string(), false));
+        let (reporter_handle, _reporter_actor) = crate::reporter::create(Default::default());
+        let admin = Admin::new(Arc::new(sender), reporter_handle.downgrade());
+
+        assert_eq!(
+            admin.execute("set-destination b").await,
+            "error: `b` is not a host:port address"
+        );
+        assert_eq!(admin.execute("set-destination b:81 drain").await, "ok");
+
+        let destination = receiver.borrow();
+        assert_eq!(destination.addr, "b:81");
+        assert_eq!(destination.drain_generation, 1);
+        assert!(destination.overridden);
+    }
+
+    #[tokio::test]
+    async fn add_named_mapping() {
+        let (sender, _receiver) = watch::channel(Destination::new("a:80".to_string(), false));
+        let (reporter_handle, _reporter_actor) = crate::reporter::create(Default::default());
+        let admin = Admin::new(Arc::new(sender), reporter_handle.downgrade());
+
+        assert_eq!(
+            admin
+                .execute("add-mapping 127.0.0.1:0 127.0.0.1:9 default")
+                .await,
+            "error: `default` can't name a mapping, it should be a word other than `default`"
+        );
+        assert_eq!(
+            admin
+                .execute("add-mapping 127.0.0.1:0 127.0.0.1:9 api")
+                .await,
+            "ok"
+        );
+        assert_eq!(
+            admin.execute("list-mappings").await,
+            "127.0.0.1:0 -> 127.0.0.1:9 (api)"
+        );
+        admin.remove_mappings();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn change_bans() {
+        let (sender, _receiver) = watch::channel(Destination::new("a:80".to_string(), false));
+        let (reporter_handle, _reporter_actor) = crate::reporter::create(Default::default());
+        let admin = Admin {
+            bans: BanList::default(),
+            ..Admin::new(Arc::new(sender), reporter_handle.downgrade())
+        };
+
+        assert_eq!(admin.execute("add-ban 10.5.0.1 10m").await, "ok");
+        assert_eq!(admin.execute("add-deny 10.4.0.0/16").await, "ok");
+        assert_eq!(
+            admin.execute("add-deny 10.4.0.0/16").await,
+            "error: 10.4.0.0/16 is denied already"
+        );
+        assert_eq!(admin.execute("add-allow 10.4.1.1").await, "ok");
+        assert!(admin.bans.refuse([10, 5, 0, 1].into()));
+        assert!(admin.bans.refuse([10, 4, 0, 1].into()));
+        assert!(!admin.bans.refuse([10, 4, 1, 1].into()));
+        assert_eq!(
+            admin.execute("list-bans").await,
+            "banned 10.5.0.1 for another 10m, 1 connection(s) refused\n\
+             denied 10.4.0.0/16\n\
+             allowed 10.4.1.1"
+        );
+
+        assert_eq!(admin.execute("remove-ban 10.5.0.1").await, "ok");
+        assert_eq!(admin.execute("remove-deny 10.4.0.0/16").await, "ok");
+        assert_eq!(admin.execute("remove-allow 10.4.1.1").await, "ok");
+        assert_eq!(
+            admin.execute("remove-ban 10.5.0.1").await,
+            "error: 10.5.0.1 is not banned"
+        );
+        assert_eq!(admin.execute("list-bans").await, "no bans");
+        assert!(!admin.bans.refuse([10, 5, 0, 1].into()));
+    }
+}
@@ -0,0 +1,259 @@
+use std::collections::VecDeque;
+use std::fmt::{Display, Formatter};
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::sync::oneshot;
+use tokio::time::Instant;
+
+/// How long connections wait in the queue before giving up, unless configured otherwise.
+pub const DEFAULT_MAX_QUEUE_TIME: Duration = Duration::from_secs(30);
+
+/// The connections admitted and the ones waiting, shared by all listeners.
+static STATE: Mutex<State> = Mutex::new(State {
+    admitted: 0,
+    limit: u64::MAX,
+    queues: VecDeque::new(),
+});
+
+/// When each destination that's paced may next be connected to, shared by all listeners.
+static NEXT_CONNECTS: Mutex<Vec<(String, Instant)>> = Mutex::new(Vec::new());
+
+/// The connections admitted and the ones waiting.
+struct State {
+    /// How many connections are admitted.
+    admitted: u64,
+
+    /// How many connections may be admitted at once, as of the last connection to arrive.
+    limit: u64,
+
+    /// The waiting connections per client address, taking turns so that one busy client
+    /// doesn't keep the others waiting. Each waiter is told when it's admitted.
+    queues: VecDeque<(IpAddr, VecDeque<oneshot::Sender<()>>)>,
+}
+
+impl State {
+    /// Hands the free slots to the waiting connections, one client address at a time.
+    fn admit_waiting(&mut self) {
+        while self.admitted < self.limit {
+            let Some((peer, mut waiters)) = self.queues.pop_front() else {
+                return;
+            };
+            let Some(waiter) = waiters.pop_front() else {
+                continue;
+            };
+
+            // Waiters that gave up can't be admitted, so the slot goes to the next one.
+            if waiter.send(()).is_ok() {
+                self.admitted += 1;
+            }
+            if !waiters.is_empty() {
+                self.queues.push_back((peer, waiters));
+            }
+        }
+    }
+}
+
+/// A slot for a connection, given back when dropped.
+#[derive(Debug)]
+pub struct Permit(());
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        let mut state = STATE.lock().unwrap();
+        state.admitted -= 1;
+        state.admit_waiting();
+    }
+}
+
+/// The outcome of asking to be admitted.
+#[derive(Debug)]
+pub enum Admission {
+    /// Admitted, after waiting this long in the queue (if at all).
+    Admitted(Permit, Duration),
+
+    /// Gave up after waiting this long in the queue.
+    TimedOut(Duration),
+}
+
+/// Admits a connection from a client address once fewer than `limit` are admitted, waiting in
+/// the queue for up to `max_wait` if there are too many.
+pub async fn admit(peer: IpAddr, limit: u64, max_wait: Duration) -> Admission {
+    let mut admitted = {
+        let mut state = STATE.lock().unwrap();
+        state.limit = limit;
+        state.admit_waiting();
+        if state.admitted < limit && state.queues.is_empty() {
+            state.admitted += 1;
+            return Admission::Admitted(Permit(()), Duration::ZERO);
+        }
+
+        let (sender, receiver) = oneshot::channel();
+        match state.queues.iter_mut().find(|(ip, _)| *ip == peer) {
+            Some((_, waiters)) => waiters.push_back(sender),
+            None => state.queues.push_back((peer, VecDeque::from([sender]))),
+        }
+        receiver
+    };
+
+    let started = Instant::now();
+    tokio::select! {
+        result = &mut admitted => match result {
+            Ok(()) => Admission::Admitted(Permit(()), started.elapsed()),
+            Err(_) => Admission::TimedOut(started.elapsed()),
+        },
+        _ = tokio::time::sleep(max_wait) => {
+            // It might have been admitted just now, in which case it takes the slot after all.
+            admitted.close();
+            match admitted.try_recv() {
+                Ok(()) => Admission::Admitted(Permit(()), started.elapsed()),
+                Err(_) => Admission::TimedOut(started.elapsed()),
+            }
+        }
+    }
+}
+
+/// How many connections may be made to a destination per second or minute, like `50/s`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConnectRate {
+    /// How many connections.
+    pub count: u32,
+
+    /// Per how long, a second or a minute.
+    pub per: Duration,
+}
+
+impl ConnectRate {
+    /// Parses a rate like `50/s` or `600/m`.
+    pub fn parse(value: &str) -> Result<ConnectRate, String> {
+        let invalid = || format!("Expected a rate like `50/s` or `600/m`, got `{}`", value);
+        let (count, per) = value.split_once('/').ok_or_else(invalid)?;
+        let per = match per {
+            "s" => Duration::from_secs(1),
+            "m" => Duration::from_secs(60),
+            _ => return Err(invalid()),
+        };
+        match count.trim().parse() {
+            Ok(count) if count > 0 => Ok(ConnectRate { count, per }),
+            _ => Err(invalid()),
+        }
+    }
+
+    /// How long to leave between connections.
+    fn interval(&self) -> Duration {
+        self.per / self.count
+    }
+}
+
+impl Display for ConnectRate {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let per = if self.per == Duration::from_secs(1) {
+            "s"
+        } else {
+            "m"
+        };
+        write!(f, "{}/{}", self.count, per)
+    }
+}
+
+/// Waits for the turn of a connection to the destination, spacing them out evenly so it's
+/// connected to at no more than the given rate. Returns how long it waited.
+pub async fn pace(destination: &str, rate: ConnectRate) -> Duration {
+    let now = Instant::now();
+    let turn = {
+        let mut next_connects = NEXT_CONNECTS.lock().unwrap();
+        let next = match next_connects
+            .iter_mut()
+            .find(|(addr, _)| addr == destination)
+        {
+            Some((_, next)) => next,
+            None => {
+                next_connects.push((destination.to_string(), now));
+                &mut next_connects.last_mut().unwrap().1
+            }
+        };
+        let turn = (*next).max(now);
+        *next = turn + rate.interval();
+        turn
+    };
+
+    tokio::time::sleep_until(turn).await;
+    turn - now
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn queues_fairly() {
+        let busy: IpAddr = [10, 0, 0, 1].into();
+        let other: IpAddr = [10, 0, 0, 2].into();
+        let wait = Duration::from_secs(10);
+
+        let Admission::Admitted(first, waited) = admit(busy, 1, wait).await else {
+            panic!("the first connection should be admitted");
+        };
+        assert_eq!(waited, Duration::ZERO);
+
+        // The busy client queues two more, then the other client one; the other client is
+        // admitted second, before the busy client's second.
+        let (order, mut admitted) = tokio::sync::mpsc::unbounded_channel();
+        for (name, peer) in [("busy 2", busy), ("busy 3", busy), ("other", other)] {
+            let order = order.clone();
+            tokio::spawn(async move {
+                if let Admission::Admitted(permit, _) = admit(peer, 1, wait).await {
+                    order.send(name).unwrap();
+                    tokio::time::sleep(Duration::from_secs(1)).await;
+                    drop(permit);
+                }
+            });
+            tokio::task::yield_now().await;
+        }
+
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        drop(first);
+        assert_eq!(admitted.recv().await, Some("busy 2"));
+        assert_eq!(admitted.recv().await, Some("other"));
+        assert_eq!(admitted.recv().await, Some("busy 3"));
+
+        // Connections that wait too long give up.
+        let Admission::Admitted(_held, _) = admit(busy, 1, wait).await else {
+            panic!("the slot should be free again");
+        };
+        match admit(other, 1, wait).await {
+            Admission::TimedOut(waited) => assert_eq!(waited, wait),
+            Admission::Admitted(..) => panic!("the connection should have given up"),
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn paces_connects() {
+        let rate = ConnectRate::parse("10/s").unwrap();
+        assert_eq!(rate.to_string(), "10/s");
+        assert!(ConnectRate::parse("0/s").is_err());
+        assert!(ConnectRate::parse("10/h").is_err());
+
+        // A burst is spread out, 100ms apart, and other destinations don't wait for it.
+        let waits = tokio::join!(
+            pace("backend:80", rate),
+            pace("backend:80", rate),
+            pace("backend:80", rate),
+            pace("other:80", rate)
+        );
+        let millis = |wait: Duration| wait.as_millis();
+        assert_eq!(
+            (
+                millis(waits.0),
+                millis(waits.1),
+                millis(waits.2),
+                millis(waits.3)
+            ),
+            (0, 100, 200, 0)
+        );
+
+        // Once it's quiet, the next one goes right away.
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        assert_eq!(pace("backend:80", rate).await, Duration::ZERO);
+    }
+}
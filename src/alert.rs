@@ -0,0 +1,285 @@
+use crate::filter;
+use crate::format::Elapsed;
+use crate::http_client;
+use crate::json;
+use crate::output::{eprint_line, print_line};
+use crate::usage;
+use std::collections::VecDeque;
+use std::fmt::{Display, Formatter};
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// The window of rates that don't specify one.
+const DEFAULT_WINDOW: Duration = Duration::from_secs(60);
+
+/// How long to wait for the webhook to respond.
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A threshold that raises an alert while it's exceeded, e.g. `error_rate>5%/1m`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Alert {
+    /// What is measured.
+    metric: Metric,
+
+    /// The value above which the alert fires.
+    threshold: f64,
+
+    /// The window of rates.
+    window: Duration,
+}
+
+/// What an alert measures.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Metric {
+    /// The number of open connections.
+    Concurrent,
+
+    /// The percentage of the connections that closed in the window that closed with an error.
+    ErrorRate,
+
+    /// The number of connections that closed with an error in the window.
+    Errors,
+}
+
+impl Alert {
+    /// Parses an alert: `concurrent>1000`, `error_rate>5%/1m` or `errors>100/1m`.
+    fn parse(alert: &str) -> Result<Alert, String> {
+        let invalid = || {
+            format!(
+                "Expected an alert like `concurrent>1000`, `error_rate>5%/1m` or \
+                 `errors>100/1m`, got `{}`",
+                alert
+            )
+        };
+
+        let (metric, rest) = alert.split_once('>').ok_or_else(invalid)?;
+        let metric = match metric.trim() {
+            "concurrent" => Metric::Concurrent,
+            "error_rate" => Metric::ErrorRate,
+            "errors" => Metric::Errors,
+            _ => return Err(invalid()),
+        };
+
+        let (threshold, window) = match rest.split_once('/') {
+            Some((_, _)) if metric == Metric::Concurrent => return Err(invalid()),
+            Some((threshold, window)) => (threshold, filter::parse_duration(window.trim())?),
+            None => (rest, DEFAULT_WINDOW),
+        };
+        let threshold = match metric {
+            Metric::ErrorRate => threshold.trim().strip_suffix('%').ok_or_else(invalid)?,
+            _ => threshold.trim(),
+        };
+        let threshold: f64 = threshold.parse().map_err(|_| invalid())?;
+        if !threshold.is_finite() {
+            return Err(invalid());
+        }
+
+        Ok(Alert {
+            metric,
+            threshold,
+            window,
+        })
+    }
+}
+
+// Thresholds are never NaN, so comparing them is an equivalence.
+impl Eq for Alert {}
+
+impl Display for Alert {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self.metric {
+            Metric::Concurrent => write!(f, "concurrent>{}", self.threshold),
+            Metric::ErrorRate => write!(f, "error_rate>{}%/{:?}", self.threshold, self.window),
+            Metric::Errors => write!(f, "errors>{}/{:?}", self.threshold, self.window),
+        }
+    }
+}
+
+/// Parses alerts, separated by semicolons.
+pub fn parse_alerts(value: &str) -> Result<Vec<Alert>, String> {
+    value
+        .split(';')
+        .map(str::trim)
+        .filter(|alert| !alert.is_empty())
+        .map(Alert::parse)
+        .collect()
+}
+
+/// Keeps track of the alerts, announcing when they fire and clear.
+#[derive(Debug, Default)]
+pub struct Alerts {
+    /// The alerts, and whether each is firing.
+    alerts: Vec<(Alert, bool)>,
+
+    /// The webhook to post to when alerts fire and clear, as `(address, path)`.
+    webhook: Option<(String, String)>,
+
+    /// When connections closed, and whether with an error, within the longest window.
+    closes: VecDeque<(Instant, bool)>,
+
+    /// The alerts that fired so far.
+    fired: Vec<String>,
+}
+
+impl Alerts {
+    /// Keeps track of the alerts, posting to the webhook (`http://host:port/path`) if there
+    /// is one.
+    pub fn new(alerts: Vec<Alert>, webhook: Option<&str>) -> Self {
+        Alerts {
+            alerts: alerts.into_iter().map(|alert| (alert, false)).collect(),
+            webhook: webhook.map(|webhook| {
+                let webhook = webhook.strip_prefix("http://").unwrap_or(webhook);
+                match webhook.split_once('/') {
+                    Some((addr, path)) => (addr.to_string(), format!("/{}", path)),
+                    None => (webhook.to_string(), "/".to_string()),
+                }
+            }),
+            ..Alerts::default()
+        }
+    }
+
+    /// The alerts that fired so far, each time they did.
+    pub fn fired(&self) -> &[String] {
+        &self.fired
+    }
+
+    /// Notes that a connection closed.
+    pub fn closed(&mut self, with_error: bool) {
+        if !self.alerts.is_empty() {
+            self.closes.push_back((Instant::now(), with_error));
+        }
+    }
+
+    /// Checks the alerts against the number of open connections and the recent closes,
+    /// announcing the ones that started or stopped firing.
+    pub fn check(&mut self, concurrent: u64) {
+        let now = Instant::now();
+        let longest = self.alerts.iter().map(|(alert, _)| alert.window).max();
+        if let Some(longest) = longest {
+            while let Some((closed, _)) = self.closes.front() {
+                match now.duration_since(*closed) > longest {
+                    true => self.closes.pop_front(),
+                    false => break,
+                };
+            }
+        }
+
+        for index in 0..self.alerts.len() {
+            let (alert, firing) = &self.alerts[index];
+            let recent = self
+                .closes
+                .iter()
+                .rev()
+                .take_while(|(closed, _)| now.duration_since(*closed) <= alert.window);
+            let (closes, errors) = recent.fold((0, 0), |(closes, errors), (_, with_error)| {
+                (closes + 1, errors + *with_error as u64)
+            });
+            let (value, description) = match alert.metric {
+                Metric::Concurrent => (concurrent as f64, format!("{} open", concurrent)),
+                Metric::ErrorRate => {
+                    let rate = match closes {
+                        0 => 0.0,
+                        _ => errors as f64 * 100.0 / closes as f64,
+                    };
+                    (
+                        rate,
+                        format!(
+                            "{:.1}% of {} closed in the last {}",
+                            rate,
+                            closes,
+                            Elapsed(alert.window)
+                        ),
+                    )
+                }
+                Metric::Errors => (
+                    errors as f64,
+                    format!("{} errors in the last {}", errors, Elapsed(alert.window)),
+                ),
+            };
+
+            let exceeded = value > alert.threshold;
+            if exceeded == *firing {
+                continue;
+            }
+
+            let name = alert.to_string();
+            match exceeded {
+                true => {
+                    print_line!("🚨 alert {} — {}", name, description);
+                    self.fired.push(name.clone());
+                }
+                false => print_line!("✅ alert {} cleared — {}", name, description),
+            }
+            self.alerts[index].1 = exceeded;
+            self.notify(&name, exceeded, &description);
+        }
+    }
+
+    /// Posts an alert that fired or cleared to the webhook, in the background.
+    fn notify(&self, name: &str, firing: bool, description: &str) {
+        let Some((addr, path)) = self.webhook.clone() else {
+            return;
+        };
+
+        let body = format!(
+            r#"{{"alert":{},"state":"{}","description":{}}}"#,
+            json::quote(name),
+            if firing { "firing" } else { "cleared" },
+            json::quote(description)
+        );
+        usage::spawn(async move {
+            let result =
+                http_client::request(&addr, "POST", &path, Some(&body), WEBHOOK_TIMEOUT).await;
+            if let Err(err) = result {
+                eprint_line!("💥️ — posting the alert to {}{} failed: {}", addr, path, err);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fires_and_clears() {
+        let alerts = parse_alerts("concurrent>2; error_rate>50%/30s; errors>1").unwrap();
+        assert_eq!(
+            alerts.iter().map(Alert::to_string).collect::<Vec<_>>(),
+            ["concurrent>2", "error_rate>50%/30s", "errors>1/60s"]
+        );
+        assert!(parse_alerts("concurrent>2/1m").is_err());
+        assert!(parse_alerts("error_rate>5").is_err());
+
+        let mut alerts = Alerts::new(alerts, None);
+        alerts.check(3);
+        alerts.check(2);
+        alerts.closed(true);
+        alerts.closed(false);
+        alerts.check(0);
+        alerts.closed(true);
+        alerts.check(0);
+        assert_eq!(
+            alerts.fired(),
+            ["concurrent>2", "error_rate>50%/30s", "errors>1/60s"]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn clears_when_the_window_passes() {
+        let mut alerts = Alerts::new(parse_alerts("errors>1/30s").unwrap(), None);
+        alerts.closed(true);
+        alerts.closed(true);
+        alerts.check(0);
+        assert!(alerts.alerts[0].1);
+
+        tokio::time::advance(Duration::from_secs(30)).await;
+        alerts.check(0);
+        assert!(alerts.alerts[0].1);
+
+        tokio::time::advance(Duration::from_millis(1)).await;
+        alerts.check(0);
+        assert!(!alerts.alerts[0].1);
+        assert!(alerts.closes.is_empty());
+    }
+}
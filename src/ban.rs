@@ -0,0 +1,771 @@
+use crate::filter;
+use crate::format::Elapsed;
+use crate::output::{eprint_line, print_line};
+use std::collections::{HashMap, VecDeque};
+use std::fmt::{Display, Formatter};
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::time::Instant;
+
+/// The window of rules that don't specify one.
+const DEFAULT_WINDOW: Duration = Duration::from_secs(60);
+
+/// The longest a ban lasts, so that far away ends still fit in an instant.
+const FOREVER: Duration = Duration::from_secs(100 * 365 * 24 * 60 * 60);
+
+/// The first lines of a ban file.
+const FILE_HEADER: &str =
+    "# sockgauge bans, rewritten as they change: `ban <address> <since> <until>` with the
+# times in seconds since the Unix epoch, `deny <network>` or `allow <network>`
+";
+
+/// The list shared by all listeners, the reporter and the admin API.
+static SHARED: OnceLock<BanList> = OnceLock::new();
+
+/// The list shared by all listeners, the reporter and the admin API.
+pub fn shared() -> &'static BanList {
+    SHARED.get_or_init(BanList::default)
+}
+
+/// The client addresses that are banned, denied or allowed.
+#[derive(Debug, Default)]
+struct List {
+    /// The addresses banned for a while.
+    banned: HashMap<IpAddr, Ban>,
+
+    /// The networks whose connections are always refused.
+    denied: Vec<(IpAddr, u8)>,
+
+    /// The networks whose connections are never refused, and that are never banned.
+    allowed: Vec<(IpAddr, u8)>,
+
+    /// How many connections were refused by the networks denied and the bans lifted.
+    refused: u64,
+
+    /// The file the list is kept in, if any.
+    file: Option<String>,
+
+    /// Counts up as the list changes, so that its file is never overwritten by an older
+    /// version of it.
+    version: u64,
+}
+
+impl List {
+    /// Whether an address is in an allowed network.
+    fn is_allowed(&self, ip: IpAddr) -> bool {
+        in_any(&self.allowed, ip)
+    }
+
+    /// Reads a ban file, leaving out the bans that are over.
+    fn parse(contents: &str) -> Result<List, String> {
+        let mut list = List::default();
+        let now = SystemTime::now();
+        for (number, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let invalid = || {
+                format!(
+                    "Expected `ban <address> <since> <until>`, `deny <network>` or \
+                     `allow <network>` on line {}, got `{}`",
+                    number + 1,
+                    line
+                )
+            };
+            let words: Vec<&str> = line.split_ascii_whitespace().collect();
+            match words.as_slice() {
+                ["ban", ip, since, until] => {
+                    let ip = ip.parse().map_err(|_| invalid())?;
+                    let since: u64 = since.parse().map_err(|_| invalid())?;
+                    let until: u64 = until.parse().map_err(|_| invalid())?;
+                    let over = UNIX_EPOCH
+                        .checked_add(Duration::from_secs(until))
+                        .is_some_and(|until| until <= now);
+                    if over {
+                        continue;
+                    }
+                    let ban = Ban {
+                        since: instant(since),
+                        until: instant(until),
+                        refused: 0,
+                    };
+                    list.banned.insert(ip, ban);
+                }
+                ["deny", network] => {
+                    list.denied
+                        .push(filter::parse_network(network).ok_or_else(invalid)?);
+                }
+                ["allow", network] => {
+                    list.allowed
+                        .push(filter::parse_network(network).ok_or_else(invalid)?);
+                }
+                _ => return Err(invalid()),
+            }
+        }
+        Ok(list)
+    }
+
+    /// Writes the list as a ban file, the bans ordered by when they're lifted.
+    fn contents(&self) -> String {
+        let mut contents = FILE_HEADER.to_string();
+        let mut banned: Vec<_> = self.banned.iter().collect();
+        banned.sort_by_key(|(ip, ban)| (ban.until, **ip));
+        for (ip, ban) in banned {
+            contents += &format!(
+                "ban {} {} {}\n",
+                ip,
+                unix_secs(ban.since),
+                unix_secs(ban.until)
+            );
+        }
+        for network in &self.denied {
+            contents += &format!("deny {}\n", display_network(*network));
+        }
+        for network in &self.allowed {
+            contents += &format!("allow {}\n", display_network(*network));
+        }
+        contents
+    }
+}
+
+/// A list of the client addresses that are banned, denied or allowed, which clones share.
+#[derive(Debug, Clone, Default)]
+pub struct BanList {
+    /// The list.
+    list: Arc<Mutex<List>>,
+
+    /// The version of the list that was last written to its file.
+    saved: Arc<Mutex<u64>>,
+}
+
+impl BanList {
+    /// Loads the bans, denied and allowed networks from a ban file, which is created if it
+    /// doesn't exist, and keeps them in it from now on.
+    pub fn load(&self, path: &str) -> Result<(), String> {
+        let contents = match std::fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(err) => return Err(format!("Could not read ban file {}: {}", path, err)),
+        };
+        let mut list =
+            List::parse(&contents).map_err(|err| format!("{} in ban file {}", err, path))?;
+        list.file = Some(path.to_string());
+        if !list.banned.is_empty() || !list.denied.is_empty() || !list.allowed.is_empty() {
+            print_line!(
+                "🚫 {} ban(s), {} denied and {} allowed network(s) loaded from {}",
+                list.banned.len(),
+                list.denied.len(),
+                list.allowed.len(),
+                path
+            );
+        }
+        *self.list.lock().unwrap() = list;
+        self.change(|_| true);
+        Ok(())
+    }
+
+    /// Changes the list, saving it if `change` says it changed it, and returns whether it did.
+    fn change(&self, change: impl FnOnce(&mut List) -> bool) -> bool {
+        let mut list = self.list.lock().unwrap();
+        if !change(&mut list) {
+            return false;
+        }
+        list.version += 1;
+        let Some(path) = list.file.clone() else {
+            return true;
+        };
+        let (contents, version) = (list.contents(), list.version);
+        drop(list);
+
+        // Connections are refused while the list is locked, so it's written after. Writes a
+        // temporary file next to it first, so that the file is never half written.
+        let saved = self.saved.clone();
+        tokio::task::spawn_blocking(move || {
+            let mut saved = saved.lock().unwrap();
+            if *saved >= version {
+                return;
+            }
+            let temporary = format!("{}.tmp", path);
+            let result = std::fs::write(&temporary, contents)
+                .and_then(|_| std::fs::rename(&temporary, &path));
+            match result {
+                Ok(()) => *saved = version,
+                Err(err) => eprint_line!("💥️ — saving the bans to {} failed: {}", path, err),
+            }
+        });
+        true
+    }
+
+    /// Whether a connection from the address is to be refused, as the address is banned or in
+    /// a denied network (and not in an allowed one). Counts the refused connection if so.
+    pub fn refuse(&self, ip: IpAddr) -> bool {
+        let mut list = self.list.lock().unwrap();
+        if list.is_allowed(ip) {
+            return false;
+        }
+
+        let list = &mut *list;
+        match list.banned.get_mut(&ip) {
+            Some(ban) if ban.until > Instant::now() => ban.refused += 1,
+            _ if in_any(&list.denied, ip) => list.refused += 1,
+            _ => return false,
+        }
+        true
+    }
+
+    /// Bans an address for a while, or for a while longer if it's banned already.
+    pub fn ban(&self, ip: IpAddr, duration: Duration) {
+        self.change(|list| {
+            let now = Instant::now();
+            let ban = list.banned.entry(ip).or_insert(Ban {
+                since: now,
+                until: now,
+                refused: 0,
+            });
+            ban.until = later(now, duration);
+            true
+        });
+    }
+
+    /// Lifts the ban of an address, returning how many of its connections were refused
+    /// meanwhile, or nothing if it isn't banned.
+    pub fn unban(&self, ip: IpAddr) -> Option<u64> {
+        let mut refused = None;
+        self.change(|list| {
+            let ban = list.banned.remove(&ip);
+            if let Some(ban) = ban {
+                list.refused += ban.refused;
+                refused = Some(ban.refused);
+            }
+            ban.is_some()
+        });
+        refused
+    }
+
+    /// Always refuses the connections of a network from now on, returning whether it wasn't
+    /// denied already.
+    pub fn deny(&self, network: (IpAddr, u8)) -> bool {
+        self.change(|list| add_network(&mut list.denied, network))
+    }
+
+    /// Stops refusing the connections of a denied network, returning whether it was denied.
+    pub fn remove_deny(&self, network: (IpAddr, u8)) -> bool {
+        self.change(|list| remove_network(&mut list.denied, network))
+    }
+
+    /// Never refuses nor bans the addresses of a network from now on, returning whether it
+    /// wasn't allowed already.
+    pub fn allow(&self, network: (IpAddr, u8)) -> bool {
+        self.change(|list| add_network(&mut list.allowed, network))
+    }
+
+    /// Stops exempting an allowed network, returning whether it was allowed.
+    pub fn remove_allow(&self, network: (IpAddr, u8)) -> bool {
+        self.change(|list| remove_network(&mut list.allowed, network))
+    }
+
+    /// Describes the bans, the bans lifted soonest first, then the denied and allowed networks.
+    pub fn describe(&self) -> Vec<String> {
+        let list = self.list.lock().unwrap();
+        let now = Instant::now();
+        let mut banned: Vec<_> = list
+            .banned
+            .iter()
+            .filter(|(_, ban)| ban.until > now)
+            .collect();
+        banned.sort_by_key(|(ip, ban)| (ban.until, **ip));
+        let mut lines: Vec<String> = banned
+            .into_iter()
+            .map(|(ip, ban)| {
+                format!(
+                    "banned {} for another {}, {} connection(s) refused",
+                    ip,
+                    Elapsed(Duration::from_secs((ban.until - now).as_secs())),
+                    ban.refused
+                )
+            })
+            .collect();
+        lines.extend(
+            list.denied
+                .iter()
+                .map(|network| format!("denied {}", display_network(*network))),
+        );
+        lines.extend(
+            list.allowed
+                .iter()
+                .map(|network| format!("allowed {}", display_network(*network))),
+        );
+        lines
+    }
+
+    /// How many connections of banned addresses, or of denied networks, were refused so far.
+    fn refused(&self) -> u64 {
+        let list = self.list.lock().unwrap();
+        list.refused + list.banned.values().map(|ban| ban.refused).sum::<u64>()
+    }
+}
+
+/// Adds a network to a list, unless it's in it already.
+fn add_network(networks: &mut Vec<(IpAddr, u8)>, network: (IpAddr, u8)) -> bool {
+    let added = !networks.contains(&network);
+    if added {
+        networks.push(network);
+    }
+    added
+}
+
+/// Removes a network from a list, if it's in it.
+fn remove_network(networks: &mut Vec<(IpAddr, u8)>, network: (IpAddr, u8)) -> bool {
+    let len = networks.len();
+    networks.retain(|listed| *listed != network);
+    networks.len() < len
+}
+
+/// Whether an address is in any of the networks.
+fn in_any(networks: &[(IpAddr, u8)], ip: IpAddr) -> bool {
+    networks
+        .iter()
+        .any(|(network, prefix)| filter::in_network(ip, *network, *prefix))
+}
+
+/// Displays a network, or just its address if it's a single one.
+pub fn display_network((ip, prefix): (IpAddr, u8)) -> String {
+    match (ip, prefix) {
+        (IpAddr::V4(_), 32) | (IpAddr::V6(_), 128) => ip.to_string(),
+        _ => format!("{}/{}", ip, prefix),
+    }
+}
+
+/// The instant a while after another, or [`FOREVER`] after it at most.
+fn later(at: Instant, duration: Duration) -> Instant {
+    at + duration.min(FOREVER)
+}
+
+/// An instant as seconds since the Unix epoch, to the nearest one.
+fn unix_secs(at: Instant) -> u64 {
+    let now = Instant::now();
+    let at = match at > now {
+        true => SystemTime::now() + (at - now),
+        false => SystemTime::now() - (now - at),
+    };
+    let secs = at.duration_since(UNIX_EPOCH).unwrap_or_default();
+    secs.as_secs_f64().round() as u64
+}
+
+/// The instant of so many seconds since the Unix epoch.
+fn instant(secs: u64) -> Instant {
+    let now = Instant::now();
+    let Some(at) = UNIX_EPOCH.checked_add(Duration::from_secs(secs)) else {
+        return later(now, FOREVER);
+    };
+    match at.duration_since(SystemTime::now()) {
+        Ok(ahead) => later(now, ahead),
+        Err(err) => now.checked_sub(err.duration()).unwrap_or(now),
+    }
+}
+
+/// A ban of a client address.
+#[derive(Debug, Clone, Copy)]
+struct Ban {
+    /// When it was banned.
+    since: Instant,
+
+    /// When it's lifted.
+    until: Instant,
+
+    /// How many of its connections were refused since.
+    refused: u64,
+}
+
+/// A rule that bans a client address for a while once it misbehaves too often, e.g.
+/// `aborts>5/1m for 10m`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BanRule {
+    /// What is counted.
+    behavior: Behavior,
+
+    /// How many times it may happen in the window; once more gets the address banned.
+    threshold: u64,
+
+    /// The window.
+    window: Duration,
+
+    /// How long the address is banned.
+    duration: Duration,
+}
+
+/// What a client does that counts towards a ban.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Behavior {
+    /// It opened a connection.
+    Connections,
+
+    /// It closed a connection without sending anything, e.g. connecting and aborting.
+    Aborts,
+
+    /// A connection of it closed with an error.
+    Errors,
+}
+
+impl Display for Behavior {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Behavior::Connections => write!(f, "connections"),
+            Behavior::Aborts => write!(f, "aborts"),
+            Behavior::Errors => write!(f, "errors"),
+        }
+    }
+}
+
+impl BanRule {
+    /// Parses a rule: `aborts>5/1m for 10m`, `errors>20/1m for 1h` or `connections>100/10s for
+    /// 5m`.
+    fn parse(rule: &str) -> Result<BanRule, String> {
+        let invalid = || {
+            format!(
+                "Expected a ban rule like `aborts>5/1m for 10m`, `errors>20/1m for 1h` or \
+                 `connections>100/10s for 5m`, got `{}`",
+                rule
+            )
+        };
+
+        let (condition, duration) = rule.split_once(" for ").ok_or_else(invalid)?;
+        let (behavior, rest) = condition.split_once('>').ok_or_else(invalid)?;
+        let behavior = match behavior.trim() {
+            "connections" => Behavior::Connections,
+            "aborts" => Behavior::Aborts,
+            "errors" => Behavior::Errors,
+            _ => return Err(invalid()),
+        };
+        let (threshold, window) = match rest.split_once('/') {
+            Some((threshold, window)) => (threshold, filter::parse_duration(window.trim())?),
+            None => (rest, DEFAULT_WINDOW),
+        };
+        let threshold = threshold.trim().parse().map_err(|_| invalid())?;
+        let duration = filter::parse_duration(duration.trim())?;
+        if window.is_zero() || duration.is_zero() {
+            return Err(invalid());
+        }
+
+        Ok(BanRule {
+            behavior,
+            threshold,
+            window,
+            duration,
+        })
+    }
+}
+
+impl Display for BanRule {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}>{}/{:?} for {:?}",
+            self.behavior, self.threshold, self.window, self.duration
+        )
+    }
+}
+
+/// Parses ban rules, separated by semicolons.
+pub fn parse_bans(value: &str) -> Result<Vec<BanRule>, String> {
+    value
+        .split(';')
+        .map(str::trim)
+        .filter(|rule| !rule.is_empty())
+        .map(BanRule::parse)
+        .collect()
+}
+
+/// Counts what clients do, banning the addresses that break a rule, and lifting the bans once
+/// they're over.
+#[derive(Debug, Default)]
+pub struct Bans {
+    /// The rules.
+    rules: Vec<BanRule>,
+
+    /// What each client address did within the longest window of the rules, oldest first.
+    recent: HashMap<IpAddr, VecDeque<(Instant, Behavior)>>,
+
+    /// How many times addresses were banned.
+    banned: u64,
+
+    /// The list the addresses are banned on.
+    list: BanList,
+}
+
+impl Bans {
+    /// Bans addresses on the list by the rules.
+    pub fn new(rules: Vec<BanRule>, list: BanList) -> Self {
+        Bans {
+            rules,
+            list,
+            ..Default::default()
+        }
+    }
+
+    /// Notes that a client opened a connection.
+    pub fn opened(&mut self, ip: IpAddr) {
+        self.note(ip, Behavior::Connections);
+    }
+
+    /// Notes that a connection of a client closed, whether without the client sending anything
+    /// and whether with an error.
+    pub fn closed(&mut self, ip: IpAddr, silent: bool, error: bool) {
+        if silent {
+            self.note(ip, Behavior::Aborts);
+        }
+        if error {
+            self.note(ip, Behavior::Errors);
+        }
+    }
+
+    /// Notes what a client did, banning it if that breaks a rule.
+    fn note(&mut self, ip: IpAddr, behavior: Behavior) {
+        if !self.rules.iter().any(|rule| rule.behavior == behavior) {
+            return;
+        }
+
+        let now = Instant::now();
+        let recent = self.recent.entry(ip).or_default();
+        recent.push_back((now, behavior));
+        let longest = self.rules.iter().map(|rule| rule.window).max();
+        prune(recent, now, longest.unwrap_or_default());
+
+        // The rule with the longest ban that's broken.
+        let broken = self
+            .rules
+            .iter()
+            .filter(|rule| rule.behavior == behavior)
+            .map(|rule| {
+                let count = recent
+                    .iter()
+                    .filter(|(at, counted)| {
+                        *counted == behavior && now.duration_since(*at) <= rule.window
+                    })
+                    .count() as u64;
+                (rule, count)
+            })
+            .filter(|(rule, count)| *count > rule.threshold)
+            .max_by_key(|(rule, _)| rule.duration);
+        let Some((rule, count)) = broken else {
+            return;
+        };
+
+        // Addresses in allowed networks break rules without consequences.
+        self.recent.remove(&ip);
+        let banned = self.list.change(|list| {
+            if list.is_allowed(ip) {
+                return false;
+            }
+            let ban = Ban {
+                since: now,
+                until: later(now, rule.duration),
+                refused: 0,
+            };
+            list.banned.insert(ip, ban);
+            true
+        });
+        if !banned {
+            return;
+        }
+        self.banned += 1;
+        print_line!(
+            "🚫 {} banned for {:?} — {} {} in the last {:?} ({})",
+            ip,
+            rule.duration,
+            count,
+            behavior,
+            rule.window,
+            rule
+        );
+    }
+
+    /// Lifts the bans that are over, and forgets what clients did before the longest window.
+    pub fn check(&mut self) {
+        let now = Instant::now();
+        let mut lifted: Vec<(IpAddr, Ban)> = Vec::new();
+        self.list.change(|list| {
+            list.banned.retain(|ip, ban| {
+                if ban.until > now {
+                    return true;
+                }
+                lifted.push((*ip, *ban));
+                false
+            });
+            list.refused += lifted.iter().map(|(_, ban)| ban.refused).sum::<u64>();
+            !lifted.is_empty()
+        });
+        lifted.sort_by_key(|(_, ban)| ban.until);
+        for (ip, ban) in lifted {
+            print_line!(
+                "🔓 {} unbanned after {:?}, {} connection(s) refused meanwhile",
+                ip,
+                ban.until - ban.since,
+                ban.refused
+            );
+        }
+
+        let longest = self.rules.iter().map(|rule| rule.window).max();
+        self.recent.retain(|_, recent| {
+            prune(recent, now, longest.unwrap_or_default());
+            !recent.is_empty()
+        });
+    }
+
+    /// How many times addresses were banned so far.
+    pub fn banned(&self) -> u64 {
+        self.banned
+    }
+
+    /// How many connections of banned addresses, or of denied networks, were refused so far.
+    pub fn refused(&self) -> u64 {
+        self.list.refused()
+    }
+}
+
+/// Forgets what happened before the window.
+fn prune(recent: &mut VecDeque<(Instant, Behavior)>, now: Instant, window: Duration) {
+    while let Some((at, _)) = recent.front() {
+        match now.duration_since(*at) > window {
+            true => recent.pop_front(),
+            false => break,
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_rules() {
+        let rules = parse_bans("aborts>5/1m for 10m; errors>20 for 1h").unwrap();
+        assert_eq!(
+            rules.iter().map(ToString::to_string).collect::<Vec<_>>(),
+            ["aborts>5/60s for 600s", "errors>20/60s for 3600s"]
+        );
+        assert!(parse_bans("aborts>5/1m").is_err());
+        assert!(parse_bans("resets>5/1m for 10m").is_err());
+        assert!(parse_bans("aborts>5/1m for 0s").is_err());
+    }
+
+    #[test]
+    fn saves_and_loads_lists() {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let contents = format!(
+            "# comment\n\nban 10.7.0.1 {} {}\nban 10.7.0.2 {} {}\ndeny 10.6.0.0/16\n\
+             deny fd00::1\nallow 10.6.1.0/24\n",
+            now - 60,
+            now + 600,
+            now - 600,
+            now - 1
+        );
+        let list = List::parse(&contents).unwrap();
+
+        // The ban that's over is left out, and the rest reads back the same.
+        assert_eq!(
+            list.contents().strip_prefix(FILE_HEADER).unwrap(),
+            format!(
+                "ban 10.7.0.1 {} {}\ndeny 10.6.0.0/16\ndeny fd00::1\nallow 10.6.1.0/24\n",
+                now - 60,
+                now + 600
+            )
+        );
+        assert!(list.is_allowed([10, 6, 1, 9].into()));
+        assert!(!list.is_allowed([10, 6, 2, 9].into()));
+
+        assert_eq!(
+            List::parse("ban 10.7.0.1 soon").unwrap_err(),
+            "Expected `ban <address> <since> <until>`, `deny <network>` or `allow <network>` \
+             on line 1, got `ban 10.7.0.1 soon`"
+        );
+        assert!(List::parse("deny 10.6.0.0/33").is_err());
+
+        // Bans that end too far away to tell last for a century.
+        let list = List::parse(&format!("ban 10.7.0.3 {} {}\n", now, u64::MAX)).unwrap();
+        let until = list.banned[&[10, 7, 0, 3].into()].until;
+        assert!(until - Instant::now() > FOREVER - Duration::from_secs(5));
+    }
+
+    #[test]
+    fn bans_for_at_most_a_century() {
+        let ip: IpAddr = [10, 8, 0, 1].into();
+        let list = BanList::default();
+        list.ban(ip, filter::parse_duration("4000000000000000h").unwrap());
+        assert!(list.refuse(ip) && list.refuse(ip));
+        assert!(list.describe()[0].starts_with("banned 10.8.0.1 for another "));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn bans_and_unbans() {
+        let ip: IpAddr = [10, 9, 0, 1].into();
+        let other: IpAddr = [10, 9, 0, 2].into();
+        let list = BanList::default();
+        let mut bans = Bans::new(parse_bans("aborts>2/1m for 10m").unwrap(), list.clone());
+
+        // Aborts that are far enough apart, or by others, don't count together.
+        bans.closed(ip, true, false);
+        tokio::time::advance(Duration::from_secs(61)).await;
+        bans.closed(ip, true, false);
+        bans.closed(ip, false, true);
+        bans.closed(other, true, false);
+        bans.closed(ip, true, false);
+        assert!(!list.refuse(ip));
+
+        bans.closed(ip, true, false);
+        assert_eq!(bans.banned(), 1);
+        assert!(list.refuse(ip) && list.refuse(ip));
+        assert!(!list.refuse(other));
+
+        tokio::time::advance(Duration::from_secs(600)).await;
+        bans.check();
+        assert!(!list.refuse(ip));
+        assert_eq!(bans.refused(), 2);
+
+        // Allowed addresses are neither refused in denied networks nor banned.
+        assert!(list.deny(([10, 9, 0, 0].into(), 24)) && list.allow((other, 32)));
+        assert!(list.refuse(ip) && !list.refuse(other));
+        for _ in 0..3 {
+            bans.closed(other, true, false);
+        }
+        assert_eq!(bans.banned(), 1);
+        assert_eq!(bans.refused(), 3);
+        assert!(list.remove_deny(([10, 9, 0, 0].into(), 24)) && list.remove_allow((other, 32)));
+        assert!(!list.refuse(ip));
+    }
+
+    #[tokio::test]
+    async fn keeps_lists_in_files() {
+        let path = std::env::temp_dir().join(format!("sockgauge-bans-{}", std::process::id()));
+        let path = path.to_str().unwrap().to_string();
+        let file = || std::fs::read_to_string(&path).unwrap_or_default();
+        let _ = std::fs::remove_file(&path);
+
+        // The file is written once the list changes, as it's written in the background.
+        let list = BanList::default();
+        list.load(&path).unwrap();
+        list.ban([10, 3, 0, 1].into(), Duration::from_secs(600));
+        list.deny(([10, 2, 0, 0].into(), 16));
+        list.allow(([10, 2, 1, 1].into(), 32));
+        while !file().ends_with("allow 10.2.1.1\n") {
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+
+        // And read back by the next run.
+        let reloaded = BanList::default();
+        reloaded.load(&path).unwrap();
+        let described = reloaded.describe();
+        assert!(described[0].starts_with("banned 10.3.0.1 for another "));
+        assert_eq!(described[1..], list.describe()[1..]);
+        assert!(reloaded.refuse([10, 3, 0, 1].into()));
+        assert!(reloaded.refuse([10, 2, 0, 1].into()));
+        assert!(!reloaded.refuse([10, 2, 1, 1].into()));
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,273 @@
+use crate::format::{Elapsed, Rate};
+use crate::json::{self, Value};
+use crate::output::print_line;
+use std::error::Error;
+use std::time::Duration;
+
+/// How far a run may fall behind its baseline before it counts as a regression.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Tolerances {
+    /// How much lower the throughput may be, as a fraction of the baseline's.
+    pub throughput: f64,
+
+    /// How much higher the mean connect latency may be, as a fraction of the baseline's.
+    pub latency: f64,
+
+    /// How much higher the error rate may be, as a difference of fractions (percentage points).
+    pub error_rate: f64,
+}
+
+// Tolerances are never NaN, so comparing them is an equivalence.
+impl Eq for Tolerances {}
+
+impl Default for Tolerances {
+    fn default() -> Self {
+        Self {
+            throughput: 0.1,
+            latency: 0.2,
+            error_rate: 0.01,
+        }
+    }
+}
+
+/// Parses a percentage like `10%` (or `10`) as a fraction.
+pub fn parse_percent(value: &str) -> Result<f64, String> {
+    match value.strip_suffix('%').unwrap_or(value).parse::<f64>() {
+        Ok(percent) if percent >= 0.0 && percent.is_finite() => Ok(percent / 100.0),
+        _ => Err(format!("Expected a percentage like `10%`, got `{}`", value)),
+    }
+}
+
+/// Runs `sockgauge compare <baseline> <current> [--<metric>-tolerance <percentage>]`: compares
+/// two run reports, returning whether the current one regressed.
+pub fn run(args: &[String]) -> Result<bool, Box<dyn Error>> {
+    let (baseline_path, current_path, tolerances) = parse_args(args)?;
+    let baseline = read(&baseline_path)?;
+    let current = read(&current_path)?;
+    print_line!("📏 comparing {} with {}", current_path, baseline_path);
+    Ok(print(&compare(&baseline, &current, tolerances)))
+}
+
+/// Reads a run report.
+pub fn read(path: &str) -> Result<Value, Box<dyn Error>> {
+    let contents = std::fs::read_to_string(path)
+        .map_err(|err| format!("Could not read the run report {}: {}", path, err))?;
+    let report = json::parse(&contents)
+        .map_err(|err| format!("Could not parse the run report {}: {}", path, err))?;
+    Ok(report)
+}
+
+/// A metric of a run compared with its baseline.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Comparison {
+    /// What was measured, e.g. `throughput`.
+    pub metric: &'static str,
+
+    /// The value in the baseline, formatted.
+    pub baseline: String,
+
+    /// The value in this run, formatted.
+    pub current: String,
+
+    /// How it changed, e.g. `-14.0%`.
+    pub change: String,
+
+    /// The tolerance it exceeded, if it regressed beyond it.
+    pub regressed: Option<String>,
+}
+
+/// Compares the throughput, mean connect latency and error rate of a run with its baseline,
+/// leaving out those that either report lacks.
+pub fn compare(baseline: &Value, current: &Value, tolerances: Tolerances) -> Vec<Comparison> {
+    let mut comparisons = Vec::new();
+
+    if let (Some(before), Some(after)) = (throughput(baseline), throughput(current)) {
+        comparisons.push(Comparison {
+            metric: "throughput",
+            baseline: Rate(before as u64).to_string(),
+            current: Rate(after as u64).to_string(),
+            change: relative_change(before, after),
+            regressed: (after < before * (1.0 - tolerances.throughput))
+                .then(|| percent(tolerances.throughput)),
+        });
+    }
+
+    if let (Some(before), Some(after)) = (connect_latency(baseline), connect_latency(current)) {
+        comparisons.push(Comparison {
+            metric: "mean connect latency",
+            baseline: Elapsed(before).to_string(),
+            current: Elapsed(after).to_string(),
+            change: relative_change(before.as_secs_f64(), after.as_secs_f64()),
+            regressed: (after.as_secs_f64() > before.as_secs_f64() * (1.0 + tolerances.latency))
+                .then(|| percent(tolerances.latency)),
+        });
+    }
+
+    if let (Some(before), Some(after)) = (error_rate(baseline), error_rate(current)) {
+        comparisons.push(Comparison {
+            metric: "error rate",
+            baseline: percent(before),
+            current: percent(after),
+            change: format!("{:+.1} points", (after - before) * 100.0),
+            regressed: (after > before + tolerances.error_rate)
+                .then(|| format!("{:.1} points", tolerances.error_rate * 100.0)),
+        });
+    }
+
+    comparisons
+}
+
+/// Prints the comparisons, returning whether any regressed.
+pub fn print(comparisons: &[Comparison]) -> bool {
+    for comparison in comparisons {
+        match &comparison.regressed {
+            Some(tolerance) => print_line!(
+                "📉 {} regressed: {} → {} ({}), beyond the tolerance of {}",
+                comparison.metric,
+                comparison.baseline,
+                comparison.current,
+                comparison.change,
+                tolerance
+            ),
+            None => print_line!(
+                "🟢 {}: {} → {} ({})",
+                comparison.metric,
+                comparison.baseline,
+                comparison.current,
+                comparison.change
+            ),
+        }
+    }
+
+    let regressions = comparisons
+        .iter()
+        .filter(|comparison| comparison.regressed.is_some())
+        .count();
+    match (comparisons.is_empty(), regressions) {
+        (true, _) => print_line!("⚠️  the reports have nothing in common to compare"),
+        (false, 0) => print_line!("📏 no regressions compared with the baseline"),
+        (false, n) => print_line!("📉 {} regression(s) compared with the baseline", n),
+    }
+    regressions > 0
+}
+
+/// The bytes forwarded per second, both ways, over the whole run.
+fn throughput(report: &Value) -> Option<f64> {
+    let aggregates = report.get("aggregates")?;
+    let bytes =
+        aggregates.get("bytes_sent")?.as_f64()? + aggregates.get("bytes_received")?.as_f64()?;
+    let duration = report.get("duration_s")?.as_f64()?;
+    (duration > 0.0).then(|| bytes / duration)
+}
+
+/// The mean latency of connecting to the destination, if anything was connected to.
+fn connect_latency(report: &Value) -> Option<Duration> {
+    let histogram = report.get("histograms")?.get("connect_latency")?;
+    let count = histogram.get("count")?.as_f64()?;
+    let sum = histogram.get("sum_ms")?.as_f64()?;
+    (count > 0.0).then(|| Duration::from_secs_f64(sum / count / 1000.0))
+}
+
+/// The fraction of connections that closed with an error, if there were any connections.
+fn error_rate(report: &Value) -> Option<f64> {
+    let aggregates = report.get("aggregates")?;
+    let total = aggregates.get("total")?.as_f64()?;
+    let errors = aggregates.get("closed_with_error")?.as_f64()?;
+    (total > 0.0).then(|| errors / total)
+}
+
+/// Formats how much a value changed relative to what it was, e.g. `-14.0%`.
+fn relative_change(before: f64, after: f64) -> String {
+    match before > 0.0 {
+        true => format!("{:+.1}%", (after - before) / before * 100.0),
+        false => "n/a".to_string(),
+    }
+}
+
+/// Formats a fraction as a percentage, e.g. `10%` or `2.5%`.
+pub fn percent(fraction: f64) -> String {
+    format!("{}%", (fraction * 1000.0).round() / 10.0)
+}
+
+/// Parses the arguments of `sockgauge compare`.
+fn parse_args(args: &[String]) -> Result<(String, String, Tolerances), Box<dyn Error>> {
+    let mut positional = Vec::new();
+    let mut tolerances = Tolerances::default();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let Some(option) = arg.strip_prefix("--") else {
+            positional.push(arg.clone());
+            continue;
+        };
+
+        let (name, value) = match option.split_once('=') {
+            Some((name, value)) => (name, value.to_string()),
+            None => (
+                option,
+                args.next()
+                    .ok_or_else(|| format!("Missing value for {}", arg))?
+                    .clone(),
+            ),
+        };
+        let tolerance = match name {
+            "throughput-tolerance" => &mut tolerances.throughput,
+            "latency-tolerance" => &mut tolerances.latency,
+            "error-rate-tolerance" => &mut tolerances.error_rate,
+            _ => return Err(format!("Unknown compare option `{}`", arg).into()),
+        };
+        *tolerance = parse_percent(&value)?;
+    }
+
+    match <[String; 2]>::try_from(positional) {
+        Ok([baseline, current]) => Ok((baseline, current, tolerances)),
+        Err(_) => Err(
+            "Usage: sockgauge compare <baseline report> <current report> [--throughput-tolerance <percentage>] [--latency-tolerance <percentage>] [--error-rate-tolerance <percentage>]"
+                .into(),
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A run report with only what's compared.
+    fn report(bytes: u64, seconds: f64, connect_ms: f64, total: u64, errors: u64) -> Value {
+        json::parse(&format!(
+            r#"{{"duration_s":{},"aggregates":{{"total":{},"closed_with_error":{},"bytes_sent":0,"bytes_received":{}}},"histograms":{{"connect_latency":{{"count":{},"sum_ms":{}}}}}}}"#,
+            seconds,
+            total,
+            errors,
+            bytes,
+            total,
+            connect_ms * total as f64
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn compares_reports() {
+        let baseline = report(10_000_000, 10.0, 2.0, 100, 1);
+        let current = report(8_000_000, 10.0, 2.2, 100, 3);
+        let comparisons = compare(&baseline, &current, Tolerances::default());
+
+        assert_eq!(comparisons.len(), 3);
+        assert_eq!(comparisons[0].change, "-20.0%");
+        assert_eq!(comparisons[0].regressed.as_deref(), Some("10%"));
+        assert_eq!(comparisons[1].current, "2.20ms");
+        assert_eq!(comparisons[1].regressed, None);
+        assert_eq!(comparisons[2].change, "+2.0 points");
+        assert_eq!(comparisons[2].regressed.as_deref(), Some("1.0 points"));
+
+        let tolerances = Tolerances {
+            throughput: parse_percent("25%").unwrap(),
+            error_rate: parse_percent("5").unwrap(),
+            ..Default::default()
+        };
+        let comparisons = compare(&baseline, &current, tolerances);
+        assert!(comparisons.iter().all(|c| c.regressed.is_none()));
+
+        assert!(compare(&baseline, &json::parse("{}").unwrap(), tolerances).is_empty());
+        assert!(parse_percent("-5%").is_err());
+    }
+}
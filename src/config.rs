@@ -0,0 +1,1624 @@
+use crate::admission::{self, ConnectRate};
+use crate::alert::{self, Alert};
+use crate::ban::{self, BanRule};
+use crate::compare::{self, Tolerances};
+use crate::control::Control;
+use crate::cpu;
+use crate::exit;
+use crate::filter::{self, Filter};
+use crate::format::BytesFormat;
+use crate::http::{self, Rule};
+use crate::mqtt;
+use crate::mux::Tunnel;
+use crate::net::{self, OutboundBind, SocketOptions};
+use crate::output::Timestamps;
+use crate::peers;
+use crate::policy::{self, Action, Policy};
+use crate::pool;
+use crate::protocol::Protocol;
+use crate::proxy::{self, Forwarding};
+use crate::reporter::{self, Sample};
+use crate::sink::LogTarget;
+use crate::timeline;
+use std::error::Error;
+use std::time::Duration;
+
+/// The configuration, assembled from the config file and the command line.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Config {
+    /// Path of the config file, if any.
+    pub config_path: Option<String>,
+
+    /// The addresses to accept connections on.
+    pub bind_addrs: Vec<String>,
+
+    /// The name of the mapping of the bind addresses to the destination, shown with every line
+    /// and event about its connections, if it's named.
+    pub name: Option<String>,
+
+    /// A range of ports to forward to the same ports on the destination host. When set, the bind
+    /// and destination addresses are hosts without a port.
+    pub ports: Option<(u16, u16)>,
+
+    /// Whether IPv6 listeners accept IPv6 only (`true`) or are dual-stack (`false`).
+    /// Uses the operating system's default when not set.
+    pub bind_v6only: Option<bool>,
+
+    /// The CPUs to run a worker thread on each of, pinned to it. Empty to leave the threads
+    /// to the operating system.
+    pub cpus: Vec<usize>,
+
+    /// Whether every pinned worker gets listeners of its own, rather than sharing them.
+    pub per_core_listeners: bool,
+
+    /// The address to forward connections to.
+    pub dest_addr: String,
+
+    /// The address to serve the admin API on, if any.
+    pub admin_addr: Option<String>,
+
+    /// Whether to fingerprint the TLS ClientHello of connections.
+    pub inspect_tls: bool,
+
+    /// Whether to watch connections for a STARTTLS-style upgrade to TLS.
+    pub detect_starttls: bool,
+
+    /// Which end of a tunnel this is, if any.
+    pub tunnel: Option<Tunnel>,
+
+    /// Whether connections carry HTTP/1.x requests.
+    pub http: bool,
+
+    /// The protocol connections speak, to count what they do, if any.
+    pub protocol: Option<Protocol>,
+
+    /// In HTTP mode, the destinations of requests for particular hosts, as `(host, address)`.
+    pub hosts: Vec<(String, String)>,
+
+    /// In HTTP mode, the rules for rewriting the headers of requests.
+    pub request_headers: Vec<Rule>,
+
+    /// In HTTP mode, the rules for rewriting the headers of responses.
+    pub response_headers: Vec<Rule>,
+
+    /// In HTTP mode, the directory to record each request and response in, if any.
+    pub record_http: Option<String>,
+
+    /// How many bytes of each body to record (in files and the HAR).
+    pub record_http_bodies: usize,
+
+    /// In HTTP mode, the file to write all requests and responses to on shutdown, if any.
+    pub har: Option<String>,
+
+    /// The directory to record the traffic of each connection in, for replaying, if any.
+    pub record_sessions: Option<String>,
+
+    /// What to do with connections whose first bytes match a pattern.
+    pub policies: Vec<Policy>,
+
+    /// The labels of every connection, as `(key, value)`.
+    pub labels: Vec<(String, String)>,
+
+    /// Other destinations to race the destination against for each connection.
+    pub race: Vec<String>,
+
+    /// How many connections may be proxied at once, if limited.
+    pub max_connections: Option<u64>,
+
+    /// How long connections wait in the queue before giving up, if not the default.
+    pub max_queue_time: Option<Duration>,
+
+    /// How long to wait for the first bytes of a connection before closing it, if at all.
+    pub first_byte_timeout: Option<Duration>,
+
+    /// Whether connections are forwarded right away with a first-byte timeout, rather than once
+    /// the client sent something.
+    pub first_byte_connect: bool,
+
+    /// How long forwarding may make no progress before it's reported as a stall, if at all.
+    pub stall_threshold: Option<Duration>,
+
+    /// How fast connections to a destination may be made, if limited. Others wait their turn.
+    pub upstream_connect_rate: Option<ConnectRate>,
+
+    /// Where connections to destinations are made from, taking turns, if not the default.
+    pub outbound_bind: Vec<OutboundBind>,
+
+    /// The network interface connections to destinations are made through, if not the one the
+    /// routing table picks.
+    pub outbound_interface: Option<String>,
+
+    /// The options of the sockets to clients and destinations.
+    pub socket_options: SocketOptions,
+
+    /// How pool destinations check their backends and warm up the ones that recover.
+    pub pool: pool::Settings,
+
+    /// Which fraction of the connections to print lines about, if not all of them.
+    pub sample: Option<Sample>,
+
+    /// The conditions for printing lines about a connection.
+    pub report_filter: Filter,
+
+    /// The thresholds to raise alerts for.
+    pub alerts: Vec<Alert>,
+
+    /// The rules to ban client addresses by.
+    pub bans: Vec<BanRule>,
+
+    /// The file to keep the bans, and the denied and allowed networks in, if any.
+    pub ban_file: Option<String>,
+
+    /// Where to post alerts to when they fire and clear, if anywhere.
+    pub alert_webhook: Option<String>,
+
+    /// How often to print sockgauge's own resource usage, if at all.
+    pub self_metrics: Option<Duration>,
+
+    /// How often to print the summary, if at all besides when exiting.
+    pub summary_interval: Option<Duration>,
+
+    /// How short connections must be to be counted together instead of printed, if at all.
+    pub coalesce_under: Option<Duration>,
+
+    /// How many client addresses to keep the connections of, if not the default.
+    pub max_peers: Option<usize>,
+
+    /// How many connections to make room for at startup, if not the default.
+    pub expected_connections: Option<usize>,
+
+    /// How much recent history to keep the samples of every second of, if not the default.
+    pub history: Option<Duration>,
+
+    /// When to stop by itself, printing the summary, for scripted runs.
+    pub exit: exit::Conditions,
+
+    /// Where to write the run report on exit, if anywhere.
+    pub report_json: Option<String>,
+
+    /// A run report to compare this run's with on exit, if any.
+    pub baseline: Option<String>,
+
+    /// How far this run may fall behind the baseline before it counts as a regression.
+    pub tolerances: Tolerances,
+
+    /// The settings as given, for the run report.
+    pub settings: Vec<(String, String)>,
+
+    /// Whether to keep a line with the current numbers at the bottom of the terminal.
+    pub status_line: bool,
+
+    /// How byte counts and rates are shown.
+    pub format_bytes: BytesFormat,
+
+    /// The timestamps to put in front of every line.
+    pub timestamps: Timestamps,
+
+    /// Where the output lines go.
+    pub log_target: LogTarget,
+
+    /// The address of the Redis server to publish connection events to, if any.
+    pub redis: Option<String>,
+
+    /// The Redis channel to publish connection events to, if any.
+    pub redis_channel: Option<String>,
+
+    /// The Redis stream to add connection events to, if any.
+    pub redis_stream: Option<String>,
+
+    /// The address of the MQTT broker to publish connection events to, if any.
+    pub mqtt: Option<String>,
+
+    /// The MQTT topic to publish connection events under, if not the default.
+    pub mqtt_topic: Option<String>,
+
+    /// The MQTT quality of service to publish connection events with.
+    pub mqtt_qos: u8,
+
+    /// The address of a Kafka broker to produce connection events through, if any.
+    pub kafka: Option<String>,
+
+    /// The Kafka topic to produce connection events to, if any.
+    pub kafka_topic: Option<String>,
+
+    /// The address of the IPFIX collector to export the flows of connections to, if any.
+    pub ipfix: Option<String>,
+
+    /// How the proxy is controlled, besides the admin API.
+    pub control: Control,
+}
+
+impl Config {
+    /// Loads the configuration from the command line arguments (excluding the program name),
+    /// reading the config file if one was specified. Command line settings take precedence.
+    pub fn load(args: &[String]) -> Result<Config, Box<dyn Error>> {
+        let cli_settings = parse_args(args)?;
+
+        let mut settings = Vec::new();
+        if let Some((_, path)) = cli_settings.iter().rev().find(|(key, _)| key == "config") {
+            let contents = std::fs::read_to_string(path)
+                .map_err(|e| format!("Could not read config file {}: {}", path, e))?;
+            settings.extend(parse_file(&contents)?);
+        }
+        settings.extend(cli_settings);
+
+        Self::from_settings(settings)
+    }
+
+    /// Builds the configuration from settings, later settings overriding earlier ones.
+    fn from_settings(settings: Vec<(String, String)>) -> Result<Config, Box<dyn Error>> {
+        let mut config = Config::default();
+        for (key, value) in &settings {
+            config.set(key, value.clone())?;
+        }
+        config.settings = settings;
+
+        if config.bind_addrs.is_empty() {
+            return Err("Specify a bind address as the first argument".into());
+        }
+
+        if config.dest_addr.is_empty() {
+            return Err("Specify a destination address as the second argument".into());
+        }
+
+        for addr in &config.bind_addrs {
+            proxy::validate_bind(addr)?;
+        }
+
+        let pipes = config.bind_addrs.iter().any(|addr| proxy::is_pipe(addr));
+        if pipes && (config.ports.is_some() || config.tunnel == Some(Tunnel::Server)) {
+            return Err("Named pipes can't be listened on with `ports` or `tunnel server`".into());
+        }
+
+        if config.control == Control::Stdio && config.status_line {
+            return Err(
+                "`status-line` can't be used with `control stdio`, which takes stdout".into(),
+            );
+        }
+
+        let linux_settings = [
+            (
+                "tcp-user-timeout",
+                config.socket_options.user_timeout.is_some(),
+            ),
+            (
+                "tcp-fast-open-listen",
+                config.socket_options.fast_open_listen,
+            ),
+            (
+                "tcp-fast-open-connect",
+                config.socket_options.fast_open_connect,
+            ),
+            ("mptcp", config.socket_options.mptcp),
+            ("outbound-interface", config.outbound_interface.is_some()),
+            ("dscp", config.socket_options.dscp.is_some()),
+            (
+                "keepalive-probes",
+                config.socket_options.unreachable_probes.is_some(),
+            ),
+        ];
+        if let Some((key, _)) = linux_settings
+            .iter()
+            .find(|(_, set)| *set && !cfg!(target_os = "linux"))
+        {
+            return Err(format!("`{}` is only supported on Linux", key).into());
+        }
+
+        if config.socket_options.unreachable_probes.is_some()
+            && config.socket_options.keepalive.is_none()
+        {
+            return Err(
+                "`keepalive-probes` requires keepalive probes to be sent (`tcp-keepalive`)".into(),
+            );
+        }
+
+        if config.per_core_listeners && config.cpus.is_empty() {
+            return Err("`per-core-listeners` requires the CPUs to pin workers to (`cpus`)".into());
+        }
+
+        if config.redis.is_some() && config.redis_channel.is_none() && config.redis_stream.is_none()
+        {
+            return Err("`redis` requires a channel (`redis-channel`) or stream (`redis-stream`) to publish to".into());
+        }
+
+        let redis_settings = [
+            ("redis-channel", config.redis_channel.is_some()),
+            ("redis-stream", config.redis_stream.is_some()),
+        ];
+        if let Some((key, _)) = redis_settings
+            .iter()
+            .find(|(_, set)| *set && config.redis.is_none())
+        {
+            return Err(format!("`{}` requires the Redis address (`redis`)", key).into());
+        }
+
+        let baseline_settings = [
+            (
+                "throughput-tolerance",
+                config.tolerances.throughput != Tolerances::default().throughput,
+            ),
+            (
+                "latency-tolerance",
+                config.tolerances.latency != Tolerances::default().latency,
+            ),
+            (
+                "error-rate-tolerance",
+                config.tolerances.error_rate != Tolerances::default().error_rate,
+            ),
+        ];
+        if let Some((key, _)) = baseline_settings
+            .iter()
+            .find(|(_, set)| *set && config.baseline.is_none())
+        {
+            return Err(format!(
+                "`{}` requires a run report to compare with (`baseline`)",
+                key
+            )
+            .into());
+        }
+
+        let mqtt_settings = [
+            ("mqtt-topic", config.mqtt_topic.is_some()),
+            ("mqtt-qos", config.mqtt_qos > 0),
+        ];
+        if let Some((key, _)) = mqtt_settings
+            .iter()
+            .find(|(_, set)| *set && config.mqtt.is_none())
+        {
+            return Err(format!("`{}` requires the MQTT broker address (`mqtt`)", key).into());
+        }
+
+        match (&config.kafka, &config.kafka_topic) {
+            (Some(_), None) => {
+                return Err("`kafka` requires a topic (`kafka-topic`) to produce to".into())
+            }
+            (None, Some(_)) => {
+                return Err("`kafka-topic` requires the Kafka broker address (`kafka`)".into())
+            }
+            _ => {}
+        }
+
+        let http_settings = [
+            ("hosts", !config.hosts.is_empty()),
+            ("request-headers", !config.request_headers.is_empty()),
+            ("response-headers", !config.response_headers.is_empty()),
+            ("record-http", config.record_http.is_some()),
+            ("har", config.har.is_some()),
+        ];
+        if let Some((key, _)) = http_settings.iter().find(|(_, set)| *set && !config.http) {
+            return Err(format!("`{}` requires HTTP mode (`http`)", key).into());
+        }
+
+        if config.http && config.protocol.is_some() {
+            return Err("`protocol` can't be combined with HTTP mode (`http`)".into());
+        }
+
+        if config.max_queue_time.is_some() && config.max_connections.is_none() {
+            return Err("`max-queue-time` requires a limit (`max-connections`)".into());
+        }
+
+        if config.pool.ejection_time.is_some() && config.pool.outlier_factor.is_none() {
+            return Err(
+                "`outlier-ejection-time` requires ejecting slow backends (`outlier-latency-factor`)"
+                    .into(),
+            );
+        }
+
+        if !config.race.is_empty() && (config.ports.is_some() || config.tunnel.is_some()) {
+            return Err("`race` can't be used with `ports` or `tunnel`".into());
+        }
+
+        for dest_addr in &config.race {
+            proxy::validate_destination(dest_addr)?;
+        }
+
+        for (_, dest_addr) in &config.hosts {
+            proxy::validate_destination(dest_addr)?;
+        }
+
+        for policy in &config.policies {
+            if let Action::Route(dest_addr) = &policy.action {
+                proxy::validate_destination(dest_addr)?;
+            }
+        }
+
+        match config.ports {
+            Some((first, _)) => {
+                for addr in config.bind_addrs.iter().chain([&config.dest_addr]) {
+                    host_only(addr)?;
+                }
+                proxy::validate_destination(&net::join_host_port(&config.dest_addr, first))?
+            }
+            None => proxy::validate_destination(&config.dest_addr)?,
+        }
+
+        Ok(config)
+    }
+
+    /// How new connections are to be forwarded, besides where to.
+    pub fn forwarding(&self) -> Forwarding {
+        Forwarding {
+            inspect_tls: self.inspect_tls,
+            detect_starttls: self.detect_starttls,
+            tunnel: self.tunnel,
+            http: self.http,
+            protocol: self.protocol,
+            hosts: self.hosts.clone(),
+            request_headers: self.request_headers.clone(),
+            response_headers: self.response_headers.clone(),
+            record_http: self.record_http.clone(),
+            record_http_bodies: self.record_http_bodies,
+            har: self.har.is_some(),
+            record_sessions: self.record_sessions.clone(),
+            policies: self.policies.clone(),
+            labels: self.labels.clone(),
+            race: self.race.clone(),
+            max_connections: self.max_connections,
+            max_queue_time: self.max_queue_time,
+            first_byte_timeout: self.first_byte_timeout,
+            first_byte_connect: self.first_byte_connect,
+            stall_threshold: self.stall_threshold,
+            upstream_connect_rate: self.upstream_connect_rate,
+        }
+    }
+
+    /// Describes the settings that differ in the `new` configuration.
+    pub fn changes(&self, new: &Config) -> Vec<String> {
+        let mut changes = Vec::new();
+        if self.bind_addrs != new.bind_addrs {
+            changes.push(format!(
+                "bind: {} -> {}",
+                self.bind_addrs.join(","),
+                new.bind_addrs.join(",")
+            ));
+        }
+
+        if self.ports != new.ports {
+            changes.push(format!(
+                "ports: {} -> {}",
+                or_none(&self.ports.map(display_ports)),
+                or_none(&new.ports.map(display_ports))
+            ));
+        }
+
+        if self.bind_v6only != new.bind_v6only {
+            changes.push(format!(
+                "bind-v6only: {} -> {}",
+                or_none(&self.bind_v6only),
+                or_none(&new.bind_v6only)
+            ));
+        }
+
+        if self.cpus != new.cpus {
+            changes.push(format!(
+                "cpus: {} -> {}",
+                cpu::display_cpus(&self.cpus),
+                cpu::display_cpus(&new.cpus)
+            ));
+        }
+
+        if self.per_core_listeners != new.per_core_listeners {
+            changes.push(format!(
+                "per-core-listeners: {} -> {}",
+                self.per_core_listeners, new.per_core_listeners
+            ));
+        }
+
+        if self.dest_addr != new.dest_addr {
+            changes.push(format!(
+                "destination: {} -> {}",
+                self.dest_addr, new.dest_addr
+            ));
+        }
+
+        if self.admin_addr != new.admin_addr {
+            changes.push(format!(
+                "admin: {} -> {}",
+                or_none(&self.admin_addr),
+                or_none(&new.admin_addr)
+            ));
+        }
+
+        if self.inspect_tls != new.inspect_tls {
+            changes.push(format!(
+                "inspect-tls: {} -> {}",
+                self.inspect_tls, new.inspect_tls
+            ));
+        }
+
+        if self.detect_starttls != new.detect_starttls {
+            changes.push(format!(
+                "detect-starttls: {} -> {}",
+                self.detect_starttls, new.detect_starttls
+            ));
+        }
+
+        if self.tunnel != new.tunnel {
+            changes.push(format!(
+                "tunnel: {} -> {}",
+                or_none(&self.tunnel),
+                or_none(&new.tunnel)
+            ));
+        }
+
+        if self.http != new.http {
+            changes.push(format!("http: {} -> {}", self.http, new.http));
+        }
+
+        if self.protocol != new.protocol {
+            changes.push(format!(
+                "protocol: {} -> {}",
+                or_none(&self.protocol),
+                or_none(&new.protocol)
+            ));
+        }
+
+        if self.hosts != new.hosts {
+            changes.push(format!(
+                "hosts: {} -> {}",
+                display_pairs(&self.hosts),
+                display_pairs(&new.hosts)
+            ));
+        }
+
+        if self.request_headers != new.request_headers {
+            changes.push(format!(
+                "request-headers: {} -> {}",
+                display_rules(&self.request_headers),
+                display_rules(&new.request_headers)
+            ));
+        }
+
+        if self.response_headers != new.response_headers {
+            changes.push(format!(
+                "response-headers: {} -> {}",
+                display_rules(&self.response_headers),
+                display_rules(&new.response_headers)
+            ));
+        }
+
+        if self.record_http != new.record_http {
+            changes.push(format!(
+                "record-http: {} -> {}",
+                or_none(&self.record_http),
+                or_none(&new.record_http)
+            ));
+        }
+
+        if self.record_http_bodies != new.record_http_bodies {
+            changes.push(format!(
+                "record-http-bodies: {} -> {}",
+                self.record_http_bodies, new.record_http_bodies
+            ));
+        }
+
+        if self.har != new.har {
+            changes.push(format!(
+                "har: {} -> {}",
+                or_none(&self.har),
+                or_none(&new.har)
+            ));
+        }
+
+        if self.record_sessions != new.record_sessions {
+            changes.push(format!(
+                "record-sessions: {} -> {}",
+                or_none(&self.record_sessions),
+                or_none(&new.record_sessions)
+            ));
+        }
+
+        if self.policies != new.policies {
+            changes.push(format!(
+                "policies: {} -> {}",
+                display_rules(&self.policies),
+                display_rules(&new.policies)
+            ));
+        }
+
+        if self.max_connections != new.max_connections {
+            changes.push(format!(
+                "max-connections: {} -> {}",
+                or_none(&self.max_connections),
+                or_none(&new.max_connections)
+            ));
+        }
+
+        if self.max_queue_time != new.max_queue_time {
+            let display = |time: Option<Duration>| {
+                display_interval(time.unwrap_or(admission::DEFAULT_MAX_QUEUE_TIME))
+            };
+            changes.push(format!(
+                "max-queue-time: {} -> {}",
+                display(self.max_queue_time),
+                display(new.max_queue_time)
+            ));
+        }
+
+        if self.first_byte_timeout != new.first_byte_timeout {
+            changes.push(format!(
+                "first-byte-timeout: {} -> {}",
+                or_none(&self.first_byte_timeout.map(display_interval)),
+                or_none(&new.first_byte_timeout.map(display_interval))
+            ));
+        }
+
+        if self.first_byte_connect != new.first_byte_connect {
+            changes.push(format!(
+                "first-byte-connect: {} -> {}",
+                self.first_byte_connect, new.first_byte_connect
+            ));
+        }
+
+        if self.stall_threshold != new.stall_threshold {
+            changes.push(format!(
+                "stall-threshold: {} -> {}",
+                or_none(&self.stall_threshold.map(display_interval)),
+                or_none(&new.stall_threshold.map(display_interval))
+            ));
+        }
+
+        if self.upstream_connect_rate != new.upstream_connect_rate {
+            changes.push(format!(
+                "upstream-connect-rate: {} -> {}",
+                or_none(&self.upstream_connect_rate),
+                or_none(&new.upstream_connect_rate)
+            ));
+        }
+
+        if self.race != new.race {
+            changes.push(format!(
+                "race: {} -> {}",
+                self.race.join(","),
+                new.race.join(",")
+            ));
+        }
+
+        if self.outbound_bind != new.outbound_bind {
+            changes.push(format!(
+                "outbound-bind: {} -> {}",
+                display_list(&self.outbound_bind),
+                display_list(&new.outbound_bind)
+            ));
+        }
+
+        if self.outbound_interface != new.outbound_interface {
+            changes.push(format!(
+                "outbound-interface: {} -> {}",
+                or_none(&self.outbound_interface),
+                or_none(&new.outbound_interface)
+            ));
+        }
+
+        let (old_socket, new_socket) = (&self.socket_options, &new.socket_options);
+        if old_socket.abortive_close != new_socket.abortive_close {
+            changes.push(format!(
+                "abortive-close: {} -> {}",
+                old_socket.abortive_close, new_socket.abortive_close
+            ));
+        }
+
+        if old_socket.user_timeout != new_socket.user_timeout {
+            changes.push(format!(
+                "tcp-user-timeout: {} -> {}",
+                or_none(&old_socket.user_timeout.map(display_interval)),
+                or_none(&new_socket.user_timeout.map(display_interval))
+            ));
+        }
+
+        if old_socket.fast_open_listen != new_socket.fast_open_listen {
+            changes.push(format!(
+                "tcp-fast-open-listen: {} -> {}",
+                old_socket.fast_open_listen, new_socket.fast_open_listen
+            ));
+        }
+
+        if old_socket.fast_open_connect != new_socket.fast_open_connect {
+            changes.push(format!(
+                "tcp-fast-open-connect: {} -> {}",
+                old_socket.fast_open_connect, new_socket.fast_open_connect
+            ));
+        }
+
+        if old_socket.unreachable_probes != new_socket.unreachable_probes {
+            changes.push(format!(
+                "keepalive-probes: {} -> {}",
+                or_none(&old_socket.unreachable_probes),
+                or_none(&new_socket.unreachable_probes)
+            ));
+        }
+
+        if old_socket.mptcp != new_socket.mptcp {
+            changes.push(format!(
+                "mptcp: {} -> {}",
+                old_socket.mptcp, new_socket.mptcp
+            ));
+        }
+
+        if old_socket.dscp != new_socket.dscp {
+            changes.push(format!(
+                "dscp: {} -> {}",
+                or_none(&old_socket.dscp),
+                or_none(&new_socket.dscp)
+            ));
+        }
+
+        if old_socket.ttl != new_socket.ttl {
+            changes.push(format!(
+                "ttl: {} -> {}",
+                or_none(&old_socket.ttl),
+                or_none(&new_socket.ttl)
+            ));
+        }
+
+        if old_socket.keepalive != new_socket.keepalive {
+            changes.push(format!(
+                "tcp-keepalive: {} -> {}",
+                or_none(&old_socket.keepalive.map(display_interval)),
+                or_none(&new_socket.keepalive.map(display_interval))
+            ));
+        }
+
+        if self.pool.health_check_interval != new.pool.health_check_interval {
+            changes.push(format!(
+                "health-check-interval: {} -> {}",
+                display_interval(self.pool.health_check_interval),
+                display_interval(new.pool.health_check_interval)
+            ));
+        }
+
+        if self.pool.slow_start != new.pool.slow_start {
+            let display = |window: Option<Duration>| match window {
+                Some(window) => display_interval(window),
+                None => "(none)".to_string(),
+            };
+            changes.push(format!(
+                "slow-start: {} -> {}",
+                display(self.pool.slow_start),
+                display(new.pool.slow_start)
+            ));
+        }
+
+        if self.pool.outlier_factor != new.pool.outlier_factor {
+            changes.push(format!(
+                "outlier-latency-factor: {} -> {}",
+                or_none(&self.pool.outlier_factor),
+                or_none(&new.pool.outlier_factor)
+            ));
+        }
+
+        if self.pool.ejection_time != new.pool.ejection_time {
+            let display = |time: Option<Duration>| {
+                display_interval(time.unwrap_or(pool::DEFAULT_EJECTION_TIME))
+            };
+            changes.push(format!(
+                "outlier-ejection-time: {} -> {}",
+                display(self.pool.ejection_time),
+                display(new.pool.ejection_time)
+            ));
+        }
+
+        if self.name != new.name {
+            changes.push(format!(
+                "name: {} -> {}",
+                or_none(&self.name),
+                or_none(&new.name)
+            ));
+        }
+
+        if self.labels != new.labels {
+            changes.push(format!(
+                "labels: {} -> {}",
+                display_pairs(&self.labels),
+                display_pairs(&new.labels)
+            ));
+        }
+
+        if self.sample != new.sample {
+            changes.push(format!(
+                "sample: {} -> {}",
+                or_none(&self.sample),
+                or_none(&new.sample)
+            ));
+        }
+
+        if self.report_filter != new.report_filter {
+            changes.push(format!(
+                "report-filter: {} -> {}",
+                self.report_filter, new.report_filter
+            ));
+        }
+
+        if self.bans != new.bans {
+            changes.push(format!(
+                "ban: {} -> {}",
+                display_rules(&self.bans),
+                display_rules(&new.bans)
+            ));
+        }
+
+        if self.ban_file != new.ban_file {
+            changes.push(format!(
+                "ban-file: {} -> {}",
+                or_none(&self.ban_file),
+                or_none(&new.ban_file)
+            ));
+        }
+
+        if self.alerts != new.alerts {
+            changes.push(format!(
+                "alert: {} -> {}",
+                display_rules(&self.alerts),
+                display_rules(&new.alerts)
+            ));
+        }
+
+        if self.alert_webhook != new.alert_webhook {
+            changes.push(format!(
+                "alert-webhook: {} -> {}",
+                or_none(&self.alert_webhook),
+                or_none(&new.alert_webhook)
+            ));
+        }
+
+        if self.self_metrics != new.self_metrics {
+            changes.push(format!(
+                "self-metrics: {} -> {}",
+                or_none(&self.self_metrics.map(display_interval)),
+                or_none(&new.self_metrics.map(display_interval))
+            ));
+        }
+
+        if self.summary_interval != new.summary_interval {
+            changes.push(format!(
+                "summary-interval: {} -> {}",
+                or_none(&self.summary_interval.map(display_interval)),
+                or_none(&new.summary_interval.map(display_interval))
+            ));
+        }
+
+        if self.coalesce_under != new.coalesce_under {
+            changes.push(format!(
+                "coalesce-under: {} -> {}",
+                or_none(&self.coalesce_under.map(display_interval)),
+                or_none(&new.coalesce_under.map(display_interval))
+            ));
+        }
+
+        if self.max_peers != new.max_peers {
+            changes.push(format!(
+                "max-peers: {} -> {}",
+                self.max_peers.unwrap_or(peers::DEFAULT_MAX_PEERS),
+                new.max_peers.unwrap_or(peers::DEFAULT_MAX_PEERS)
+            ));
+        }
+
+        if self.expected_connections != new.expected_connections {
+            changes.push(format!(
+                "expected-connections: {} -> {}",
+                or_none(&self.expected_connections),
+                or_none(&new.expected_connections)
+            ));
+        }
+
+        if self.history != new.history {
+            let display = |history: Option<Duration>| {
+                display_interval(history.unwrap_or(timeline::DEFAULT_LENGTH))
+            };
+            changes.push(format!(
+                "history: {} -> {}",
+                display(self.history),
+                display(new.history)
+            ));
+        }
+
+        if self.exit.connections != new.exit.connections {
+            changes.push(format!(
+                "exit-after-connections: {} -> {}",
+                or_none(&self.exit.connections),
+                or_none(&new.exit.connections)
+            ));
+        }
+
+        if self.exit.after != new.exit.after {
+            changes.push(format!(
+                "exit-after: {} -> {}",
+                or_none(&self.exit.after.map(display_interval)),
+                or_none(&new.exit.after.map(display_interval))
+            ));
+        }
+
+        if self.exit.idle != new.exit.idle {
+            changes.push(format!(
+                "exit-when-idle: {} -> {}",
+                or_none(&self.exit.idle.map(display_interval)),
+                or_none(&new.exit.idle.map(display_interval))
+            ));
+        }
+
+        if self.report_json != new.report_json {
+            changes.push(format!(
+                "report-json: {} -> {}",
+                or_none(&self.report_json),
+                or_none(&new.report_json)
+            ));
+        }
+
+        if self.baseline != new.baseline {
+            changes.push(format!(
+                "baseline: {} -> {}",
+                or_none(&self.baseline),
+                or_none(&new.baseline)
+            ));
+        }
+
+        let tolerances = [
+            (
+                "throughput-tolerance",
+                self.tolerances.throughput,
+                new.tolerances.throughput,
+            ),
+            (
+                "latency-tolerance",
+                self.tolerances.latency,
+                new.tolerances.latency,
+            ),
+            (
+                "error-rate-tolerance",
+                self.tolerances.error_rate,
+                new.tolerances.error_rate,
+            ),
+        ];
+        for (key, old, new) in tolerances {
+            if old != new {
+                changes.push(format!(
+                    "{}: {} -> {}",
+                    key,
+                    compare::percent(old),
+                    compare::percent(new)
+                ));
+            }
+        }
+
+        if self.status_line != new.status_line {
+            changes.push(format!(
+                "status-line: {} -> {}",
+                self.status_line, new.status_line
+            ));
+        }
+
+        if self.format_bytes != new.format_bytes {
+            changes.push(format!(
+                "format-bytes: {} -> {}",
+                self.format_bytes, new.format_bytes
+            ));
+        }
+
+        if self.timestamps != new.timestamps {
+            changes.push(format!(
+                "timestamps: {} -> {}",
+                self.timestamps, new.timestamps
+            ));
+        }
+
+        if self.log_target != new.log_target {
+            changes.push(format!(
+                "log-target: {} -> {}",
+                self.log_target, new.log_target
+            ));
+        }
+
+        if self.redis != new.redis {
+            changes.push(format!(
+                "redis: {} -> {}",
+                or_none(&self.redis),
+                or_none(&new.redis)
+            ));
+        }
+
+        if self.redis_channel != new.redis_channel {
+            changes.push(format!(
+                "redis-channel: {} -> {}",
+                or_none(&self.redis_channel),
+                or_none(&new.redis_channel)
+            ));
+        }
+
+        if self.redis_stream != new.redis_stream {
+            changes.push(format!(
+                "redis-stream: {} -> {}",
+                or_none(&self.redis_stream),
+                or_none(&new.redis_stream)
+            ));
+        }
+
+        if self.mqtt != new.mqtt {
+            changes.push(format!(
+                "mqtt: {} -> {}",
+                or_none(&self.mqtt),
+                or_none(&new.mqtt)
+            ));
+        }
+
+        if self.mqtt_topic != new.mqtt_topic {
+            changes.push(format!(
+                "mqtt-topic: {} -> {}",
+                or_none(&self.mqtt_topic),
+                or_none(&new.mqtt_topic)
+            ));
+        }
+
+        if self.mqtt_qos != new.mqtt_qos {
+            changes.push(format!("mqtt-qos: {} -> {}", self.mqtt_qos, new.mqtt_qos));
+        }
+
+        if self.control != new.control {
+            changes.push(format!("control: {} -> {}", self.control, new.control));
+        }
+
+        if self.kafka != new.kafka {
+            changes.push(format!(
+                "kafka: {} -> {}",
+                or_none(&self.kafka),
+                or_none(&new.kafka)
+            ));
+        }
+
+        if self.kafka_topic != new.kafka_topic {
+            changes.push(format!(
+                "kafka-topic: {} -> {}",
+                or_none(&self.kafka_topic),
+                or_none(&new.kafka_topic)
+            ));
+        }
+
+        if self.ipfix != new.ipfix {
+            changes.push(format!(
+                "ipfix: {} -> {}",
+                or_none(&self.ipfix),
+                or_none(&new.ipfix)
+            ));
+        }
+
+        changes
+    }
+
+    /// Applies a single setting.
+    fn set(&mut self, key: &str, value: String) -> Result<(), Box<dyn Error>> {
+        match key {
+            "config" => self.config_path = Some(value),
+            "bind" => {
+                self.bind_addrs = value
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|addr| !addr.is_empty())
+                    .map(str::to_string)
+                    .collect()
+            }
+            "ports" => self.ports = Some(parse_ports(&value)?),
+            "name" => {
+                reporter::validate_mapping_name(&value)?;
+                self.name = Some(value)
+            }
+            "abortive-close" => self.socket_options.abortive_close = parse_bool(key, &value)?,
+            "mptcp" => self.socket_options.mptcp = parse_bool(key, &value)?,
+            "tcp-fast-open-listen" => {
+                self.socket_options.fast_open_listen = parse_bool(key, &value)?
+            }
+            "tcp-fast-open-connect" => {
+                self.socket_options.fast_open_connect = parse_bool(key, &value)?
+            }
+            "tcp-user-timeout" | "tcp-keepalive" => {
+                let duration = match filter::parse_duration(&value)? {
+                    duration if duration.is_zero() => {
+                        return Err(format!("`{}` must be longer than 0s", key).into())
+                    }
+                    duration => Some(duration),
+                };
+                match key {
+                    "tcp-user-timeout" => self.socket_options.user_timeout = duration,
+                    _ => self.socket_options.keepalive = duration,
+                }
+            }
+            "keepalive-probes" => {
+                self.socket_options.unreachable_probes = match value.parse() {
+                    Ok(probes) if probes > 0 => Some(probes),
+                    _ => {
+                        return Err(format!(
+                            "Expected a number of keepalive probes from 1 to 255, got `{}`",
+                            value
+                        )
+                        .into())
+                    }
+                }
+            }
+            "outbound-bind" => {
+                self.outbound_bind = value
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|bind| !bind.is_empty())
+                    .map(parse_outbound_bind)
+                    .collect::<Result<_, _>>()?
+            }
+            "outbound-interface" => {
+                self.outbound_interface = match value.trim() {
+                    "" => return Err("Expected a network interface, like `eth1`".into()),
+                    interface => Some(interface.to_string()),
+                }
+            }
+            "dscp" => self.socket_options.dscp = Some(net::parse_dscp(&value)?),
+            "ttl" => {
+                self.socket_options.ttl = match value.parse() {
+                    Ok(ttl) if ttl > 0 => Some(ttl),
+                    _ => {
+                        return Err(format!("Expected a TTL from 1 to 255, got `{}`", value).into())
+                    }
+                }
+            }
+            "bind-v6only" => self.bind_v6only = Some(parse_bool(key, &value)?),
+            "cpus" => self.cpus = cpu::parse_cpus(&value)?,
+            "per-core-listeners" => self.per_core_listeners = parse_bool(key, &value)?,
+            "destination" => self.dest_addr = value,
+            "admin" => self.admin_addr = Some(value),
+            "inspect-tls" => self.inspect_tls = parse_bool(key, &value)?,
+            "detect-starttls" => self.detect_starttls = parse_bool(key, &value)?,
+            "first-byte-connect" => self.first_byte_connect = parse_bool(key, &value)?,
+            "tunnel" => self.tunnel = Some(Tunnel::parse(&value)?),
+            "http" => self.http = parse_bool(key, &value)?,
+            "protocol" => self.protocol = Some(Protocol::parse(&value)?),
+            "hosts" => self.hosts = parse_hosts(&value)?,
+            "request-headers" => self.request_headers = http::parse_rules(&value)?,
+            "response-headers" => self.response_headers = http::parse_rules(&value)?,
+            "record-http" => self.record_http = Some(value),
+            "har" => self.har = Some(value),
+            "record-sessions" => self.record_sessions = Some(value),
+            "policies" => self.policies = policy::parse_policies(&value)?,
+            "labels" => self.labels = parse_labels(&value)?,
+            "max-connections" => {
+                self.max_connections = match value.parse() {
+                    Ok(max) if max > 0 => Some(max),
+                    _ => {
+                        return Err(
+                            format!("Expected a number of connections, got `{}`", value).into()
+                        )
+                    }
+                }
+            }
+            "max-peers" => {
+                self.max_peers = match value.parse() {
+                    Ok(max) if max > 0 => Some(max),
+                    _ => {
+                        return Err(format!(
+                            "Expected a number of client addresses, got `{}`",
+                            value
+                        )
+                        .into())
+                    }
+                }
+            }
+            "expected-connections" => {
+                self.expected_connections = match value.replace('_', "").parse() {
+                    Ok(expected) => Some(expected),
+                    _ => {
+                        return Err(
+                            format!("Expected a number of connections, got `{}`", value).into()
+                        )
+                    }
+                }
+            }
+            "max-queue-time" => self.max_queue_time = Some(filter::parse_duration(&value)?),
+            "first-byte-timeout" | "stall-threshold" => {
+                let duration = match filter::parse_duration(&value)? {
+                    duration if duration.is_zero() => {
+                        return Err(format!("`{}` must be longer than 0s", key).into())
+                    }
+                    duration => Some(duration),
+                };
+                match key {
+                    "first-byte-timeout" => self.first_byte_timeout = duration,
+                    _ => self.stall_threshold = duration,
+                }
+            }
+            "upstream-connect-rate" => {
+                self.upstream_connect_rate = Some(ConnectRate::parse(&value)?)
+            }
+            "health-check-interval" => {
+                self.pool.health_check_interval = match filter::parse_duration(&value)? {
+                    interval if interval.is_zero() => {
+                        return Err("`health-check-interval` must be longer than 0s".into())
+                    }
+                    interval => interval,
+                }
+            }
+            "slow-start" => self.pool.slow_start = Some(filter::parse_duration(&value)?),
+            "outlier-latency-factor" => {
+                self.pool.outlier_factor = match value.parse::<f64>() {
+                    Ok(factor) if factor > 1.0 && factor.is_finite() => Some(factor),
+                    _ => {
+                        return Err(format!(
+                            "Expected a factor greater than 1 like `3`, got `{}`",
+                            value
+                        )
+                        .into())
+                    }
+                }
+            }
+            "outlier-ejection-time" => {
+                self.pool.ejection_time = Some(filter::parse_duration(&value)?)
+            }
+            "race" => {
+                self.race = value
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|addr| !addr.is_empty())
+                    .map(str::to_string)
+                    .collect()
+            }
+            "sample" => self.sample = Some(Sample::parse(&value)?),
+            "report-filter" => self.report_filter = Filter::parse(&value)?,
+            "alert" => self.alerts = alert::parse_alerts(&value)?,
+            "ban" => self.bans = ban::parse_bans(&value)?,
+            "ban-file" => self.ban_file = Some(value),
+            "alert-webhook" => self.alert_webhook = Some(value),
+            "redis" => self.redis = Some(value),
+            "redis-channel" => self.redis_channel = Some(value),
+            "redis-stream" => self.redis_stream = Some(value),
+            "mqtt" => self.mqtt = Some(value),
+            "mqtt-topic" => self.mqtt_topic = Some(value),
+            "mqtt-qos" => self.mqtt_qos = mqtt::parse_qos(&value)?,
+            "kafka" => self.kafka = Some(value),
+            "kafka-topic" => self.kafka_topic = Some(value),
+            "ipfix" => self.ipfix = Some(value),
+            "control" => self.control = Control::parse(&value)?,
+            "self-metrics" => self.self_metrics = Some(filter::parse_duration(&value)?),
+            "status-line" => self.status_line = parse_bool(key, &value)?,
+            "format-bytes" => self.format_bytes = BytesFormat::parse(&value)?,
+            "timestamps" => self.timestamps = Timestamps::parse(&value)?,
+            "log-target" => self.log_target = LogTarget::parse(&value)?,
+            "summary-interval" => self.summary_interval = Some(filter::parse_duration(&value)?),
+            "coalesce-under" => self.coalesce_under = Some(filter::parse_duration(&value)?),
+            "history" => {
+                self.history = match filter::parse_duration(&value)? {
+                    history if history < Duration::from_secs(1) => {
+                        return Err("`history` must be at least 1s".into())
+                    }
+                    history => Some(history),
+                }
+            }
+            "exit-after-connections" => {
+                self.exit.connections = match value.parse() {
+                    Ok(connections) if connections > 0 => Some(connections),
+                    _ => {
+                        return Err(
+                            format!("Expected a number of connections, got `{}`", value).into()
+                        )
+                    }
+                }
+            }
+            "report-json" => self.report_json = Some(value),
+            "baseline" => self.baseline = Some(value),
+            "throughput-tolerance" => self.tolerances.throughput = compare::parse_percent(&value)?,
+            "latency-tolerance" => self.tolerances.latency = compare::parse_percent(&value)?,
+            "error-rate-tolerance" => self.tolerances.error_rate = compare::parse_percent(&value)?,
+            "exit-after" => self.exit.after = Some(filter::parse_duration(&value)?),
+            "exit-when-idle" => self.exit.idle = Some(filter::parse_duration(&value)?),
+            "record-http-bodies" => {
+                self.record_http_bodies = value
+                    .parse()
+                    .map_err(|_| format!("Expected a number of bytes, got `{}`", value))?
+            }
+            _ => return Err(format!("Unknown setting `{}`", key).into()),
+        }
+
+        Ok(())
+    }
+}
+
+/// Settings that don't take a value on the command line; `--switch` means `--switch=true`.
+const SWITCHES: &[&str] = &[
+    "bind-v6only",
+    "per-core-listeners",
+    "inspect-tls",
+    "detect-starttls",
+    "first-byte-connect",
+    "http",
+    "status-line",
+    "abortive-close",
+    "tcp-fast-open-listen",
+    "tcp-fast-open-connect",
+    "mptcp",
+];
+
+/// Displays an optional setting.
+fn or_none<T: ToString>(value: &Option<T>) -> String {
+    match value {
+        Some(value) => value.to_string(),
+        None => "(none)".to_string(),
+    }
+}
+
+/// Parses a port range like `9000-9010`, or a single port.
+fn parse_ports(value: &str) -> Result<(u16, u16), Box<dyn Error>> {
+    let (first, last) = value.split_once('-').unwrap_or((value, value));
+    let invalid = || format!("Expected a port range like `9000-9010`, got `{}`", value);
+    let first = first.trim().parse::<u16>().map_err(|_| invalid())?;
+    let last = last.trim().parse::<u16>().map_err(|_| invalid())?;
+    if first == 0 || first > last {
+        return Err(invalid().into());
+    }
+
+    Ok((first, last))
+}
+
+/// Parses an outbound bind address like `10.0.0.5`, `10.0.0.5:40000-41000` or
+/// `[2001:db8::5]:40000-41000`.
+fn parse_outbound_bind(value: &str) -> Result<OutboundBind, Box<dyn Error>> {
+    let invalid = || {
+        format!(
+            "Expected an IP address with an optional port range like `10.0.0.5:40000-41000`, got `{}`",
+            value
+        )
+    };
+    if let Ok(ip) = value.trim_matches(['[', ']']).parse() {
+        return Ok(OutboundBind { ip, ports: None });
+    }
+
+    let (ip, ports) = value.rsplit_once(':').ok_or_else(invalid)?;
+    let ip = ip.trim_matches(['[', ']']).parse().map_err(|_| invalid())?;
+    Ok(OutboundBind {
+        ip,
+        ports: Some(parse_ports(ports)?),
+    })
+}
+
+/// Displays an interval, e.g. `10s`.
+fn display_interval(interval: Duration) -> String {
+    format!("{:?}", interval)
+}
+
+/// Checks that an address is a host without a port (IPv6 addresses with or without brackets),
+/// as needed with a port range.
+fn host_only(addr: &str) -> Result<(), String> {
+    let bare = addr.trim_start_matches('[').trim_end_matches(']');
+    match addr.contains(':') && bare.parse::<std::net::Ipv6Addr>().is_err() {
+        true => Err(format!(
+            "With `ports`, addresses are hosts without a port, got `{}`",
+            addr
+        )),
+        false => Ok(()),
+    }
+}
+
+/// Displays a port range.
+fn display_ports((first, last): (u16, u16)) -> String {
+    format!("{}-{}", first, last)
+}
+
+/// Parses host routes like `api.test=10.0.0.5:8080, web.test=10.0.0.6:80`.
+fn parse_hosts(value: &str) -> Result<Vec<(String, String)>, Box<dyn Error>> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|route| !route.is_empty())
+        .map(|route| match route.split_once('=') {
+            Some((host, dest_addr)) if !host.trim().is_empty() => Ok((
+                host.trim().to_ascii_lowercase(),
+                dest_addr.trim().to_string(),
+            )),
+            _ => Err(format!("Expected a route like `host=address`, got `{}`", route).into()),
+        })
+        .collect()
+}
+
+/// Parses labels like `tenant=abc, test=run42`.
+fn parse_labels(value: &str) -> Result<Vec<(String, String)>, Box<dyn Error>> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|label| !label.is_empty())
+        .map(|label| match label.split_once('=') {
+            Some((key, value)) if !key.trim().is_empty() => {
+                Ok((key.trim().to_string(), value.trim().to_string()))
+            }
+            _ => Err(format!("Expected a label like `key=value`, got `{}`", label).into()),
+        })
+        .collect()
+}
+
+/// Displays `key=value` pairs, like host routes or labels.
+fn display_pairs(pairs: &[(String, String)]) -> String {
+    match pairs.is_empty() {
+        true => "(none)".to_string(),
+        false => pairs
+            .iter()
+            .map(|(key, value)| format!("{}={}", key, value))
+            .collect::<Vec<_>>()
+            .join(","),
+    }
+}
+
+/// Displays header rewrite rules, policies, alerts or ban rules.
+fn display_rules<T: ToString>(rules: &[T]) -> String {
+    match rules.is_empty() {
+        true => "(none)".to_string(),
+        false => rules
+            .iter()
+            .map(T::to_string)
+            .collect::<Vec<_>>()
+            .join("; "),
+    }
+}
+
+/// Displays a comma-separated list, like the outbound bind addresses.
+fn display_list<T: ToString>(items: &[T]) -> String {
+    match items.is_empty() {
+        true => "(none)".to_string(),
+        false => items.iter().map(T::to_string).collect::<Vec<_>>().join(","),
+    }
+}
+
+/// Parses a `true`/`false` setting.
+fn parse_bool(key: &str, value: &str) -> Result<bool, Box<dyn Error>> {
+    match value {
+        "true" => Ok(true),
+        "false" => Ok(false),
+        _ => Err(format!("Expected `true` or `false` for `{}`, got `{}`", key, value).into()),
+    }
+}
+
+/// Parses command line arguments into settings. The first two positional arguments
+/// are the bind and destination addresses; everything else is `--key value`, `--key=value`
+/// or, for switches, just `--key`.
+fn parse_args(args: &[String]) -> Result<Vec<(String, String)>, Box<dyn Error>> {
+    let mut settings = Vec::new();
+    let mut positional = ["bind", "destination"].into_iter();
+    let mut args = args.iter();
+
+    while let Some(arg) = args.next() {
+        match arg.strip_prefix("--") {
+            Some(flag) => match flag.split_once('=') {
+                Some((key, value)) => settings.push((key.to_string(), value.to_string())),
+                None if SWITCHES.contains(&flag) => {
+                    settings.push((flag.to_string(), "true".to_string()))
+                }
+                None => {
+                    let value = args
+                        .next()
+                        .ok_or_else(|| format!("Missing value for --{}", flag))?;
+                    settings.push((flag.to_string(), value.clone()));
+                }
+            },
+            None => {
+                let key = positional
+                    .next()
+                    .ok_or_else(|| format!("Unexpected argument `{}`", arg))?;
+                settings.push((key.to_string(), arg.clone()));
+            }
+        }
+    }
+
+    Ok(settings)
+}
+
+/// Parses the config file: `key = value` lines, with `#` starting a comment.
+fn parse_file(contents: &str) -> Result<Vec<(String, String)>, Box<dyn Error>> {
+    let mut settings = Vec::new();
+
+    for (index, line) in contents.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or_default().trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let (key, value) = line.split_once('=').ok_or_else(|| {
+            format!(
+                "Expected `key = value` on line {} of the config file",
+                index + 1
+            )
+        })?;
+        settings.push((key.trim().to_string(), value.trim().to_string()));
+    }
+
+    Ok(settings)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn command_line_overrides_config_file() {
+        let mut settings =
+            parse_file("# gauging\nbind = 0.0.0.0:80, [::]:80\ndestination = a:80\n").unwrap();
+        let args = ["--bind-v6only", "--destination=b:80"].map(str::to_string);
+        settings.extend(parse_args(&args).unwrap());
+
+        let config = Config::from_settings(settings).unwrap();
+        assert_eq!(config.bind_addrs, ["0.0.0.0:80", "[::]:80"]);
+        assert_eq!(config.bind_v6only, Some(true));
+        assert_eq!(config.dest_addr, "b:80");
+    }
+
+    #[test]
+    fn parses_labels() {
+        let labels = parse_labels(" tenant = abc,, test=run42, empty=").unwrap();
+        assert_eq!(display_pairs(&labels), "tenant=abc,test=run42,empty=");
+        for invalid in ["tenant", "=abc", "tenant=abc, run42"] {
+            assert!(parse_labels(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn parses_expected_connections() {
+        let args = ["--expected-connections=100_000", "0.0.0.0:80", "a:80"].map(str::to_string);
+        let config = Config::from_settings(parse_args(&args).unwrap()).unwrap();
+        assert_eq!(config.expected_connections, Some(100_000));
+
+        let args = ["--expected-connections=lots", "0.0.0.0:80", "a:80"].map(str::to_string);
+        let error = Config::from_settings(parse_args(&args).unwrap()).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Expected a number of connections, got `lots`"
+        );
+    }
+
+    #[test]
+    fn requires_a_kafka_topic() {
+        let config = |args: &[&str]| {
+            let mut args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+            args.extend(["0.0.0.0:80".to_string(), "a:80".to_string()]);
+            Config::from_settings(parse_args(&args).unwrap())
+        };
+        let kafka = config(&["--kafka=k:9092", "--kafka-topic=events"]).unwrap();
+        assert_eq!(kafka.kafka.as_deref(), Some("k:9092"));
+        assert_eq!(kafka.kafka_topic.as_deref(), Some("events"));
+        assert_eq!(
+            config(&["--kafka=k:9092"]).unwrap_err().to_string(),
+            "`kafka` requires a topic (`kafka-topic`) to produce to"
+        );
+        assert_eq!(
+            config(&["--kafka-topic=events"]).unwrap_err().to_string(),
+            "`kafka-topic` requires the Kafka broker address (`kafka`)"
+        );
+    }
+
+    #[test]
+    fn listens_on_named_pipes_on_windows() {
+        let settings = parse_args(&["npipe:////./pipe/in", "a:80"].map(str::to_string)).unwrap();
+        let result = Config::from_settings(settings).map_err(|err| err.to_string());
+        match cfg!(windows) {
+            true => assert_eq!(result.unwrap().bind_addrs, ["npipe:////./pipe/in"]),
+            false => assert_eq!(
+                result.unwrap_err(),
+                "`npipe:////./pipe/in` is a named pipe, which only works on Windows"
+            ),
+        }
+
+        let settings =
+            parse_args(&["--tunnel=server", "npipe:////./pipe/in", "a:80"].map(str::to_string))
+                .unwrap();
+        assert!(Config::from_settings(settings).is_err());
+    }
+
+    #[test]
+    fn parses_port_ranges() {
+        assert_eq!(parse_ports("9000-9010").unwrap(), (9000, 9010));
+        assert_eq!(parse_ports(" 9000 - 9000 ").unwrap(), (9000, 9000));
+        assert_eq!(parse_ports("80").unwrap(), (80, 80));
+        for invalid in ["9010-9000", "0-10", "9000-", "9000-70000", "a-b", ""] {
+            assert!(parse_ports(invalid).is_err(), "{}", invalid);
+        }
+
+        // The destination is a host, to which the ports are added.
+        let args = ["--ports=9000-9010", "0.0.0.0", "db.internal"].map(str::to_string);
+        let config = Config::from_settings(parse_args(&args).unwrap()).unwrap();
+        assert_eq!(config.ports, Some((9000, 9010)));
+        let args = ["--ports=9000-9010", "::", "[2001:db8::1]"].map(str::to_string);
+        assert!(Config::from_settings(parse_args(&args).unwrap()).is_ok());
+        for (bind, destination) in [("0.0.0.0", "db.internal:5432"), ("0.0.0.0:80", "db")] {
+            let args = ["--ports=9000-9010", bind, destination].map(str::to_string);
+            let error = Config::from_settings(parse_args(&args).unwrap()).unwrap_err();
+            assert!(error.to_string().starts_with("With `ports`"), "{}", error);
+        }
+    }
+}
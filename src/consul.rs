@@ -0,0 +1,186 @@
+use crate::output::eprint_line;
+use crate::{discovery, http_client, json, net};
+use std::io;
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio::sync::watch;
+
+/// Prefix of Consul destinations, e.g. `consul://my-service` or `consul://my-service?tag=primary`.
+pub const PREFIX: &str = "consul://";
+
+/// The Consul agent to ask when `CONSUL_HTTP_ADDR` isn't set.
+const DEFAULT_AGENT: &str = "127.0.0.1:8500";
+
+/// How long Consul may hold a blocking query before answering with unchanged results.
+const WAIT: &str = "60s";
+
+/// How long to wait for a blocking query, which is a bit longer than Consul holds it.
+const QUERY_TIMEOUT: Duration = Duration::from_secs(75);
+
+/// Checks that the destination looks like `service` or `service?tag=tag`.
+pub fn validate(target: &str) -> Result<(), String> {
+    match parse(target) {
+        Some(_) => Ok(()),
+        None => Err(format!(
+            "`{}{}` is not a Consul service like {}my-service?tag=primary",
+            PREFIX, target, PREFIX
+        )),
+    }
+}
+
+/// Connects to one of the healthy instances of a Consul service. The instances are followed with
+/// blocking queries, so registrations and failing health checks are picked up as they happen.
+pub async fn connect(target: &str) -> Result<TcpStream, io::Error> {
+    let (service, tag) = parse(target).ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidInput, validate(target).unwrap_err())
+    })?;
+
+    let destination = format!("{}{}", PREFIX, target);
+    discovery::connect(&destination.clone(), |sender| {
+        watch_service(destination, service, tag, sender)
+    })
+    .await
+}
+
+/// Follows the healthy instances of the service until nobody is interested anymore.
+async fn watch_service(
+    destination: String,
+    service: String,
+    tag: Option<String>,
+    sender: watch::Sender<Option<Vec<String>>>,
+) {
+    let agent = agent();
+    let mut index = 0;
+    while !sender.is_closed() {
+        match query(&agent, &service, tag.as_deref(), index).await {
+            Ok((targets, new_index)) => {
+                // Consul asks clients to start over when the index goes backwards.
+                index = if new_index < index { 0 } else { new_index };
+                discovery::update(&destination, &sender, targets);
+            }
+            Err(err) => {
+                eprint_line!("💥️ — watching {} failed: {}", destination, err);
+                index = 0;
+                tokio::time::sleep(discovery::RETRY_DELAY).await;
+            }
+        }
+    }
+}
+
+/// Asks the agent for the healthy instances, blocking until they changed since `index`.
+async fn query(
+    agent: &str,
+    service: &str,
+    tag: Option<&str>,
+    index: u64,
+) -> io::Result<(Vec<String>, u64)> {
+    let mut path = format!(
+        "/v1/health/service/{}?passing=true&index={}&wait={}",
+        service, index, WAIT
+    );
+    if let Some(tag) = tag {
+        path.push_str(&format!("&tag={}", tag));
+    }
+
+    let (head, body) = http_client::request(agent, "GET", &path, None, QUERY_TIMEOUT).await?;
+    let new_index = head
+        .header("X-Consul-Index")
+        .and_then(|index| index.parse().ok())
+        .unwrap_or(0);
+
+    let invalid = |err: String| io::Error::new(io::ErrorKind::InvalidData, err);
+    let entries = json::parse(&body).map_err(invalid)?;
+    let targets = entries
+        .as_array()
+        .ok_or_else(|| invalid("expected an array of service entries".to_string()))?
+        .iter()
+        .filter_map(|entry| {
+            let service = entry.get("Service")?;
+            let port = service.get("Port")?.as_f64()? as u16;
+
+            // Services without their own address are reachable on the node's address.
+            let address = match service.get("Address").and_then(json::Value::as_str) {
+                Some(address) if !address.is_empty() => address,
+                _ => entry.get("Node")?.get("Address")?.as_str()?,
+            };
+
+            Some(net::join_host_port(address, port))
+        })
+        .collect();
+
+    Ok((targets, new_index))
+}
+
+/// Returns the address of the Consul agent, honoring `CONSUL_HTTP_ADDR` like the Consul CLI.
+fn agent() -> String {
+    match std::env::var("CONSUL_HTTP_ADDR") {
+        Ok(addr) if !addr.is_empty() => addr.trim_start_matches("http://").to_string(),
+        _ => DEFAULT_AGENT.to_string(),
+    }
+}
+
+/// Splits `service?tag=tag` into the service name and the optional tag.
+fn parse(target: &str) -> Option<(String, Option<String>)> {
+    let valid = |name: &str| {
+        !name.is_empty()
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.')
+    };
+
+    let (service, tag) = match target.split_once('?') {
+        Some((service, query)) => (service, Some(query.strip_prefix("tag=")?)),
+        None => (target, None),
+    };
+
+    if !valid(service) || !tag.is_none_or(valid) {
+        return None;
+    }
+
+    Some((service.to_string(), tag.map(str::to_string)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    #[test]
+    fn parses_targets() {
+        assert_eq!(parse("web"), Some(("web".to_string(), None)));
+        assert_eq!(
+            parse("web?tag=primary"),
+            Some(("web".to_string(), Some("primary".to_string())))
+        );
+        assert_eq!(parse("web?dc=east"), None);
+        assert_eq!(parse("web/1"), None);
+        assert!(validate("").is_err());
+    }
+
+    #[tokio::test]
+    async fn queries_healthy_instances() {
+        // One instance with an address of its own, one on its node's, and one without a port.
+        let body = r#"[
+            {"Node": {"Address": "10.0.0.1"}, "Service": {"Address": "10.0.1.1", "Port": 8080}},
+            {"Node": {"Address": "fd00::2"}, "Service": {"Address": "", "Port": 8081}},
+            {"Node": {"Address": "10.0.0.3"}, "Service": {"Address": "10.0.1.3"}}
+        ]"#;
+        let response = format!("HTTP/1.0 200 OK\r\nX-Consul-Index: 42\r\n\r\n{}", body);
+        let (addr, mut requests) = testing::http_server(response).await.unwrap();
+
+        let (targets, index) = query(&addr.to_string(), "web", Some("primary"), 41)
+            .await
+            .unwrap();
+        assert_eq!(targets, ["10.0.1.1:8080", "[fd00::2]:8081"]);
+        assert_eq!(index, 42);
+        let request = requests.recv().await.unwrap();
+        assert!(request.starts_with(
+            "GET /v1/health/service/web?passing=true&index=41&wait=60s&tag=primary HTTP/1.0\r\n"
+        ));
+
+        let (addr, _) = testing::http_server("HTTP/1.0 200 OK\r\n\r\n{}".to_string())
+            .await
+            .unwrap();
+        assert!(query(&addr.to_string(), "web", None, 0).await.is_err());
+    }
+}
@@ -0,0 +1,259 @@
+use crate::admin::Admin;
+use crate::json::{self, Value};
+use crate::output;
+use crate::reporter::WeakReporterHandle;
+use std::fmt::{Display, Formatter};
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::sync::mpsc;
+
+/// The methods understood besides the admin API's commands.
+const SNAPSHOT: &str = "snapshot";
+const SHUTDOWN: &str = "shutdown";
+
+/// The named parameters of the admin API's commands, in the order the commands take them.
+const PARAMS: [&str; 7] = [
+    "bind",
+    "address",
+    "network",
+    "destination",
+    "backend",
+    "weight",
+    "duration",
+];
+
+/// The codes of JSON-RPC errors.
+const PARSE_ERROR: i32 = -32700;
+const INVALID_REQUEST: i32 = -32600;
+const METHOD_NOT_FOUND: i32 = -32601;
+const INVALID_PARAMS: i32 = -32602;
+const COMMAND_FAILED: i32 = -32000;
+
+/// How the proxy is controlled, besides the admin API.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Control {
+    /// Only through signals and the admin API.
+    #[default]
+    None,
+
+    /// Through JSON-RPC requests on stdin, answered on stdout along with the events.
+    Stdio,
+}
+
+impl Control {
+    /// Parses `none` or `stdio`.
+    pub fn parse(value: &str) -> Result<Control, String> {
+        match value {
+            "none" => Ok(Control::None),
+            "stdio" => Ok(Control::Stdio),
+            _ => Err(format!(
+                "Expected `none` or `stdio` as the control mode, got `{}`",
+                value
+            )),
+        }
+    }
+}
+
+impl Display for Control {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Control::None => write!(f, "none"),
+            Control::Stdio => write!(f, "stdio"),
+        }
+    }
+}
+
+/// Answers JSON-RPC requests from stdin, one per line, until stdin closes or `shutdown` is
+/// requested, then asks for the proxy to stop through `stop`.
+pub async fn run_stdio(admin: Admin, reporter_handle: WeakReporterHandle, stop: mpsc::Sender<()>) {
+    let mut lines = BufReader::new(tokio::io::stdin()).lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let (response, shutdown) = answer(&admin, &reporter_handle, &line).await;
+        if let Some(response) = response {
+            output::print_raw(&response);
+        }
+        if shutdown {
+            break;
+        }
+    }
+
+    let _ = stop.send(()).await;
+}
+
+/// Answers a request, returning the response (unless it was a notification) and whether to shut
+/// down.
+async fn answer(
+    admin: &Admin,
+    reporter_handle: &WeakReporterHandle,
+    line: &str,
+) -> (Option<String>, bool) {
+    let request = match json::parse(line) {
+        Ok(request) => request,
+        Err(err) => return (Some(error(&Value::Null, PARSE_ERROR, &err)), false),
+    };
+
+    // Requests without an ID are notifications, which get no response.
+    let id = request.get("id").cloned();
+    let Some(method) = request.get("method").and_then(Value::as_str) else {
+        let id = id.unwrap_or(Value::Null);
+        return (
+            Some(error(&id, INVALID_REQUEST, "expected a method")),
+            false,
+        );
+    };
+
+    let respond = |result: Result<Value, (i32, String)>| {
+        let id = id.as_ref()?;
+        Some(match result {
+            Ok(result) => format!(r#"{{"jsonrpc":"2.0","id":{},"result":{}}}"#, id, result),
+            Err((code, message)) => error(id, code, &message),
+        })
+    };
+
+    match method {
+        SHUTDOWN => (respond(Ok(Value::String("ok".to_string()))), true),
+        SNAPSHOT => (respond(snapshot(reporter_handle).await), false),
+        _ => {
+            let result = match command(method, request.get("params")) {
+                Ok(command) => execute(admin, &command).await,
+                Err(err) => Err(err),
+            };
+            (respond(result), false)
+        }
+    }
+}
+
+/// Turns a method and its parameters (positional, or named as in `PARAMS` and `drain`) into a
+/// command for the admin API.
+fn command(method: &str, params: Option<&Value>) -> Result<String, (i32, String)> {
+    let invalid = || {
+        (
+            INVALID_PARAMS,
+            "expected the parameters as strings or numbers, positional or named".to_string(),
+        )
+    };
+    let word = |param: &Value| match param {
+        Value::String(param) => Ok(param.clone()),
+        Value::Number(_) => Ok(param.to_string()),
+        _ => Err(invalid()),
+    };
+    let mut words = vec![method.to_string()];
+    match params {
+        None | Some(Value::Null) => {}
+        Some(Value::Array(params)) => {
+            for param in params {
+                words.push(word(param)?);
+            }
+        }
+        Some(params @ Value::Object(_)) => {
+            for name in PARAMS {
+                if let Some(param) = params.get(name) {
+                    words.push(word(param)?);
+                }
+            }
+            if params.get("drain") == Some(&Value::Bool(true)) {
+                words.push("drain".to_string());
+            }
+        }
+        Some(_) => return Err(invalid()),
+    }
+
+    Ok(words.join(" "))
+}
+
+/// Executes a command of the admin API, turning its response into a result.
+async fn execute(admin: &Admin, command: &str) -> Result<Value, (i32, String)> {
+    let response = admin.execute(command).await;
+    match response.strip_prefix("error: ") {
+        Some(err) if err.starts_with("unknown command") => Err((METHOD_NOT_FOUND, err.to_string())),
+        Some(err) => Err((COMMAND_FAILED, err.to_string())),
+        None => Ok(Value::String(response)),
+    }
+}
+
+/// Takes a snapshot of the reporter's state, as JSON.
+async fn snapshot(reporter_handle: &WeakReporterHandle) -> Result<Value, (i32, String)> {
+    let snapshot = match reporter_handle.upgrade() {
+        Some(reporter_handle) => reporter_handle.snapshot().await,
+        None => None,
+    };
+    let snapshot =
+        snapshot.ok_or_else(|| (COMMAND_FAILED, "the reporter has stopped".to_string()))?;
+
+    let count = |n: u64| Value::Number(n as f64);
+    Ok(Value::Object(vec![
+        ("open".to_string(), count(snapshot.open)),
+        ("total".to_string(), count(snapshot.total)),
+        (
+            "closed_with_error".to_string(),
+            count(snapshot.closed_with_error),
+        ),
+        (
+            "alerts_fired".to_string(),
+            Value::Array(
+                snapshot
+                    .alerts_fired
+                    .into_iter()
+                    .map(Value::String)
+                    .collect(),
+            ),
+        ),
+    ]))
+}
+
+/// Formats an error response.
+fn error(id: &Value, code: i32, message: &str) -> String {
+    format!(
+        r#"{{"jsonrpc":"2.0","id":{},"error":{{"code":{},"message":{}}}}}"#,
+        id,
+        code,
+        json::quote(message)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proxy::Destination;
+    use std::sync::Arc;
+    use tokio::sync::watch;
+
+    #[tokio::test]
+    async fn answers_requests() {
+        let (sender, receiver) = watch::channel(Destination::new("a:80".to_string(), false));
+        let (reporter_handle, reporter_actor) = crate::reporter::create(Default::default());
+        tokio::spawn(reporter_actor.run());
+        let weak = reporter_handle.downgrade();
+        let admin = Admin::new(Arc::new(sender), weak.clone());
+
+        let request = r#"{"jsonrpc":"2.0","id":1,"method":"set-destination","params":{"address":"b:80","drain":true}}"#;
+        assert_eq!(
+            answer(&admin, &weak, request).await,
+            (
+                Some(r#"{"jsonrpc":"2.0","id":1,"result":"ok"}"#.to_string()),
+                false
+            )
+        );
+        assert_eq!(receiver.borrow().addr, "b:80");
+        assert_eq!(receiver.borrow().drain_generation, 1);
+
+        let request = r#"{"jsonrpc":"2.0","id":"s","method":"snapshot"}"#;
+        assert_eq!(
+            answer(&admin, &weak, request).await.0.unwrap(),
+            r#"{"jsonrpc":"2.0","id":"s","result":{"open":0,"total":0,"closed_with_error":0,"alerts_fired":[]}}"#
+        );
+
+        let request = r#"{"jsonrpc":"2.0","id":2,"method":"frobnicate"}"#;
+        let response = answer(&admin, &weak, request).await.0.unwrap();
+        assert!(response.starts_with(r#"{"jsonrpc":"2.0","id":2,"error":{"code":-32601,"#));
+
+        let request = r#"{"jsonrpc":"2.0","method":"stats"}"#;
+        assert_eq!(answer(&admin, &weak, request).await, (None, false));
+
+        let request = r#"{"jsonrpc":"2.0","id":3,"method":"shutdown"}"#;
+        assert!(answer(&admin, &weak, request).await.1);
+    }
+}
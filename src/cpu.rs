@@ -0,0 +1,124 @@
+use crate::output::{eprint_line, print_line};
+use std::io;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::runtime::{Builder, Runtime};
+
+/// How long to wait for the workers of a pinned runtime to start.
+const WORKER_START_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Parses a list of CPUs like `0-3,6`.
+pub fn parse_cpus(value: &str) -> Result<Vec<usize>, String> {
+    let invalid = || format!("Expected a list of CPUs like `0-3,6`, got `{}`", value);
+    let mut cpus = Vec::new();
+    for range in value.split(',').map(str::trim).filter(|r| !r.is_empty()) {
+        let (first, last) = range.split_once('-').unwrap_or((range, range));
+        let first: usize = first.trim().parse().map_err(|_| invalid())?;
+        let last: usize = last.trim().parse().map_err(|_| invalid())?;
+        if first > last {
+            return Err(invalid());
+        }
+
+        for cpu in first..=last {
+            if !cpus.contains(&cpu) {
+                cpus.push(cpu);
+            }
+        }
+    }
+
+    match cpus.is_empty() {
+        true => Err(invalid()),
+        false => Ok(cpus),
+    }
+}
+
+/// Displays a list of CPUs, e.g. `0,1,2,3,6`.
+pub fn display_cpus(cpus: &[usize]) -> String {
+    let cpus: Vec<String> = cpus.iter().map(usize::to_string).collect();
+    cpus.join(",")
+}
+
+/// Builds the runtime. With CPUs, it has one worker thread per CPU, pinned to it, so the
+/// connections a worker handles stay on its core.
+pub fn runtime(cpus: &[usize]) -> io::Result<Runtime> {
+    let mut builder = Builder::new_multi_thread();
+    builder.enable_all();
+    if cpus.is_empty() {
+        return builder.build();
+    }
+
+    // The workers are the first threads to start, all while building the runtime. Threads that
+    // start later are for blocking work (like resolving names) and aren't pinned.
+    let started = Arc::new(AtomicUsize::new(0));
+    let worker_cpus = cpus.to_vec();
+    let runtime = builder
+        .worker_threads(cpus.len())
+        .on_thread_start({
+            let started = started.clone();
+            move || {
+                let index = started.fetch_add(1, Ordering::SeqCst);
+                if let Some(cpu) = worker_cpus.get(index) {
+                    if let Err(err) = pin(*cpu) {
+                        eprint_line!("💥️ — pinning a worker to CPU {} failed: {}", cpu, err);
+                    }
+                }
+            }
+        })
+        .build()?;
+
+    let waiting = Instant::now();
+    while started.load(Ordering::SeqCst) < cpus.len() && waiting.elapsed() < WORKER_START_TIMEOUT {
+        std::thread::sleep(Duration::from_millis(1));
+    }
+
+    print_line!(
+        "📌 running {} worker(s), pinned to CPUs {}",
+        cpus.len(),
+        display_cpus(cpus)
+    );
+    Ok(runtime)
+}
+
+/// Pins the calling thread to a CPU.
+#[cfg(target_os = "linux")]
+fn pin(cpu: usize) -> io::Result<()> {
+    // SAFETY: `set` is a valid, zeroed CPU set, and `CPU_SET` checks the CPU is in range.
+    unsafe {
+        let mut set: libc::cpu_set_t = std::mem::zeroed();
+        if cpu >= 8 * std::mem::size_of::<libc::cpu_set_t>() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("there is no CPU {}", cpu),
+            ));
+        }
+
+        libc::CPU_SET(cpu, &mut set);
+        match libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &set) {
+            0 => Ok(()),
+            _ => Err(io::Error::last_os_error()),
+        }
+    }
+}
+
+/// Threads are only pinned on Linux.
+#[cfg(not(target_os = "linux"))]
+fn pin(_cpu: usize) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "pinning threads is only supported on Linux",
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_cpus() {
+        assert_eq!(parse_cpus("0-3, 6,2").unwrap(), [0, 1, 2, 3, 6]);
+        assert_eq!(display_cpus(&parse_cpus("4").unwrap()), "4");
+        assert!(parse_cpus("3-1").is_err());
+        assert!(parse_cpus("").is_err());
+    }
+}
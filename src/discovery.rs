@@ -0,0 +1,134 @@
+use crate::net;
+use crate::output::print_line;
+use crate::srv;
+use crate::usage;
+use std::collections::HashMap;
+use std::future::Future;
+use std::io;
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio::sync::watch;
+
+/// How long a connection waits for the first answer from the registry.
+const FIRST_LOOKUP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long to wait before watching again after the registry failed.
+pub const RETRY_DELAY: Duration = Duration::from_secs(5);
+
+/// The `host:port` targets of a registry-backed destination, `None` until first resolved.
+pub type Targets = watch::Receiver<Option<Vec<String>>>;
+
+/// The targets of every registry-backed destination, kept up to date by a watch task each.
+static WATCHES: OnceLock<Mutex<HashMap<String, Targets>>> = OnceLock::new();
+
+/// Connects to one of the targets of a registry-backed destination. The first connection starts
+/// the watch task that follows the registry, later connections use what it found last.
+/// Connections are spread randomly, failing over to the other targets.
+pub async fn connect<F, W>(destination: &str, watch: W) -> io::Result<TcpStream>
+where
+    W: FnOnce(watch::Sender<Option<Vec<String>>>) -> F,
+    F: Future<Output = ()> + Send + 'static,
+{
+    let mut targets = {
+        let mut watches = WATCHES.get_or_init(Mutex::default).lock().unwrap();
+        watches
+            .entry(destination.to_string())
+            .or_insert_with(|| {
+                let (sender, receiver) = watch::channel(None);
+                usage::spawn(watch(sender));
+                receiver
+            })
+            .clone()
+    };
+
+    let mut targets = tokio::time::timeout(FIRST_LOOKUP_TIMEOUT, targets.wait_for(Option::is_some))
+        .await
+        .map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::TimedOut,
+                format!("{} has not been resolved yet", destination),
+            )
+        })?
+        .map_err(|_| io::Error::other("the registry watch stopped"))?
+        .clone()
+        .unwrap_or_default();
+
+    if targets.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("{} has no instances", destination),
+        ));
+    }
+
+    let start = (srv::random() % targets.len() as u64) as usize;
+    targets.rotate_left(start);
+
+    let mut last_err = None;
+    for target in targets {
+        match net::connect(target.as_str()).await {
+            Ok(stream) => return Ok(stream),
+            Err(err) => last_err = Some(err),
+        }
+    }
+
+    Err(last_err.expect("at least one target was tried"))
+}
+
+/// Publishes the targets found in the registry, printing them when they changed.
+pub fn update(
+    destination: &str,
+    sender: &watch::Sender<Option<Vec<String>>>,
+    mut targets: Vec<String>,
+) {
+    targets.sort();
+    sender.send_if_modified(|current| {
+        if current.as_ref() == Some(&targets) {
+            return false;
+        }
+
+        print_line!(
+            "🧭 {} has {} instance(s): {}",
+            destination,
+            targets.len(),
+            targets.join(", ")
+        );
+        *current = Some(targets);
+        true
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    #[tokio::test]
+    async fn connects_to_a_target_that_answers() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let listening = listener.local_addr().unwrap().to_string();
+        let refused = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let refused_addr = refused.local_addr().unwrap().to_string();
+        drop(refused);
+
+        // The first connection starts the watch, and every one fails over to the listener.
+        for _ in 0..4 {
+            let targets = vec![refused_addr.clone(), listening.clone()];
+            let stream = connect("test://two", |sender| async move {
+                update("test://two", &sender, targets);
+                sender.closed().await;
+            })
+            .await
+            .unwrap();
+            assert_eq!(stream.peer_addr().unwrap().to_string(), listening);
+        }
+
+        let error = connect("test://none", |sender| async move {
+            update("test://none", &sender, Vec::new());
+            sender.closed().await;
+        })
+        .await
+        .unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::NotFound);
+    }
+}
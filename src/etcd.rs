@@ -0,0 +1,214 @@
+use crate::output::eprint_line;
+use crate::{base64, discovery, http_client, json};
+use std::io;
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio::sync::watch;
+
+/// Prefix of etcd destinations, e.g. `etcd:///services/web` for a key holding one `host:port`
+/// address or `etcd:///services/web/` for every address under a prefix.
+pub const PREFIX: &str = "etcd://";
+
+/// The etcd endpoint to ask when `ETCDCTL_ENDPOINTS` isn't set.
+const DEFAULT_ENDPOINT: &str = "127.0.0.1:2379";
+
+/// How long to wait for a range request.
+const RANGE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long a watch may stay quiet before reading the keys again, in case the stream silently
+/// broke.
+const RESYNC_INTERVAL: Duration = Duration::from_secs(300);
+
+/// Checks that the destination is an absolute key or key prefix.
+pub fn validate(key: &str) -> Result<(), String> {
+    match key.starts_with('/') && key.len() > 1 {
+        true => Ok(()),
+        false => Err(format!(
+            "`{}{}` is not an etcd key like {}/services/web",
+            PREFIX, key, PREFIX
+        )),
+    }
+}
+
+/// Connects to one of the addresses stored under the key. The key is watched through etcd's
+/// JSON gateway, so addresses written to it are picked up as they change.
+pub async fn connect(key: &str) -> Result<TcpStream, io::Error> {
+    validate(key).map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+
+    let destination = format!("{}{}", PREFIX, key);
+    let key = key.to_string();
+    discovery::connect(&destination.clone(), |sender| {
+        watch_key(destination, key, sender)
+    })
+    .await
+}
+
+/// Follows the addresses under the key until nobody is interested anymore.
+async fn watch_key(destination: String, key: String, sender: watch::Sender<Option<Vec<String>>>) {
+    let endpoint = endpoint();
+    let mut range = format!(r#""key":"{}""#, base64::encode(key.as_bytes()));
+    if let Some(range_end) = prefix_end(key.as_bytes()) {
+        range.push_str(&format!(r#","range_end":"{}""#, base64::encode(&range_end)));
+    }
+
+    while !sender.is_closed() {
+        let result = async {
+            let (targets, revision) = read(&endpoint, &range).await?;
+            discovery::update(&destination, &sender, targets);
+            wait_for_change(&endpoint, &range, revision).await
+        };
+
+        if let Err(err) = result.await {
+            eprint_line!("💥️ — watching {} failed: {}", destination, err);
+            tokio::time::sleep(discovery::RETRY_DELAY).await;
+        }
+    }
+}
+
+/// Reads the addresses in the range, along with the revision they were read at.
+async fn read(endpoint: &str, range: &str) -> io::Result<(Vec<String>, u64)> {
+    let body = format!("{{{}}}", range);
+    let (_, body) =
+        http_client::request(endpoint, "POST", "/v3/kv/range", Some(&body), RANGE_TIMEOUT).await?;
+
+    let invalid = |err: String| io::Error::new(io::ErrorKind::InvalidData, err);
+    let response = json::parse(&body).map_err(invalid)?;
+
+    // etcd encodes 64-bit integers as strings.
+    let revision = response
+        .get("header")
+        .and_then(|header| header.get("revision"))
+        .and_then(json::Value::as_str)
+        .and_then(|revision| revision.parse().ok())
+        .ok_or_else(|| invalid("the response has no revision".to_string()))?;
+
+    let targets = response
+        .get("kvs")
+        .and_then(json::Value::as_array)
+        .unwrap_or_default()
+        .iter()
+        .filter_map(|kv| kv.get("value")?.as_str())
+        .filter_map(base64::decode)
+        .filter_map(|value| String::from_utf8(value).ok())
+        .map(|value| value.trim().to_string())
+        .filter(|value| !value.is_empty())
+        .collect();
+
+    Ok((targets, revision))
+}
+
+/// Watches the range from the revision after `revision`, returning when anything changed.
+async fn wait_for_change(endpoint: &str, range: &str, revision: u64) -> io::Result<()> {
+    let body = format!(
+        r#"{{"create_request":{{{},"start_revision":"{}"}}}}"#,
+        range,
+        revision + 1
+    );
+    let (head, mut reader) = http_client::send(endpoint, "POST", "/v3/watch", Some(&body)).await?;
+    if head.status != 200 {
+        return Err(io::Error::other(format!(
+            "POST /v3/watch returned {}",
+            head.status
+        )));
+    }
+
+    // The gateway streams one JSON message per line; the first confirms the watch was created.
+    let mut line = String::new();
+    loop {
+        line.clear();
+        let read = tokio::time::timeout(
+            RESYNC_INTERVAL,
+            http_client::read_line(&mut reader, &mut line),
+        )
+        .await;
+        match read {
+            Err(_) | Ok(Ok(0)) => return Ok(()),
+            Ok(Err(err)) => return Err(err),
+            Ok(Ok(_)) => {}
+        }
+
+        let message =
+            json::parse(&line).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        let result = message.get("result");
+        if let Some(error) = message.get("error") {
+            return Err(io::Error::other(format!("the watch failed: {:?}", error)));
+        }
+
+        let changed = result
+            .and_then(|result| result.get("events"))
+            .and_then(json::Value::as_array)
+            .is_some_and(|events| !events.is_empty());
+        let canceled =
+            result.and_then(|result| result.get("canceled")) == Some(&json::Value::Bool(true));
+        if changed || canceled {
+            return Ok(());
+        }
+    }
+}
+
+/// Returns the etcd endpoint, honoring the first of `ETCDCTL_ENDPOINTS` like etcdctl.
+fn endpoint() -> String {
+    let endpoints = std::env::var("ETCDCTL_ENDPOINTS").unwrap_or_default();
+    match endpoints.split(',').next().map(str::trim) {
+        Some(endpoint) if !endpoint.is_empty() => {
+            endpoint.trim_start_matches("http://").to_string()
+        }
+        _ => DEFAULT_ENDPOINT.to_string(),
+    }
+}
+
+/// Returns the end of the range covering every key under `key`, if it is a prefix.
+fn prefix_end(key: &[u8]) -> Option<Vec<u8>> {
+    if !key.ends_with(b"/") {
+        return None;
+    }
+
+    // The '/' can always be incremented, so this never carries.
+    let mut end = key.to_vec();
+    *end.last_mut()? += 1;
+    Some(end)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    #[test]
+    fn prefix_ends() {
+        assert_eq!(prefix_end(b"/web/").unwrap(), b"/web0");
+    }
+
+    #[tokio::test]
+    async fn reads_addresses() {
+        // Keys and values are base64, and values that aren't addresses are left out.
+        let kv = |key: &str, value: &[u8]| {
+            format!(
+                r#"{{"key":"{}","value":"{}"}}"#,
+                base64::encode(key.as_bytes()),
+                base64::encode(value)
+            )
+        };
+        let body = format!(
+            r#"{{"header":{{"revision":"17"}},"kvs":[{},{},{},{{"key":"L3dlYi80","value":"!"}}]}}"#,
+            kv("/web/1", b"10.0.0.1:80\n"),
+            kv("/web/2", b" "),
+            kv("/web/3", &[0xff, 0xfe]),
+        );
+        let response = format!("HTTP/1.0 200 OK\r\n\r\n{}", body);
+        let (addr, mut requests) = testing::http_server(response).await.unwrap();
+
+        let range = r#""key":"L3dlYi8=","range_end":"L3dlYjA=""#;
+        let (targets, revision) = read(&addr.to_string(), range).await.unwrap();
+        assert_eq!((targets, revision), (vec!["10.0.0.1:80".to_string()], 17));
+        let request = requests.recv().await.unwrap();
+        assert!(request.starts_with("POST /v3/kv/range HTTP/1.0\r\n"));
+        assert!(request.ends_with(&format!("\r\n\r\n{{{}}}", range)));
+
+        // Without a revision, the response can't be watched from.
+        let (addr, _) = testing::http_server("HTTP/1.0 200 OK\r\n\r\n{}".to_string())
+            .await
+            .unwrap();
+        assert!(read(&addr.to_string(), range).await.is_err());
+    }
+}
@@ -0,0 +1,119 @@
+use crate::histogram::Histogram;
+use crate::protocol::{Parser, Protocol, Stats};
+use tokio::time::Instant;
+
+/// Infers the requests and responses of a protocol it knows nothing about: what the client
+/// sends until the server sends something back is taken as a request, and the time from the
+/// last byte of it to the first byte back as the round trip of its response.
+#[derive(Debug, Default)]
+pub struct Exchanges {
+    /// When the client last sent something, if the server hasn't sent anything back since.
+    awaiting: Option<Instant>,
+
+    /// How many requests the client sent.
+    requests: u64,
+
+    /// How long the server took to respond to the requests it responded to.
+    round_trips: Histogram,
+}
+
+impl Parser for Exchanges {
+    fn client(&mut self, bytes: &[u8]) {
+        if bytes.is_empty() {
+            return;
+        }
+        if self.awaiting.is_none() {
+            self.requests += 1;
+        }
+        self.awaiting = Some(Instant::now());
+    }
+
+    fn server(&mut self, bytes: &[u8]) {
+        if bytes.is_empty() {
+            return;
+        }
+
+        // The rest of a response, or what the server sends unasked (e.g. a greeting), is not a
+        // round trip.
+        if let Some(sent) = self.awaiting.take() {
+            self.round_trips.record(sent.elapsed());
+        }
+    }
+
+    fn stats(&self) -> Stats {
+        let exchanges = self.round_trips.count();
+        let mut timings = Vec::new();
+        if let (Some(mean), Some(max)) = (self.round_trips.mean(), self.round_trips.max()) {
+            timings.push(("mean round trip", mean));
+            timings.push(("max round trip", max));
+        }
+
+        Stats {
+            protocol: Protocol::Generic,
+            counts: vec![
+                ("exchanges", exchanges),
+                ("unanswered", self.requests - exchanges),
+            ],
+            timings,
+            details: Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test(start_paused = true)]
+    async fn infers_round_trips() {
+        let mut exchanges = Exchanges::default();
+
+        // A greeting, then a request sent in two writes, answered in two.
+        exchanges.server(b"hello");
+        exchanges.client(b"req");
+        tokio::time::sleep(Duration::from_millis(5)).await;
+        exchanges.client(b"uest");
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        exchanges.server(b"resp");
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        exchanges.server(b"onse");
+
+        // A quicker one, then one that's left unanswered.
+        exchanges.client(b"request");
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        exchanges.server(b"response");
+        exchanges.client(b"request");
+
+        assert_eq!(
+            exchanges.stats().to_string(),
+            "2 exchanges, 1 unanswered, mean round trip 15.0ms, max round trip 20.0ms"
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn takes_any_bytes() {
+        let mut exchanges = Exchanges::default();
+
+        // Nothing read is nothing sent.
+        exchanges.client(b"");
+        exchanges.server(b"");
+        assert_eq!(exchanges.stats().to_string(), "0 exchanges, 0 unanswered");
+
+        // A server that talks unasked, then a large request that isn't text, answered by a
+        // flood.
+        for _ in 0..1000 {
+            exchanges.server(&[0xff; 100]);
+        }
+        exchanges.client(&vec![0xfe; 1 << 20]);
+        tokio::time::sleep(Duration::from_millis(3)).await;
+        for _ in 0..1000 {
+            exchanges.server(b"\x1b\x00\xff");
+        }
+        exchanges.client(b"");
+        assert_eq!(
+            exchanges.stats().to_string(),
+            "1 exchanges, 0 unanswered, mean round trip 3.00ms, max round trip 3.00ms"
+        );
+    }
+}
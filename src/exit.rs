@@ -0,0 +1,112 @@
+use crate::format::Elapsed;
+use crate::reporter::WeakReporterHandle;
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// How often the conditions are checked.
+const CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// When to stop by itself, for scripted runs. The first condition met stops it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Conditions {
+    /// Once this many connections have closed.
+    pub connections: Option<u64>,
+
+    /// Once this long has passed since starting.
+    pub after: Option<Duration>,
+
+    /// Once no connections have been open for this long.
+    pub idle: Option<Duration>,
+}
+
+impl Conditions {
+    /// Whether any condition is set.
+    pub fn any(&self) -> bool {
+        self.connections.is_some() || self.after.is_some() || self.idle.is_some()
+    }
+}
+
+/// Waits until one of the conditions is met, returning why. Never returns if none are set or
+/// the reporter stops.
+pub async fn wait(conditions: Conditions, reporter_handle: WeakReporterHandle) -> String {
+    let start = Instant::now();
+    let mut idle_since = start;
+    let mut checks = tokio::time::interval(CHECK_INTERVAL);
+    loop {
+        checks.tick().await;
+        let now = Instant::now();
+        if let Some(after) = conditions.after {
+            if now >= start + after {
+                return format!("{} passed", Elapsed(after));
+            }
+        }
+
+        let snapshot = match reporter_handle.upgrade() {
+            Some(reporter_handle) => reporter_handle.snapshot().await,
+            None => None,
+        };
+        let Some(snapshot) = snapshot else {
+            return std::future::pending().await;
+        };
+
+        let closed = snapshot.total - snapshot.open;
+        if let Some(connections) = conditions.connections {
+            if closed >= connections {
+                return format!("{} connection(s) closed", closed);
+            }
+        }
+
+        if snapshot.open > 0 {
+            idle_since = now;
+        } else if let Some(idle) = conditions.idle {
+            if now >= idle_since + idle {
+                return format!("no connections were open for {}", Elapsed(idle));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::reporter::{self, Connection, Event, Traffic, DEFAULT_MAPPING};
+    use std::sync::Arc;
+
+    #[tokio::test(start_paused = true)]
+    async fn waits_for_conditions() {
+        let (reporter_handle, reporter_actor) = reporter::create(Default::default());
+        tokio::spawn(reporter_actor.run());
+        let weak = reporter_handle.downgrade();
+
+        let start = Instant::now();
+        let conditions = Conditions {
+            after: Some(Duration::from_secs(120)),
+            idle: Some(Duration::from_secs(30)),
+            ..Default::default()
+        };
+        assert_eq!(
+            wait(conditions, weak.clone()).await,
+            "no connections were open for 30s"
+        );
+        assert!(start.elapsed() < Duration::from_secs(31));
+
+        let connection = Connection {
+            peer: ([10, 0, 0, 1], 4000).into(),
+            local: ([127, 0, 0, 1], 8080).into(),
+            mapping: Arc::from(DEFAULT_MAPPING),
+            stream: None,
+            labels: Arc::new([]),
+        };
+        reporter_handle.report(Event::Opened(connection.clone()));
+        let start = Instant::now();
+        assert_eq!(wait(conditions, weak.clone()).await, "2m passed");
+        assert_eq!(start.elapsed(), Duration::from_secs(120));
+
+        reporter_handle.report(Event::ClosedGracefully(connection, Traffic::default()));
+        let conditions = Conditions {
+            connections: Some(1),
+            ..Default::default()
+        };
+        assert_eq!(wait(conditions, weak).await, "1 connection(s) closed");
+    }
+}
@@ -0,0 +1,233 @@
+use crate::reporter::Connection;
+use std::cmp::Ordering;
+use std::fmt::{Display, Formatter};
+use std::net::IpAddr;
+use std::time::Duration;
+
+/// Conditions that connections must all meet to be reported, e.g.
+/// `duration>5s; peer in 10.0.0.0/8`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Filter(Vec<Condition>);
+
+/// A condition on a connection.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Condition {
+    /// How long the connection was open compares to a duration like this, e.g. `duration>5s`.
+    Duration(Ordering, bool, Duration),
+
+    /// The client's address is (or, when negated, isn't) in a network, e.g.
+    /// `peer in 10.0.0.0/8`.
+    Peer(bool, IpAddr, u8),
+
+    /// The port the connection came in on is (or, when negated, isn't) this one, e.g. `port=443`.
+    Port(bool, u16),
+}
+
+impl Filter {
+    /// Parses conditions separated by semicolons.
+    pub fn parse(value: &str) -> Result<Filter, String> {
+        value
+            .split(';')
+            .map(str::trim)
+            .filter(|condition| !condition.is_empty())
+            .map(Condition::parse)
+            .collect::<Result<_, _>>()
+            .map(Filter)
+    }
+
+    /// Whether a connection meets every condition. Until the connection closes, its duration
+    /// is `None`, which meets no condition on the duration.
+    pub fn allows(&self, connection: &Connection, duration: Option<Duration>) -> bool {
+        self.0
+            .iter()
+            .all(|condition| condition.allows(connection, duration))
+    }
+}
+
+impl Display for Filter {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if self.0.is_empty() {
+            return write!(f, "(none)");
+        }
+
+        let conditions: Vec<String> = self.0.iter().map(Condition::to_string).collect();
+        write!(f, "{}", conditions.join("; "))
+    }
+}
+
+impl Condition {
+    /// Parses a condition: `duration` with `<`, `<=`, `>` or `>=` and a duration, `peer in`
+    /// or `peer not in` a network, or `port` with `=` or `!=` and a port.
+    fn parse(condition: &str) -> Result<Condition, String> {
+        let invalid = || {
+            format!(
+                "Expected a condition like `duration>5s`, `peer in 10.0.0.0/8` or `port=443`, \
+                 got `{}`",
+                condition
+            )
+        };
+
+        if let Some(rest) = condition.strip_prefix("duration") {
+            let rest = rest.trim_start();
+            let (ordering, or_equal, duration) = match rest.as_bytes() {
+                [b'<', b'=', ..] => (Ordering::Less, true, &rest[2..]),
+                [b'>', b'=', ..] => (Ordering::Greater, true, &rest[2..]),
+                [b'<', ..] => (Ordering::Less, false, &rest[1..]),
+                [b'>', ..] => (Ordering::Greater, false, &rest[1..]),
+                _ => return Err(invalid()),
+            };
+            return Ok(Condition::Duration(
+                ordering,
+                or_equal,
+                parse_duration(duration.trim())?,
+            ));
+        }
+
+        if let Some(rest) = condition.strip_prefix("peer ") {
+            let (negated, network) = match rest.trim().strip_prefix("not ") {
+                Some(rest) => (true, rest.trim()),
+                None => (false, rest.trim()),
+            };
+            let network = network.strip_prefix("in ").ok_or_else(invalid)?.trim();
+            let (ip, prefix) = parse_network(network).ok_or_else(invalid)?;
+            return Ok(Condition::Peer(negated, ip, prefix));
+        }
+
+        if let Some(rest) = condition.strip_prefix("port") {
+            let rest = rest.trim_start();
+            let (negated, port) = match rest.strip_prefix("!=") {
+                Some(port) => (true, port),
+                None => (false, rest.strip_prefix('=').ok_or_else(invalid)?),
+            };
+            let port = port.trim().parse().map_err(|_| invalid())?;
+            return Ok(Condition::Port(negated, port));
+        }
+
+        Err(invalid())
+    }
+
+    /// Whether a connection meets the condition.
+    fn allows(&self, connection: &Connection, duration: Option<Duration>) -> bool {
+        match self {
+            Condition::Duration(ordering, or_equal, limit) => duration.is_some_and(|duration| {
+                let compared = duration.cmp(limit);
+                compared == *ordering || (*or_equal && compared == Ordering::Equal)
+            }),
+            Condition::Peer(negated, network, prefix) => {
+                in_network(connection.peer.ip(), *network, *prefix) != *negated
+            }
+            Condition::Port(negated, port) => (connection.local.port() == *port) != *negated,
+        }
+    }
+}
+
+impl Display for Condition {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Condition::Duration(ordering, or_equal, duration) => write!(
+                f,
+                "duration{}{}{:?}",
+                if *ordering == Ordering::Less {
+                    "<"
+                } else {
+                    ">"
+                },
+                if *or_equal { "=" } else { "" },
+                duration
+            ),
+            Condition::Peer(negated, ip, prefix) => write!(
+                f,
+                "peer {}in {}/{}",
+                if *negated { "not " } else { "" },
+                ip,
+                prefix
+            ),
+            Condition::Port(negated, port) => {
+                write!(f, "port{}={}", if *negated { "!" } else { "" }, port)
+            }
+        }
+    }
+}
+
+/// Parses a duration like `500ms`, `5s`, `1.5m` or `1h`.
+pub fn parse_duration(value: &str) -> Result<Duration, String> {
+    let invalid = || format!("Expected a duration like `5s` or `500ms`, got `{}`", value);
+    let split = value
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .ok_or_else(invalid)?;
+    let (number, unit) = value.split_at(split);
+    let number: f64 = number.parse().map_err(|_| invalid())?;
+    let seconds = match unit {
+        "ms" => number / 1000.0,
+        "s" => number,
+        "m" => number * 60.0,
+        "h" => number * 3600.0,
+        _ => return Err(invalid()),
+    };
+
+    Duration::try_from_secs_f64(seconds).map_err(|_| invalid())
+}
+
+/// Parses a network like `10.0.0.0/8` or `fd00::/8`, or a single address.
+pub fn parse_network(value: &str) -> Option<(IpAddr, u8)> {
+    let (ip, prefix) = match value.split_once('/') {
+        Some((ip, prefix)) => (ip.parse::<IpAddr>().ok()?, prefix.parse().ok()?),
+        None => {
+            let ip = value.parse::<IpAddr>().ok()?;
+            (ip, if ip.is_ipv4() { 32 } else { 128 })
+        }
+    };
+
+    match (ip, prefix) {
+        (IpAddr::V4(_), 0..=32) | (IpAddr::V6(_), 0..=128) => Some((ip, prefix)),
+        _ => None,
+    }
+}
+
+/// Whether an address is in a network.
+pub fn in_network(ip: IpAddr, network: IpAddr, prefix: u8) -> bool {
+    let (ip, network, bits) = match (ip, network) {
+        (IpAddr::V4(ip), IpAddr::V4(network)) => {
+            (u32::from(ip) as u128, u32::from(network) as u128, 32)
+        }
+        (IpAddr::V6(ip), IpAddr::V6(network)) => (u128::from(ip), u128::from(network), 128),
+        _ => return false,
+    };
+
+    // Compare the first `prefix` bits of the addresses.
+    let shift = bits - prefix as u32;
+    shift >= 128 || ip >> shift == network >> shift
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn filters_connections() {
+        let filter = Filter::parse("duration>=1.5s; peer not in 10.0.0.0/8; port!=22").unwrap();
+        assert_eq!(
+            filter.to_string(),
+            "duration>=1.5s; peer not in 10.0.0.0/8; port!=22"
+        );
+
+        let connection = |peer: &str, port: u16| Connection {
+            peer: peer.parse().unwrap(),
+            local: ([127, 0, 0, 1], port).into(),
+            mapping: Arc::from("default"),
+            stream: None,
+            labels: Arc::new([]),
+        };
+        let long = Some(Duration::from_secs(2));
+        assert!(filter.allows(&connection("192.168.1.5:4000", 80), long));
+        assert!(!filter.allows(&connection("192.168.1.5:4000", 80), None));
+        assert!(!filter.allows(&connection("10.1.2.3:4000", 80), long));
+        assert!(!filter.allows(&connection("192.168.1.5:4000", 22), long));
+
+        let all = Filter::parse("peer in 0.0.0.0/0").unwrap();
+        assert!(all.allows(&connection("10.1.2.3:4000", 80), None));
+        assert!(Filter::parse("duration>5").is_err());
+        assert!(Filter::parse("peer in 10.0.0.0/33").is_err());
+    }
+}
@@ -0,0 +1,264 @@
+use std::borrow::Cow;
+use std::fmt::{Display, Formatter, Write};
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::time::Duration;
+
+/// How byte counts are shown, as set by `set_bytes_format`.
+static BYTES_FORMAT: AtomicU8 = AtomicU8::new(BytesFormat::Binary as u8);
+
+/// How byte counts are shown.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BytesFormat {
+    /// In powers of 1024, e.g. `1.24 GiB` and `3.10 MiB/s`.
+    #[default]
+    Binary,
+
+    /// In powers of 1000, e.g. `1.33 GB`, with rates in bits as for network links, e.g.
+    /// `87.3 Mbit/s`.
+    Si,
+
+    /// As plain numbers of bytes, e.g. `1331439862 B`.
+    Raw,
+}
+
+impl BytesFormat {
+    /// Parses `binary`, `si` or `raw`.
+    pub fn parse(value: &str) -> Result<BytesFormat, String> {
+        match value {
+            "binary" => Ok(BytesFormat::Binary),
+            "si" => Ok(BytesFormat::Si),
+            "raw" => Ok(BytesFormat::Raw),
+            _ => Err(format!(
+                "Expected `binary`, `si` or `raw` as the byte format, got `{}`",
+                value
+            )),
+        }
+    }
+
+    /// The format set by `set_bytes_format`.
+    fn current() -> BytesFormat {
+        match BYTES_FORMAT.load(Ordering::Relaxed) {
+            1 => BytesFormat::Si,
+            2 => BytesFormat::Raw,
+            _ => BytesFormat::Binary,
+        }
+    }
+}
+
+impl Display for BytesFormat {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BytesFormat::Binary => write!(f, "binary"),
+            BytesFormat::Si => write!(f, "si"),
+            BytesFormat::Raw => write!(f, "raw"),
+        }
+    }
+}
+
+/// Sets how byte counts are shown from now on.
+pub fn set_bytes_format(format: BytesFormat) {
+    BYTES_FORMAT.store(format as u8, Ordering::Relaxed);
+}
+
+/// A number of bytes, shown in the current format.
+pub struct Bytes(pub u64);
+
+impl Display for Bytes {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", bytes(self.0, BytesFormat::current()))
+    }
+}
+
+/// A number of bytes per second, shown in the current format.
+pub struct Rate(pub u64);
+
+impl Display for Rate {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", rate(self.0, BytesFormat::current()))
+    }
+}
+
+/// Shows a number of bytes in a format.
+fn bytes(value: u64, format: BytesFormat) -> String {
+    match format {
+        BytesFormat::Binary => scaled(value, 1024, "B", &["KiB", "MiB", "GiB", "TiB"]),
+        BytesFormat::Si => scaled(value, 1000, "B", &["kB", "MB", "GB", "TB"]),
+        BytesFormat::Raw => format!("{} B", value),
+    }
+}
+
+/// Shows a number of bytes per second in a format.
+fn rate(value: u64, format: BytesFormat) -> String {
+    match format {
+        BytesFormat::Si => {
+            let units = ["kbit/s", "Mbit/s", "Gbit/s", "Tbit/s"];
+            scaled(value.saturating_mul(8), 1000, "bit/s", &units)
+        }
+        _ => format!("{}/s", bytes(value, format)),
+    }
+}
+
+/// Shows a number in the largest unit that keeps it at least 1, with three significant
+/// digits, e.g. `1.24 GiB` or `87.3 Mbit/s`. `units` are the powers of `base`, after `unit`.
+fn scaled(value: u64, base: u64, unit: &str, units: &[&str]) -> String {
+    if value < base {
+        return format!("{} {}", value, unit);
+    }
+
+    let base = base as f64;
+    let mut value = value as f64 / base;
+    let mut unit = 0;
+    while value >= base && unit < units.len() - 1 {
+        value /= base;
+        unit += 1;
+    }
+
+    match value {
+        value if value < 10.0 => format!("{:.2} {}", value, units[unit]),
+        value if value < 100.0 => format!("{:.1} {}", value, units[unit]),
+        value => format!("{:.0} {}", value, units[unit]),
+    }
+}
+
+/// A duration, shown to a precision that suits its length, e.g. `850µs`, `2.71ms`, `4.21s`,
+/// `10s`, `3m 42s` or `2h 5m`.
+pub struct Elapsed(pub Duration);
+
+impl Display for Elapsed {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let seconds = self.0.as_secs();
+        match seconds {
+            0 if self.0.as_millis() == 0 => write!(f, "{}µs", self.0.as_micros()),
+            0 => fraction(f, self.0.as_secs_f64() * 1000.0, "ms"),
+            1..=59 if self.0.subsec_nanos() == 0 => write!(f, "{}s", seconds),
+            1..=59 => fraction(f, self.0.as_secs_f64(), "s"),
+            60..=3599 => pair(f, seconds / 60, "m", seconds % 60, "s"),
+            3600..=86399 => pair(f, seconds / 3600, "h", seconds % 3600 / 60, "m"),
+            _ => pair(f, seconds / 86400, "d", seconds % 86400 / 3600, "h"),
+        }
+    }
+}
+
+/// Writes a duration in a unit with three significant digits, e.g. `2.71ms` or `42.5s`.
+fn fraction(f: &mut Formatter<'_>, value: f64, unit: &str) -> std::fmt::Result {
+    match value {
+        value if value < 10.0 => write!(f, "{:.2}{}", value, unit),
+        value if value < 100.0 => write!(f, "{:.1}{}", value, unit),
+        value => write!(f, "{:.0}{}", value, unit),
+    }
+}
+
+/// Writes a duration in two units, leaving out the smaller one when it's zero, e.g. `3m 42s`
+/// or `1m`.
+fn pair(
+    f: &mut Formatter<'_>,
+    large: u64,
+    large_unit: &str,
+    small: u64,
+    small_unit: &str,
+) -> std::fmt::Result {
+    match small {
+        0 => write!(f, "{}{}", large, large_unit),
+        _ => write!(f, "{}{} {}{}", large, large_unit, small, small_unit),
+    }
+}
+
+/// Turns bytes a peer sent into text that's safe to print: control characters (like the escape
+/// that starts ANSI escape sequences) and bytes that aren't UTF-8 are escaped, e.g. `\x1b`.
+pub fn sanitize(bytes: &[u8]) -> String {
+    let mut sanitized = String::with_capacity(bytes.len());
+    for chunk in bytes.utf8_chunks() {
+        for c in chunk.valid().chars() {
+            push_sanitized(&mut sanitized, c, false);
+        }
+        for byte in chunk.invalid() {
+            let _ = write!(sanitized, "\\x{:02x}", byte);
+        }
+    }
+    sanitized
+}
+
+/// Escapes the control characters of a line about to be printed, other than the line breaks
+/// of multiline output, in case something a peer sent ended up in it unsanitized.
+pub fn sanitize_line(line: &str) -> Cow<'_, str> {
+    if !line.chars().any(|c| c != '\n' && unsafe_char(c)) {
+        return Cow::Borrowed(line);
+    }
+    let mut sanitized = String::with_capacity(line.len() + 8);
+    for c in line.chars() {
+        push_sanitized(&mut sanitized, c, true);
+    }
+    Cow::Owned(sanitized)
+}
+
+/// Whether a terminal might do something with a character rather than show it: control
+/// characters, and those that reorder bidirectional text.
+fn unsafe_char(c: char) -> bool {
+    c.is_control() || matches!(c, '\u{202a}'..='\u{202e}' | '\u{2066}'..='\u{2069}')
+}
+
+/// Adds a character, escaped if it's unsafe to print.
+fn push_sanitized(sanitized: &mut String, c: char, keep_newlines: bool) {
+    match c {
+        '\n' if keep_newlines => sanitized.push('\n'),
+        '\n' => sanitized.push_str("\\n"),
+        '\r' => sanitized.push_str("\\r"),
+        '\t' => sanitized.push_str("\\t"),
+        c if unsafe_char(c) && (c as u32) < 0x100 => {
+            let _ = write!(sanitized, "\\x{:02x}", c as u32);
+        }
+        c if unsafe_char(c) => {
+            let _ = write!(sanitized, "\\u{{{:04x}}}", c as u32);
+        }
+        c => sanitized.push(c),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_durations_and_bytes() {
+        let elapsed = |micros| Elapsed(Duration::from_micros(micros)).to_string();
+        assert_eq!(elapsed(850), "850µs");
+        assert_eq!(elapsed(2_705), "2.71ms");
+        assert_eq!(elapsed(200_880), "201ms");
+        assert_eq!(elapsed(4_210_000), "4.21s");
+        assert_eq!(elapsed(10_000_000), "10s");
+        assert_eq!(elapsed(222_000_000), "3m 42s");
+        assert_eq!(elapsed(60_000_000), "1m");
+        assert_eq!(elapsed(7_500_000_000), "2h 5m");
+
+        assert_eq!(bytes(1_331_439_862, BytesFormat::Binary), "1.24 GiB");
+        assert_eq!(rate(20_582, BytesFormat::Binary), "20.1 KiB/s");
+        assert_eq!(bytes(512, BytesFormat::Binary), "512 B");
+        assert_eq!(bytes(1_331_439_862, BytesFormat::Si), "1.33 GB");
+        assert_eq!(rate(10_912_500, BytesFormat::Si), "87.3 Mbit/s");
+        assert_eq!(rate(0, BytesFormat::Si), "0 bit/s");
+        assert_eq!(bytes(1_331_439_862, BytesFormat::Raw), "1331439862 B");
+        assert_eq!(rate(512, BytesFormat::Raw), "512 B/s");
+    }
+
+    #[test]
+    fn sanitizes_text() {
+        assert_eq!(sanitize(b"example.com"), "example.com");
+        assert_eq!(
+            sanitize(b"\x1b[2J\x1b]0;pwned\x07caf\xc3\xa9\xff\r\n"),
+            "\\x1b[2J\\x1b]0;pwned\\x07caf\u{e9}\\xff\\r\\n"
+        );
+        assert_eq!(
+            sanitize("evil\u{202e}gpj.exe".as_bytes()),
+            "evil\\u{202e}gpj.exe"
+        );
+
+        assert!(matches!(
+            sanitize_line("📊 summary\n   ok"),
+            Cow::Borrowed(_)
+        ));
+        assert_eq!(
+            sanitize_line("🌐 GET /\u{1b}[31mred\n   next"),
+            "🌐 GET /\\x1b[31mred\n   next"
+        );
+    }
+}
@@ -0,0 +1,176 @@
+use std::io;
+use tokio::net::TcpListener;
+
+/// Environment variable used to pass the listeners' file descriptors (comma-separated) to the
+/// successor process.
+pub const LISTEN_FD_VAR: &str = "SOCKGAUGE_LISTEN_FD";
+
+/// Takes over the listeners handed over by a previous process, if any.
+#[cfg(unix)]
+pub fn inherited_listeners() -> io::Result<Option<Vec<std::net::TcpListener>>> {
+    let Ok(fds) = std::env::var(LISTEN_FD_VAR) else {
+        return Ok(None);
+    };
+
+    // Don't leak the variable to any successor we might start ourselves.
+    std::env::remove_var(LISTEN_FD_VAR);
+    take_listeners(&fds).map(Some)
+}
+
+/// Takes ownership of the listeners with the given (comma-separated) file descriptors.
+#[cfg(unix)]
+fn take_listeners(fds: &str) -> io::Result<Vec<std::net::TcpListener>> {
+    use std::os::unix::io::{FromRawFd, RawFd};
+
+    let fds = fds
+        .split(',')
+        .map(|fd| fd.parse::<RawFd>())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "invalid listener fd"))?;
+
+    let mut listeners = Vec::with_capacity(fds.len());
+    for fd in fds {
+        set_cloexec(fd, true)?;
+
+        // SAFETY: the previous process handed us ownership of this descriptor.
+        let listener = unsafe { std::net::TcpListener::from_raw_fd(fd) };
+        listener.set_nonblocking(true)?;
+        listeners.push(listener);
+    }
+
+    Ok(listeners)
+}
+
+/// Listener handover is not supported on this platform.
+#[cfg(not(unix))]
+pub fn inherited_listeners() -> io::Result<Option<Vec<std::net::TcpListener>>> {
+    Ok(None)
+}
+
+/// Starts a new sockgauge process with the same arguments which inherits the listeners.
+/// Returns the process ID of the successor.
+#[cfg(unix)]
+pub fn spawn_successor(listeners: &[TcpListener]) -> io::Result<u32> {
+    use std::os::unix::io::AsRawFd;
+    use std::process::Command;
+
+    let fds: Vec<_> = listeners.iter().map(|l| l.as_raw_fd()).collect();
+    let fds_var = fds
+        .iter()
+        .map(|fd| fd.to_string())
+        .collect::<Vec<_>>()
+        .join(",");
+
+    // The descriptors must survive the `exec` for the successor to pick them up.
+    for fd in &fds {
+        set_cloexec(*fd, false)?;
+    }
+
+    let child = Command::new(std::env::current_exe()?)
+        .args(std::env::args_os().skip(1))
+        .env(LISTEN_FD_VAR, fds_var)
+        .spawn();
+
+    for fd in &fds {
+        set_cloexec(*fd, true)?;
+    }
+
+    Ok(child?.id())
+}
+
+/// Listener handover is not supported on this platform.
+#[cfg(not(unix))]
+pub fn spawn_successor(_listeners: &[TcpListener]) -> io::Result<u32> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "listener handover is only supported on Unix",
+    ))
+}
+
+/// Sets or clears the close-on-exec flag of the descriptor.
+#[cfg(unix)]
+fn set_cloexec(fd: std::os::unix::io::RawFd, cloexec: bool) -> io::Result<()> {
+    // SAFETY: only manipulates descriptor flags.
+    unsafe {
+        let flags = libc::fcntl(fd, libc::F_GETFD);
+        if flags < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let flags = if cloexec {
+            flags | libc::FD_CLOEXEC
+        } else {
+            flags & !libc::FD_CLOEXEC
+        };
+
+        if libc::fcntl(fd, libc::F_SETFD, flags) < 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::os::unix::io::AsRawFd;
+
+    #[tokio::test]
+    async fn takes_over_handed_over_listeners() {
+        // What a predecessor hands over: its listeners' descriptors, kept open across `exec`.
+        let listeners: Vec<_> = (0..2)
+            .map(|_| std::net::TcpListener::bind("127.0.0.1:0").unwrap())
+            .collect();
+        let fds: Vec<_> = listeners
+            .iter()
+            // SAFETY: duplicates a descriptor this test owns.
+            .map(|listener| unsafe { libc::dup(listener.as_raw_fd()) })
+            .collect();
+        let inherited = take_listeners(&format!("{},{}", fds[0], fds[1])).unwrap();
+
+        // The listeners are the same sockets, and accept connections on the runtime.
+        assert_eq!(inherited.len(), 2);
+        for ((listener, inherited), fd) in listeners.iter().zip(inherited).zip(fds) {
+            assert_eq!(inherited.as_raw_fd(), fd);
+            let address = listener.local_addr().unwrap();
+            assert_eq!(inherited.local_addr().unwrap(), address);
+            let inherited = TcpListener::from_std(inherited).unwrap();
+            let (connected, accepted) =
+                tokio::join!(tokio::net::TcpStream::connect(address), inherited.accept());
+            assert_eq!(
+                accepted.unwrap().1,
+                connected.unwrap().local_addr().unwrap()
+            );
+        }
+
+        let error = take_listeners("3,x").unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn passes_descriptors_only_when_asked() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let fd = listener.as_raw_fd();
+        let child_sees_fd = || {
+            std::process::Command::new("sh")
+                .arg("-c")
+                .arg(format!("test -e /proc/self/fd/{}", fd))
+                .status()
+                .unwrap()
+                .success()
+        };
+
+        // Rust opens sockets close-on-exec, so children don't get them unless handed over.
+        assert!(!child_sees_fd());
+        set_cloexec(fd, false).unwrap();
+        assert!(child_sees_fd());
+        set_cloexec(fd, true).unwrap();
+        assert!(!child_sees_fd());
+        assert_eq!(
+            set_cloexec(-1, true).unwrap_err().raw_os_error(),
+            Some(libc::EBADF)
+        );
+    }
+}
@@ -0,0 +1,271 @@
+use crate::http::{Captured, Exchange, Head};
+use crate::net::DisplayAddr;
+use crate::reporter::Connection;
+use crate::{base64, json, record};
+use std::io;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// How many entries are kept for the log; later ones are only counted.
+const MAX_ENTRIES: usize = 10_000;
+
+/// The entries collected so far, as JSON, and how many more were dropped.
+static ENTRIES: Mutex<(Vec<String>, usize)> = Mutex::new((Vec::new(), 0));
+
+/// Adds a request and its response to the log, unless it's full.
+pub fn collect(connection: &Connection, exchange: &Exchange) {
+    // The entry is described without holding the lock, unless the log is full already.
+    let full = ENTRIES.lock().unwrap().0.len() >= MAX_ENTRIES;
+    let entry = (!full).then(|| entry(connection, exchange));
+    let mut entries = ENTRIES.lock().unwrap();
+    match entry {
+        Some(entry) if entries.0.len() < MAX_ENTRIES => entries.0.push(entry),
+        _ => entries.1 += 1,
+    }
+}
+
+/// Writes the collected entries as an HTTP Archive (HAR 1.2), returning how many there were
+/// and how many were dropped as the log was full.
+pub fn write(path: &str) -> io::Result<(usize, usize)> {
+    let (log, written, dropped) = {
+        let entries = ENTRIES.lock().unwrap();
+        let log = format!(
+            r#"{{"log":{{"version":"1.2","creator":{{"name":"sockgauge","version":"{}"}},"pages":[],"entries":[{}]}}}}"#,
+            env!("CARGO_PKG_VERSION"),
+            entries.0.join(",")
+        );
+        (log, entries.0.len(), entries.1)
+    };
+    std::fs::write(path, log)?;
+    Ok((written, dropped))
+}
+
+/// Describes an exchange as a HAR entry.
+fn entry(connection: &Connection, exchange: &Exchange) -> String {
+    let (request, response) = (&exchange.request, &exchange.response);
+    let target = request.start_line.split(' ').nth(1).unwrap_or("/");
+    let url = match target.contains("://") {
+        true => target.to_string(),
+        false => {
+            let host = match request.header("host") {
+                Some(host) => host.to_string(),
+                None => DisplayAddr(connection.local).to_string(),
+            };
+            format!("http://{}{}", host, target)
+        }
+    };
+    let query: Vec<String> = target
+        .split_once('?')
+        .map(|(_, query)| query.split('&').filter(|pair| !pair.is_empty()))
+        .into_iter()
+        .flatten()
+        .map(|pair| {
+            let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+            name_value(name, value)
+        })
+        .collect();
+
+    let post_data = match exchange.request_body.size {
+        0 => String::new(),
+        _ => format!(
+            r#","postData":{{"mimeType":{},"text":{}}}"#,
+            json::quote(request.header("content-type").unwrap_or_default()),
+            json::quote(&String::from_utf8_lossy(&exchange.request_body.bytes))
+        ),
+    };
+
+    let receive = exchange.duration.saturating_sub(exchange.latency);
+    format!(
+        concat!(
+            r#"{{"startedDateTime":"{}","time":{},"#,
+            r#""request":{{"method":{},"url":{},"httpVersion":{},"cookies":[],"headers":[{}],"queryString":[{}],"headersSize":-1,"bodySize":{}{}}},"#,
+            r#""response":{{"status":{},"statusText":{},"httpVersion":{},"cookies":[],"headers":[{}],"content":{},"redirectURL":{},"headersSize":-1,"bodySize":{}}},"#,
+            r#""cache":{{}},"timings":{{"send":0,"wait":{},"receive":{}}},"connection":{}}}"#
+        ),
+        record::rfc3339(exchange.started),
+        millis(exchange.duration),
+        json::quote(request.method()),
+        json::quote(&url),
+        json::quote(request.start_line.split(' ').nth(2).unwrap_or_default()),
+        headers(request),
+        query.join(","),
+        exchange.request_body.size,
+        post_data,
+        response.status().unwrap_or_default(),
+        json::quote(
+            response
+                .start_line
+                .splitn(3, ' ')
+                .nth(2)
+                .unwrap_or_default()
+        ),
+        json::quote(response.start_line.split(' ').next().unwrap_or_default()),
+        headers(response),
+        content(response, &exchange.response_body),
+        json::quote(response.header("location").unwrap_or_default()),
+        exchange.response_body.size,
+        millis(exchange.latency),
+        millis(receive),
+        json::quote(&DisplayAddr(connection.peer).to_string())
+    )
+}
+
+/// The headers of a message, with sensitive values redacted, as HAR name/value pairs.
+fn headers(head: &Head) -> String {
+    record::sanitize(head)
+        .headers
+        .iter()
+        .map(|(name, value)| name_value(name, value))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Describes a response body, as far as it was recorded. Text that isn't UTF-8 is base64
+/// encoded.
+fn content(head: &Head, body: &Captured) -> String {
+    let mut content = format!(
+        r#"{{"size":{},"mimeType":{}"#,
+        body.size,
+        json::quote(head.header("content-type").unwrap_or_default())
+    );
+
+    if !body.bytes.is_empty() {
+        match std::str::from_utf8(&body.bytes) {
+            Ok(text) => content.push_str(&format!(r#","text":{}"#, json::quote(text))),
+            Err(_) => content.push_str(&format!(
+                r#","text":"{}","encoding":"base64""#,
+                base64::encode(&body.bytes)
+            )),
+        }
+    }
+
+    if body.size > body.bytes.len() as u64 {
+        content.push_str(&format!(
+            r#","comment":"the first {} of {} bytes""#,
+            body.bytes.len(),
+            body.size
+        ));
+    }
+
+    content.push('}');
+    content
+}
+
+/// A HAR name/value pair.
+fn name_value(name: &str, value: &str) -> String {
+    format!(
+        r#"{{"name":{},"value":{}}}"#,
+        json::quote(name),
+        json::quote(value)
+    )
+}
+
+/// A duration in (fractional) milliseconds.
+fn millis(duration: Duration) -> String {
+    format!("{:.3}", duration.as_secs_f64() * 1000.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::json::Value;
+    use crate::reporter::DEFAULT_MAPPING;
+    use std::sync::Arc;
+    use std::time::UNIX_EPOCH;
+
+    /// The start of a body of `size` bytes.
+    fn captured(bytes: &[u8], size: u64) -> Captured {
+        let mut captured = Captured::default();
+        captured.bytes = bytes.to_vec();
+        captured.size = size;
+        captured
+    }
+
+    /// A connection and a POST exchange on it.
+    fn exchange() -> (Connection, Exchange) {
+        let connection = Connection {
+            peer: ([10, 0, 0, 1], 52262).into(),
+            local: ([127, 0, 0, 1], 8080).into(),
+            mapping: Arc::from(DEFAULT_MAPPING),
+            stream: None,
+            labels: Arc::new([]),
+        };
+        let exchange = Exchange {
+            request: Head::parse(
+                b"POST /search?q=gauge&empty=&flag HTTP/1.1\r\nHost: api.example\r\nAuthorization: Bearer secret\r\nContent-Type: text/plain\r\n\r\n",
+            )
+            .unwrap(),
+            request_body: captured(b"hello", 5),
+            response: Head::parse(
+                b"HTTP/1.1 404 Not Found\r\nContent-Type: application/octet-stream\r\nSet-Cookie: id=1\r\n\r\n",
+            )
+            .unwrap(),
+            response_body: captured(&[0xff, 0x00, 0x80], 10),
+            started: UNIX_EPOCH + Duration::from_millis(1_792_037_134_123),
+            latency: Duration::from_millis(3),
+            duration: Duration::from_millis(5),
+        };
+        (connection, exchange)
+    }
+
+    #[test]
+    fn describes_exchanges() {
+        let (connection, exchange) = exchange();
+        let entry = json::parse(&entry(&connection, &exchange)).unwrap();
+        let request = entry.get("request").unwrap();
+        let response = entry.get("response").unwrap();
+        assert_eq!(
+            entry.get("startedDateTime").unwrap().as_str(),
+            Some("2026-10-15T04:05:34.123Z")
+        );
+        assert_eq!(
+            request.get("url").unwrap().as_str(),
+            Some("http://api.example/search?q=gauge&empty=&flag")
+        );
+        assert_eq!(
+            request.get("queryString").unwrap().to_string(),
+            r#"[{"name":"q","value":"gauge"},{"name":"empty","value":""},{"name":"flag","value":""}]"#
+        );
+        assert_eq!(
+            request.get("headers").unwrap().to_string(),
+            r#"[{"name":"Host","value":"api.example"},{"name":"Authorization","value":"[redacted]"},{"name":"Content-Type","value":"text/plain"}]"#
+        );
+        assert_eq!(
+            request.get("postData").unwrap().to_string(),
+            r#"{"mimeType":"text/plain","text":"hello"}"#
+        );
+        assert_eq!(response.get("status"), Some(&Value::Number(404.0)));
+        assert_eq!(
+            response.get("statusText").unwrap().as_str(),
+            Some("Not Found")
+        );
+        assert_eq!(
+            response.get("headers").unwrap().to_string(),
+            r#"[{"name":"Content-Type","value":"application/octet-stream"},{"name":"Set-Cookie","value":"[redacted]"}]"#
+        );
+        assert_eq!(
+            response.get("content").unwrap().to_string(),
+            r#"{"size":10,"mimeType":"application/octet-stream","text":"/wCA","encoding":"base64","comment":"the first 3 of 10 bytes"}"#
+        );
+        assert_eq!(
+            entry.get("timings").unwrap().to_string(),
+            r#"{"send":0,"wait":3,"receive":2}"#
+        );
+    }
+
+    #[test]
+    fn keeps_at_most_max_entries() {
+        let (connection, exchange) = exchange();
+        for _ in 0..MAX_ENTRIES + 5 {
+            collect(&connection, &exchange);
+        }
+
+        let path = std::env::temp_dir().join(format!("sockgauge-{}.har", std::process::id()));
+        let path = path.to_str().unwrap();
+        assert_eq!(write(path).unwrap(), (MAX_ENTRIES, 5));
+        let log = json::parse(&std::fs::read_to_string(path).unwrap()).unwrap();
+        std::fs::remove_file(path).unwrap();
+        let entries = log.get("log").unwrap().get("entries").unwrap();
+        assert_eq!(entries.as_array().unwrap().len(), MAX_ENTRIES);
+    }
+}
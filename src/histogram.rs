@@ -0,0 +1,169 @@
+use crate::json::Value;
+use std::time::Duration;
+
+/// The upper bounds of the buckets in microseconds, in steps of 1, 2 and 5 from 100µs to an
+/// hour. Longer durations go into a last, unbounded bucket.
+const BOUNDS: [u64; 23] = [
+    100,
+    200,
+    500,
+    1_000,
+    2_000,
+    5_000,
+    10_000,
+    20_000,
+    50_000,
+    100_000,
+    200_000,
+    500_000,
+    1_000_000,
+    2_000_000,
+    5_000_000,
+    10_000_000,
+    20_000_000,
+    50_000_000,
+    100_000_000,
+    200_000_000,
+    500_000_000,
+    1_000_000_000,
+    3_600_000_000,
+];
+
+/// Counts durations in fixed buckets, so histograms of different runs line up.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Histogram {
+    /// The counts per bucket, the last one for durations beyond all bounds.
+    counts: [u64; BOUNDS.len() + 1],
+
+    /// All durations added up.
+    sum: Duration,
+
+    /// The shortest and longest durations, if any were counted.
+    min: Option<Duration>,
+    max: Option<Duration>,
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Self {
+            counts: [0; BOUNDS.len() + 1],
+            sum: Duration::ZERO,
+            min: None,
+            max: None,
+        }
+    }
+}
+
+impl Histogram {
+    /// Counts a duration.
+    pub fn record(&mut self, duration: Duration) {
+        let micros = duration.as_micros();
+        let bucket = BOUNDS
+            .iter()
+            .position(|&bound| micros <= bound as u128)
+            .unwrap_or(BOUNDS.len());
+        self.counts[bucket] += 1;
+        self.sum += duration;
+        self.min = Some(self.min.map_or(duration, |min| min.min(duration)));
+        self.max = Some(self.max.map_or(duration, |max| max.max(duration)));
+    }
+
+    /// How many durations were counted.
+    pub fn count(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    /// The mean duration, if any were counted.
+    pub fn mean(&self) -> Option<Duration> {
+        let count = self.count();
+        (count > 0).then(|| self.sum.div_f64(count as f64))
+    }
+
+    /// The longest duration, if any were counted.
+    pub fn max(&self) -> Option<Duration> {
+        self.max
+    }
+
+    /// The duration that `p` percent of the counted ones are at most, if any were counted: the
+    /// upper bound of the bucket it falls in, but no more than the longest duration counted.
+    pub fn percentile(&self, p: f64) -> Option<Duration> {
+        let (min, max) = self.min.zip(self.max)?;
+        let rank = ((p / 100.0 * self.count() as f64).ceil() as u64).max(1);
+        let mut counted = 0;
+        for (i, &count) in self.counts.iter().enumerate() {
+            counted += count;
+            if counted >= rank {
+                let bound = BOUNDS
+                    .get(i)
+                    .map_or(max, |&bound| Duration::from_micros(bound));
+                return Some(bound.clamp(min, max));
+            }
+        }
+        Some(max)
+    }
+
+    /// Serializes the histogram, with each bucket's upper bound in milliseconds (`null` for the
+    /// unbounded one) and its count, not including the buckets before it.
+    pub fn to_json(&self) -> Value {
+        let millis = |duration: Duration| Value::Number(duration.as_secs_f64() * 1000.0);
+        let buckets = self
+            .counts
+            .iter()
+            .enumerate()
+            .map(|(i, &count)| {
+                let bound = match BOUNDS.get(i) {
+                    Some(&bound) => Value::Number(bound as f64 / 1000.0),
+                    None => Value::Null,
+                };
+                Value::Object(vec![
+                    ("le_ms".to_string(), bound),
+                    ("count".to_string(), Value::Number(count as f64)),
+                ])
+            })
+            .collect();
+
+        Value::Object(vec![
+            ("count".to_string(), Value::Number(self.count() as f64)),
+            ("sum_ms".to_string(), millis(self.sum)),
+            ("min_ms".to_string(), self.min.map_or(Value::Null, millis)),
+            ("max_ms".to_string(), self.max.map_or(Value::Null, millis)),
+            ("buckets".to_string(), Value::Array(buckets)),
+        ])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_durations() {
+        let mut histogram = Histogram::default();
+        for millis in [0, 1, 3, 3, 7_200_000] {
+            histogram.record(Duration::from_millis(millis));
+        }
+
+        assert_eq!(histogram.counts[0], 1);
+        assert_eq!(histogram.counts[3], 1);
+        assert_eq!(histogram.counts[5], 2);
+        assert_eq!(histogram.counts[BOUNDS.len()], 1);
+
+        let json = histogram.to_json();
+        assert_eq!(json.get("count"), Some(&Value::Number(5.0)));
+        assert_eq!(json.get("max_ms"), Some(&Value::Number(7_200_000.0)));
+        let buckets = json.get("buckets").unwrap().as_array().unwrap();
+        assert_eq!(buckets.len(), 24);
+        assert_eq!(buckets[5].to_string(), r#"{"le_ms":5,"count":2}"#);
+        assert_eq!(buckets[23].to_string(), r#"{"le_ms":null,"count":1}"#);
+
+        // Percentiles are the bounds of their buckets, within the shortest and longest.
+        assert_eq!(histogram.percentile(20.0), Some(Duration::from_micros(100)));
+        assert_eq!(histogram.percentile(40.0), Some(Duration::from_millis(1)));
+        assert_eq!(histogram.percentile(50.0), Some(Duration::from_millis(5)));
+        assert_eq!(
+            histogram.percentile(99.0),
+            Some(Duration::from_millis(7_200_000))
+        );
+        assert_eq!(Histogram::default().percentile(50.0), None);
+    }
+}
@@ -0,0 +1,1055 @@
+use crate::regex::Regex;
+use std::fmt::{Display, Formatter};
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+use tokio::io::{
+    AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt,
+};
+use tokio::sync::{mpsc, oneshot};
+
+/// The most we read of a message head before giving up on it.
+const MAX_HEAD: u64 = 64 * 1024;
+
+/// The most we read of a line in a chunked body.
+const MAX_LINE: u64 = 8 * 1024;
+
+/// The start line and header fields of an HTTP/1.x request or response.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Head {
+    /// The start line, e.g. `GET / HTTP/1.1` or `HTTP/1.1 200 OK`.
+    pub start_line: String,
+
+    /// The header fields, in order.
+    pub headers: Vec<(String, String)>,
+}
+
+impl Head {
+    /// Parses a complete message head, as read by `read_head`. Returns `None` if it isn't one.
+    pub fn parse(bytes: &[u8]) -> Option<Head> {
+        let text = std::str::from_utf8(bytes).ok()?;
+        let mut lines = text
+            .split_terminator('\n')
+            .map(|line| line.strip_suffix('\r').unwrap_or(line))
+            .skip_while(|line| line.is_empty());
+
+        // A request line ends with the version, a status line starts with it.
+        let start_line = lines.next()?.to_string();
+        let parts: Vec<&str> = start_line.split(' ').collect();
+        let request = parts.len() == 3 && parts[2].starts_with("HTTP/");
+        if !request && !parts[0].starts_with("HTTP/") {
+            return None;
+        }
+
+        let mut headers = Vec::new();
+        for line in lines.by_ref() {
+            if line.is_empty() {
+                return Some(Head {
+                    start_line,
+                    headers,
+                });
+            }
+
+            let (name, value) = line.split_once(':')?;
+            if name.is_empty() || name.ends_with([' ', '\t']) {
+                return None;
+            }
+            headers.push((name.to_string(), value.trim().to_string()));
+        }
+
+        // The blank line that ends the head is missing.
+        None
+    }
+
+    /// Serializes the head, ending with the blank line.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = format!("{}\r\n", self.start_line);
+        for (name, value) in &self.headers {
+            bytes.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        bytes.push_str("\r\n");
+        bytes.into_bytes()
+    }
+
+    /// The method of a request.
+    pub fn method(&self) -> &str {
+        self.start_line.split(' ').next().unwrap_or_default()
+    }
+
+    /// The status code of a response.
+    pub fn status(&self) -> Option<u16> {
+        self.start_line.split(' ').nth(1)?.parse().ok()
+    }
+
+    /// Applies header rewrite rules, in order.
+    pub fn rewrite(&mut self, rules: &[Rule]) {
+        for rule in rules {
+            match rule {
+                Rule::Set(name, value) => {
+                    self.headers
+                        .retain(|(key, _)| !key.eq_ignore_ascii_case(name));
+                    self.headers.push((name.clone(), value.clone()));
+                }
+                Rule::Remove(name) => self
+                    .headers
+                    .retain(|(key, _)| !key.eq_ignore_ascii_case(name)),
+                Rule::Replace(name, regex, replacement) => {
+                    for (_, value) in self
+                        .headers
+                        .iter_mut()
+                        .filter(|(key, _)| key.eq_ignore_ascii_case(name))
+                    {
+                        *value = regex.replace_all(value, replacement);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Whether the body is chunked, which is the case if chunked is the last transfer coding.
+    fn is_chunked(&self) -> bool {
+        self.header("transfer-encoding").is_some_and(|codings| {
+            let last = codings.rsplit(',').next().unwrap_or_default();
+            last.trim().eq_ignore_ascii_case("chunked")
+        })
+    }
+
+    /// How the body of a request is delimited.
+    fn request_body(&self) -> Body {
+        if self.is_chunked() {
+            return Body::Chunked;
+        }
+
+        // Other transfer codings can't be delimited, so they last until the connection closes.
+        if self.header("transfer-encoding").is_some() {
+            return Body::UntilClose;
+        }
+
+        match self.header("content-length").map(str::parse) {
+            Some(Ok(length)) => Body::Length(length),
+            Some(Err(_)) => Body::UntilClose,
+            None => Body::Length(0),
+        }
+    }
+
+    /// How the body of a response to a request with the given method is delimited.
+    fn response_body(&self, method: &str) -> Body {
+        if method == "HEAD" || matches!(self.status(), Some(100..=199 | 204 | 304)) {
+            return Body::Length(0);
+        }
+
+        if self.is_chunked() {
+            return Body::Chunked;
+        }
+
+        match self.header("content-length").map(str::parse) {
+            Some(Ok(length)) if self.header("transfer-encoding").is_none() => Body::Length(length),
+            _ => Body::UntilClose,
+        }
+    }
+
+    /// The value of the first header field with the given (case-insensitive) name.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// The host a request is for, lowercase and without the port: the authority of an
+    /// absolute-form target (as sent to proxies), otherwise the `Host` header.
+    pub fn host(&self) -> Option<String> {
+        let target = self.start_line.split(' ').nth(1)?;
+        let authority = match target.split_once("://") {
+            Some((_, rest)) => rest.split(['/', '?']).next()?,
+            None => self.header("host")?,
+        };
+
+        // Strip the port, minding IPv6 addresses: bracketed, or bare (without a port).
+        let host = match authority.rsplit_once(':') {
+            Some((host, port))
+                if port.bytes().all(|b| b.is_ascii_digit())
+                    && (!host.contains(':') || host.ends_with(']')) =>
+            {
+                host
+            }
+            _ => authority,
+        };
+        let host = host.trim_end_matches('.').to_ascii_lowercase();
+        match host.is_empty() {
+            true => None,
+            false => Some(host),
+        }
+    }
+}
+
+/// Reads up to and including the blank line that ends a message head. Stops early if the
+/// stream ends or the head gets too large, returning whatever was read.
+pub async fn read_head<R: AsyncBufRead + Unpin>(reader: &mut R) -> io::Result<Vec<u8>> {
+    let mut head = Vec::new();
+    let mut started = false;
+    loop {
+        let mut limited = (&mut *reader).take(MAX_HEAD - head.len() as u64);
+        let start = head.len();
+        if limited.read_until(b'\n', &mut head).await? == 0 {
+            return Ok(head);
+        }
+
+        // Blank lines before the start line are ignored; after it, one ends the head.
+        let blank = matches!(&head[start..], b"\n" | b"\r\n");
+        if blank && started {
+            return Ok(head);
+        }
+        started |= !blank;
+    }
+}
+
+/// A header rewrite rule.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Rule {
+    /// Sets a header, replacing any existing ones with the same name.
+    Set(String, String),
+
+    /// Removes a header.
+    Remove(String),
+
+    /// Replaces the matches of a regex in the values of a header, with a replacement that can
+    /// refer to groups as `$1`.
+    Replace(String, Regex, String),
+}
+
+impl Rule {
+    /// Parses a rule: `set Name: value`, `remove Name` or `replace Name /regex/replacement/`.
+    fn parse(rule: &str) -> Result<Rule, String> {
+        let invalid = || {
+            format!(
+                "Expected a rule like `set Name: value`, `remove Name` or \
+                 `replace Name /regex/replacement/`, got `{}`",
+                rule
+            )
+        };
+
+        let (action, rest) = rule.split_once(' ').ok_or_else(invalid)?;
+        let rest = rest.trim();
+        match action {
+            "set" => {
+                let (name, value) = rest.split_once(':').ok_or_else(invalid)?;
+                Ok(Rule::Set(
+                    header_name(name.trim())?,
+                    value.trim().to_string(),
+                ))
+            }
+            "remove" => Ok(Rule::Remove(header_name(rest)?)),
+            "replace" => {
+                let (name, substitution) = rest.split_once(' ').ok_or_else(invalid)?;
+                let substitution = substitution.trim();
+                let inner = substitution
+                    .strip_prefix('/')
+                    .and_then(|inner| inner.strip_suffix('/'))
+                    .ok_or_else(invalid)?;
+
+                // The regex ends at the first `/` that isn't escaped.
+                let mut escaped = false;
+                let middle = inner
+                    .char_indices()
+                    .find(|(_, c)| {
+                        let found = *c == '/' && !escaped;
+                        escaped = *c == '\\' && !escaped;
+                        found
+                    })
+                    .map(|(index, _)| index)
+                    .ok_or_else(invalid)?;
+                let pattern = inner[..middle].replace("\\/", "/");
+                let regex = Regex::new(&pattern)?;
+                let replacement = inner[middle + 1..].replace("\\/", "/");
+                Ok(Rule::Replace(header_name(name)?, regex, replacement))
+            }
+            _ => Err(invalid()),
+        }
+    }
+}
+
+/// Displays a rule the way it is configured.
+impl Display for Rule {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Rule::Set(name, value) => write!(f, "set {}: {}", name, value),
+            Rule::Remove(name) => write!(f, "remove {}", name),
+            Rule::Replace(name, regex, replacement) => write!(
+                f,
+                "replace {} /{}/{}/",
+                name,
+                regex.as_str().replace('/', "\\/"),
+                replacement.replace('/', "\\/")
+            ),
+        }
+    }
+}
+
+/// Parses header rewrite rules, separated by semicolons.
+pub fn parse_rules(value: &str) -> Result<Vec<Rule>, String> {
+    value
+        .split(';')
+        .map(str::trim)
+        .filter(|rule| !rule.is_empty())
+        .map(Rule::parse)
+        .collect()
+}
+
+/// Checks that a header name has no whitespace or separators in it.
+fn header_name(name: &str) -> Result<String, String> {
+    match !name.is_empty() && name.bytes().all(|b| b.is_ascii_graphic() && b != b':') {
+        true => Ok(name.to_string()),
+        false => Err(format!("`{}` is not a header name", name)),
+    }
+}
+
+/// How a message body is delimited.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Body {
+    /// A body of a known length (which may be zero).
+    Length(u64),
+
+    /// A chunked body, ending with an empty chunk.
+    Chunked,
+
+    /// A body that lasts until the connection closes.
+    UntilClose,
+}
+
+/// A request and the response to it.
+#[derive(Debug)]
+pub struct Exchange {
+    /// The request head, as forwarded.
+    pub request: Head,
+
+    /// The start of the request body.
+    pub request_body: Captured,
+
+    /// The response head, as forwarded.
+    pub response: Head,
+
+    /// The start of the response body.
+    pub response_body: Captured,
+
+    /// When the request was forwarded.
+    pub started: SystemTime,
+
+    /// How long the server took to start responding.
+    pub latency: Duration,
+
+    /// How long the exchange took, until the end of the response.
+    pub duration: Duration,
+}
+
+/// The start of a message body, up to a limit.
+#[derive(Debug, Clone, Default)]
+pub struct Captured {
+    /// The first bytes of the body.
+    pub bytes: Vec<u8>,
+
+    /// The size of the whole body.
+    pub size: u64,
+
+    /// How many bytes to keep.
+    limit: usize,
+}
+
+impl Captured {
+    /// Creates an empty capture keeping at most `limit` bytes.
+    fn new(limit: usize) -> Self {
+        Self {
+            limit,
+            ..Self::default()
+        }
+    }
+
+    /// Adds data passing through.
+    fn push(&mut self, data: &[u8]) {
+        let room = self.limit.saturating_sub(self.bytes.len());
+        self.bytes.extend_from_slice(&data[..room.min(data.len())]);
+        self.size += data.len() as u64;
+    }
+}
+
+/// A request that was forwarded to the server, waiting for its response.
+pub struct Pending {
+    /// The request head, as forwarded.
+    head: Head,
+
+    /// The start of the request body, once it has been forwarded.
+    body: Arc<Mutex<Captured>>,
+
+    /// When the request was forwarded.
+    started: (SystemTime, Instant),
+
+    /// For requests that ask to switch protocols (upgrades and `CONNECT`), told when the
+    /// server agrees. Dropped when it doesn't.
+    switched: Option<oneshot::Sender<()>>,
+}
+
+/// Forwards requests from the client to the server, starting with the head that was already
+/// read, applying the rules to each head and keeping up to `capture` bytes of each body. Requests
+/// are queued as pending so their responses can be told apart. Anything that isn't HTTP is
+/// forwarded as-is.
+pub async fn forward_requests<R, W>(
+    mut reader: R,
+    mut writer: W,
+    first_head: Vec<u8>,
+    (rules, capture): (&[Rule], usize),
+    pending: mpsc::UnboundedSender<Pending>,
+) -> io::Result<()>
+where
+    R: AsyncBufRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let mut bytes = first_head;
+    while !bytes.is_empty() {
+        let Some(mut head) = Head::parse(&bytes) else {
+            return forward_rest(&bytes, &mut reader, &mut writer).await;
+        };
+        head.rewrite(rules);
+
+        // Queue the request before the server can see it.
+        let switching = head.method() == "CONNECT" || head.header("upgrade").is_some();
+        let (switched, switched_receiver) = match switching {
+            true => {
+                let (sender, receiver) = oneshot::channel();
+                (Some(sender), Some(receiver))
+            }
+            false => (None, None),
+        };
+        let body = Arc::default();
+        let _ = pending.send(Pending {
+            head: head.clone(),
+            body: Arc::clone(&body),
+            started: (SystemTime::now(), Instant::now()),
+            switched,
+        });
+
+        write_head(&mut writer, &head, &bytes, rules).await?;
+        let mut captured = Captured::new(capture);
+        forward_body(&mut reader, &mut writer, head.request_body(), &mut captured).await?;
+        *body.lock().unwrap() = captured;
+
+        // After switching protocols, the connection no longer carries HTTP.
+        if let Some(switched_receiver) = switched_receiver {
+            if switched_receiver.await.is_ok() {
+                return forward_rest(&[], &mut reader, &mut writer).await;
+            }
+        }
+
+        bytes = read_head(&mut reader).await?;
+    }
+
+    Ok(())
+}
+
+/// Forwards responses from the server to the client, applying the rules to each head, keeping
+/// up to `capture` bytes of each body, and calling `on_exchange` once a response is complete.
+/// Anything that isn't HTTP is forwarded as-is.
+pub async fn forward_responses<R, W>(
+    mut reader: R,
+    mut writer: W,
+    (rules, capture): (&[Rule], usize),
+    mut pending: mpsc::UnboundedReceiver<Pending>,
+    mut on_exchange: impl FnMut(Exchange),
+) -> io::Result<()>
+where
+    R: AsyncBufRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    loop {
+        let bytes = read_head(&mut reader).await?;
+        if bytes.is_empty() {
+            return Ok(());
+        }
+
+        let Some(mut head) = Head::parse(&bytes) else {
+            return forward_rest(&bytes, &mut reader, &mut writer).await;
+        };
+        let latency = Instant::now();
+        head.rewrite(rules);
+        write_head(&mut writer, &head, &bytes, rules).await?;
+
+        // Interim responses precede the final response to the same request.
+        let status = head.status().unwrap_or_default();
+        if (100..200).contains(&status) && status != 101 {
+            continue;
+        }
+
+        // Servers can respond without a request, e.g. with a timeout before closing.
+        let mut captured = Captured::new(capture);
+        let Ok(request) = pending.try_recv() else {
+            let body = head.response_body("");
+            forward_body(&mut reader, &mut writer, body, &mut captured).await?;
+            continue;
+        };
+
+        let body = head.response_body(request.head.method());
+        let mut exchange = Exchange {
+            request: request.head,
+            request_body: Captured::default(),
+            response: head,
+            response_body: Captured::default(),
+            started: request.started.0,
+            latency: latency.duration_since(request.started.1),
+            duration: Duration::ZERO,
+        };
+
+        // Let the client side know if the server switched protocols.
+        let switched = status == 101
+            || (exchange.request.method() == "CONNECT" && (200..300).contains(&status));
+        if let (Some(sender), true) = (request.switched, switched) {
+            let _ = sender.send(());
+            exchange.request_body = std::mem::take(&mut *request.body.lock().unwrap());
+            exchange.duration = exchange.latency;
+            on_exchange(exchange);
+            return forward_rest(&[], &mut reader, &mut writer).await;
+        }
+
+        forward_body(&mut reader, &mut writer, body, &mut captured).await?;
+        exchange.request_body = std::mem::take(&mut *request.body.lock().unwrap());
+        exchange.response_body = captured;
+        exchange.duration = request.started.1.elapsed();
+        on_exchange(exchange);
+    }
+}
+
+/// Writes a head, as it was read if no rules apply, or as rewritten.
+async fn write_head<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    head: &Head,
+    bytes: &[u8],
+    rules: &[Rule],
+) -> io::Result<()> {
+    match rules.is_empty() {
+        true => writer.write_all(bytes).await,
+        false => writer.write_all(&head.to_bytes()).await,
+    }
+}
+
+/// Forwards a message body, keeping its start.
+async fn forward_body<R, W>(
+    reader: &mut R,
+    writer: &mut W,
+    body: Body,
+    captured: &mut Captured,
+) -> io::Result<()>
+where
+    R: AsyncBufRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    match body {
+        Body::Length(length) => forward_data(reader, writer, Some(length), captured).await,
+        Body::UntilClose => forward_data(reader, writer, None, captured).await,
+        Body::Chunked => loop {
+            // Each chunk starts with its size in hex, optionally followed by extensions.
+            let line = forward_line(reader, writer).await?;
+            let size = line.split(|b| *b == b';').next().unwrap_or_default();
+            let size = std::str::from_utf8(size).unwrap_or_default().trim();
+            let Ok(size) = u64::from_str_radix(size, 16) else {
+                // Garbled (or ended); pass the rest on as-is.
+                return forward_data(reader, writer, None, &mut Captured::default()).await;
+            };
+
+            if size == 0 {
+                // Trailer fields, up to a blank line.
+                while !matches!(
+                    forward_line(reader, writer).await?.as_slice(),
+                    b"" | b"\n" | b"\r\n"
+                ) {}
+                return Ok(());
+            }
+
+            forward_data(reader, writer, Some(size), captured).await?;
+            if forward_line(reader, writer).await?.is_empty() {
+                return Ok(());
+            }
+        },
+    }
+}
+
+/// Forwards `length` bytes of data, or everything until the stream ends, keeping the start.
+async fn forward_data<R, W>(
+    reader: &mut R,
+    writer: &mut W,
+    mut length: Option<u64>,
+    captured: &mut Captured,
+) -> io::Result<()>
+where
+    R: AsyncBufRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    while length != Some(0) {
+        let buffer = reader.fill_buf().await?;
+        if buffer.is_empty() {
+            break;
+        }
+
+        let n = length.map_or(buffer.len(), |length| buffer.len().min(length as usize));
+        writer.write_all(&buffer[..n]).await?;
+        captured.push(&buffer[..n]);
+        reader.consume(n);
+        length = length.map(|length| length - n as u64);
+    }
+
+    Ok(())
+}
+
+/// Forwards a line (of limited length), returning it.
+async fn forward_line<R, W>(reader: &mut R, writer: &mut W) -> io::Result<Vec<u8>>
+where
+    R: AsyncBufRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let mut line = Vec::new();
+    (&mut *reader)
+        .take(MAX_LINE)
+        .read_until(b'\n', &mut line)
+        .await?;
+    writer.write_all(&line).await?;
+    Ok(line)
+}
+
+/// Forwards data that was already read and then everything else as-is.
+async fn forward_rest<R, W>(read: &[u8], reader: &mut R, writer: &mut W) -> io::Result<()>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    writer.write_all(read).await?;
+    tokio::io::copy(reader, writer).await?;
+    Ok(())
+}
+
+/// Looks up the destination for a host in `host=destination` routes.
+pub fn route<'a>(routes: &'a [(String, String)], host: &str) -> Option<&'a str> {
+    routes
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case(host))
+        .map(|(_, dest_addr)| dest_addr.as_str())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::BufReader;
+
+    /// Passes what a client sends through `forward_requests` to a server that reads the given
+    /// number of bytes before each scripted answer, and those answers back through
+    /// `forward_responses`, over small duplex streams so everything arrives in pieces. Returns
+    /// what the server and the client received, and the exchanges.
+    async fn proxy(
+        client: &[u8],
+        script: &[(usize, &[u8])],
+        (request_rules, response_rules): (&[Rule], &[Rule]),
+    ) -> (Vec<u8>, Vec<u8>, Vec<Exchange>) {
+        let (mut client_writer, client_reader) = tokio::io::duplex(5);
+        let (upstream_writer, mut upstream_reader) = tokio::io::duplex(5);
+        let (mut server_writer, server_reader) = tokio::io::duplex(5);
+        let (pending, pending_receiver) = mpsc::unbounded_channel();
+        let mut downstream = Vec::new();
+        let mut exchanges = Vec::new();
+
+        let client_side = async move {
+            client_writer.write_all(client).await.unwrap();
+        };
+        let requests = async move {
+            let mut reader = BufReader::new(client_reader);
+            let first_head = read_head(&mut reader).await?;
+            let rules = (request_rules, 8);
+            forward_requests(reader, upstream_writer, first_head, rules, pending).await
+        };
+        let server = async move {
+            let mut received = Vec::new();
+            for (length, answer) in script {
+                let mut request = vec![0; *length];
+                upstream_reader.read_exact(&mut request).await.unwrap();
+                received.extend(request);
+                server_writer.write_all(answer).await.unwrap();
+            }
+            drop(server_writer);
+            upstream_reader.read_to_end(&mut received).await.unwrap();
+            received
+        };
+        let responses = forward_responses(
+            BufReader::new(server_reader),
+            &mut downstream,
+            (response_rules, 8),
+            pending_receiver,
+            |exchange| exchanges.push(exchange),
+        );
+
+        let ((), requests, received, responses) =
+            tokio::join!(client_side, requests, server, responses);
+        requests.unwrap();
+        responses.unwrap();
+        (received, downstream, exchanges)
+    }
+
+    /// A summary of an exchange: the request start line, the response status and the captured
+    /// bodies with their sizes.
+    type Summary<'a> = (&'a str, Option<u16>, (&'a [u8], u64), (&'a [u8], u64));
+
+    /// Summarizes an exchange.
+    fn summary(exchange: &Exchange) -> Summary<'_> {
+        (
+            &exchange.request.start_line,
+            exchange.response.status(),
+            (&exchange.request_body.bytes, exchange.request_body.size),
+            (&exchange.response_body.bytes, exchange.response_body.size),
+        )
+    }
+
+    #[tokio::test]
+    async fn reads_the_host_of_requests() {
+        let mut stream: &[u8] = b"\r\nGET / HTTP/1.1\r\nHost: API.test:8080\r\n\r\nbody";
+        let head = read_head(&mut stream).await.unwrap();
+        assert_eq!(stream, b"body");
+
+        let head = Head::parse(&head).unwrap();
+        assert_eq!(head.start_line, "GET / HTTP/1.1");
+        assert_eq!(head.host().as_deref(), Some("api.test"));
+
+        let proxied = Head::parse(b"GET http://[::1]:80/x HTTP/1.1\nHost: a\n\n").unwrap();
+        assert_eq!(proxied.host().as_deref(), Some("[::1]"));
+        assert_eq!(Head::parse(b"GET / HTTP/1.1\r\nHost: a\r\n"), None);
+        assert_eq!(Head::parse(b"GET /\r\nHost: a\r\n\r\n"), None);
+        assert_eq!(Head::parse(b"SSH-2.0-x\r\n\r\n"), None);
+        assert!(Head::parse(b"HTTP/1.0 200\r\n\r\n").is_some());
+    }
+
+    #[test]
+    fn reads_hosts_at_the_edges() {
+        let cases: &[(&str, Option<&str>)] = &[
+            ("GET / HTTP/1.1\n\n", None),
+            ("GET / HTTP/1.1\nHost:\n\n", None),
+            ("GET / HTTP/1.1\nHost: :8080\n\n", None),
+            ("GET / HTTP/1.1\nHost: .\n\n", None),
+            ("GET / HTTP/1.1\nHost: API.test.\n\n", Some("api.test")),
+            ("GET / HTTP/1.1\nHost: api.test.:443\n\n", Some("api.test")),
+            ("GET / HTTP/1.1\nHost: api.test:\n\n", Some("api.test")),
+            ("GET / HTTP/1.1\nHost: [::1]\n\n", Some("[::1]")),
+            (
+                "GET / HTTP/1.1\nHost: [FE80::1]:8080\n\n",
+                Some("[fe80::1]"),
+            ),
+            ("GET / HTTP/1.1\nHost: ::1\n\n", Some("::1")),
+            (
+                "GET / HTTP/1.1\nHost: fe80::1:8080\n\n",
+                Some("fe80::1:8080"),
+            ),
+            ("GET http://[::1]/x HTTP/1.1\nHost: a\n\n", Some("[::1]")),
+            ("GET http://b.test?q HTTP/1.1\nHost: a\n\n", Some("b.test")),
+            ("GET http:///x HTTP/1.1\nHost: a\n\n", None),
+            ("GET http://B.test HTTP/1.1\n\n", Some("b.test")),
+        ];
+        for (head, host) in cases {
+            let head = Head::parse(head.as_bytes()).unwrap();
+            assert_eq!(head.host().as_deref(), *host, "{:?}", head);
+        }
+    }
+
+    #[test]
+    fn parses_rules() {
+        let cases: &[(&str, Result<&str, ()>)] = &[
+            ("set X-Env: prod", Ok("set X-Env: prod")),
+            ("set  X-Empty:", Ok("set X-Empty: ")),
+            ("set X-Time:  10:30 ", Ok("set X-Time: 10:30")),
+            ("remove Cookie", Ok("remove Cookie")),
+            (
+                "replace Authorization /(Bearer) .*/$1 ***/",
+                Ok("replace Authorization /(Bearer) .*/$1 ***/"),
+            ),
+            (
+                r"replace Location  /^http:\/\/old/https:\/\/new/ ",
+                Ok(r"replace Location /^http:\/\/old/https:\/\/new/"),
+            ),
+            ("replace X /a//", Ok("replace X /a//")),
+            ("set X", Err(())),
+            ("set Bad Name: value", Err(())),
+            ("remove", Err(())),
+            ("remove Bad:Name", Err(())),
+            ("replace X /a/", Err(())),
+            (r"replace X /a\/b/", Err(())),
+            ("replace X a/b/", Err(())),
+            ("replace X /(/b/", Err(())),
+            ("drop X", Err(())),
+        ];
+        for (rule, expected) in cases {
+            let parsed = Rule::parse(rule);
+            let displayed = parsed.as_ref().map(Rule::to_string).map_err(|_| ());
+            assert_eq!(displayed.as_deref(), expected.as_deref(), "{}", rule);
+
+            // Rules read back the same as they are displayed.
+            if let Ok(parsed) = parsed {
+                assert_eq!(Rule::parse(&parsed.to_string()), Ok(parsed));
+            }
+        }
+
+        let Ok(Rule::Replace(_, regex, replacement)) =
+            Rule::parse(r"replace Location /^http:\/\/old/https:\/\/new/")
+        else {
+            panic!("not a replace rule");
+        };
+        assert_eq!(regex.as_str(), "^http://old");
+        assert_eq!(replacement, "https://new");
+
+        let rules = parse_rules(" remove A ;; set B: c; ").unwrap();
+        assert_eq!(
+            rules,
+            [Rule::Remove("A".into()), Rule::Set("B".into(), "c".into())]
+        );
+        assert!(parse_rules("remove A; nonsense").is_err());
+        assert_eq!(parse_rules(""), Ok(vec![]));
+    }
+
+    #[test]
+    fn rewrites_heads() {
+        let mut head = Head::parse(
+            b"GET / HTTP/1.1\r\nHost: a\r\ncookie: x\r\nSet-Cookie: y\r\n\
+              Authorization: Bearer t\r\nCOOKIE: z\r\n\r\n",
+        )
+        .unwrap();
+        let rules = parse_rules(
+            "set host: b; remove Cookie; replace authorization /(Bearer) .*/$1 ***/; \
+             replace Missing /.*/x/",
+        )
+        .unwrap();
+        head.rewrite(&rules);
+
+        assert_eq!(head.start_line, "GET / HTTP/1.1");
+        let headers: Vec<_> = head
+            .headers
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+            .collect();
+        assert_eq!(
+            headers,
+            [
+                ("Set-Cookie", "y"),
+                ("Authorization", "Bearer ***"),
+                ("host", "b")
+            ]
+        );
+        assert_eq!(
+            head.to_bytes(),
+            b"GET / HTTP/1.1\r\nSet-Cookie: y\r\nAuthorization: Bearer ***\r\nhost: b\r\n\r\n"
+        );
+
+        // Setting a header twice keeps one.
+        head.rewrite(&parse_rules("set Host: c; set HOST: d").unwrap());
+        assert_eq!(head.header("host"), Some("d"));
+        assert_eq!(head.headers.len(), 3);
+    }
+
+    #[test]
+    fn captures_up_to_the_limit() {
+        let mut captured = Captured::new(4);
+        captured.push(b"ab");
+        captured.push(b"");
+        captured.push(b"cdef");
+        captured.push(b"gh");
+        assert_eq!(captured.bytes, b"abcd");
+        assert_eq!(captured.size, 8);
+
+        let mut nothing = Captured::new(0);
+        nothing.push(b"abc");
+        assert_eq!((nothing.bytes.as_slice(), nothing.size), (&b""[..], 3));
+    }
+
+    #[tokio::test]
+    async fn forwards_pipelined_exchanges() {
+        let upload = b"POST /upload HTTP/1.1\r\nHost: a.test\r\nTransfer-Encoding: chunked\r\n\r\n\
+                       5;ext=1\r\nhello\r\n6\r\n world\r\n0\r\nChecksum: x\r\n\r\n";
+        let next = b"GET /next HTTP/1.1\r\nHost: a.test\r\nX-Secret: s\r\n\r\n";
+        let next_forwarded = b"GET /next HTTP/1.1\r\nHost: a.test\r\n\r\n";
+        let uploaded = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n\
+                         3\r\nabc\r\n0\r\nTrailer: t\r\n\r\n";
+        let missing = b"HTTP/1.1 404 Not Found\r\nContent-Length: 4\r\n\r\ngone";
+
+        let request_rules = parse_rules("remove X-Secret").unwrap();
+        let response_rules = parse_rules("set Server: proxy").unwrap();
+        let (received, downstream, exchanges) = proxy(
+            &[&upload[..], next].concat(),
+            &[(upload.len(), uploaded), (next_forwarded.len(), missing)],
+            (&request_rules, &response_rules),
+        )
+        .await;
+
+        assert_eq!(received, [&upload[..], next_forwarded].concat());
+        assert_eq!(
+            String::from_utf8(downstream).unwrap(),
+            "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\nServer: proxy\r\n\r\n\
+             3\r\nabc\r\n0\r\nTrailer: t\r\n\r\n\
+             HTTP/1.1 404 Not Found\r\nContent-Length: 4\r\nServer: proxy\r\n\r\ngone"
+        );
+
+        let summaries: Vec<_> = exchanges.iter().map(summary).collect();
+        assert_eq!(
+            summaries,
+            [
+                (
+                    "POST /upload HTTP/1.1",
+                    Some(200),
+                    (&b"hello wo"[..], 11),
+                    (&b"abc"[..], 3)
+                ),
+                (
+                    "GET /next HTTP/1.1",
+                    Some(404),
+                    (&b""[..], 0),
+                    (&b"gone"[..], 4)
+                ),
+            ]
+        );
+        assert_eq!(exchanges[1].request.header("x-secret"), None);
+        assert_eq!(exchanges[1].response.header("server"), Some("proxy"));
+    }
+
+    #[tokio::test]
+    async fn passes_interim_responses_on() {
+        let head = b"POST /p HTTP/1.1\r\nExpect: 100-continue\r\nContent-Length: 3\r\n\r\n";
+        let answers = b"HTTP/1.1 103 Early Hints\r\nLink: </s>\r\n\r\n\
+                        HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok";
+        let (received, downstream, exchanges) = proxy(
+            &[&head[..], b"abc"].concat(),
+            &[(head.len(), b"HTTP/1.1 100 Continue\r\n\r\n"), (3, answers)],
+            (&[], &[]),
+        )
+        .await;
+
+        assert_eq!(received, [&head[..], b"abc"].concat());
+        assert_eq!(
+            downstream,
+            [&b"HTTP/1.1 100 Continue\r\n\r\n"[..], answers].concat()
+        );
+        let summaries: Vec<_> = exchanges.iter().map(summary).collect();
+        assert_eq!(
+            summaries,
+            [(
+                "POST /p HTTP/1.1",
+                Some(200),
+                (&b"abc"[..], 3),
+                (&b"ok"[..], 2)
+            )]
+        );
+    }
+
+    #[tokio::test]
+    async fn stops_parsing_after_switching_protocols() {
+        // An upgrade the server accepts: what follows isn't parsed, even if it looks like HTTP.
+        let upgrade = b"GET /chat HTTP/1.1\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\r\n";
+        let switching = b"HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\n\r\n";
+        let tunneled = b"GET / HTTP/1.1\r\n\r\n";
+        let (received, downstream, exchanges) = proxy(
+            &[&upgrade[..], tunneled].concat(),
+            &[
+                (upgrade.len(), &[&switching[..], b"\x81\x02yo"].concat()),
+                (tunneled.len(), b"HTTP/1.1 200 OK\r\n\r\n"),
+            ],
+            (&[], &[]),
+        )
+        .await;
+        assert_eq!(received, [&upgrade[..], tunneled].concat());
+        assert_eq!(
+            downstream,
+            [&switching[..], b"\x81\x02yo", b"HTTP/1.1 200 OK\r\n\r\n"].concat()
+        );
+        let summaries: Vec<_> = exchanges.iter().map(summary).collect();
+        assert_eq!(
+            summaries,
+            [(
+                "GET /chat HTTP/1.1",
+                Some(101),
+                (&b""[..], 0),
+                (&b""[..], 0)
+            )]
+        );
+
+        // A tunnel: rules apply to the CONNECT exchange, not to what goes through it.
+        let connect = b"CONNECT b.test:443 HTTP/1.1\r\nHost: b.test:443\r\n\r\n";
+        let tunneled = b"GET / HTTP/1.1\r\nX-Secret: s\r\n\r\n";
+        let request_rules = parse_rules("remove X-Secret").unwrap();
+        let response_rules = parse_rules("set Server: proxy").unwrap();
+        let (received, downstream, exchanges) = proxy(
+            &[&connect[..], tunneled].concat(),
+            &[
+                (
+                    connect.len(),
+                    b"HTTP/1.1 200 Connection established\r\n\r\n",
+                ),
+                (tunneled.len(), b"HTTP/1.1 200 OK\r\n\r\n"),
+            ],
+            (&request_rules, &response_rules),
+        )
+        .await;
+        assert_eq!(received, [&connect[..], tunneled].concat());
+        assert_eq!(
+            String::from_utf8(downstream).unwrap(),
+            "HTTP/1.1 200 Connection established\r\nServer: proxy\r\n\r\nHTTP/1.1 200 OK\r\n\r\n"
+        );
+        assert_eq!(exchanges.len(), 1);
+        assert_eq!(exchanges[0].request.method(), "CONNECT");
+
+        // An upgrade the server declines: the connection carries on with HTTP.
+        let upgrade = b"GET /ws HTTP/1.1\r\nUpgrade: websocket\r\n\r\n";
+        let after = b"GET /after HTTP/1.1\r\n\r\n";
+        let (received, _, exchanges) = proxy(
+            &[&upgrade[..], after].concat(),
+            &[
+                (
+                    upgrade.len(),
+                    b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n",
+                ),
+                (after.len(), b"HTTP/1.1 204 No Content\r\n\r\n"),
+            ],
+            (&[], &[]),
+        )
+        .await;
+        assert_eq!(received, [&upgrade[..], after].concat());
+        let statuses: Vec<_> = exchanges
+            .iter()
+            .map(|exchange| exchange.response.status())
+            .collect();
+        assert_eq!(statuses, [Some(200), Some(204)]);
+    }
+
+    #[tokio::test]
+    async fn passes_garbled_chunks_on_as_is() {
+        let request = b"POST /x HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n\
+                        2\r\nab\r\nfffffffffffffffffff\r\nwhatever\r\n0\r\n\r\n";
+        let response = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n\
+                         3\r\nabc\r\nnope\r\nrest";
+        let (received, downstream, exchanges) =
+            proxy(request, &[(request.len(), response)], (&[], &[])).await;
+
+        assert_eq!(received, request);
+        assert_eq!(downstream, response);
+        let summaries: Vec<_> = exchanges.iter().map(summary).collect();
+        assert_eq!(
+            summaries,
+            [(
+                "POST /x HTTP/1.1",
+                Some(200),
+                (&b"ab"[..], 2),
+                (&b"abc"[..], 3)
+            )]
+        );
+    }
+
+    #[tokio::test]
+    async fn forwards_what_is_not_http_as_is() {
+        let garbage = b"\x16\x03\x01 not http\r\n\r\nat all";
+        let (received, downstream, exchanges) = proxy(
+            garbage,
+            &[(garbage.len(), b"SSH-2.0-x\r\n\r\n\x00\x01")],
+            (&[], &[]),
+        )
+        .await;
+        assert_eq!(received, garbage);
+        assert_eq!(downstream, b"SSH-2.0-x\r\n\r\n\x00\x01");
+        assert!(exchanges.is_empty());
+    }
+}
@@ -0,0 +1,221 @@
+use std::io;
+use std::time::Duration;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+
+/// The longest status or header line (or line of a streamed body) accepted.
+const MAX_LINE: usize = 64 * 1024;
+
+/// The most headers a response may have.
+const MAX_HEADERS: usize = 100;
+
+/// The largest response body accepted, so a broken server can't make sockgauge allocate
+/// without bound.
+const MAX_BODY: usize = 16 << 20;
+
+/// The status and headers of an HTTP response.
+#[derive(Debug)]
+pub struct Head {
+    /// The status code.
+    pub status: u16,
+
+    /// The headers, in the order they were received.
+    pub headers: Vec<(String, String)>,
+}
+
+impl Head {
+    /// Returns the value of the header, ignoring the case of its name.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+/// Sends a request and returns the response body, failing on non-2xx statuses.
+pub async fn request(
+    addr: &str,
+    method: &str,
+    path: &str,
+    body: Option<&str>,
+    timeout: Duration,
+) -> io::Result<(Head, String)> {
+    let exchange = async {
+        let (head, reader) = send(addr, method, path, body).await?;
+        let mut body = String::new();
+        reader
+            .take(MAX_BODY as u64 + 1)
+            .read_to_string(&mut body)
+            .await?;
+        if body.len() > MAX_BODY {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{} {} returned more than {} bytes", method, path, MAX_BODY),
+            ));
+        }
+        if !(200..300).contains(&head.status) {
+            return Err(io::Error::other(format!(
+                "{} {} returned {}: {}",
+                method,
+                path,
+                head.status,
+                body.trim()
+            )));
+        }
+
+        Ok((head, body))
+    };
+
+    tokio::time::timeout(timeout, exchange).await.map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::TimedOut,
+            format!("{} {} timed out", method, path),
+        )
+    })?
+}
+
+/// Sends a request and returns the response head along with a reader for the body, which is
+/// useful for streaming responses. HTTP/1.0 is used so the body is never chunked and simply
+/// ends when the server closes the connection.
+pub async fn send(
+    addr: &str,
+    method: &str,
+    path: &str,
+    body: Option<&str>,
+) -> io::Result<(Head, BufReader<TcpStream>)> {
+    let mut stream = TcpStream::connect(addr).await?;
+
+    // Write the request.
+    let body = body.unwrap_or_default();
+    let request = format!(
+        "{} {} HTTP/1.0\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+        method,
+        path,
+        addr,
+        body.len(),
+        body
+    );
+    stream.write_all(request.as_bytes()).await?;
+
+    // Read the status line.
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    read_line(&mut reader, &mut line).await?;
+    let status = line
+        .split_whitespace()
+        .nth(1)
+        .and_then(|status| status.parse::<u16>().ok())
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("invalid HTTP status line `{}`", line.trim()),
+            )
+        })?;
+
+    // Read the headers, up to the empty line.
+    let mut headers = Vec::new();
+    loop {
+        line.clear();
+        if read_line(&mut reader, &mut line).await? == 0 || line.trim().is_empty() {
+            break;
+        }
+        if headers.len() == MAX_HEADERS {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("more than {} headers", MAX_HEADERS),
+            ));
+        }
+
+        if let Some((name, value)) = line.split_once(':') {
+            headers.push((name.trim().to_string(), value.trim().to_string()));
+        }
+    }
+
+    Ok((Head { status, headers }, reader))
+}
+
+/// Reads a line, failing if it's longer than [`MAX_LINE`] rather than reading on.
+pub async fn read_line(
+    reader: &mut (impl AsyncBufRead + Unpin),
+    line: &mut String,
+) -> io::Result<usize> {
+    let read = reader.take(MAX_LINE as u64).read_line(line).await?;
+    if read == MAX_LINE && !line.ends_with('\n') {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("a line longer than {} bytes", MAX_LINE),
+        ));
+    }
+
+    Ok(read)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    /// Sends a GET request to a server answering with the response.
+    async fn get(response: String) -> io::Result<(Head, String)> {
+        let (addr, _) = testing::http_server(response).await?;
+        request(&addr.to_string(), "GET", "/", None, Duration::from_secs(5)).await
+    }
+
+    #[tokio::test]
+    async fn sends_requests() {
+        let response = "HTTP/1.0 200 OK\r\nX-Index: 7\r\n\r\n[1, 2]".to_string();
+        let (addr, mut requests) = testing::http_server(response).await.unwrap();
+        let (head, body) = request(
+            &addr.to_string(),
+            "POST",
+            "/v1/things",
+            Some("{}"),
+            Duration::from_secs(5),
+        )
+        .await
+        .unwrap();
+        assert_eq!((head.status, head.header("x-index")), (200, Some("7")));
+        assert_eq!(body, "[1, 2]");
+        assert_eq!(
+            requests.recv().await.unwrap(),
+            format!(
+                "POST /v1/things HTTP/1.0\r\nHost: {}\r\nContent-Type: application/json\r\n\
+                 Content-Length: 2\r\n\r\n{{}}",
+                addr
+            )
+        );
+
+        let error = get("HTTP/1.0 503 Unavailable\r\n\r\nno leader\n".to_string()).await;
+        assert_eq!(
+            error.unwrap_err().to_string(),
+            "GET / returned 503: no leader"
+        );
+        assert!(get("SSH-2.0-OpenSSH\r\n\r\n".to_string()).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn bounds_responses() {
+        let long_header = format!(
+            "HTTP/1.0 200 OK\r\nX-Long: {}\r\n\r\n",
+            "a".repeat(MAX_LINE)
+        );
+        let error = get(long_header).await.unwrap_err();
+        assert_eq!(error.to_string(), "a line longer than 65536 bytes");
+
+        let headers = "X-Header: 1\r\n".repeat(MAX_HEADERS + 1);
+        let error = get(format!("HTTP/1.0 200 OK\r\n{}\r\n", headers)).await;
+        assert_eq!(error.unwrap_err().to_string(), "more than 100 headers");
+
+        let body = "a".repeat(MAX_BODY + 1);
+        let error = get(format!("HTTP/1.0 200 OK\r\n\r\n{}", body)).await;
+        assert_eq!(
+            error.unwrap_err().to_string(),
+            "GET / returned more than 16777216 bytes"
+        );
+        let (_, body) = get(format!("HTTP/1.0 200 OK\r\n\r\n{}", &body[1..]))
+            .await
+            .unwrap();
+        assert_eq!(body.len(), MAX_BODY);
+    }
+}
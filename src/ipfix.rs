@@ -0,0 +1,215 @@
+use crate::output::eprint_line;
+use std::io;
+use std::net::{IpAddr, SocketAddr, UdpSocket};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The version number of IPFIX messages.
+const VERSION: u16 = 10;
+
+/// The set ID of template sets.
+const TEMPLATE_SET: u16 = 2;
+
+/// The template IDs of flows between IPv4 and between IPv6 addresses.
+const IPV4_TEMPLATE: u16 = 256;
+const IPV6_TEMPLATE: u16 = 257;
+
+/// The information elements of a flow after its addresses, as `(ID, length)`: the source and
+/// destination ports, the protocol, the bytes, the packets and the start and end times.
+const FIELDS: [(u16, u16); 7] = [(7, 2), (11, 2), (4, 1), (1, 8), (2, 8), (152, 8), (153, 8)];
+
+/// The information elements of source and destination addresses, in IPv4 and in IPv6.
+const IPV4_ADDRESSES: [(u16, u16); 2] = [(8, 4), (12, 4)];
+const IPV6_ADDRESSES: [(u16, u16); 2] = [(27, 16), (28, 16)];
+
+/// The protocol number of TCP.
+const TCP: u8 = 6;
+
+/// Traffic in one direction of a connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Flow {
+    /// Where the traffic came from.
+    pub source: SocketAddr,
+
+    /// Where the traffic went.
+    pub destination: SocketAddr,
+
+    /// How many bytes were sent.
+    pub bytes: u64,
+
+    /// Roughly how many packets were sent: the reads or writes it took.
+    pub packets: u64,
+
+    /// When the connection opened.
+    pub start: SystemTime,
+
+    /// When the connection closed.
+    pub end: SystemTime,
+}
+
+/// Exports flows to an IPFIX collector over UDP.
+#[derive(Debug)]
+pub struct Exporter {
+    /// Connected to the collector.
+    socket: UdpSocket,
+
+    /// The address of the collector, for messages about failures.
+    addr: String,
+
+    /// How many flows were exported so far, which numbers the messages.
+    sequence: u32,
+
+    /// Whether the last export failed, so failures are only reported when they start.
+    failing: bool,
+}
+
+impl Exporter {
+    /// Creates an exporter to the collector at the address.
+    pub fn connect(addr: &str) -> io::Result<Self> {
+        let socket = UdpSocket::bind(match addr.starts_with('[') {
+            true => "[::]:0",
+            false => "0.0.0.0:0",
+        })?;
+        socket.connect(addr)?;
+        socket.set_nonblocking(true)?;
+        Ok(Self {
+            socket,
+            addr: addr.to_string(),
+            sequence: 0,
+            failing: false,
+        })
+    }
+
+    /// Exports flows, as a message of their own. The templates go along with every message,
+    /// as UDP collectors might have missed (or forgotten) them.
+    pub fn export(&mut self, flows: &[Flow]) {
+        let message = message(flows, self.sequence, SystemTime::now());
+        self.sequence = self.sequence.wrapping_add(flows.len() as u32);
+        match (self.socket.send(&message), self.failing) {
+            (Ok(_), _) => self.failing = false,
+            (Err(_), true) => {}
+            (Err(err), false) => {
+                eprint_line!("💥️ — exporting flows to {} failed: {}", self.addr, err);
+                self.failing = true;
+            }
+        }
+    }
+}
+
+/// Encodes an IPFIX message with the templates and the flows.
+fn message(flows: &[Flow], sequence: u32, now: SystemTime) -> Vec<u8> {
+    let mut templates = Vec::new();
+    for (id, addresses) in [
+        (IPV4_TEMPLATE, IPV4_ADDRESSES),
+        (IPV6_TEMPLATE, IPV6_ADDRESSES),
+    ] {
+        templates.extend_from_slice(&id.to_be_bytes());
+        templates.extend_from_slice(&((addresses.len() + FIELDS.len()) as u16).to_be_bytes());
+        for (field, length) in addresses.iter().chain(FIELDS.iter()) {
+            templates.extend_from_slice(&field.to_be_bytes());
+            templates.extend_from_slice(&length.to_be_bytes());
+        }
+    }
+
+    let mut body = Vec::new();
+    push_set(&mut body, TEMPLATE_SET, &templates);
+    for flow in flows {
+        let (template, record) = record(flow);
+        push_set(&mut body, template, &record);
+    }
+
+    let mut message = Vec::new();
+    message.extend_from_slice(&VERSION.to_be_bytes());
+    message.extend_from_slice(&((16 + body.len()) as u16).to_be_bytes());
+    message.extend_from_slice(&(millis(now) / 1000).to_be_bytes()[4..]);
+    message.extend_from_slice(&sequence.to_be_bytes());
+    message.extend_from_slice(&0u32.to_be_bytes()); // The observation domain
+    message.extend_from_slice(&body);
+    message
+}
+
+/// Encodes a flow as a data record, returning it along with its template ID. Flows between
+/// IPv4 addresses use the IPv4 template, others the IPv6 one.
+fn record(flow: &Flow) -> (u16, Vec<u8>) {
+    let mut record = Vec::new();
+    let template = match (flow.source.ip(), flow.destination.ip()) {
+        (IpAddr::V4(source), IpAddr::V4(destination)) => {
+            record.extend_from_slice(&source.octets());
+            record.extend_from_slice(&destination.octets());
+            IPV4_TEMPLATE
+        }
+        (source, destination) => {
+            for ip in [source, destination] {
+                let ip = match ip {
+                    IpAddr::V4(ip) => ip.to_ipv6_mapped(),
+                    IpAddr::V6(ip) => ip,
+                };
+                record.extend_from_slice(&ip.octets());
+            }
+            IPV6_TEMPLATE
+        }
+    };
+
+    record.extend_from_slice(&flow.source.port().to_be_bytes());
+    record.extend_from_slice(&flow.destination.port().to_be_bytes());
+    record.push(TCP);
+    record.extend_from_slice(&flow.bytes.to_be_bytes());
+    record.extend_from_slice(&flow.packets.to_be_bytes());
+    record.extend_from_slice(&millis(flow.start).to_be_bytes());
+    record.extend_from_slice(&millis(flow.end).to_be_bytes());
+    (template, record)
+}
+
+/// Appends a set, with its header.
+fn push_set(body: &mut Vec<u8>, id: u16, contents: &[u8]) {
+    body.extend_from_slice(&id.to_be_bytes());
+    body.extend_from_slice(&((4 + contents.len()) as u16).to_be_bytes());
+    body.extend_from_slice(contents);
+}
+
+/// The milliseconds since the Unix epoch.
+fn millis(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn encodes_messages() {
+        let start = UNIX_EPOCH + Duration::from_millis(1_792_037_134_123);
+        let flow = Flow {
+            source: "10.0.0.1:52262".parse().unwrap(),
+            destination: "10.0.0.2:80".parse().unwrap(),
+            bytes: 1500,
+            packets: 2,
+            start,
+            end: start + Duration::from_millis(250),
+        };
+        let message = message(&[flow], 7, flow.end);
+
+        // The header, then the templates (4 + 2 × (4 + 9 × 4) bytes).
+        assert_eq!(message[..4], [0, 10, 0, 149]);
+        assert_eq!(message[4..8], 1_792_037_134u32.to_be_bytes());
+        assert_eq!(message[8..12], 7u32.to_be_bytes());
+        assert_eq!(message[16..20], [0, 2, 0, 84]);
+
+        // A record of 45 bytes, with the IPv4 template.
+        let set = &message[100..];
+        assert_eq!(set[..4], [1, 0, 0, 49]);
+        assert_eq!(set[4..12], [10, 0, 0, 1, 10, 0, 0, 2]);
+        assert_eq!(set[12..17], [0xcc, 0x26, 0, 80, TCP]);
+        assert_eq!(set[17..25], 1500u64.to_be_bytes());
+        assert_eq!(set[41..], 1_792_037_134_373u64.to_be_bytes());
+
+        let v6 = Flow {
+            destination: "[::1]:80".parse().unwrap(),
+            ..flow
+        };
+        assert_eq!(record(&v6).0, IPV6_TEMPLATE);
+        assert_eq!(record(&v6).1.len(), 16 + 16 + 37);
+    }
+}
@@ -0,0 +1,152 @@
+use crate::net;
+use crate::output::print_line;
+use crate::srv;
+use std::collections::HashMap;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::{Mutex, OnceLock};
+use tokio::net::TcpStream;
+
+/// Prefix of Kubernetes service destinations, e.g. `k8s://my-service.my-namespace:8080`.
+pub const PREFIX: &str = "k8s://";
+
+/// The ready pod addresses last seen per service, used to report changes.
+static LAST_SEEN: OnceLock<Mutex<HashMap<String, Vec<SocketAddr>>>> = OnceLock::new();
+
+/// Checks that the destination looks like `service.namespace:port`.
+pub fn validate(target: &str) -> Result<(), String> {
+    match parse(target) {
+        Some(_) => Ok(()),
+        None => Err(format!(
+            "`{}{}` is not a Kubernetes service like {}my-service.my-namespace:8080",
+            PREFIX, target, PREFIX
+        )),
+    }
+}
+
+/// Connects to one of the ready pods of a headless service. Kubernetes publishes the ready pod
+/// IPs of headless services in DNS, so resolving on every connection follows pods as they come
+/// and go. Connections are spread randomly, failing over to the other pods.
+pub async fn connect(target: &str) -> Result<TcpStream, io::Error> {
+    let (host, port) = parse(target).ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidInput, validate(target).unwrap_err())
+    })?;
+
+    let mut addrs: Vec<SocketAddr> = tokio::net::lookup_host((host.as_str(), port))
+        .await?
+        .collect();
+    addrs.sort();
+    report_changes(target, &addrs);
+
+    if addrs.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("{} has no ready pods", target),
+        ));
+    }
+
+    let start = (srv::random() % addrs.len() as u64) as usize;
+    addrs.rotate_left(start);
+
+    let mut last_err = None;
+    for addr in addrs {
+        match net::connect(addr).await {
+            Ok(stream) => return Ok(stream),
+            Err(err) => last_err = Some(err),
+        }
+    }
+
+    Err(last_err.expect("at least one address was tried"))
+}
+
+/// Splits `service.namespace:port` into the service's DNS name and the port. A name that
+/// already is fully qualified (e.g. on a cluster with a custom domain) is used as-is.
+fn parse(target: &str) -> Option<(String, u16)> {
+    let (name, port) = target.rsplit_once(':')?;
+    let port = port.parse::<u16>().ok()?;
+    let labels: Vec<&str> = name.split('.').collect();
+    if labels.len() < 2 || labels.iter().any(|l| l.is_empty()) {
+        return None;
+    }
+
+    match labels.len() {
+        2 => Some((format!("{}.svc.cluster.local", name), port)),
+        _ => Some((name.to_string(), port)),
+    }
+}
+
+/// Prints the ready pods whenever they change.
+fn report_changes(target: &str, addrs: &[SocketAddr]) {
+    let mut last_seen = LAST_SEEN.get_or_init(Mutex::default).lock().unwrap();
+    if last_seen.get(target).map(Vec::as_slice) == Some(addrs) {
+        return;
+    }
+
+    let pods: Vec<String> = addrs.iter().map(|addr| addr.to_string()).collect();
+    print_line!(
+        "☸️  {}{} has {} ready pod(s): {}",
+        PREFIX,
+        target,
+        addrs.len(),
+        pods.join(", ")
+    );
+    last_seen.insert(target.to_string(), addrs.to_vec());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Harness;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[test]
+    fn parses_services() {
+        let cases = [
+            ("web.shop:8080", Some(("web.shop.svc.cluster.local", 8080))),
+            (
+                "web.shop.svc.example.org:80",
+                Some(("web.shop.svc.example.org", 80)),
+            ),
+            ("web:8080", None),
+            ("web.shop", None),
+            ("web..shop:80", None),
+            ("web.shop:http", None),
+            ("web.shop:70000", None),
+        ];
+        for (target, expected) in cases {
+            let parsed = parse(target);
+            let parsed = parsed.as_ref().map(|(host, port)| (host.as_str(), *port));
+            assert_eq!(parsed, expected, "{}", target);
+            assert_eq!(validate(target).is_ok(), expected.is_some());
+        }
+    }
+
+    #[tokio::test]
+    async fn forwards_to_ready_pods() {
+        // An IP address stands in for the name of a service with one ready pod.
+        let harness = Harness::start_with(|destination| {
+            destination.addr = format!("{}{}", PREFIX, destination.addr);
+        })
+        .await
+        .unwrap();
+        let target = harness.destination.borrow().addr[PREFIX.len()..].to_string();
+
+        let mut client = harness.connect().await.unwrap();
+        client.write_all(b"hello").await.unwrap();
+        let mut echoed = [0; 5];
+        client.read_exact(&mut echoed).await.unwrap();
+        assert_eq!(&echoed, b"hello");
+
+        let pods = LAST_SEEN.get().unwrap().lock().unwrap()[&target].clone();
+        assert_eq!(pods, [target.parse::<SocketAddr>().unwrap()]);
+
+        // Without a pod to take it, the connection fails.
+        let closed = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let target = closed.local_addr().unwrap().to_string();
+        drop(closed);
+        let error = connect(&target).await.unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::ConnectionRefused);
+        let error = connect("web:80").await.unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+    }
+}
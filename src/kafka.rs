@@ -0,0 +1,524 @@
+use crate::digest;
+use crate::output::eprint_line;
+use crate::publisher::{self, Publisher};
+use std::future::Future;
+use std::io;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::sync::mpsc;
+
+/// The most events sent in one record batch.
+const MAX_BATCH: usize = 500;
+
+/// The largest response accepted, so garbage can't make sockgauge allocate without bound.
+const MAX_RESPONSE: usize = 1 << 20;
+
+/// The requests used, with their API keys and versions. These versions are supported by
+/// Kafka 2.1 and later.
+const PRODUCE: (i16, i16) = (0, 7);
+const METADATA: (i16, i16) = (3, 7);
+
+/// How long the broker may take to write the events, in milliseconds.
+const PRODUCE_TIMEOUT_MS: i32 = 5000;
+
+/// The partition events are produced to, so they stay in order.
+const PARTITION: i32 = 0;
+
+/// The produce errors that a batch is sent again after, through the partition's leader as
+/// looked up again: an unknown topic or partition, no leader, another leader, and a timeout.
+const RETRIABLE: [i16; 4] = [3, 5, 6, 7];
+
+/// Where to produce events in Kafka.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Target {
+    /// The address of a broker to look up the partition's leader with.
+    pub addr: String,
+
+    /// The topic to produce events to.
+    pub topic: String,
+}
+
+/// Produces events to Kafka in the background, at least once: a batch is sent again until the
+/// leader acknowledged it, so one lost along with the connection may end up in Kafka twice.
+#[derive(Debug)]
+pub struct Kafka {
+    /// Used for sending events to the background task.
+    sender: mpsc::Sender<String>,
+}
+
+impl Kafka {
+    /// Starts producing to a target.
+    pub fn start(target: Target) -> Self {
+        Self {
+            sender: publisher::start(target),
+        }
+    }
+
+    /// Produces an event, given as JSON. Events are dropped while Kafka can't keep up.
+    pub fn publish(&self, event: String) {
+        let _ = self.sender.try_send(event);
+    }
+}
+
+impl Publisher for Target {
+    type Event = String;
+    type Pending = Vec<String>;
+
+    fn describe(&self) -> String {
+        format!("producing events to Kafka at {}", self.addr)
+    }
+
+    fn send<'a>(
+        &'a self,
+        receiver: &'a mut mpsc::Receiver<String>,
+        pending: &'a mut Vec<String>,
+    ) -> impl Future<Output = io::Result<()>> + Send + 'a {
+        send(self, receiver, pending)
+    }
+}
+
+/// Looks up the partition's leader, connects to it and sends the events in batches until there
+/// are no more (returning `Ok`) or the connection fails. A batch is left in `pending` until the
+/// leader acknowledged it, to send after reconnecting otherwise.
+async fn send(
+    target: &Target,
+    receiver: &mut mpsc::Receiver<String>,
+    pending: &mut Vec<String>,
+) -> io::Result<()> {
+    let mut bootstrap = TcpStream::connect(&target.addr).await?;
+    bootstrap.write_all(&metadata(&target.topic)).await?;
+    let leader = parse_leader(&read_response(&mut bootstrap).await?, &target.topic)?;
+    drop(bootstrap);
+    let mut stream = TcpStream::connect(&leader).await?;
+
+    let mut correlation_id = 0;
+    loop {
+        if pending.is_empty() {
+            match receiver.recv().await {
+                Some(event) => pending.push(event),
+                None => return Ok(()),
+            }
+            while pending.len() < MAX_BATCH {
+                match receiver.try_recv() {
+                    Ok(event) => pending.push(event),
+                    Err(_) => break,
+                }
+            }
+        }
+
+        // Events queue up while the leader writes a batch, and go in the next one.
+        correlation_id += 1;
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_millis() as i64);
+        let batch = record_batch(pending, timestamp);
+        stream
+            .write_all(&produce(correlation_id, &target.topic, &batch))
+            .await?;
+        acknowledged(&read_response(&mut stream).await?, &leader)?;
+        pending.clear();
+    }
+}
+
+/// Checks the response to a batch, failing if it should be sent again (through the leader as
+/// looked up again). Batches Kafka refuses otherwise, e.g. as too large, are given up on.
+fn acknowledged(response: &[u8], leader: &str) -> io::Result<()> {
+    match parse_produce_error(response)? {
+        Some(code) if RETRIABLE.contains(&code) => Err(io::Error::other(format!(
+            "the leader at {} refused events with error code {}",
+            leader, code
+        ))),
+        Some(code) => {
+            eprint_line!(
+                "💥️ — Kafka at {} refused events with error code {}",
+                leader,
+                code
+            );
+            Ok(())
+        }
+        None => Ok(()),
+    }
+}
+
+/// Encodes a request with its header and length.
+fn request((api_key, api_version): (i16, i16), correlation_id: i32, body: &[u8]) -> Vec<u8> {
+    let mut request = Vec::new();
+    request.extend_from_slice(&api_key.to_be_bytes());
+    request.extend_from_slice(&api_version.to_be_bytes());
+    request.extend_from_slice(&correlation_id.to_be_bytes());
+    push_string(&mut request, "sockgauge");
+    request.extend_from_slice(body);
+
+    let mut framed = (request.len() as i32).to_be_bytes().to_vec();
+    framed.extend(request);
+    framed
+}
+
+/// Encodes a metadata request for a topic, creating it if the broker allows.
+fn metadata(topic: &str) -> Vec<u8> {
+    let mut body = 1i32.to_be_bytes().to_vec();
+    push_string(&mut body, topic);
+    body.push(1);
+    request(METADATA, 0, &body)
+}
+
+/// Encodes a produce request of a record batch to the partition of a topic, acknowledged by
+/// the leader.
+fn produce(correlation_id: i32, topic: &str, batch: &[u8]) -> Vec<u8> {
+    let mut body = Vec::new();
+    body.extend_from_slice(&(-1i16).to_be_bytes()); // No transaction
+    body.extend_from_slice(&1i16.to_be_bytes()); // Acknowledged by the leader
+    body.extend_from_slice(&PRODUCE_TIMEOUT_MS.to_be_bytes());
+    body.extend_from_slice(&1i32.to_be_bytes());
+    push_string(&mut body, topic);
+    body.extend_from_slice(&1i32.to_be_bytes());
+    body.extend_from_slice(&PARTITION.to_be_bytes());
+    body.extend_from_slice(&(batch.len() as i32).to_be_bytes());
+    body.extend_from_slice(batch);
+    request(PRODUCE, correlation_id, &body)
+}
+
+/// Encodes events as a record batch (magic 2) of records without keys, all with the timestamp
+/// in milliseconds.
+fn record_batch(events: &[String], timestamp: i64) -> Vec<u8> {
+    // Everything the checksum covers, from the attributes on.
+    let mut checked = Vec::new();
+    checked.extend_from_slice(&0i16.to_be_bytes()); // Not compressed
+    checked.extend_from_slice(&(events.len() as i32 - 1).to_be_bytes());
+    checked.extend_from_slice(&timestamp.to_be_bytes());
+    checked.extend_from_slice(&timestamp.to_be_bytes());
+    checked.extend_from_slice(&(-1i64).to_be_bytes()); // No producer ID
+    checked.extend_from_slice(&(-1i16).to_be_bytes()); // or epoch
+    checked.extend_from_slice(&(-1i32).to_be_bytes()); // or sequence
+    checked.extend_from_slice(&(events.len() as i32).to_be_bytes());
+    for (offset, event) in events.iter().enumerate() {
+        let mut record = vec![0]; // No attributes
+        push_varint(&mut record, 0); // The timestamp delta
+        push_varint(&mut record, offset as i64);
+        push_varint(&mut record, -1); // No key
+        push_varint(&mut record, event.len() as i64);
+        record.extend_from_slice(event.as_bytes());
+        push_varint(&mut record, 0); // No headers
+        push_varint(&mut checked, record.len() as i64);
+        checked.extend(record);
+    }
+
+    let mut batch = 0i64.to_be_bytes().to_vec(); // The base offset, set by the broker
+    batch.extend_from_slice(&(checked.len() as i32 + 9).to_be_bytes());
+    batch.extend_from_slice(&(-1i32).to_be_bytes()); // The partition leader epoch
+    batch.push(2); // The magic
+    batch.extend_from_slice(&digest::crc32c(&checked).to_be_bytes());
+    batch.extend(checked);
+    batch
+}
+
+/// Appends a string with its 16-bit length.
+fn push_string(bytes: &mut Vec<u8>, string: &str) {
+    bytes.extend_from_slice(&(string.len() as i16).to_be_bytes());
+    bytes.extend_from_slice(string.as_bytes());
+}
+
+/// Appends a zigzag-encoded variable-length integer.
+fn push_varint(bytes: &mut Vec<u8>, value: i64) {
+    let mut zigzag = ((value << 1) ^ (value >> 63)) as u64;
+    while zigzag >= 0x80 {
+        bytes.push(zigzag as u8 | 0x80);
+        zigzag >>= 7;
+    }
+    bytes.push(zigzag as u8);
+}
+
+/// Reads a response, returning what follows its correlation ID.
+async fn read_response(reader: &mut (impl AsyncRead + Unpin)) -> io::Result<Vec<u8>> {
+    let length = reader.read_i32().await?;
+    let length = usize::try_from(length)
+        .ok()
+        .filter(|length| (4..=MAX_RESPONSE).contains(length))
+        .ok_or_else(|| io::Error::other(format!("a response of {} bytes", length)))?;
+    let mut response = vec![0; length];
+    reader.read_exact(&mut response).await?;
+    Ok(response.split_off(4))
+}
+
+/// Finds the address of the leader of the partition in a metadata response.
+fn parse_leader(response: &[u8], topic: &str) -> io::Result<String> {
+    let mut reader = Reader(response);
+    reader.i32()?; // The throttle time
+    let mut brokers = Vec::new();
+    for _ in 0..reader.count()? {
+        let node_id = reader.i32()?;
+        let host = reader.string()?.unwrap_or_default();
+        let port = reader.i32()?;
+        reader.string()?; // The rack
+        brokers.push((node_id, host, port));
+    }
+    reader.string()?; // The cluster ID
+    reader.i32()?; // The controller
+
+    for _ in 0..reader.count()? {
+        let error_code = reader.i16()?;
+        let name = reader.string()?;
+        reader.take(1)?; // Whether it's internal
+        let mut leader = None;
+        for _ in 0..reader.count()? {
+            let partition_error_code = reader.i16()?;
+            let partition = reader.i32()?;
+            let leader_id = reader.i32()?;
+            reader.i32()?; // The leader epoch
+            for _ in 0..3 {
+                // The replicas, those in sync and those offline.
+                let nodes = reader.count()?;
+                reader.take(nodes * 4)?;
+            }
+            if partition == PARTITION {
+                leader = Some((partition_error_code, leader_id));
+            }
+        }
+
+        if name.as_deref() != Some(topic) {
+            continue;
+        }
+
+        let leader_id = match (error_code, leader) {
+            (0, Some((0, leader_id))) => leader_id,
+            (0, Some((code, _))) | (code, _) if code != 0 => {
+                return Err(io::Error::other(format!(
+                    "the topic {} isn't available, with error code {}",
+                    topic, code
+                )))
+            }
+            _ => {
+                return Err(io::Error::other(format!(
+                    "the topic {} has no partition {}",
+                    topic, PARTITION
+                )))
+            }
+        };
+
+        return match brokers.iter().find(|(node_id, _, _)| *node_id == leader_id) {
+            Some((_, host, port)) if host.contains(':') => Ok(format!("[{}]:{}", host, port)),
+            Some((_, host, port)) => Ok(format!("{}:{}", host, port)),
+            None => Err(io::Error::other(format!(
+                "the leader of topic {} is unknown",
+                topic
+            ))),
+        };
+    }
+
+    Err(io::Error::other(format!("no metadata for topic {}", topic)))
+}
+
+/// Finds the first error code of a partition in a produce response, if there is one.
+fn parse_produce_error(response: &[u8]) -> io::Result<Option<i16>> {
+    let mut reader = Reader(response);
+    for _ in 0..reader.count()? {
+        reader.string()?;
+        for _ in 0..reader.count()? {
+            reader.i32()?; // The partition
+            let error_code = reader.i16()?;
+            reader.take(24)?; // The offsets and the time the events were appended
+            if error_code != 0 {
+                return Ok(Some(error_code));
+            }
+        }
+    }
+    Ok(None)
+}
+
+/// Reads the fields of a response, failing at its end.
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    /// Takes the next bytes.
+    fn take(&mut self, n: usize) -> io::Result<&'a [u8]> {
+        if self.0.len() < n {
+            return Err(io::Error::other("a truncated response"));
+        }
+
+        let (taken, rest) = self.0.split_at(n);
+        self.0 = rest;
+        Ok(taken)
+    }
+
+    /// Takes a 16-bit integer.
+    fn i16(&mut self) -> io::Result<i16> {
+        Ok(i16::from_be_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    /// Takes a 32-bit integer.
+    fn i32(&mut self) -> io::Result<i32> {
+        Ok(i32::from_be_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    /// Takes the length of an array, which is empty if null.
+    fn count(&mut self) -> io::Result<usize> {
+        Ok(usize::try_from(self.i32()?).unwrap_or(0))
+    }
+
+    /// Takes a string with its 16-bit length, which may be null.
+    fn string(&mut self) -> io::Result<Option<String>> {
+        match usize::try_from(self.i16()?) {
+            Ok(length) => Ok(Some(
+                String::from_utf8_lossy(self.take(length)?).into_owned(),
+            )),
+            Err(_) => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    /// Reads a request, returning its API key and version and its body.
+    async fn read_request(stream: &mut TcpStream) -> ((i16, i16), Vec<u8>) {
+        let length = stream.read_i32().await.unwrap();
+        let mut request = vec![0; length as usize];
+        stream.read_exact(&mut request).await.unwrap();
+        let mut reader = Reader(&request);
+        let api = (reader.i16().unwrap(), reader.i16().unwrap());
+        reader.i32().unwrap();
+        assert_eq!(reader.string().unwrap().as_deref(), Some("sockgauge"));
+        (api, reader.0.to_vec())
+    }
+
+    /// Writes a response with its length and correlation ID.
+    async fn write_response(stream: &mut TcpStream, body: &[u8]) {
+        let mut response = ((body.len() + 4) as i32).to_be_bytes().to_vec();
+        response.extend_from_slice(&0i32.to_be_bytes());
+        response.extend_from_slice(body);
+        stream.write_all(&response).await.unwrap();
+    }
+
+    /// Takes a zigzag-encoded variable-length integer.
+    fn varint(reader: &mut Reader) -> i64 {
+        let mut zigzag = 0u64;
+        for shift in (0..).step_by(7) {
+            let byte = reader.take(1).unwrap()[0];
+            zigzag |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                break;
+            }
+        }
+        (zigzag >> 1) as i64 ^ -((zigzag & 1) as i64)
+    }
+
+    #[tokio::test]
+    async fn produces_events_to_the_leader() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let kafka = Kafka::start(Target {
+            addr: listener.local_addr().unwrap().to_string(),
+            topic: "events".to_string(),
+        });
+        kafka.publish(r#"{"event":"opened"}"#.to_string());
+        kafka.publish(r#"{"event":"closed"}"#.to_string());
+        drop(kafka);
+
+        // The broker asks for the topic, and is told that broker 2 leads it.
+        let (mut bootstrap, _) = listener.accept().await.unwrap();
+        let (api, body) = read_request(&mut bootstrap).await;
+        assert_eq!(api, METADATA);
+        let mut expected = 1i32.to_be_bytes().to_vec();
+        push_string(&mut expected, "events");
+        expected.push(1);
+        assert_eq!(body, expected);
+
+        // No throttling, and two brokers, of which only the second listens.
+        let mut response = 0i32.to_be_bytes().to_vec();
+        response.extend_from_slice(&2i32.to_be_bytes());
+        for node_id in [1i32, 2] {
+            response.extend_from_slice(&node_id.to_be_bytes());
+            push_string(&mut response, "127.0.0.1");
+            let port = match node_id {
+                1 => 1,
+                _ => port as i32,
+            };
+            response.extend_from_slice(&port.to_be_bytes());
+            response.extend_from_slice(&(-1i16).to_be_bytes());
+        }
+        // The cluster and its controller, then the topic with its one partition.
+        push_string(&mut response, "cluster");
+        response.extend_from_slice(&1i32.to_be_bytes());
+        response.extend_from_slice(&1i32.to_be_bytes());
+        response.extend_from_slice(&0i16.to_be_bytes());
+        push_string(&mut response, "events");
+        response.push(0);
+        response.extend_from_slice(&1i32.to_be_bytes());
+        response.extend_from_slice(&0i16.to_be_bytes());
+        response.extend_from_slice(&PARTITION.to_be_bytes());
+        response.extend_from_slice(&2i32.to_be_bytes());
+        response.extend_from_slice(&0i32.to_be_bytes());
+        for nodes in [&[1i32, 2][..], &[1, 2], &[]] {
+            response.extend_from_slice(&(nodes.len() as i32).to_be_bytes());
+            for node in nodes {
+                response.extend_from_slice(&node.to_be_bytes());
+            }
+        }
+        write_response(&mut bootstrap, &response).await;
+
+        // Both events go to the leader in one batch.
+        let (mut leader, _) = listener.accept().await.unwrap();
+        let (api, body) = read_request(&mut leader).await;
+        assert_eq!(api, PRODUCE);
+        let mut reader = Reader(&body);
+        assert_eq!(reader.string().unwrap(), None);
+        assert_eq!(reader.i16().unwrap(), 1);
+        reader.i32().unwrap();
+        assert_eq!(reader.count().unwrap(), 1);
+        assert_eq!(reader.string().unwrap().as_deref(), Some("events"));
+        assert_eq!(reader.count().unwrap(), 1);
+        assert_eq!(reader.i32().unwrap(), PARTITION);
+        let length = reader.count().unwrap();
+        let batch = reader.take(length).unwrap();
+        assert!(reader.0.is_empty());
+
+        let mut batch = Reader(batch);
+        batch.take(8).unwrap();
+        assert_eq!(batch.count().unwrap(), batch.0.len());
+        batch.take(4).unwrap();
+        assert_eq!(batch.take(1).unwrap(), [2]);
+        let crc = batch.i32().unwrap() as u32;
+        assert_eq!(crc, digest::crc32c(batch.0));
+        batch.take(2 + 4 + 8 + 8 + 8 + 2 + 4).unwrap();
+        let mut events = Vec::new();
+        let records = batch.count().unwrap();
+        for offset in 0..records {
+            let length = varint(&mut batch) as usize;
+            let mut record = Reader(batch.take(length).unwrap());
+            record.take(1).unwrap();
+            assert_eq!(varint(&mut record), 0);
+            assert_eq!(varint(&mut record), offset as i64);
+            assert_eq!(varint(&mut record), -1);
+            let value = varint(&mut record) as usize;
+            events.push(String::from_utf8(record.take(value).unwrap().to_vec()).unwrap());
+            assert_eq!(varint(&mut record), 0);
+            assert!(record.0.is_empty());
+        }
+        assert_eq!(events, [r#"{"event":"opened"}"#, r#"{"event":"closed"}"#]);
+    }
+
+    #[test]
+    fn reads_produce_errors() {
+        let response = |error_code: i16| {
+            let mut response = 1i32.to_be_bytes().to_vec();
+            push_string(&mut response, "events");
+            response.extend_from_slice(&1i32.to_be_bytes());
+            response.extend_from_slice(&PARTITION.to_be_bytes());
+            response.extend_from_slice(&error_code.to_be_bytes());
+            response.extend_from_slice(&[0; 24]);
+            response.extend_from_slice(&0i32.to_be_bytes());
+            response
+        };
+        assert_eq!(parse_produce_error(&response(0)).unwrap(), None);
+        assert_eq!(parse_produce_error(&response(3)).unwrap(), Some(3));
+        assert!(parse_produce_error(&response(0)[..20]).is_err());
+
+        // Batches are sent again after leader changes, and given up on if they can't be stored.
+        assert!(acknowledged(&response(0), "k:9092").is_ok());
+        assert!(acknowledged(&response(6), "k:9092").is_err());
+        assert!(acknowledged(&response(10), "k:9092").is_ok());
+    }
+}
@@ -1,28 +1,650 @@
+mod admin;
+mod admission;
+mod alert;
+mod ban;
+mod base64;
+mod compare;
+mod config;
+mod consul;
+mod control;
+mod cpu;
+mod digest;
+mod discovery;
+mod etcd;
+mod exchanges;
+mod exit;
+mod filter;
+mod format;
+mod handover;
+mod har;
+mod histogram;
+mod http;
+mod http_client;
+mod ipfix;
+mod json;
+mod k8s;
+mod kafka;
+mod mqtt;
+mod mux;
+mod mysql;
+mod net;
+mod output;
+mod peers;
+mod policy;
+mod pool;
+mod postgres;
+mod protocol;
 mod proxy;
+mod publisher;
+mod record;
+mod redis;
+mod regex;
+mod replay;
+mod report;
 mod reporter;
+mod resp;
+mod session;
+mod signal;
+mod sink;
+mod smtp;
+mod spark;
+mod srv;
+mod ssh;
+mod starttls;
+mod storm;
+#[cfg(any(test, feature = "testing"))]
+#[cfg_attr(not(test), allow(dead_code))]
+mod testing;
+mod timeline;
+mod tls;
+mod usage;
+use admin::Admin;
+use config::Config;
+use control::Control;
+use mux::Tunnel;
+use output::{eprint_line, print_line};
+use proxy::Destination;
+use reporter::{Finished, ReporterActor, Stopped, WeakReporterHandle, DEFAULT_MAPPING};
+use signal::Signal;
 use std::error::Error;
+use std::sync::Arc;
+use std::time::SystemTime;
+use tokio::net::TcpListener;
+use tokio::sync::{mpsc, watch};
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn Error>> {
-    let mut args = std::env::args();
-    let bind_addr = args
-        .nth(1)
-        .ok_or("Specify a bind address as the first argument")?;
-    let dest_addr = args
-        .next()
-        .ok_or("Specify a destination address as the second argument")?;
+/// Counts allocations, for `--self-metrics`; without it, it only checks that it needn't.
+#[global_allocator]
+static ALLOCATOR: usage::Counting = usage::Counting;
 
-    println!("⚡️ sockgauge is forwarding {} -> {}", bind_addr, dest_addr);
+fn main() -> Result<(), Box<dyn Error>> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().is_some_and(|arg| arg == "replay") {
+        return cpu::runtime(&[])?.block_on(replay::run(&args[1..]));
+    }
+    if args.first().is_some_and(|arg| arg == "compare") {
+        if compare::run(&args[1..])? {
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
+    // The runtime's workers depend on the configuration, so it's loaded first.
+    let config = Config::load(&args)?;
+    cpu::runtime(&config.cpus)?.block_on(run(args, config))
+}
+
+/// Runs the proxy with the configuration loaded from the arguments.
+async fn run(args: Vec<String>, mut config: Config) -> Result<(), Box<dyn Error>> {
+    let started = SystemTime::now();
+    output::set_log_target(&config.log_target).map_err(|err| {
+        format!(
+            "Could not connect to the log target {}: {}",
+            config.log_target, err
+        )
+    })?;
+    output::set_timestamps(config.timestamps);
+    format::set_bytes_format(config.format_bytes);
+    pool::set_settings(config.pool);
+    net::set_outbound_bind(config.outbound_bind.clone());
+    net::set_outbound_interface(config.outbound_interface.clone());
+    if config.self_metrics.is_some() {
+        usage::count_allocations();
+    }
+    net::set_socket_options(config.socket_options);
+    if let Some(path) = &config.ban_file {
+        ban::shared().load(path)?;
+    }
+    // With requests taken on stdin, the lines on stdout become events to go along with the
+    // responses.
+    output::set_json_events(config.control == Control::Stdio);
+    match config.ports {
+        Some((first, last)) => print_line!(
+            "⚡️ sockgauge is forwarding {} -> {} on ports {}-{}",
+            config.bind_addrs.join(", "),
+            config.dest_addr,
+            first,
+            last
+        ),
+        None => print_line!(
+            "⚡️ sockgauge is forwarding {} -> {}",
+            config.bind_addrs.join(", "),
+            config.dest_addr
+        ),
+    }
+
+    match config.tunnel {
+        Some(Tunnel::Client) => print_line!(
+            "🚇 tunnel client: connections are multiplexed over one carrier to {}",
+            config.dest_addr
+        ),
+        Some(Tunnel::Server) => {
+            print_line!("🚇 tunnel server: accepting carriers from sockgauge tunnel clients")
+        }
+        None => {}
+    }
+
+    for (host, dest_addr) in &config.hosts {
+        print_line!("🌐 requests for {} are forwarded to {}", host, dest_addr);
+    }
+
+    if config.status_line && !output::enable_status() {
+        eprint_line!("⚠️  the status line needs a terminal, so it's left out");
+    }
+
+    // The baseline is read right away, so a run isn't wasted on one that can't be compared with.
+    let baseline = match &config.baseline {
+        Some(path) => Some(compare::read(path)?),
+        None => None,
+    };
+
+    let ipfix = match &config.ipfix {
+        Some(addr) => Some(ipfix::Exporter::connect(addr).map_err(|err| {
+            format!(
+                "Could not export flows to the IPFIX collector {}: {}",
+                addr, err
+            )
+        })?),
+        None => None,
+    };
+
+    // Make room for the connections up front, rather than growing the tables mid-run.
+    if let Some(expected) = config.expected_connections {
+        proxy::reserve(expected);
+    }
 
     // Create a reporter and spawn a task to run it.
-    let (reporter_handle, reporter_actor) = reporter::create();
-    let reporter_join_handle = tokio::spawn(reporter_actor.run());
+    let (reporter_handle, reporter_actor) = reporter::create(reporter::Options {
+        sample: config.sample,
+        filter: config.report_filter.clone(),
+        alerts: config.alerts.clone(),
+        bans: config.bans.clone(),
+        alert_webhook: config.alert_webhook.clone(),
+        self_metrics: config.self_metrics,
+        summary_interval: config.summary_interval,
+        coalesce_under: config.coalesce_under,
+        max_peers: config.max_peers,
+        expected_connections: config.expected_connections,
+        status_line: config.status_line,
+        history: config.history,
+        redis: config.redis.clone().map(|addr| redis::Target {
+            addr,
+            channel: config.redis_channel.clone(),
+            stream: config.redis_stream.clone(),
+        }),
+        mqtt: config.mqtt.clone().map(|addr| mqtt::Target {
+            addr,
+            topic: config
+                .mqtt_topic
+                .clone()
+                .unwrap_or_else(|| mqtt::DEFAULT_TOPIC.to_string()),
+            qos: config.mqtt_qos,
+        }),
+        kafka: config.kafka.clone().map(|addr| kafka::Target {
+            addr,
+            topic: config.kafka_topic.clone().unwrap_or_default(),
+        }),
+        ipfix,
+    });
+    let reporter_join_handle = usage::spawn(supervise(reporter_actor));
+
+    // The destination can change when the config is reloaded or through the admin API.
+    let (destination_sender, destination) = watch::channel(Destination {
+        forwarding: config.forwarding(),
+        ..Destination::new(config.dest_addr.clone(), config.ports.is_some())
+    });
+    let destination_sender = Arc::new(destination_sender);
+
+    // Serve the admin API if requested.
+    let admin = Admin::new(destination_sender.clone(), reporter_handle.downgrade());
+    let admin_join_handle = match &config.admin_addr {
+        Some(admin_addr) => {
+            let admin_listener = TcpListener::bind(admin_addr).await?;
+            print_line!("🛠️  admin API listening on {}", admin_addr);
+            Some(usage::spawn(admin.clone().run(admin_listener)))
+        }
+        None => None,
+    };
+
+    // Take requests on stdin if asked to, stopping when it closes.
+    let (stop_sender, mut stop) = mpsc::channel(1);
+    if config.control == Control::Stdio {
+        usage::spawn(control::run_stdio(
+            admin.clone(),
+            reporter_handle.downgrade(),
+            stop_sender.clone(),
+        ));
+    }
+
+    // Stop by itself once an exit condition is met, for scripted runs.
+    if config.exit.any() {
+        let conditions = config.exit;
+        let reporter_handle = reporter_handle.downgrade();
+        usage::spawn(async move {
+            let reason = exit::wait(conditions, reporter_handle).await;
+            print_line!("🏁 exiting, as {}", reason);
+            let _ = stop_sender.send(()).await;
+        });
+    }
+
+    // Listen for signals before binding so an early upgrade request isn't lost.
+    // Reloading only makes sense when there is a config file to reload.
+    let mut signals = match config.config_path {
+        Some(_) => signal::listen(&[
+            Signal::Upgrade,
+            Signal::Reload,
+            Signal::Interrupt,
+            Signal::Terminate,
+        ])?,
+        None => signal::listen(&[Signal::Upgrade, Signal::Interrupt, Signal::Terminate])?,
+    };
+    let summary_handle = reporter_handle.downgrade();
+    let groups = match config.per_core_listeners {
+        true => config.cpus.len(),
+        false => 1,
+    };
+    let listeners =
+        proxy::bind(&config.bind_addrs, config.ports, config.bind_v6only, groups).await?;
+    let listeners = Arc::new(listeners);
 
-    // Run the proxy
-    proxy::run(bind_addr, dest_addr, reporter_handle).await?;
+    // Run the proxy until it fails or the listener has been handed over.
+    {
+        let mapping: Arc<str> = config.name.as_deref().unwrap_or(DEFAULT_MAPPING).into();
+        let bind_addrs = config.bind_addrs.clone();
+        let pipes = proxy::run_pipes(
+            &bind_addrs,
+            mapping.clone(),
+            destination.clone(),
+            reporter_handle.clone(),
+        );
+        let groups = proxy::run_groups(
+            listeners.clone(),
+            groups,
+            mapping,
+            destination,
+            reporter_handle,
+        );
+        let proxy = async {
+            tokio::select! {
+                result = groups => result,
+                result = pipes => result,
+            }
+        };
+        tokio::pin!(proxy);
+        loop {
+            tokio::select! {
+                result = &mut proxy => {
+                    result?;
+                    break;
+                }
+                Some(signal) = signals.recv() => match signal {
+                    Signal::Upgrade => match handover::spawn_successor(&listeners) {
+                        Ok(pid) => {
+                            print_line!("🔁 handed the listeners over to process {}, draining connections", pid);
+                            break;
+                        }
+                        Err(err) => eprint_line!("💥️ — handing the listeners over failed: {}", err),
+                    },
+                    Signal::Reload => reload(&args, &mut config, &destination_sender),
+                    Signal::Interrupt | Signal::Terminate => {
+                        stop_with_summary(&summary_handle, &config, started, baseline.as_ref()).await;
+                        return Ok(());
+                    }
+                },
+                Some(()) = stop.recv() => {
+                    stop_with_summary(&summary_handle, &config, started, baseline.as_ref()).await;
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    // Stop accepting; in-flight connections keep running until they close.
+    drop(listeners);
+    admin.remove_mappings();
+    if let Some(admin_join_handle) = admin_join_handle {
+        admin_join_handle.abort();
+    }
 
     // Wait for the reporter task to finish.
-    let _ = tokio::join!(reporter_join_handle);
+    let (mut alerts_fired, mut regressed) = (false, false);
+    if let Ok(finished) = reporter_join_handle.await {
+        alerts_fired = finished.alerts_fired;
+        regressed = finish_report(&config, started, baseline.as_ref(), finished.aggregates);
+    }
+    write_har(&config);
+    exit_if_failed(alerts_fired, regressed);
 
     Ok(())
 }
+
+/// Runs the reporter until it's done, running it again whenever it crashes, with what it counted
+/// so far and its mailbox, so the handles keep reaching it and gauging goes on where it left off.
+async fn supervise(mut reporter_actor: ReporterActor) -> Finished {
+    loop {
+        match reporter_actor.run().await {
+            Stopped::Finished(finished) => return finished,
+            Stopped::Crashed(actor, reason) => {
+                eprint_line!(
+                    "💥️ — the reporter crashed: {}; restarting it with what it counted so far",
+                    reason
+                );
+                reporter_actor = *actor;
+            }
+        }
+    }
+}
+
+/// Prints the summary and stops, as when interrupted, exiting with status 1 if alerts fired or
+/// the run regressed compared with the baseline.
+async fn stop_with_summary(
+    summary_handle: &WeakReporterHandle,
+    config: &Config,
+    started: SystemTime,
+    baseline: Option<&json::Value>,
+) {
+    let (mut alerts_fired, mut regressed) = (false, false);
+    output::remove_status();
+    if let Some(reporter_handle) = summary_handle.upgrade() {
+        if let Some(summary) = reporter_handle.summary().await {
+            print_line!("{}", summary);
+        }
+        alerts_fired = reporter_handle
+            .snapshot()
+            .await
+            .is_some_and(|snapshot| !snapshot.alerts_fired.is_empty());
+        if let Some(aggregates) = reporter_handle.aggregates().await {
+            regressed = finish_report(config, started, baseline, aggregates);
+        }
+    }
+    write_har(config);
+    exit_if_failed(alerts_fired, regressed);
+}
+
+/// Writes the run report if asked to, and compares it with the baseline if there is one,
+/// returning whether it regressed.
+fn finish_report(
+    config: &Config,
+    started: SystemTime,
+    baseline: Option<&json::Value>,
+    aggregates: json::Value,
+) -> bool {
+    if config.report_json.is_none() && baseline.is_none() {
+        return false;
+    }
+
+    let report = report::build(&config.settings, started, aggregates);
+    if let Some(path) = &config.report_json {
+        match report::write(path, &report) {
+            Ok(()) => print_line!("💾 wrote the run report to {}", path),
+            Err(err) => eprint_line!("💥️ — writing {} failed: {}", path, err),
+        }
+    }
+
+    let Some(baseline) = baseline else {
+        return false;
+    };
+    let comparisons = compare::compare(baseline, &report, config.tolerances);
+    compare::print(&comparisons)
+}
+
+/// Writes the HTTP requests and responses seen so far to the HAR file, if there is one.
+fn write_har(config: &Config) {
+    let Some(path) = &config.har else {
+        return;
+    };
+
+    match har::write(path) {
+        Ok((count, 0)) => print_line!("💾 wrote {} HTTP request(s) to {}", count, path),
+        Ok((count, dropped)) => print_line!(
+            "💾 wrote {} HTTP request(s) to {}, leaving out the last {} as there were too many",
+            count,
+            path,
+            dropped
+        ),
+        Err(err) => eprint_line!("💥️ — writing {} failed: {}", path, err),
+    }
+}
+
+/// Exits with a failure status if any alerts fired or the run regressed compared with the
+/// baseline, e.g. to fail a load test in CI.
+fn exit_if_failed(alerts_fired: bool, regressed: bool) {
+    if alerts_fired {
+        eprint_line!("🚨 exiting with status 1, as alerts fired");
+        std::process::exit(1);
+    }
+
+    if regressed {
+        eprint_line!("📉 exiting with status 1, as the run regressed compared with the baseline");
+        std::process::exit(1);
+    }
+}
+
+/// Reloads the configuration and applies whatever changed to new connections.
+fn reload(args: &[String], config: &mut Config, destination: &watch::Sender<Destination>) {
+    let mut new_config = match Config::load(args) {
+        Ok(new_config) => new_config,
+        Err(err) => {
+            eprint_line!("💥️ — reloading the configuration failed: {}", err);
+            return;
+        }
+    };
+
+    let changes = config.changes(&new_config);
+    if changes.is_empty() {
+        print_line!("🔄 configuration reloaded, nothing changed");
+        return;
+    }
+
+    for change in &changes {
+        print_line!("🔄 configuration reloaded — {}", change);
+    }
+
+    // The listeners are already bound (and are inherited across upgrades), so they stay put.
+    if new_config.bind_addrs != config.bind_addrs
+        || new_config.ports != config.ports
+        || new_config.bind_v6only != config.bind_v6only
+    {
+        print_line!("⚠️  changing the bind addresses or ports requires a full restart");
+        new_config.bind_addrs = config.bind_addrs.clone();
+        new_config.ports = config.ports;
+        new_config.bind_v6only = config.bind_v6only;
+    }
+
+    // The workers are pinned when the runtime starts.
+    if new_config.cpus != config.cpus || new_config.per_core_listeners != config.per_core_listeners
+    {
+        print_line!("⚠️  changing the CPUs or per-core listeners requires a full restart");
+        new_config.cpus = config.cpus.clone();
+        new_config.per_core_listeners = config.per_core_listeners;
+    }
+
+    // The connections of the listeners are reported under the name they started with.
+    if new_config.name != config.name {
+        print_line!("⚠️  changing the name requires a full restart");
+        new_config.name = config.name.clone();
+    }
+
+    // Likewise, the admin API keeps running where it was started.
+    if new_config.admin_addr != config.admin_addr {
+        print_line!("⚠️  changing the admin address requires a full restart");
+        new_config.admin_addr = config.admin_addr.clone();
+    }
+
+    if new_config.control != config.control {
+        print_line!("⚠️  changing the control mode requires a full restart");
+        new_config.control = config.control;
+    }
+
+    if new_config.baseline != config.baseline {
+        print_line!("⚠️  changing the baseline requires a full restart");
+        new_config.baseline = config.baseline.clone();
+    }
+
+    if new_config.exit != config.exit {
+        print_line!("⚠️  changing the exit conditions requires a full restart");
+        new_config.exit = config.exit;
+    }
+
+    // The bans are kept in the file they were loaded from.
+    if new_config.ban_file != config.ban_file {
+        print_line!("⚠️  changing the ban file requires a full restart");
+        new_config.ban_file = config.ban_file.clone();
+    }
+
+    // And the reporter keeps sampling, filtering, alerting and measuring the way it started.
+    if new_config.sample != config.sample
+        || new_config.report_filter != config.report_filter
+        || new_config.alerts != config.alerts
+        || new_config.bans != config.bans
+        || new_config.alert_webhook != config.alert_webhook
+        || new_config.self_metrics != config.self_metrics
+        || new_config.summary_interval != config.summary_interval
+        || new_config.coalesce_under != config.coalesce_under
+        || new_config.max_peers != config.max_peers
+        || new_config.expected_connections != config.expected_connections
+        || new_config.status_line != config.status_line
+        || new_config.history != config.history
+        || new_config.redis != config.redis
+        || new_config.redis_channel != config.redis_channel
+        || new_config.redis_stream != config.redis_stream
+        || new_config.mqtt != config.mqtt
+        || new_config.mqtt_topic != config.mqtt_topic
+        || new_config.mqtt_qos != config.mqtt_qos
+        || new_config.kafka != config.kafka
+        || new_config.kafka_topic != config.kafka_topic
+        || new_config.ipfix != config.ipfix
+    {
+        print_line!(
+            "⚠️  changing sample, report-filter, alert, alert-webhook, ban, self-metrics, summary-interval, coalesce-under, max-peers, expected-connections, status-line, history, redis, mqtt, kafka or ipfix requires a full restart"
+        );
+        new_config.sample = config.sample;
+        new_config.report_filter = config.report_filter.clone();
+        new_config.alerts = config.alerts.clone();
+        new_config.bans = config.bans.clone();
+        new_config.alert_webhook = config.alert_webhook.clone();
+        new_config.self_metrics = config.self_metrics;
+        new_config.summary_interval = config.summary_interval;
+        new_config.coalesce_under = config.coalesce_under;
+        new_config.max_peers = config.max_peers;
+        new_config.expected_connections = config.expected_connections;
+        new_config.status_line = config.status_line;
+        new_config.history = config.history;
+        new_config.redis = config.redis.clone();
+        new_config.redis_channel = config.redis_channel.clone();
+        new_config.redis_stream = config.redis_stream.clone();
+        new_config.mqtt = config.mqtt.clone();
+        new_config.mqtt_topic = config.mqtt_topic.clone();
+        new_config.mqtt_qos = config.mqtt_qos;
+        new_config.kafka = config.kafka.clone();
+        new_config.kafka_topic = config.kafka_topic.clone();
+        new_config.ipfix = config.ipfix.clone();
+    }
+
+    if new_config.log_target != config.log_target {
+        if let Err(err) = output::set_log_target(&new_config.log_target) {
+            eprint_line!(
+                "💥️ — switching the log target to {} failed: {}",
+                new_config.log_target,
+                err
+            );
+            new_config.log_target = config.log_target.clone();
+        }
+    }
+
+    // Lines are formatted as they're printed, so the new formats apply right away.
+    output::set_timestamps(new_config.timestamps);
+    format::set_bytes_format(new_config.format_bytes);
+
+    // Likewise, the pools' health checks pick up the new settings on their next round.
+    pool::set_settings(new_config.pool);
+
+    // And new connections to destinations are made from the new outbound bind address and
+    // interface, and new sockets get the new options.
+    net::set_outbound_bind(new_config.outbound_bind.clone());
+    net::set_outbound_interface(new_config.outbound_interface.clone());
+    net::set_socket_options(new_config.socket_options);
+
+    // Only what changed in the configuration is applied, and a destination set through the admin
+    // API takes precedence over the configured one.
+    if new_config.dest_addr != config.dest_addr {
+        let overridden = destination.borrow().overridden;
+        match overridden {
+            true => print_line!(
+                "⚠️  keeping the destination {} set via the admin API rather than {}, until a \
+                 full restart",
+                destination.borrow().addr,
+                new_config.dest_addr
+            ),
+            false => destination
+                .send_modify(|destination| destination.addr = new_config.dest_addr.clone()),
+        }
+    }
+    let forwarding = new_config.forwarding();
+    if forwarding != config.forwarding() {
+        destination.send_modify(|destination| destination.forwarding = forwarding);
+    }
+
+    *config = new_config;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn reload_applies_what_changed() {
+        let args = |extra: &[&str]| {
+            let mut args = vec!["127.0.0.1:0".to_string()];
+            args.extend(extra.iter().map(|arg| arg.to_string()));
+            args
+        };
+        let mut config = Config::load(&args(&["a:80"])).unwrap();
+        let (destination, receiver) = watch::channel(Destination {
+            forwarding: config.forwarding(),
+            ..Destination::new(config.dest_addr.clone(), false)
+        });
+
+        // The configured destination applies once it changes, and so does the rest.
+        reload(
+            &args(&["b:80", "--first-byte-timeout", "1s"]),
+            &mut config,
+            &destination,
+        );
+        assert_eq!(receiver.borrow().addr, "b:80");
+        assert_eq!(
+            receiver.borrow().forwarding.first_byte_timeout,
+            Some(Duration::from_secs(1))
+        );
+
+        // A destination set through the admin API stays, whatever changes.
+        destination.send_modify(|destination| {
+            destination.addr = "c:80".to_string();
+            destination.overridden = true;
+        });
+        reload(&args(&["b:80"]), &mut config, &destination);
+        reload(&args(&["d:80"]), &mut config, &destination);
+        assert_eq!(receiver.borrow().addr, "c:80");
+        assert_eq!(receiver.borrow().forwarding.first_byte_timeout, None);
+    }
+}
@@ -0,0 +1,530 @@
+use crate::format;
+use crate::protocol::{Parser, Protocol, Stats};
+use crate::proxy::AbortOnDrop;
+use crate::publisher::{self, Publisher};
+use crate::usage;
+use std::collections::HashSet;
+use std::future::Future;
+use std::io;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::sync::mpsc;
+use tokio::time::Instant;
+
+/// How long the broker waits for a packet before it drops the connection.
+const KEEP_ALIVE: Duration = Duration::from_secs(60);
+
+/// The topic events are published under, unless configured otherwise.
+pub const DEFAULT_TOPIC: &str = "sockgauge";
+
+/// The packet types used, shifted into the first byte of a packet.
+const CONNECT: u8 = 0x10;
+const CONNACK: u8 = 0x20;
+const PUBLISH: u8 = 0x30;
+const PUBREC: u8 = 0x50;
+const PUBREL: u8 = 0x62;
+const PINGREQ: u8 = 0xc0;
+
+/// How many bytes of a packet's body are kept, enough for a CONNECT or a topic name.
+const MAX_KEPT: usize = 1024;
+
+/// How many distinct topics of a connection are told apart; more are counted as one.
+const MAX_TOPICS: usize = 1000;
+
+/// How far past its keep alive a client may go quiet before the broker drops it.
+const KEEP_ALIVE_GRACE: f64 = 1.5;
+
+/// Where and how to publish events over MQTT.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Target {
+    /// The address of the broker.
+    pub addr: String,
+
+    /// The topic events are published under, each in a subtopic of its type, e.g.
+    /// `sockgauge/opened`.
+    pub topic: String,
+
+    /// The quality of service to publish with: at most (0), at least (1) or exactly once (2).
+    pub qos: u8,
+}
+
+/// Parses a quality of service, `0`, `1` or `2`.
+pub fn parse_qos(value: &str) -> Result<u8, String> {
+    match value.parse() {
+        Ok(qos) if qos <= 2 => Ok(qos),
+        _ => Err(format!(
+            "Expected 0, 1 or 2 as the MQTT quality of service, got `{}`",
+            value
+        )),
+    }
+}
+
+/// Publishes events to an MQTT broker in the background.
+#[derive(Debug)]
+pub struct Mqtt {
+    /// Used for sending events, with their types, to the background task.
+    sender: mpsc::Sender<(&'static str, String)>,
+}
+
+impl Mqtt {
+    /// Starts publishing to a target.
+    pub fn start(target: Target) -> Self {
+        Self {
+            sender: publisher::start(target),
+        }
+    }
+
+    /// Publishes an event of a type, given as JSON. Events are dropped while the broker can't
+    /// keep up.
+    pub fn publish(&self, event: &'static str, json: String) {
+        let _ = self.sender.try_send((event, json));
+    }
+}
+
+impl Publisher for Target {
+    type Event = (&'static str, String);
+    type Pending = Option<(&'static str, String)>;
+
+    fn describe(&self) -> String {
+        format!("publishing events to MQTT at {}", self.addr)
+    }
+
+    fn send<'a>(
+        &'a self,
+        receiver: &'a mut mpsc::Receiver<(&'static str, String)>,
+        pending: &'a mut Option<(&'static str, String)>,
+    ) -> impl Future<Output = io::Result<()>> + Send + 'a {
+        send(self, receiver, pending)
+    }
+}
+
+/// Connects and sends the events until there are no more (returning `Ok`) or the connection
+/// fails. An event that couldn't be sent is left in `pending`, to send after reconnecting.
+async fn send(
+    target: &Target,
+    receiver: &mut mpsc::Receiver<(&'static str, String)>,
+    pending: &mut Option<(&'static str, String)>,
+) -> io::Result<()> {
+    let mut stream = TcpStream::connect(&target.addr).await?;
+    stream.write_all(&connect()).await?;
+    match read_packet(&mut stream).await? {
+        (CONNACK, body) if body.get(1) == Some(&0) => {}
+        (CONNACK, body) => {
+            return Err(io::Error::other(format!(
+                "the broker refused the connection with code {}",
+                body.get(1).copied().unwrap_or_default()
+            )))
+        }
+        (kind, _) => {
+            return Err(io::Error::other(format!(
+                "expected a CONNACK, got packet type {}",
+                kind >> 4
+            )))
+        }
+    }
+
+    // Packets are read in a task of their own, as a read can't be interrupted midway.
+    let (mut reader, mut writer) = stream.into_split();
+    let (releases, mut to_release) = mpsc::unbounded_channel();
+    let _reading = AbortOnDrop(usage::spawn(async move {
+        loop {
+            match read_packet(&mut reader).await {
+                // Acknowledgements need no answer, except that exactly-once delivery is
+                // released once the broker has received the message.
+                Ok((PUBREC, body)) => {
+                    let _ = releases.send(Ok([body[0], body[1]]));
+                }
+                Ok(_) => {}
+                Err(err) => {
+                    let _ = releases.send(Err(err));
+                    return Ok(());
+                }
+            }
+        }
+    }));
+
+    let mut ping = tokio::time::interval_at(Instant::now() + KEEP_ALIVE / 2, KEEP_ALIVE / 2);
+    let mut packet_id: u16 = 0;
+    loop {
+        let (event, json) = match pending.take() {
+            Some(event) => event,
+            None => tokio::select! {
+                event = receiver.recv() => match event {
+                    Some(event) => event,
+                    None => return Ok(()),
+                },
+                release = to_release.recv() => {
+                    let [high, low] = release.unwrap_or(Err(io::ErrorKind::UnexpectedEof.into()))?;
+                    writer.write_all(&[PUBREL, 2, high, low]).await?;
+                    continue;
+                }
+                _ = ping.tick() => {
+                    writer.write_all(&[PINGREQ, 0]).await?;
+                    continue;
+                }
+            },
+        };
+
+        packet_id = packet_id.checked_add(1).unwrap_or(1);
+        let topic = format!("{}/{}", target.topic, event);
+        let packet = publish(&topic, target.qos, packet_id, json.as_bytes());
+        if let Err(err) = writer.write_all(&packet).await {
+            *pending = Some((event, json));
+            return Err(err);
+        }
+    }
+}
+
+/// Encodes a CONNECT packet, for a clean session.
+fn connect() -> Vec<u8> {
+    let mut body = Vec::new();
+    push_string(&mut body, "MQTT");
+    body.push(4); // MQTT 3.1.1
+    body.push(0x02); // A clean session
+    body.extend_from_slice(&(KEEP_ALIVE.as_secs() as u16).to_be_bytes());
+    push_string(&mut body, &format!("sockgauge-{}", std::process::id()));
+    packet(CONNECT, &body)
+}
+
+/// Encodes a PUBLISH packet. The packet ID is only sent when the quality of service needs it.
+fn publish(topic: &str, qos: u8, packet_id: u16, payload: &[u8]) -> Vec<u8> {
+    let mut body = Vec::new();
+    push_string(&mut body, topic);
+    if qos > 0 {
+        body.extend_from_slice(&packet_id.to_be_bytes());
+    }
+    body.extend_from_slice(payload);
+    packet(PUBLISH | qos << 1, &body)
+}
+
+/// Encodes a packet: its type and flags, the length of the body and the body.
+fn packet(kind: u8, body: &[u8]) -> Vec<u8> {
+    let mut packet = vec![kind];
+    let mut length = body.len();
+    loop {
+        let byte = (length % 128) as u8;
+        length /= 128;
+        match length {
+            0 => {
+                packet.push(byte);
+                break;
+            }
+            _ => packet.push(byte | 0x80),
+        }
+    }
+    packet.extend_from_slice(body);
+    packet
+}
+
+/// Appends a string, prefixed with its length.
+fn push_string(body: &mut Vec<u8>, value: &str) {
+    body.extend_from_slice(&(value.len() as u16).to_be_bytes());
+    body.extend_from_slice(value.as_bytes());
+}
+
+/// Reads a packet, returning its type (with the flags masked out) and body.
+async fn read_packet<R: AsyncRead + Unpin>(reader: &mut R) -> io::Result<(u8, Vec<u8>)> {
+    let kind = reader.read_u8().await?;
+    let mut length = 0;
+    for shift in (0..28).step_by(7) {
+        let byte = reader.read_u8().await?;
+        length |= ((byte & 0x7f) as usize) << shift;
+        if byte & 0x80 == 0 {
+            break;
+        }
+    }
+
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body).await?;
+    if kind & 0xf0 == PUBREC & 0xf0 && body.len() < 2 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "a PUBREC without a packet ID",
+        ));
+    }
+    Ok((kind & 0xf0, body))
+}
+
+/// Counts what an MQTT client does: its publishes, the topics it publishes to and its pings,
+/// and how often it went quiet for longer than its keep alive allows.
+#[derive(Debug, Default)]
+pub struct Session {
+    /// The type and flags of the current packet, and the bytes of its length so far.
+    header: Vec<u8>,
+
+    /// How many body bytes of the current packet are still to come, once its header is in.
+    remaining: Option<usize>,
+
+    /// The kept body of the current packet.
+    body: Vec<u8>,
+
+    /// When the client last sent a packet, once it connected.
+    last_packet: Option<Instant>,
+
+    /// The keep alive the client connected with, if any.
+    keep_alive: Option<Duration>,
+
+    /// The client's ID and protocol version, from its CONNECT.
+    client_id: Option<Vec<u8>>,
+    version: Option<&'static str>,
+
+    /// How many PUBLISH packets the client sent, and to which topics.
+    publishes: u64,
+    topics: HashSet<Vec<u8>>,
+
+    /// How many PINGREQ packets the client sent.
+    pings: u64,
+
+    /// How often the client went quiet for longer than its keep alive allows.
+    keep_alive_misses: u64,
+}
+
+impl Session {
+    /// Handles the packet that's complete, getting ready for the next one.
+    fn finish(&mut self, kind: u8) {
+        let body = std::mem::take(&mut self.body);
+        self.client_packet(kind, &body);
+        self.header.clear();
+        self.remaining = None;
+    }
+
+    /// Handles a packet the client sent.
+    fn client_packet(&mut self, kind: u8, body: &[u8]) {
+        let now = Instant::now();
+        self.check_keep_alive(now);
+        self.last_packet = Some(now);
+
+        match kind & 0xf0 {
+            CONNECT => self.connect(body),
+            PUBLISH => {
+                self.publishes += 1;
+                if let Some((topic, _)) = string(body) {
+                    if self.topics.len() < MAX_TOPICS || self.topics.contains(topic) {
+                        self.topics.insert(topic.to_vec());
+                    }
+                }
+            }
+            PINGREQ => self.pings += 1,
+            _ => {}
+        }
+    }
+
+    /// Reads a CONNECT: the protocol name and level, the connect flags, the keep alive,
+    /// properties (as of MQTT 5), then the client ID.
+    fn connect(&mut self, body: &[u8]) {
+        let Some((_name, used)) = string(body) else {
+            return;
+        };
+        let rest = &body[used..];
+        let Some(&[level, _flags, keep_alive_high, keep_alive_low]) = rest.get(..4) else {
+            return;
+        };
+        self.version = match level {
+            3 => Some("3.1"),
+            4 => Some("3.1.1"),
+            5 => Some("5.0"),
+            _ => None,
+        };
+        let keep_alive = u16::from_be_bytes([keep_alive_high, keep_alive_low]);
+        self.keep_alive = (keep_alive > 0).then(|| Duration::from_secs(keep_alive as u64));
+
+        let mut rest = &rest[4..];
+        if level == 5 {
+            let Some((properties, len)) = variable_length(rest) else {
+                return;
+            };
+            rest = rest.get(len + properties..).unwrap_or_default();
+        }
+        self.client_id = string(rest).map(|(client_id, _)| client_id.to_vec());
+    }
+
+    /// Counts a miss if the client was quiet for longer than its keep alive allows until `now`.
+    fn check_keep_alive(&mut self, now: Instant) {
+        if let (Some(last), Some(keep_alive)) = (self.last_packet, self.keep_alive) {
+            if now - last > keep_alive.mul_f64(KEEP_ALIVE_GRACE) {
+                self.keep_alive_misses += 1;
+            }
+        }
+    }
+}
+
+impl Parser for Session {
+    fn client(&mut self, mut bytes: &[u8]) {
+        while !bytes.is_empty() {
+            let Some(remaining) = self.remaining else {
+                // The type and flags, then the length in up to 4 bytes of 7 bits.
+                self.header.push(bytes[0]);
+                bytes = &bytes[1..];
+                if self.header.len() > 1 {
+                    if let Some((length, _)) = variable_length(&self.header[1..]) {
+                        self.remaining = Some(length);
+                    } else if self.header.len() == 5 {
+                        self.header.clear();
+                    }
+                }
+                if self.remaining != Some(0) {
+                    continue;
+                }
+                let kind = self.header[0];
+                self.finish(kind);
+                continue;
+            };
+
+            let take = remaining.min(bytes.len());
+            let room = MAX_KEPT.saturating_sub(self.body.len());
+            self.body.extend_from_slice(&bytes[..take.min(room)]);
+            bytes = &bytes[take..];
+            self.remaining = Some(remaining - take);
+            if remaining == take {
+                let kind = self.header[0];
+                self.finish(kind);
+            }
+        }
+    }
+
+    fn server(&mut self, _bytes: &[u8]) {}
+
+    fn stats(&self) -> Stats {
+        // A client that went quiet for too long before the connection closed missed it too.
+        let mut keep_alive_misses = self.keep_alive_misses;
+        if let (Some(last), Some(keep_alive)) = (self.last_packet, self.keep_alive) {
+            if last.elapsed() > keep_alive.mul_f64(KEEP_ALIVE_GRACE) {
+                keep_alive_misses += 1;
+            }
+        }
+
+        let mut details = Vec::new();
+        if let Some(client_id) = &self.client_id {
+            details.push(("client ID", format::sanitize(client_id)));
+        }
+        if let Some(version) = self.version {
+            details.push(("MQTT", version.to_string()));
+        }
+        if let Some(keep_alive) = self.keep_alive {
+            details.push(("keep alive", format!("{}s", keep_alive.as_secs())));
+        }
+
+        Stats {
+            protocol: Protocol::Mqtt,
+            counts: vec![
+                ("publishes", self.publishes),
+                ("topics", self.topics.len() as u64),
+                ("pings", self.pings),
+                ("keep alive misses", keep_alive_misses),
+            ],
+            timings: Vec::new(),
+            details,
+        }
+    }
+}
+
+/// Reads a string prefixed with its length from the front of `bytes`, returning its raw bytes
+/// (which may not be UTF-8, clients being what they are) and how many bytes it took with its
+/// length.
+fn string(bytes: &[u8]) -> Option<(&[u8], usize)> {
+    let len = u16::from_be_bytes([*bytes.first()?, *bytes.get(1)?]) as usize;
+    let value = bytes.get(2..2 + len)?;
+    Some((value, 2 + len))
+}
+
+/// Reads a variable length integer (7 bits per byte, the high bit set on all but the last) from
+/// the front of `bytes`, returning it and how many bytes it took.
+fn variable_length(bytes: &[u8]) -> Option<(usize, usize)> {
+    let mut value = 0;
+    for (i, &byte) in bytes.iter().take(4).enumerate() {
+        value |= ((byte & 0x7f) as usize) << (7 * i);
+        if byte & 0x80 == 0 {
+            return Some((value, i + 1));
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodes_packets() {
+        assert_eq!(
+            publish("sockgauge/opened", 0, 1, b"{}"),
+            b"\x30\x14\x00\x10sockgauge/opened{}"
+        );
+        assert_eq!(publish("a", 1, 258, b"x"), b"\x32\x06\x00\x01a\x01\x02x");
+        assert_eq!(&packet(PUBLISH, &[0; 200])[..3], [0x30, 0xc8, 0x01]);
+        assert_eq!(parse_qos("2"), Ok(2));
+        assert!(parse_qos("3").is_err());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn gauges_sessions() {
+        let mut session = Session::default();
+
+        // MQTT 3.1.1 with a keep alive of 60s, split across reads, then two publishes.
+        let connect = connect();
+        session.client(&connect[..5]);
+        session.client(&connect[5..]);
+        let mut publishes = publish("sensors/1", 0, 1, b"21.5");
+        publishes.extend(publish("sensors/2", 1, 2, &[0; 300]));
+        session.client(&publishes);
+
+        // The client pings in time once, then goes quiet for too long.
+        tokio::time::sleep(Duration::from_secs(60)).await;
+        session.client(&[PINGREQ, 0]);
+        tokio::time::sleep(Duration::from_secs(100)).await;
+        session.client(&publish("sensors/1", 0, 3, b"21.7"));
+
+        assert_eq!(
+            session.stats().to_string(),
+            format!(
+                "3 publishes, 2 topics, 1 pings, 1 keep alive misses (client ID sockgauge-{}, MQTT 3.1.1, keep alive 60s)",
+                std::process::id()
+            )
+        );
+    }
+
+    #[test]
+    fn gives_up_on_hostile_connects() {
+        let stats = |body: &[u8]| {
+            let mut session = Session::default();
+            session.client(&packet(CONNECT, body));
+            session.client(&[PINGREQ, 0]);
+            session.stats().to_string()
+        };
+
+        // Truncated, in the protocol name and right after it.
+        assert_eq!(
+            stats(b"\x00\x04MQ"),
+            "0 publishes, 0 topics, 1 pings, 0 keep alive misses"
+        );
+        assert_eq!(
+            stats(b"\x00\x04MQTT\x04"),
+            "0 publishes, 0 topics, 1 pings, 0 keep alive misses"
+        );
+
+        // A protocol name that isn't UTF-8, which is longer once decoded, and a client ID with
+        // control characters.
+        assert_eq!(
+            stats(b"\x00\x04\xff\xfe\xfd\xfc\x04\x02\x00\x3c\x00\x03a\x1bb"),
+            "0 publishes, 0 topics, 1 pings, 0 keep alive misses (client ID a\\x1bb, MQTT 3.1.1, \
+             keep alive 60s)"
+        );
+
+        // Lengths larger than the packet.
+        assert_eq!(
+            stats(b"\xff\xffMQTT"),
+            "0 publishes, 0 topics, 1 pings, 0 keep alive misses"
+        );
+        assert_eq!(
+            stats(b"\x00\x04MQTT\x04\x02\x00\x00\x01\x00abc"),
+            "0 publishes, 0 topics, 1 pings, 0 keep alive misses (MQTT 3.1.1)"
+        );
+        let mut session = Session::default();
+        session.client(&packet(PUBLISH, b"\x01\x00topic"));
+        assert_eq!(
+            session.stats().to_string(),
+            "1 publishes, 0 topics, 0 pings, 0 keep alive misses"
+        );
+    }
+}
@@ -0,0 +1,431 @@
+use crate::net;
+use crate::output::{eprint_line, print_line};
+use crate::usage;
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::io;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use tokio::io::{
+    AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufWriter, DuplexStream, ReadHalf,
+    WriteHalf,
+};
+use tokio::net::TcpStream;
+use tokio::sync::{mpsc, Semaphore};
+
+/// Which end of a tunnel sockgauge is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Tunnel {
+    /// Accepts plain connections and multiplexes them over one carrier connection to the
+    /// destination, which is another sockgauge in server mode.
+    Client,
+
+    /// Accepts carrier connections and forwards each stream on them to the destination.
+    Server,
+}
+
+impl Tunnel {
+    /// Parses `client` or `server`.
+    pub fn parse(value: &str) -> Result<Tunnel, String> {
+        match value {
+            "client" => Ok(Tunnel::Client),
+            "server" => Ok(Tunnel::Server),
+            _ => Err(format!(
+                "Expected `client` or `server` for `tunnel`, got `{}`",
+                value
+            )),
+        }
+    }
+}
+
+impl Display for Tunnel {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Tunnel::Client => write!(f, "client"),
+            Tunnel::Server => write!(f, "server"),
+        }
+    }
+}
+
+/// Sent by the client when the carrier connects, so a misconfigured server fails clearly.
+const PREFACE: &[u8; 8] = b"SGMUX/1\n";
+
+/// Frame types. Every frame is a type byte, a stream ID and a payload length (both big-endian
+/// u32s) followed by the payload.
+const FRAME_OPEN: u8 = 0;
+const FRAME_DATA: u8 = 1;
+const FRAME_CLOSE: u8 = 2;
+const FRAME_RESET: u8 = 3;
+const FRAME_WINDOW_UPDATE: u8 = 4;
+
+/// The largest payload of a data frame.
+const MAX_PAYLOAD: usize = 16 * 1024;
+
+/// How many bytes a stream may send before the other end has to grant more, so that one slow
+/// stream can't hold up the others on the carrier.
+const INITIAL_WINDOW: usize = 256 * 1024;
+
+/// The carriers of the tunnel client, by destination.
+static CARRIERS: OnceLock<tokio::sync::Mutex<HashMap<String, Mux>>> = OnceLock::new();
+
+/// A frame on the carrier.
+struct Frame {
+    /// One of the `FRAME_` types.
+    kind: u8,
+
+    /// The stream the frame belongs to.
+    stream: u32,
+
+    /// The data, or the increment of a window update.
+    payload: Vec<u8>,
+}
+
+/// What the other end sent on a stream.
+enum Incoming {
+    /// Data to pass on.
+    Data(Vec<u8>),
+
+    /// The other end is done sending.
+    Close,
+}
+
+/// A multiplexer running over a carrier connection.
+#[derive(Clone)]
+pub struct Mux {
+    /// State shared with the tasks driving the carrier and the streams.
+    shared: Arc<Shared>,
+}
+
+/// State of a multiplexer.
+struct Shared {
+    /// Frames to write to the carrier.
+    frames: mpsc::UnboundedSender<Frame>,
+
+    /// The open streams, by ID.
+    streams: Mutex<HashMap<u32, Stream>>,
+
+    /// The ID of the next stream opened from this end.
+    next_id: AtomicU32,
+
+    /// Set once the carrier closed.
+    closed: AtomicBool,
+}
+
+/// The multiplexer's side of an open stream.
+struct Stream {
+    /// Passes what the other end sent to the stream's task.
+    incoming: mpsc::UnboundedSender<Incoming>,
+
+    /// How many more bytes the stream may send.
+    credit: Arc<Semaphore>,
+}
+
+/// Opens a stream to a tunnel server, connecting a carrier first if there isn't one yet.
+pub async fn open_stream(dest_addr: &str) -> io::Result<DuplexStream> {
+    let mut carriers = CARRIERS
+        .get_or_init(tokio::sync::Mutex::default)
+        .lock()
+        .await;
+
+    if let Some(mux) = carriers.get(dest_addr) {
+        if !mux.is_closed() {
+            return mux.open();
+        }
+    }
+
+    let mut carrier = net::connect(dest_addr).await?;
+    carrier.set_nodelay(true)?;
+    carrier.write_all(PREFACE).await?;
+    print_line!("🚇 tunnel carrier to {} connected", dest_addr);
+
+    let label = format!("to {}", dest_addr);
+    let (mux, _) = Mux::start(carrier, label);
+    carriers.insert(dest_addr.to_string(), mux.clone());
+    mux.open()
+}
+
+/// Serves the streams of a carrier accepted by the tunnel server, returning them as they are
+/// opened by the client.
+pub async fn accept_carrier(
+    mut carrier: TcpStream,
+    label: String,
+) -> io::Result<mpsc::UnboundedReceiver<(u32, DuplexStream)>> {
+    let mut preface = [0; PREFACE.len()];
+    carrier.read_exact(&mut preface).await?;
+    if &preface != PREFACE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "not a sockgauge tunnel client",
+        ));
+    }
+
+    carrier.set_nodelay(true)?;
+    print_line!("🚇 tunnel carrier {} connected", label);
+    let (_, accepted) = Mux::start(carrier, label);
+    Ok(accepted)
+}
+
+impl Mux {
+    /// Starts driving the carrier. Streams opened by the other end are passed to the returned
+    /// receiver.
+    fn start(
+        carrier: impl AsyncRead + AsyncWrite + Send + 'static,
+        label: String,
+    ) -> (Mux, mpsc::UnboundedReceiver<(u32, DuplexStream)>) {
+        let (frames, frames_receiver) = mpsc::unbounded_channel();
+        let (accepted, accepted_receiver) = mpsc::unbounded_channel();
+        let shared = Arc::new(Shared {
+            frames,
+            streams: Mutex::default(),
+            next_id: AtomicU32::new(1),
+            closed: AtomicBool::new(false),
+        });
+
+        let (reader, writer) = tokio::io::split(carrier);
+        usage::spawn(write_frames(frames_receiver, writer));
+        usage::spawn({
+            let shared = shared.clone();
+            async move {
+                if let Err(err) = read_frames(&shared, reader, accepted).await {
+                    eprint_line!("💥️ — tunnel carrier {} failed: {}", label, err);
+                }
+
+                // Reset the streams that were still open.
+                shared.closed.store(true, Ordering::SeqCst);
+                for (_, stream) in shared.streams.lock().unwrap().drain() {
+                    stream.credit.close();
+                }
+                print_line!("🚇 tunnel carrier {} closed", label);
+            }
+        });
+
+        (Mux { shared }, accepted_receiver)
+    }
+
+    /// Opens a new stream.
+    fn open(&self) -> io::Result<DuplexStream> {
+        let id = self.shared.next_id.fetch_add(2, Ordering::SeqCst);
+        let stream = spawn_stream(&self.shared, id);
+        self.shared.send(FRAME_OPEN, id, Vec::new())?;
+        Ok(stream)
+    }
+
+    /// Whether the carrier has closed.
+    fn is_closed(&self) -> bool {
+        self.shared.closed.load(Ordering::SeqCst)
+    }
+}
+
+impl Shared {
+    /// Queues a frame for the carrier.
+    fn send(&self, kind: u8, stream: u32, payload: Vec<u8>) -> io::Result<()> {
+        let frame = Frame {
+            kind,
+            stream,
+            payload,
+        };
+        self.frames
+            .send(frame)
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "the tunnel carrier closed"))
+    }
+}
+
+/// Writes queued frames to the carrier, flushing whenever the queue runs empty.
+async fn write_frames(
+    mut frames: mpsc::UnboundedReceiver<Frame>,
+    writer: WriteHalf<impl AsyncWrite>,
+) -> io::Result<()> {
+    let mut writer = BufWriter::new(writer);
+    while let Some(mut frame) = frames.recv().await {
+        loop {
+            writer.write_u8(frame.kind).await?;
+            writer.write_u32(frame.stream).await?;
+            writer.write_u32(frame.payload.len() as u32).await?;
+            writer.write_all(&frame.payload).await?;
+
+            frame = match frames.try_recv() {
+                Ok(frame) => frame,
+                Err(_) => break,
+            };
+        }
+
+        writer.flush().await?;
+    }
+
+    Ok(())
+}
+
+/// Reads frames from the carrier and dispatches them to the streams until the carrier closes.
+async fn read_frames(
+    shared: &Arc<Shared>,
+    reader: ReadHalf<impl AsyncRead>,
+    accepted: mpsc::UnboundedSender<(u32, DuplexStream)>,
+) -> io::Result<()> {
+    let mut reader = tokio::io::BufReader::new(reader);
+    loop {
+        let kind = match reader.read_u8().await {
+            Ok(kind) => kind,
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+            Err(err) => return Err(err),
+        };
+        let id = reader.read_u32().await?;
+        let len = reader.read_u32().await? as usize;
+        if len > MAX_PAYLOAD {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("frame of {} bytes is too large", len),
+            ));
+        }
+
+        let mut payload = vec![0; len];
+        reader.read_exact(&mut payload).await?;
+
+        let mut streams = shared.streams.lock().unwrap();
+        match kind {
+            FRAME_OPEN => {
+                drop(streams);
+                let _ = accepted.send((id, spawn_stream(shared, id)));
+            }
+            FRAME_DATA | FRAME_CLOSE => {
+                if let Some(stream) = streams.get(&id) {
+                    let incoming = match kind {
+                        FRAME_DATA => Incoming::Data(payload),
+                        _ => Incoming::Close,
+                    };
+                    let _ = stream.incoming.send(incoming);
+                }
+            }
+            FRAME_RESET => {
+                if let Some(stream) = streams.remove(&id) {
+                    stream.credit.close();
+                }
+            }
+            FRAME_WINDOW_UPDATE if payload.len() == 4 => {
+                if let Some(stream) = streams.get(&id) {
+                    let increment =
+                        u32::from_be_bytes([payload[0], payload[1], payload[2], payload[3]]);
+                    stream.credit.add_permits(increment as usize);
+                }
+            }
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("unexpected frame type {}", kind),
+                ))
+            }
+        }
+    }
+}
+
+/// Registers a stream and spawns the task moving its data, returning the end to proxy with.
+fn spawn_stream(shared: &Arc<Shared>, id: u32) -> DuplexStream {
+    let (stream, mux_end) = tokio::io::duplex(MAX_PAYLOAD * 4);
+    let (incoming, incoming_receiver) = mpsc::unbounded_channel();
+    let credit = Arc::new(Semaphore::new(INITIAL_WINDOW));
+    shared.streams.lock().unwrap().insert(
+        id,
+        Stream {
+            incoming,
+            credit: credit.clone(),
+        },
+    );
+
+    let shared = shared.clone();
+    usage::spawn(async move {
+        let (reader, writer) = tokio::io::split(mux_end);
+        let result = tokio::try_join!(
+            send_stream(&shared, id, reader, credit),
+            receive_stream(&shared, id, writer, incoming_receiver)
+        );
+
+        // Tell the other end to drop the stream if it didn't finish cleanly.
+        if result.is_err() {
+            let _ = shared.send(FRAME_RESET, id, Vec::new());
+        }
+        shared.streams.lock().unwrap().remove(&id);
+    });
+
+    stream
+}
+
+/// Sends what is written to the stream as data frames, as far as the window allows.
+async fn send_stream(
+    shared: &Shared,
+    id: u32,
+    mut reader: ReadHalf<DuplexStream>,
+    credit: Arc<Semaphore>,
+) -> io::Result<()> {
+    let reset = || io::Error::new(io::ErrorKind::ConnectionReset, "the stream was reset");
+    let mut buffer = vec![0; MAX_PAYLOAD];
+    loop {
+        let read = reader.read(&mut buffer).await?;
+        if read == 0 {
+            return shared.send(FRAME_CLOSE, id, Vec::new());
+        }
+
+        credit
+            .acquire_many(read as u32)
+            .await
+            .map_err(|_| reset())?
+            .forget();
+        shared.send(FRAME_DATA, id, buffer[..read].to_vec())?;
+    }
+}
+
+/// Writes the data the other end sent to the stream, granting more window as it is consumed.
+async fn receive_stream(
+    shared: &Shared,
+    id: u32,
+    mut writer: WriteHalf<DuplexStream>,
+    mut incoming: mpsc::UnboundedReceiver<Incoming>,
+) -> io::Result<()> {
+    while let Some(message) = incoming.recv().await {
+        match message {
+            Incoming::Data(data) => {
+                writer.write_all(&data).await?;
+                let increment = (data.len() as u32).to_be_bytes().to_vec();
+                shared.send(FRAME_WINDOW_UPDATE, id, increment)?;
+            }
+            Incoming::Close => return writer.shutdown().await,
+        }
+    }
+
+    Err(io::Error::new(
+        io::ErrorKind::ConnectionReset,
+        "the stream was reset",
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn streams_carry_data_both_ways() {
+        let (client_end, server_end) = tokio::io::duplex(1024);
+        let (client, _) = Mux::start(client_end, "client".to_string());
+        let (_server, mut accepted) = Mux::start(server_end, "server".to_string());
+
+        // More than the window, so the stream has to wait for window updates.
+        let data = vec![7u8; INITIAL_WINDOW * 3];
+        let mut stream = client.open().unwrap();
+        let sent = data.clone();
+        let sender = tokio::spawn(async move {
+            stream.write_all(&sent).await.unwrap();
+            stream.shutdown().await.unwrap();
+            let mut reply = String::new();
+            stream.read_to_string(&mut reply).await.unwrap();
+            reply
+        });
+
+        let (id, mut accepted_stream) = accepted.recv().await.unwrap();
+        assert_eq!(id, 1);
+        let mut received = Vec::new();
+        accepted_stream.read_to_end(&mut received).await.unwrap();
+        assert_eq!(received, data);
+        accepted_stream.write_all(b"thanks").await.unwrap();
+        accepted_stream.shutdown().await.unwrap();
+
+        assert_eq!(sender.await.unwrap(), "thanks");
+    }
+}
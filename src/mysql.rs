@@ -0,0 +1,508 @@
+use crate::format;
+use crate::protocol::{Parser, Protocol, Stats};
+
+/// How many bytes of a packet's payload are kept, enough for a handshake.
+const MAX_KEPT: usize = 1024;
+
+/// The capability flags that matter here.
+const CLIENT_CONNECT_WITH_DB: u32 = 0x8;
+const CLIENT_COMPRESS: u32 = 0x20;
+const CLIENT_SSL: u32 = 0x800;
+const CLIENT_DEPRECATE_EOF: u32 = 0x0100_0000;
+
+/// The most columns a result can have.
+const MAX_COLUMNS: u64 = 4096;
+
+/// The status flag of the last packet of a result set, when another one follows.
+const SERVER_MORE_RESULTS_EXISTS: u16 = 0x8;
+
+/// The commands counted: text queries, and executions of prepared statements.
+const COM_QUERY: u8 = 0x03;
+const COM_STMT_EXECUTE: u8 = 0x17;
+
+/// A packet, with the first `MAX_KEPT` bytes of its payload.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Packet {
+    /// How long its payload is.
+    len: usize,
+
+    /// The start of its payload.
+    payload: Vec<u8>,
+}
+
+/// Reassembles the packets of one direction from the bytes as they're read: a 3-byte
+/// little-endian length, a sequence id, and the payload.
+#[derive(Debug, Default)]
+struct Packets {
+    /// The header and the kept payload of the current packet.
+    current: Vec<u8>,
+
+    /// How many payload bytes of the current packet are still to come, once its header is in.
+    remaining: Option<usize>,
+}
+
+impl Packets {
+    /// Reads at most one packet from the front of `bytes`, returning how many bytes it used and
+    /// the packet if it's complete.
+    fn next(&mut self, bytes: &[u8]) -> (usize, Option<Packet>) {
+        let mut used = 0;
+        let remaining = match self.remaining {
+            Some(remaining) => remaining,
+            None => {
+                used = (4 - self.current.len()).min(bytes.len());
+                self.current.extend_from_slice(&bytes[..used]);
+                if self.current.len() < 4 {
+                    return (used, None);
+                }
+                u32::from_le_bytes([self.current[0], self.current[1], self.current[2], 0]) as usize
+            }
+        };
+
+        let take = remaining.min(bytes.len() - used);
+        let room = (4 + MAX_KEPT).saturating_sub(self.current.len());
+        self.current
+            .extend_from_slice(&bytes[used..used + take.min(room)]);
+        used += take;
+        if remaining > take {
+            self.remaining = Some(remaining - take);
+            return (used, None);
+        }
+
+        self.remaining = None;
+        let header = std::mem::take(&mut self.current);
+        let len = u32::from_le_bytes([header[0], header[1], header[2], 0]) as usize;
+        let payload = header[4..].to_vec();
+        (used, Some(Packet { len, payload }))
+    }
+
+    /// The payload length of the current packet, once its header is in.
+    fn len(&self) -> Option<usize> {
+        let header = self.current.get(..4)?;
+        Some(u32::from_le_bytes([header[0], header[1], header[2], 0]) as usize)
+    }
+}
+
+/// Where a connection is at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+enum Phase {
+    /// Waiting for the server's greeting.
+    #[default]
+    Greeting,
+
+    /// Waiting for the client's answer to it.
+    Response,
+
+    /// Authenticating, until the server says it's OK.
+    Authentication,
+
+    /// Exchanging commands and their results.
+    Commands,
+
+    /// Encrypted, compressed, or not understood, so nothing more is counted.
+    Done,
+}
+
+/// Where the reply to the last command is at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+enum Reply {
+    /// Not waiting for a result that's counted.
+    #[default]
+    Idle,
+
+    /// Waiting for the result of a query: an OK, an error, or the number of columns.
+    Awaiting,
+
+    /// Reading this many more column definitions.
+    Columns(u64),
+
+    /// Waiting for the EOF after the column definitions.
+    ColumnsEof,
+
+    /// Reading rows, until the last packet.
+    Rows,
+}
+
+/// Counts the queries, rows and errors of a MySQL connection, and what it negotiated.
+#[derive(Debug, Default)]
+pub struct Mysql {
+    /// Where the connection is at.
+    phase: Phase,
+
+    /// Where the reply to the last command is at.
+    reply: Reply,
+
+    /// The packets from the client.
+    client: Packets,
+
+    /// The packets from the server.
+    server: Packets,
+
+    /// The capabilities of the server, from its greeting.
+    server_capabilities: u32,
+
+    /// The capabilities both sides have, from the client's answer.
+    capabilities: u32,
+
+    /// How many text queries the client sent.
+    queries: u64,
+
+    /// How many prepared statements the client executed.
+    executes: u64,
+
+    /// How many rows the results had, and how many bytes.
+    rows: u64,
+    result_bytes: u64,
+
+    /// How many errors the server sent.
+    errors: u64,
+
+    /// The server's version, and the user and database the client connected as.
+    version: Option<String>,
+    user: Option<String>,
+    database: Option<String>,
+
+    /// Whether the client asked to encrypt the connection.
+    tls: bool,
+}
+
+impl Mysql {
+    /// Reads the server's greeting: the protocol version, the server's, the connection id, the
+    /// first part of the auth data, and the capabilities in two halves.
+    fn greeting(&mut self, payload: &[u8]) {
+        let mut fields = payload[1..].splitn(2, |&b| b == 0);
+        let version = fields.next().unwrap_or_default();
+        self.version = Some(format::sanitize(version));
+        let rest = fields.next().unwrap_or_default();
+        if let (Some(lower), Some(upper)) = (rest.get(13..15), rest.get(18..20)) {
+            self.server_capabilities = u32::from_le_bytes([lower[0], lower[1], upper[0], upper[1]]);
+        }
+    }
+
+    /// Reads the client's answer to the greeting: its capabilities, the most it sends at once,
+    /// a character set and filler, then the user, the auth data and the database.
+    fn response(&mut self, packet: &Packet) {
+        let Some(capabilities) = packet.payload.get(..4) else {
+            self.phase = Phase::Done;
+            return;
+        };
+        let capabilities = u32::from_le_bytes(capabilities.try_into().unwrap());
+        self.capabilities = capabilities & self.server_capabilities;
+
+        // An SSL request is the start of an answer, after which the client starts TLS.
+        if packet.len == 32 && capabilities & CLIENT_SSL != 0 {
+            self.tls = true;
+            self.phase = Phase::Done;
+            return;
+        }
+
+        let fields = packet.payload.get(32..).unwrap_or_default();
+        let user_len = fields.iter().position(|&b| b == 0).unwrap_or(fields.len());
+        self.user = Some(format::sanitize(&fields[..user_len]));
+        if capabilities & CLIENT_CONNECT_WITH_DB != 0 {
+            // The auth data is length-encoded, so it may hold zeros; only find the database
+            // after it when it's short.
+            let rest = fields.get(user_len + 1..).unwrap_or_default();
+            if let Some((&len, rest)) = rest.split_first().filter(|(&len, _)| len < 0xfb) {
+                if let Some(database) = rest.get(len as usize..) {
+                    let database = database.split(|&b| b == 0).next().unwrap_or_default();
+                    self.database = Some(format::sanitize(database));
+                }
+            }
+        }
+
+        self.phase = match self.capabilities & CLIENT_COMPRESS {
+            0 => Phase::Authentication,
+            _ => Phase::Done,
+        };
+    }
+
+    /// Follows the result of a command by the next packet from the server.
+    fn reply(&mut self, packet: &Packet) {
+        let first = packet.payload.first().copied();
+        self.reply = match (self.reply, first) {
+            (Reply::Idle, _) => Reply::Idle,
+            (_, Some(0xff)) => {
+                self.errors += 1;
+                Reply::Idle
+            }
+            (Reply::Awaiting, Some(0x00 | 0xfb) | None) => Reply::Idle,
+            (Reply::Awaiting, Some(_)) => match length_encoded(&packet.payload) {
+                Some((columns, _)) if columns > MAX_COLUMNS => {
+                    self.phase = Phase::Done;
+                    Reply::Idle
+                }
+                Some((columns, _)) if columns > 0 => Reply::Columns(columns),
+                _ => Reply::Idle,
+            },
+            (Reply::Columns(1), _) if self.capabilities & CLIENT_DEPRECATE_EOF != 0 => Reply::Rows,
+            (Reply::Columns(1), _) => Reply::ColumnsEof,
+            (Reply::Columns(columns), _) => Reply::Columns(columns - 1),
+            (Reply::ColumnsEof, _) => Reply::Rows,
+
+            // The last packet is an EOF (or an OK with the same header), unless it's a row whose
+            // first column is longer than fits in a packet.
+            (Reply::Rows, Some(0xfe)) if packet.len < 0xff_ffff => {
+                match self.status(&packet.payload) & SERVER_MORE_RESULTS_EXISTS {
+                    0 => Reply::Idle,
+                    _ => Reply::Awaiting,
+                }
+            }
+            (Reply::Rows, _) => {
+                self.rows += 1;
+                self.result_bytes += packet.len as u64;
+                Reply::Rows
+            }
+        };
+    }
+
+    /// The status flags of the EOF (or OK) packet that ends a result set.
+    fn status(&self, payload: &[u8]) -> u16 {
+        let status = match self.capabilities & CLIENT_DEPRECATE_EOF {
+            // The number of warnings, then the status.
+            0 => payload.get(3..5),
+
+            // The affected rows and last insert id, then the status.
+            _ => length_encoded(&payload[1..])
+                .and_then(|(_, rest)| length_encoded(rest))
+                .and_then(|(_, rest)| rest.get(..2)),
+        };
+        status.map_or(0, |status| u16::from_le_bytes([status[0], status[1]]))
+    }
+}
+
+/// Reads a length-encoded integer from the front of `bytes`, returning it and the rest.
+fn length_encoded(bytes: &[u8]) -> Option<(u64, &[u8])> {
+    let (&first, rest) = bytes.split_first()?;
+    let len = match first {
+        0xfc => 2,
+        0xfd => 3,
+        0xfe => 8,
+        0xfb | 0xff => return None,
+        _ => return Some((first as u64, rest)),
+    };
+    let value = rest.get(..len)?;
+    let mut buffer = [0; 8];
+    buffer[..len].copy_from_slice(value);
+    Some((u64::from_le_bytes(buffer), &rest[len..]))
+}
+
+impl Parser for Mysql {
+    fn client(&mut self, mut bytes: &[u8]) {
+        while !bytes.is_empty() {
+            if matches!(self.phase, Phase::Greeting | Phase::Done) {
+                return;
+            }
+            let (used, packet) = self.client.next(bytes);
+            bytes = &bytes[used..];
+            let Some(packet) = packet else {
+                continue;
+            };
+
+            match self.phase {
+                Phase::Response => self.response(&packet),
+                Phase::Commands => {
+                    self.reply = match packet.payload.first().copied() {
+                        Some(COM_QUERY) => {
+                            self.queries += 1;
+                            Reply::Awaiting
+                        }
+                        Some(COM_STMT_EXECUTE) => {
+                            self.executes += 1;
+                            Reply::Awaiting
+                        }
+                        _ => Reply::Idle,
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn server(&mut self, mut bytes: &[u8]) {
+        while !bytes.is_empty() {
+            if self.phase == Phase::Done {
+                return;
+            }
+            let (used, packet) = self.server.next(bytes);
+            bytes = &bytes[used..];
+            let Some(packet) = packet else {
+                // A greeting is short, so a server that starts with a long packet isn't MySQL.
+                if self.phase == Phase::Greeting && self.server.len() > Some(MAX_KEPT) {
+                    self.phase = Phase::Done;
+                }
+                continue;
+            };
+
+            match (self.phase, packet.payload.first().copied()) {
+                (Phase::Greeting, Some(10)) => {
+                    self.greeting(&packet.payload);
+                    self.phase = Phase::Response;
+                }
+                (Phase::Greeting, _) => self.phase = Phase::Done,
+                (Phase::Authentication, Some(0x00)) => self.phase = Phase::Commands,
+                (Phase::Authentication, Some(0xff)) => {
+                    self.errors += 1;
+                    self.phase = Phase::Done;
+                }
+                (Phase::Commands, _) => self.reply(&packet),
+                _ => {}
+            }
+        }
+    }
+
+    fn stats(&self) -> Stats {
+        let mut details = Vec::new();
+        if let Some(version) = &self.version {
+            details.push(("server", version.clone()));
+        }
+        if let Some(user) = &self.user {
+            details.push(("user", user.clone()));
+        }
+        if let Some(database) = &self.database {
+            details.push(("database", database.clone()));
+        }
+        if self.tls {
+            details.push(("TLS", "requested".to_string()));
+        } else if self.capabilities & CLIENT_COMPRESS != 0 {
+            details.push(("compression", "on".to_string()));
+        }
+
+        Stats {
+            protocol: Protocol::Mysql,
+            counts: vec![
+                ("queries", self.queries),
+                ("prepared executions", self.executes),
+                ("rows", self.rows),
+                ("result bytes", self.result_bytes),
+                ("errors", self.errors),
+            ],
+            timings: Vec::new(),
+            details,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A packet with the given sequence id.
+    fn packet(seq: u8, payload: &[u8]) -> Vec<u8> {
+        let mut packet = (payload.len() as u32).to_le_bytes()[..3].to_vec();
+        packet.push(seq);
+        packet.extend_from_slice(payload);
+        packet
+    }
+
+    /// The server's greeting, with the given capabilities.
+    fn greeting(capabilities: u32) -> Vec<u8> {
+        let mut payload = b"\x0a8.0.36\0".to_vec();
+        payload.extend_from_slice(&[1, 0, 0, 0]);
+        payload.extend_from_slice(b"abcdefgh\0");
+        payload.extend_from_slice(&capabilities.to_le_bytes()[..2]);
+        payload.extend_from_slice(&[0xff, 2, 0]);
+        payload.extend_from_slice(&capabilities.to_le_bytes()[2..]);
+        payload.extend_from_slice(&[21; 11]);
+        packet(0, &payload)
+    }
+
+    #[test]
+    fn counts_queries() {
+        let capabilities = CLIENT_CONNECT_WITH_DB | CLIENT_SSL | CLIENT_DEPRECATE_EOF;
+        let mut mysql = Mysql::default();
+        mysql.server(&greeting(capabilities));
+
+        let mut response = capabilities.to_le_bytes().to_vec();
+        response.extend_from_slice(&[0; 28]);
+        response.extend_from_slice(b"app\0\x02pwshop\0");
+        mysql.client(&packet(1, &response));
+        mysql.server(&packet(2, &[0, 0, 0, 2, 0, 0, 0]));
+
+        // A query with two rows, its packets split across reads.
+        mysql.client(&packet(0, b"\x03SELECT id FROM orders"));
+        let mut result = packet(1, &[1]);
+        result.extend(packet(
+            2,
+            b"\x03def\x04shop\x06orders\x06orders\x02id\x02id",
+        ));
+        result.extend(packet(3, b"\x011"));
+        result.extend(packet(4, b"\x012"));
+        result.extend(packet(5, &[0xfe, 0, 0, 2, 0, 0, 0]));
+        mysql.server(&result[..10]);
+        mysql.server(&result[10..]);
+
+        // A query that fails, and a prepared statement that's executed.
+        mysql.client(&packet(0, b"\x03SELECT nope"));
+        mysql.server(&packet(1, b"\xff\x48\x04#42S22Unknown column"));
+        mysql.client(&packet(0, &[COM_STMT_EXECUTE, 1, 0, 0, 0, 0, 1, 0, 0, 0]));
+        mysql.server(&packet(1, &[0, 1, 0, 2, 0, 0, 0]));
+
+        assert_eq!(
+            mysql.stats().to_string(),
+            "2 queries, 1 prepared executions, 2 rows, 4 result bytes, 1 errors (server 8.0.36, user app, database shop)"
+        );
+
+        // Once the client asks for TLS, nothing more can be seen.
+        let mut mysql = Mysql::default();
+        mysql.server(&greeting(capabilities));
+        let mut request = capabilities.to_le_bytes().to_vec();
+        request.extend_from_slice(&[0; 28]);
+        mysql.client(&packet(1, &request));
+        assert!(mysql
+            .stats()
+            .to_string()
+            .ends_with("(server 8.0.36, TLS requested)"));
+    }
+
+    #[test]
+    fn gives_up_on_garbage() {
+        let capabilities = CLIENT_CONNECT_WITH_DB | CLIENT_DEPRECATE_EOF;
+
+        // Truncated greetings and answers are read as far as they go.
+        let mut mysql = Mysql::default();
+        mysql.server(&packet(0, &[10]));
+        mysql.client(&packet(1, &[1, 2]));
+        assert_eq!(mysql.phase, Phase::Done);
+        assert_eq!(
+            mysql.stats().to_string(),
+            "0 queries, 0 prepared executions, 0 rows, 0 result bytes, 0 errors (server )"
+        );
+
+        let mut mysql = Mysql::default();
+        let greeting = greeting(capabilities);
+        mysql.server(&greeting[..greeting.len() - 1]);
+        assert_eq!(mysql.phase, Phase::Greeting);
+
+        // Names that aren't UTF-8 are shown sanitized, and auth data longer than the answer
+        // hides the database.
+        let mut mysql = Mysql::default();
+        mysql.server(&packet(0, b"\x0a\xff\x1b[2J\0"));
+        let mut response = capabilities.to_le_bytes().to_vec();
+        response.extend_from_slice(&[0; 28]);
+        response.extend_from_slice(b"\xfe\x1b\0\xfa");
+        mysql.client(&packet(1, &response));
+        mysql.server(&packet(2, &[0, 0, 0, 2, 0, 0, 0]));
+        assert_eq!(
+            mysql.stats().to_string(),
+            "0 queries, 0 prepared executions, 0 rows, 0 result bytes, 0 errors \
+             (server \\xff\\x1b[2J, user \\xfe\\x1b)"
+        );
+        assert_eq!(mysql.phase, Phase::Commands);
+
+        // A result with more columns than there can be ends the parsing, as does a server that
+        // doesn't greet.
+        mysql.client(&packet(0, b"\x03SELECT 1"));
+        mysql.server(&packet(
+            1,
+            &[0xfe, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff],
+        ));
+        mysql.client(&packet(0, b"\x03SELECT 1"));
+        assert_eq!((mysql.phase, mysql.queries), (Phase::Done, 1));
+
+        let mut mysql = Mysql::default();
+        mysql.server(b"SSH-2.0-OpenSSH_9.2\r\n");
+        mysql.server(&[0xff; 64]);
+        mysql.client(&packet(0, b"\x03SELECT 1"));
+        assert_eq!((mysql.phase, mysql.queries), (Phase::Done, 0));
+    }
+}
@@ -0,0 +1,753 @@
+use socket2::{Domain, Protocol, SockRef, Socket, TcpKeepalive, Type};
+use std::fmt::{Display, Formatter};
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicU16, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::net::{TcpListener, TcpSocket, TcpStream, ToSocketAddrs};
+
+/// Where connections to destinations are made from, as set by `set_outbound_bind`.
+static OUTBOUND_BIND: Mutex<Vec<OutboundBind>> = Mutex::new(Vec::new());
+
+/// The network interface connections to destinations are made through, as set by
+/// `set_outbound_interface`.
+static OUTBOUND_INTERFACE: Mutex<Option<String>> = Mutex::new(None);
+
+/// The next outbound bind address to connect from, as an index into them.
+static NEXT_BIND: AtomicUsize = AtomicUsize::new(0);
+
+/// The next port of the outbound port range to try, as an offset into the range.
+static NEXT_PORT: AtomicU16 = AtomicU16::new(0);
+
+/// The options of sockets accepted or connected, as set by `set_socket_options`.
+static SOCKET_OPTIONS: Mutex<SocketOptions> = Mutex::new(SocketOptions {
+    abortive_close: false,
+    user_timeout: None,
+    keepalive: None,
+    fast_open_listen: false,
+    fast_open_connect: false,
+    mptcp: false,
+    unreachable_probes: None,
+    dscp: None,
+    ttl: None,
+});
+
+/// How many connections with TCP Fast Open data may wait to be accepted, like the backlog.
+#[cfg(target_os = "linux")]
+const FAST_OPEN_QUEUE: libc::c_int = 1024;
+
+/// The bit of `tcpi_options` telling that the data in a SYN was acknowledged.
+const TCPI_OPT_SYN_DATA: u8 = 0x20;
+
+/// The protocol of Multipath TCP sockets, and the level and name of their `struct mptcp_info`
+/// option. Not in the `libc` version used.
+#[cfg(target_os = "linux")]
+const IPPROTO_MPTCP: libc::c_int = 262;
+#[cfg(target_os = "linux")]
+const SOL_MPTCP: libc::c_int = 284;
+#[cfg(target_os = "linux")]
+const MPTCP_INFO: libc::c_int = 1;
+
+/// Options of the sockets to clients and destinations.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SocketOptions {
+    /// Whether sockets are closed with a reset rather than a FIN.
+    pub abortive_close: bool,
+
+    /// How long sent data may go unacknowledged before the connection is dropped
+    /// (`TCP_USER_TIMEOUT`, Linux only), if not the operating system's default.
+    pub user_timeout: Option<Duration>,
+
+    /// How long a connection may be idle before keepalive probes are sent, if at all, and on
+    /// Linux, how long to wait for an answer before sending the next one.
+    pub keepalive: Option<Duration>,
+
+    /// Whether listeners accept data in the SYN (TCP Fast Open, Linux only).
+    pub fast_open_listen: bool,
+
+    /// Whether connections to destinations send their first data in the SYN when they can
+    /// (TCP Fast Open, Linux only).
+    pub fast_open_connect: bool,
+
+    /// Whether listeners and connections to destinations use Multipath TCP, falling back to
+    /// plain TCP with peers that don't (Linux only).
+    pub mptcp: bool,
+
+    /// How many keepalive probes in a row a peer may leave unanswered before its connection is
+    /// closed as unreachable, if it's watched for that (Linux only).
+    pub unreachable_probes: Option<u8>,
+
+    /// The DSCP code point to mark what's sent to clients and destinations with, if any (Linux
+    /// only).
+    pub dscp: Option<u8>,
+
+    /// The TTL (IPv4) or hop limit (IPv6) of what's sent to destinations, if not the operating
+    /// system's default.
+    pub ttl: Option<u8>,
+}
+
+/// The local address to make connections to destinations from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OutboundBind {
+    /// The IP address.
+    pub ip: IpAddr,
+
+    /// The ports to use, taking turns, or `None` for any port the operating system picks.
+    pub ports: Option<(u16, u16)>,
+}
+
+impl Display for OutboundBind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self.ports {
+            Some((first, last)) => {
+                write!(
+                    f,
+                    "{}-{}",
+                    join_host_port(&self.ip.to_string(), first),
+                    last
+                )
+            }
+            None => write!(f, "{}", self.ip),
+        }
+    }
+}
+
+/// Sets where connections to destinations are made from from now on, if not the default.
+pub fn set_outbound_bind(binds: Vec<OutboundBind>) {
+    *OUTBOUND_BIND.lock().unwrap() = binds;
+}
+
+/// Sets the network interface connections to destinations are made through from now on, if
+/// not the one the routing table picks.
+pub fn set_outbound_interface(interface: Option<String>) {
+    *OUTBOUND_INTERFACE.lock().unwrap() = interface;
+}
+
+/// Sets the options of sockets accepted or connected from now on.
+pub fn set_socket_options(options: SocketOptions) {
+    *SOCKET_OPTIONS.lock().unwrap() = options;
+}
+
+/// Whether sockets are closed with a reset rather than a FIN.
+pub fn abortive_close() -> bool {
+    SOCKET_OPTIONS.lock().unwrap().abortive_close
+}
+
+/// Applies the socket options to a listener.
+pub fn apply_listener_options(listener: &TcpListener) -> io::Result<()> {
+    #[cfg(target_os = "linux")]
+    if SOCKET_OPTIONS.lock().unwrap().fast_open_listen {
+        set_tcp_option(listener, libc::TCP_FASTOPEN, FAST_OPEN_QUEUE)?;
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    let _ = listener;
+    Ok(())
+}
+
+/// Applies the socket options to a socket accepted or connected.
+pub fn apply_socket_options(stream: &TcpStream) -> io::Result<()> {
+    let options = *SOCKET_OPTIONS.lock().unwrap();
+    if options.abortive_close {
+        stream.set_linger(Some(Duration::ZERO))?;
+    }
+    if let Some(idle) = options.keepalive {
+        let keepalive = TcpKeepalive::new().with_time(idle);
+        // Unanswered probes are repeated just as often, rather than every 75 seconds.
+        #[cfg(target_os = "linux")]
+        let keepalive = keepalive.with_interval(idle);
+        SockRef::from(stream).set_tcp_keepalive(&keepalive)?;
+    }
+    if let Some(timeout) = options.user_timeout {
+        set_user_timeout(stream, timeout)?;
+    }
+    if let Some(dscp) = options.dscp {
+        set_dscp(stream, dscp)?;
+    }
+    Ok(())
+}
+
+/// Marks what's sent on a socket with a DSCP code point, the upper six bits of the TOS byte of
+/// IPv4 and the traffic class of IPv6. IPv6 sockets get both, for their IPv4-mapped peers.
+#[cfg(target_os = "linux")]
+fn set_dscp(stream: &TcpStream, dscp: u8) -> io::Result<()> {
+    let tos = libc::c_int::from(dscp) << 2;
+    set_option(stream, libc::IPPROTO_IP, libc::IP_TOS, tos)?;
+    if stream.local_addr()?.is_ipv6() {
+        set_option(stream, libc::IPPROTO_IPV6, libc::IPV6_TCLASS, tos)?;
+    }
+    Ok(())
+}
+
+/// Only Linux marks DSCP code points, which the config doesn't allow setting elsewhere.
+#[cfg(not(target_os = "linux"))]
+fn set_dscp(_stream: &TcpStream, _dscp: u8) -> io::Result<()> {
+    Ok(())
+}
+
+/// Parses a DSCP code point, by number (`0` to `63`) or by name, like `ef`, `af41` or `cs1`.
+pub fn parse_dscp(value: &str) -> Result<u8, String> {
+    let invalid = || {
+        format!(
+            "Expected a DSCP code point from 0 to 63, or a name like `ef`, `af41` or `cs1`, got `{}`",
+            value
+        )
+    };
+    let name = value.to_ascii_lowercase();
+    let digit = |c: u8, range: std::ops::RangeInclusive<u8>| {
+        Some(c.wrapping_sub(b'0')).filter(|digit| range.contains(digit))
+    };
+    let dscp = match name.as_bytes() {
+        b"ef" => Some(46),
+        b"va" => Some(44),
+        b"le" => Some(1),
+        [b'c', b's', class] => digit(*class, 0..=7).map(|class| class << 3),
+        [b'a', b'f', class, drop] => digit(*class, 1..=4)
+            .zip(digit(*drop, 1..=3))
+            .map(|(class, drop)| class << 3 | drop << 1),
+        _ => value.parse().ok().filter(|dscp| *dscp < 64),
+    };
+    dscp.ok_or_else(invalid)
+}
+
+/// Drops a connection whose sent data goes unacknowledged for this long (`TCP_USER_TIMEOUT`).
+#[cfg(target_os = "linux")]
+fn set_user_timeout(stream: &TcpStream, timeout: Duration) -> io::Result<()> {
+    SockRef::from(stream).set_tcp_user_timeout(Some(timeout))
+}
+
+/// Only Linux has a user timeout, which the config doesn't allow setting elsewhere.
+#[cfg(not(target_os = "linux"))]
+fn set_user_timeout(_stream: &TcpStream, _timeout: Duration) -> io::Result<()> {
+    Ok(())
+}
+
+/// Whether a connection was opened with TCP Fast Open, its first data acknowledged in the SYN.
+pub fn used_fast_open(stream: &TcpStream) -> bool {
+    tcp_info_head(&SockRef::from(stream)).is_some_and(|info| info[5] & TCPI_OPT_SYN_DATA != 0)
+}
+
+/// The first bytes of a socket's `struct tcp_info` (`tcpi_state`, `tcpi_ca_state`,
+/// `tcpi_retransmits`, `tcpi_probes`, `tcpi_backoff`, `tcpi_options` and the window scales),
+/// which is all that's used of it, and isn't in the `libc` version used.
+#[cfg(target_os = "linux")]
+fn tcp_info_head(socket: &Socket) -> Option<[u8; 8]> {
+    use std::os::unix::io::AsRawFd;
+
+    let mut info = [0u8; 8];
+    let mut len = info.len() as libc::socklen_t;
+    // SAFETY: the buffer is as long as given, and the kernel writes at most that much.
+    let result = unsafe {
+        libc::getsockopt(
+            socket.as_raw_fd(),
+            libc::IPPROTO_TCP,
+            libc::TCP_INFO,
+            info.as_mut_ptr() as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    (result == 0 && len as usize == info.len()).then_some(info)
+}
+
+/// Only Linux has TCP Fast Open and the keepalive state, which the config doesn't allow
+/// relying on elsewhere.
+#[cfg(not(target_os = "linux"))]
+fn tcp_info_head(_socket: &Socket) -> Option<[u8; 8]> {
+    None
+}
+
+/// Sets an integer TCP option of a socket.
+#[cfg(target_os = "linux")]
+fn set_tcp_option(
+    socket: &impl std::os::unix::io::AsRawFd,
+    option: libc::c_int,
+    value: libc::c_int,
+) -> io::Result<()> {
+    set_option(socket, libc::IPPROTO_TCP, option, value)
+}
+
+/// Sets an integer option of a socket, at the given level.
+#[cfg(target_os = "linux")]
+fn set_option(
+    socket: &impl std::os::unix::io::AsRawFd,
+    level: libc::c_int,
+    option: libc::c_int,
+    value: libc::c_int,
+) -> io::Result<()> {
+    // SAFETY: the option value is a `c_int` of the given size.
+    let result = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            level,
+            option,
+            &value as *const libc::c_int as *const libc::c_void,
+            std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    match result {
+        0 => Ok(()),
+        _ => Err(io::Error::last_os_error()),
+    }
+}
+
+/// How many subflows a Multipath TCP connection has, if it is one: it's `None` for plain TCP
+/// connections, including those that fell back to it because their peer doesn't do Multipath
+/// TCP.
+pub fn subflows(stream: &TcpStream) -> Option<u8> {
+    mptcp_subflows(&SockRef::from(stream))
+}
+
+/// How many subflows a Multipath TCP socket has, if it is one.
+#[cfg(target_os = "linux")]
+fn mptcp_subflows(socket: &Socket) -> Option<u8> {
+    use std::os::unix::io::AsRawFd;
+
+    // The extra subflows are the first byte of `struct mptcp_info`, which isn't in the `libc`
+    // version used, so only the start of it is read.
+    let mut info = [0u8; 8];
+    let mut len = info.len() as libc::socklen_t;
+    // SAFETY: the buffer is as long as given, and the kernel writes at most that much.
+    let result = unsafe {
+        libc::getsockopt(
+            socket.as_raw_fd(),
+            SOL_MPTCP,
+            MPTCP_INFO,
+            info.as_mut_ptr() as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    (result == 0 && len >= 1).then(|| info[0].saturating_add(1))
+}
+
+/// Only Linux has Multipath TCP, which the config doesn't allow enabling elsewhere.
+#[cfg(not(target_os = "linux"))]
+fn mptcp_subflows(_socket: &Socket) -> Option<u8> {
+    None
+}
+
+/// Creates a stream socket, using Multipath TCP if enabled and the kernel supports it.
+fn stream_socket(domain: Domain) -> io::Result<Socket> {
+    #[cfg(target_os = "linux")]
+    if SOCKET_OPTIONS.lock().unwrap().mptcp {
+        if let Ok(socket) = Socket::new(domain, Type::STREAM, Some(IPPROTO_MPTCP.into())) {
+            return Ok(socket);
+        }
+    }
+
+    Socket::new(domain, Type::STREAM, Some(Protocol::TCP))
+}
+
+/// A handle to a socket, to close it with a reset or look at it once the connection is over,
+/// wherever it ended up, e.g. wrapped in readers and writers.
+#[derive(Debug)]
+pub struct SocketHandle(Socket);
+
+impl SocketHandle {
+    /// Creates a handle to the socket.
+    pub fn new(stream: &TcpStream) -> io::Result<Self> {
+        SockRef::from(stream).try_clone().map(Self)
+    }
+
+    /// Makes the socket send a reset once it's closed (by dropping it).
+    pub fn reset(self) -> io::Result<()> {
+        self.0.set_linger(Some(Duration::ZERO))
+    }
+
+    /// How many subflows the socket has, if it's a Multipath TCP one.
+    pub fn subflows(&self) -> Option<u8> {
+        mptcp_subflows(&self.0)
+    }
+
+    /// How many keepalive probes in a row the peer left unanswered so far.
+    pub fn unanswered_probes(&self) -> u8 {
+        tcp_info_head(&self.0).map_or(0, |info| info[3])
+    }
+}
+
+/// Whether handles to accepted sockets are needed to look at them while they're proxied.
+pub fn inspect_accepted() -> bool {
+    let options = SOCKET_OPTIONS.lock().unwrap();
+    options.mptcp || options.unreachable_probes.is_some()
+}
+
+/// How many keepalive probes in a row a peer may leave unanswered before its connection is
+/// closed as unreachable, if connections are watched for that.
+pub fn unreachable_probes() -> Option<u8> {
+    SOCKET_OPTIONS.lock().unwrap().unreachable_probes
+}
+
+/// Connects to a destination, like `TcpStream::connect`, from the outbound bind addresses if
+/// any are set. They take turns, and when one has no free port left, the next one is tried.
+/// Only resolved addresses of the same family as a bind address are connected to from it.
+pub async fn connect(addr: impl ToSocketAddrs) -> io::Result<TcpStream> {
+    let mut binds = OUTBOUND_BIND.lock().unwrap().clone();
+    let options = *SOCKET_OPTIONS.lock().unwrap();
+    let plain = !options.fast_open_connect
+        && !options.mptcp
+        && options.ttl.is_none()
+        && OUTBOUND_INTERFACE.lock().unwrap().is_none();
+    let stream = match binds.is_empty() {
+        true if plain => TcpStream::connect(addr).await?,
+        true => {
+            let addrs: Vec<SocketAddr> = tokio::net::lookup_host(addr).await?.collect();
+            connect_any(&addrs).await?
+        }
+        false => {
+            let first = NEXT_BIND.fetch_add(1, Ordering::Relaxed) % binds.len();
+            binds.rotate_left(first);
+            let addrs: Vec<SocketAddr> = tokio::net::lookup_host(addr).await?.collect();
+            connect_from_any(&binds, &addrs).await?
+        }
+    };
+
+    apply_socket_options(&stream)?;
+    Ok(stream)
+}
+
+/// Connects to the first of the resolved addresses that accepts the connection, like
+/// `TcpStream::connect`.
+async fn connect_any(addrs: &[SocketAddr]) -> io::Result<TcpStream> {
+    let mut last_err = None;
+    for &addr in addrs {
+        match outbound_socket(addr)?.connect(addr).await {
+            Ok(stream) => return Ok(stream),
+            Err(err) => last_err = Some(err),
+        }
+    }
+
+    Err(last_err.unwrap_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "could not resolve to any address",
+        )
+    }))
+}
+
+/// Creates a socket to connect to a resolved address with, through the outbound interface if
+/// set, with the TTL if set (from the SYN on), using TCP Fast Open and Multipath TCP if enabled.
+fn outbound_socket(addr: SocketAddr) -> io::Result<TcpSocket> {
+    let socket = stream_socket(Domain::for_address(addr))?;
+    let options = *SOCKET_OPTIONS.lock().unwrap();
+    match (options.ttl, addr) {
+        (Some(ttl), SocketAddr::V4(_)) => socket.set_ttl(ttl.into())?,
+        (Some(ttl), SocketAddr::V6(_)) => socket.set_unicast_hops_v6(ttl.into())?,
+        (None, _) => {}
+    }
+    #[cfg(target_os = "linux")]
+    if let Some(interface) = OUTBOUND_INTERFACE.lock().unwrap().as_deref() {
+        socket
+            .bind_device(Some(interface.as_bytes()))
+            .map_err(|err| {
+                io::Error::new(
+                    err.kind(),
+                    format!("could not connect through {}: {}", interface, err),
+                )
+            })?;
+    }
+    socket.set_nonblocking(true)?;
+    let socket = TcpSocket::from_std_stream(socket.into());
+
+    #[cfg(target_os = "linux")]
+    if options.fast_open_connect {
+        set_tcp_option(&socket, libc::TCP_FASTOPEN_CONNECT, 1)?;
+    }
+    Ok(socket)
+}
+
+/// Connects to one of the resolved addresses from the first bind address that has a free port.
+async fn connect_from_any(binds: &[OutboundBind], addrs: &[SocketAddr]) -> io::Result<TcpStream> {
+    let mut last_err = None;
+    for &bind in binds {
+        for &addr in addrs
+            .iter()
+            .filter(|addr| addr.is_ipv4() == bind.ip.is_ipv4())
+        {
+            match connect_from(bind, addr).await {
+                Ok(stream) => return Ok(stream),
+                Err(err) if err.kind() == io::ErrorKind::AddrNotAvailable => {
+                    // Out of ports from this address, so the next one it is.
+                    last_err = Some(io::Error::new(
+                        io::ErrorKind::AddrNotAvailable,
+                        format!("no free port to connect from {}: {}", bind.ip, err),
+                    ));
+                    break;
+                }
+                Err(err) => last_err = Some(err),
+            }
+        }
+    }
+
+    Err(last_err.unwrap_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "the destination has no address of the same family as the outbound bind addresses",
+        )
+    }))
+}
+
+/// Connects to a resolved address from the outbound bind address, trying the ports of its
+/// range in turn until one is free.
+async fn connect_from(bind: OutboundBind, addr: SocketAddr) -> io::Result<TcpStream> {
+    let socket = || outbound_socket(addr);
+
+    let Some((first, last)) = bind.ports else {
+        let socket = socket()?;
+        bind_address_no_port(&socket);
+        socket.bind(SocketAddr::new(bind.ip, 0))?;
+        return socket.connect(addr).await;
+    };
+
+    let count = last - first + 1;
+    for _ in 0..count {
+        let port = first + NEXT_PORT.fetch_add(1, Ordering::Relaxed) % count;
+        let socket = socket()?;
+        match socket.bind(SocketAddr::new(bind.ip, port)) {
+            Ok(()) => return socket.connect(addr).await,
+            Err(err) if err.kind() == io::ErrorKind::AddrInUse => continue,
+            Err(err) => return Err(err),
+        }
+    }
+
+    Err(io::Error::new(
+        io::ErrorKind::AddrNotAvailable,
+        format!("all outbound ports {}-{} are in use", first, last),
+    ))
+}
+
+/// Leaves picking the port of a socket bound to port 0 until it connects, so that ports are
+/// only taken per destination rather than for good (`IP_BIND_ADDRESS_NO_PORT`, Linux only).
+#[cfg(target_os = "linux")]
+fn bind_address_no_port(socket: &TcpSocket) {
+    use std::os::unix::io::AsRawFd;
+
+    /// Not in the `libc` version used.
+    const IP_BIND_ADDRESS_NO_PORT: libc::c_int = 24;
+
+    let enabled: libc::c_int = 1;
+    // SAFETY: the option value is a `c_int` of the given size. Failing is harmless, as the
+    // port is then picked when binding.
+    unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            libc::IPPROTO_IP,
+            IP_BIND_ADDRESS_NO_PORT,
+            &enabled as *const libc::c_int as *const libc::c_void,
+            std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+        );
+    }
+}
+
+/// Ports are picked when binding elsewhere.
+#[cfg(not(target_os = "linux"))]
+fn bind_address_no_port(_socket: &TcpSocket) {}
+
+/// Binds a listener on the address. For IPv6 addresses, `v6only` picks between accepting IPv6
+/// only and dual-stack; `None` keeps the operating system's default.
+pub async fn listen(addr: &str, v6only: Option<bool>) -> Result<TcpListener, io::Error> {
+    listen_with(addr, v6only, false).await
+}
+
+/// Binds a listener on the address like `listen`, allowing more listeners on the same address
+/// (`SO_REUSEPORT`), between which the kernel spreads the connections.
+pub async fn listen_shared(addr: &str, v6only: Option<bool>) -> Result<TcpListener, io::Error> {
+    listen_with(addr, v6only, true).await
+}
+
+/// Binds a listener on the address, shared with other listeners or not.
+async fn listen_with(
+    addr: &str,
+    v6only: Option<bool>,
+    shared: bool,
+) -> Result<TcpListener, io::Error> {
+    let mut last_err = None;
+
+    // Like `TcpListener::bind`, use the first resolved address that we can bind to.
+    for addr in tokio::net::lookup_host(addr).await? {
+        match listen_on(addr, v6only, shared) {
+            Ok(listener) => return Ok(listener),
+            Err(err) => last_err = Some(err),
+        }
+    }
+
+    Err(last_err.unwrap_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{} did not resolve to any address", addr),
+        )
+    }))
+}
+
+/// Binds a listener on a resolved address.
+fn listen_on(
+    addr: SocketAddr,
+    v6only: Option<bool>,
+    shared: bool,
+) -> Result<TcpListener, io::Error> {
+    let socket = stream_socket(Domain::for_address(addr))?;
+
+    // Same as `TcpListener::bind`, so restarts don't trip over connections in TIME_WAIT.
+    #[cfg(unix)]
+    socket.set_reuse_address(true)?;
+
+    #[cfg(unix)]
+    if shared {
+        socket.set_reuse_port(true)?;
+    }
+    #[cfg(not(unix))]
+    if shared {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "sharing an address between listeners is only supported on Unix",
+        ));
+    }
+
+    if let (SocketAddr::V6(_), Some(v6only)) = (addr, v6only) {
+        socket.set_only_v6(v6only)?;
+    }
+
+    socket.bind(&addr.into())?;
+    socket.listen(1024)?;
+    socket.set_nonblocking(true)?;
+    TcpListener::from_std(socket.into())
+}
+
+/// Combines a host and a port into an address, adding brackets around IPv6 hosts.
+pub fn join_host_port(host: &str, port: u16) -> String {
+    match host.contains(':') && !host.starts_with('[') {
+        true => format!("[{}]:{}", host, port),
+        false => format!("{}:{}", host, port),
+    }
+}
+
+/// Unwraps IPv4-mapped IPv6 addresses (as accepted by dual-stack listeners) into IPv4 addresses.
+pub fn normalize(addr: SocketAddr) -> SocketAddr {
+    match addr {
+        SocketAddr::V6(v6) => match v6.ip().to_ipv4_mapped() {
+            Some(ip) => SocketAddr::new(ip.into(), v6.port()),
+            None => addr,
+        },
+        SocketAddr::V4(_) => addr,
+    }
+}
+
+/// Displays a socket address, naming the interface of scoped IPv6 addresses
+/// (e.g. `[fe80::1%eth0]:5000` rather than `[fe80::1%2]:5000`).
+pub struct DisplayAddr(pub SocketAddr);
+
+impl Display for DisplayAddr {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if let SocketAddr::V6(v6) = self.0 {
+            if let Some(name) = interface_name(v6.scope_id()) {
+                return write!(f, "[{}%{}]:{}", v6.ip(), name, v6.port());
+            }
+        }
+
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Looks up the name of the network interface with the given index.
+#[cfg(unix)]
+fn interface_name(index: u32) -> Option<String> {
+    if index == 0 {
+        return None;
+    }
+
+    let mut buf = [0 as libc::c_char; libc::IF_NAMESIZE];
+    // SAFETY: the buffer is `IF_NAMESIZE` long, as required.
+    let name = unsafe { libc::if_indextoname(index, buf.as_mut_ptr()) };
+    if name.is_null() {
+        return None;
+    }
+
+    // SAFETY: on success, the buffer holds a NUL-terminated name.
+    let name = unsafe { std::ffi::CStr::from_ptr(name) };
+    name.to_str().ok().map(str::to_string)
+}
+
+/// Interface names are only looked up on Unix.
+#[cfg(not(unix))]
+fn interface_name(_index: u32) -> Option<String> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ipv4_mapped_addresses_are_unwrapped() {
+        let mapped: SocketAddr = "[::ffff:10.0.0.1]:5000".parse().unwrap();
+        assert_eq!(normalize(mapped), "10.0.0.1:5000".parse().unwrap());
+
+        let v6: SocketAddr = "[2001:db8::1]:5000".parse().unwrap();
+        assert_eq!(normalize(v6), v6);
+        assert_eq!(DisplayAddr(v6).to_string(), "[2001:db8::1]:5000");
+        assert_eq!(join_host_port("2001:db8::1", 5000), "[2001:db8::1]:5000");
+    }
+
+    #[test]
+    fn parses_dscp() {
+        assert_eq!(parse_dscp("46"), Ok(46));
+        assert_eq!(parse_dscp("EF"), Ok(46));
+        assert_eq!(parse_dscp("af41"), Ok(34));
+        assert_eq!(parse_dscp("af13"), Ok(14));
+        assert_eq!(parse_dscp("cs1"), Ok(8));
+        assert!(parse_dscp("64").is_err());
+        assert!(parse_dscp("af51").is_err());
+        assert!(parse_dscp("cs").is_err());
+    }
+
+    #[tokio::test]
+    async fn connects_with_ttl() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        // Harmless to the other tests, which connect over loopback.
+        set_socket_options(SocketOptions {
+            ttl: Some(7),
+            ..Default::default()
+        });
+        let stream = connect(addr).await;
+        set_socket_options(SocketOptions::default());
+        assert_eq!(stream.unwrap().ttl().unwrap(), 7);
+    }
+
+    #[tokio::test]
+    async fn connects_from_outbound_bind() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let bind = OutboundBind {
+            ip: [127, 0, 0, 1].into(),
+            ports: Some((47631, 47632)),
+        };
+        assert_eq!(bind.to_string(), "127.0.0.1:47631-47632");
+
+        // The ports take turns, skipping those in use, until they're all in use.
+        let first = connect_from(bind, addr).await.unwrap();
+        let second = connect_from(bind, addr).await.unwrap();
+        let mut ports =
+            [first.local_addr().unwrap(), second.local_addr().unwrap()].map(|local| local.port());
+        ports.sort();
+        assert_eq!(ports, [47631, 47632]);
+        let err = connect_from(bind, addr).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AddrNotAvailable);
+
+        // Then the next bind address takes over, until it runs out of ports too.
+        let other = OutboundBind {
+            ip: [127, 0, 0, 2].into(),
+            ports: Some((47633, 47633)),
+        };
+        let third = connect_from_any(&[bind, other], &[addr]).await.unwrap();
+        assert_eq!(
+            third.local_addr().unwrap(),
+            "127.0.0.2:47633".parse().unwrap()
+        );
+        let err = connect_from_any(&[bind, other], &[addr]).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AddrNotAvailable);
+        assert!(err
+            .to_string()
+            .starts_with("no free port to connect from 127.0.0.2"));
+    }
+}
@@ -0,0 +1,308 @@
+use crate::format;
+use crate::json;
+use crate::record;
+use crate::sink::{self, LogTarget, Sink};
+use std::fmt::{Arguments, Display, Formatter};
+use std::io::{self, IsTerminal, Write};
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Clears the line the cursor is on and moves the cursor to its start.
+const CLEAR_LINE: &str = "\r\x1b[2K";
+
+/// The status line kept at the bottom of the terminal, if enabled.
+static STATUS: Mutex<Option<String>> = Mutex::new(None);
+
+/// Where the lines go instead of stdout and stderr, as set by `set_log_target`.
+static SINK: Mutex<Option<Sink>> = Mutex::new(None);
+
+/// Whether lines to stdout are JSON-RPC notifications, as set by `set_json_events`.
+static JSON_EVENTS: AtomicBool = AtomicBool::new(false);
+
+/// The timestamps put in front of every line, as set by `set_timestamps`.
+static TIMESTAMPS: AtomicU8 = AtomicU8::new(Timestamps::None as u8);
+
+/// The timestamps put in front of every line, to correlate events with other logs.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Timestamps {
+    /// No timestamps.
+    #[default]
+    None,
+
+    /// RFC 3339 in UTC, e.g. `2026-10-15T04:05:34.123Z`.
+    Utc,
+
+    /// RFC 3339 in the local time zone, e.g. `2026-10-15T06:05:34.123+02:00`.
+    Local,
+
+    /// Seconds since the Unix epoch, e.g. `1792037134.123`.
+    Epoch,
+}
+
+impl Timestamps {
+    /// Parses `none`, `utc`, `local` or `epoch`.
+    pub fn parse(value: &str) -> Result<Timestamps, String> {
+        match value {
+            "none" => Ok(Timestamps::None),
+            "utc" => Ok(Timestamps::Utc),
+            "local" => Ok(Timestamps::Local),
+            "epoch" => Ok(Timestamps::Epoch),
+            _ => Err(format!(
+                "Expected `none`, `utc`, `local` or `epoch` as the timestamps, got `{}`",
+                value
+            )),
+        }
+    }
+
+    /// The timestamps set by `set_timestamps`.
+    fn current() -> Timestamps {
+        match TIMESTAMPS.load(Ordering::Relaxed) {
+            1 => Timestamps::Utc,
+            2 => Timestamps::Local,
+            3 => Timestamps::Epoch,
+            _ => Timestamps::None,
+        }
+    }
+
+    /// Formats a time as a timestamp, if there are any.
+    fn format(self, time: SystemTime) -> Option<String> {
+        match self {
+            Timestamps::None => None,
+            Timestamps::Utc => Some(record::rfc3339(time)),
+            Timestamps::Local => Some(record::rfc3339_offset(time, local_offset(time))),
+            Timestamps::Epoch => {
+                let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+                Some(format!(
+                    "{}.{:03}",
+                    since_epoch.as_secs(),
+                    since_epoch.subsec_millis()
+                ))
+            }
+        }
+    }
+}
+
+impl Display for Timestamps {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Timestamps::None => write!(f, "none"),
+            Timestamps::Utc => write!(f, "utc"),
+            Timestamps::Local => write!(f, "local"),
+            Timestamps::Epoch => write!(f, "epoch"),
+        }
+    }
+}
+
+/// Puts timestamps in front of every line from now on.
+pub fn set_timestamps(timestamps: Timestamps) {
+    TIMESTAMPS.store(timestamps as u8, Ordering::Relaxed);
+}
+
+/// Sends the lines to a log target from now on. Lines that can't be sent are printed instead.
+pub fn set_log_target(target: &LogTarget) -> io::Result<()> {
+    let sink = target.connect()?;
+    *SINK.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) = sink;
+    Ok(())
+}
+
+/// Prints the lines to stdout as JSON-RPC `event` notifications from now on, e.g.
+/// `{"jsonrpc":"2.0","method":"event","params":{"event":"opened","line":"🟢 …"}}`, so they can
+/// go along with the responses of the stdio control mode.
+pub fn set_json_events(enabled: bool) {
+    JSON_EVENTS.store(enabled, Ordering::Relaxed);
+}
+
+/// Formats a line as an `event` notification, with the kind of event (if known) and the
+/// timestamp (if enabled).
+fn json_event(line: &str, timestamp: Option<String>) -> String {
+    let mut params = Vec::new();
+    if let Some(event) = sink::event(line) {
+        params.push(format!(r#""event":{}"#, json::quote(event)));
+    }
+    if let Some(timestamp) = timestamp {
+        params.push(format!(r#""time":{}"#, json::quote(&timestamp)));
+    }
+    params.push(format!(r#""line":{}"#, json::quote(line)));
+    format!(
+        r#"{{"jsonrpc":"2.0","method":"event","params":{{{}}}}}"#,
+        params.join(",")
+    )
+}
+
+/// How many seconds the local time zone is ahead of UTC at a time.
+#[cfg(unix)]
+fn local_offset(time: SystemTime) -> i64 {
+    let seconds = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let Ok(seconds) = libc::time_t::try_from(seconds) else {
+        return 0;
+    };
+
+    // SAFETY: `tm` is a valid, zeroed struct that `localtime_r` fills in.
+    unsafe {
+        let mut tm: libc::tm = std::mem::zeroed();
+        match libc::localtime_r(&seconds, &mut tm).is_null() {
+            true => 0,
+            false => tm.tm_gmtoff as i64,
+        }
+    }
+}
+
+/// The local time zone is taken to be UTC where it isn't looked up.
+#[cfg(not(unix))]
+fn local_offset(_time: SystemTime) -> i64 {
+    0
+}
+
+/// Prints a line to stdout like `println!`, above the status line if there is one.
+macro_rules! print_line {
+    ($($arg:tt)*) => {
+        $crate::output::line(format_args!($($arg)*), false)
+    };
+}
+
+/// Prints a line to stderr like `eprintln!`, above the status line if there is one.
+macro_rules! eprint_line {
+    ($($arg:tt)*) => {
+        $crate::output::line(format_args!($($arg)*), true)
+    };
+}
+
+pub(crate) use {eprint_line, print_line};
+
+/// Prints a line, with a timestamp if enabled, clearing the status line first and drawing it
+/// again below the line. With a log target, the line is sent there instead, which stamps it
+/// itself. Control characters are escaped, so what peers send can't mess with the terminal.
+pub fn line(args: Arguments<'_>, stderr: bool) {
+    let line = args.to_string();
+    let line = format::sanitize_line(&line);
+    if let Some(sink) = SINK
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .as_ref()
+    {
+        if sink.send(&line, stderr).is_ok() {
+            return;
+        }
+    }
+
+    let timestamp = Timestamps::current().format(SystemTime::now());
+    let line = match (stderr, timestamp) {
+        (false, timestamp) if JSON_EVENTS.load(Ordering::Relaxed) => json_event(&line, timestamp),
+        (_, Some(timestamp)) => format!("{} {}", timestamp, line),
+        (_, None) => line.into_owned(),
+    };
+
+    let status = STATUS
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    match (stderr, status.as_deref()) {
+        (false, Some(status)) => {
+            let _ = write_above_status(&mut std::io::stdout().lock(), &line, status);
+        }
+        (false, None) => println!("{}", line),
+        (true, Some(status)) => {
+            print!("{}", CLEAR_LINE);
+            eprintln!("{}", line);
+            print!("{}", status);
+            let _ = std::io::stdout().flush();
+        }
+        (true, None) => eprintln!("{}", line),
+    }
+}
+
+/// Writes a line where the status line is, and the status line again below it.
+fn write_above_status(out: &mut impl Write, line: &str, status: &str) -> io::Result<()> {
+    write!(out, "{}{}\n{}", CLEAR_LINE, line, status)?;
+    out.flush()
+}
+
+/// Prints a line to stdout as it is, e.g. a response in the stdio control mode.
+pub fn print_raw(line: &str) {
+    let _status = STATUS
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    println!("{}", line);
+}
+
+/// Keeps a status line at the bottom of the terminal from now on, if stdout is one. Returns
+/// whether it is.
+pub fn enable_status() -> bool {
+    if !std::io::stdout().is_terminal() {
+        return false;
+    }
+
+    let mut status = STATUS
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    status.get_or_insert_with(String::new);
+    true
+}
+
+/// Replaces the text of the status line, if it's enabled.
+pub fn set_status(text: String) {
+    let mut status = STATUS
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    if let Some(status) = status.as_mut() {
+        let _ = write_status(&mut std::io::stdout().lock(), &text);
+        *status = text;
+    }
+}
+
+/// Writes the status line over the one there was.
+fn write_status(out: &mut impl Write, text: &str) -> io::Result<()> {
+    write!(out, "{}{}", CLEAR_LINE, text)?;
+    out.flush()
+}
+
+/// Removes the status line for good, e.g. before exiting, so it doesn't end up in the middle
+/// of what is printed afterwards.
+pub fn remove_status() {
+    let mut status = STATUS
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    if status.take().is_some() {
+        print!("{}", CLEAR_LINE);
+        let _ = std::io::stdout().flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn formats_timestamps() {
+        let time = UNIX_EPOCH + Duration::from_millis(1_792_037_134_123);
+        assert_eq!(Timestamps::None.format(time), None);
+        assert_eq!(
+            Timestamps::Utc.format(time).unwrap(),
+            "2026-10-15T04:05:34.123Z"
+        );
+        assert_eq!(Timestamps::Epoch.format(time).unwrap(), "1792037134.123");
+        assert_eq!(Timestamps::parse("local"), Ok(Timestamps::Local));
+        assert!(Timestamps::parse("iso").is_err());
+
+        assert_eq!(
+            json_event("🟢 1 — \"new\"", None),
+            r#"{"jsonrpc":"2.0","method":"event","params":{"event":"opened","line":"🟢 1 — \"new\""}}"#
+        );
+    }
+
+    #[test]
+    fn keeps_the_status_line_at_the_bottom() {
+        let mut out = Vec::new();
+        write_status(&mut out, "📶 1 open").unwrap();
+        write_above_status(&mut out, "🟢     2 — new connection", "📶 1 open").unwrap();
+        write_status(&mut out, "📶 2 open").unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "\r\x1b[2K📶 1 open\r\x1b[2K🟢     2 — new connection\n📶 1 open\r\x1b[2K📶 2 open"
+        );
+    }
+}
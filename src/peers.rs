@@ -0,0 +1,144 @@
+use crate::reporter::PeerStats;
+use std::collections::{BTreeMap, HashMap};
+use std::net::IpAddr;
+use std::time::Duration;
+
+/// How many client addresses to keep the connections of, unless configured otherwise.
+pub const DEFAULT_MAX_PEERS: usize = 100_000;
+
+/// The connections per client address, keeping at most so many addresses: once there are more,
+/// those whose connections all closed the longest ago are forgotten.
+#[derive(Debug)]
+pub struct Peers {
+    /// The connections per client address, and when the last of them closed, if none are open.
+    stats: HashMap<IpAddr, (PeerStats, Option<u64>)>,
+
+    /// The addresses without open connections, by when the last of them closed, oldest first.
+    idle: BTreeMap<u64, IpAddr>,
+
+    /// Counts up as connections close, to order them.
+    closes: u64,
+
+    /// How many addresses to keep.
+    max: usize,
+
+    /// How many addresses were forgotten.
+    evicted: u64,
+}
+
+impl Peers {
+    /// Keeps the connections of at most `max` client addresses (though more while they all
+    /// have connections open), with room for `expected` of them up front.
+    pub fn new(max: usize, expected: usize) -> Self {
+        Peers {
+            stats: HashMap::with_capacity(expected.min(max)),
+            idle: BTreeMap::new(),
+            closes: 0,
+            max,
+            evicted: 0,
+        }
+    }
+
+    /// Counts a connection from the address opening, returning how many addresses were
+    /// forgotten to make room for it.
+    pub fn opened(&mut self, ip: IpAddr) -> u64 {
+        let (peer, closed_at) = self.stats.entry(ip).or_default();
+        if let Some(closed_at) = closed_at.take() {
+            self.idle.remove(&closed_at);
+        }
+        peer.open += 1;
+        peer.total += 1;
+
+        let mut evicted = 0;
+        while self.stats.len() > self.max {
+            let Some((_, ip)) = self.idle.pop_first() else {
+                break;
+            };
+            self.stats.remove(&ip);
+            evicted += 1;
+        }
+        self.evicted += evicted;
+        evicted
+    }
+
+    /// Counts a connection from the address closing after being open for `duration`.
+    pub fn closed(&mut self, ip: IpAddr, duration: Duration) {
+        let Some((peer, closed_at)) = self.stats.get_mut(&ip) else {
+            return;
+        };
+        peer.open = peer.open.saturating_sub(1);
+        peer.connected += duration;
+        if peer.open == 0 && closed_at.is_none() {
+            self.closes += 1;
+            *closed_at = Some(self.closes);
+            self.idle.insert(self.closes, ip);
+        }
+    }
+
+    /// The connections of an address, if it's kept.
+    pub fn get_mut(&mut self, ip: &IpAddr) -> Option<&mut PeerStats> {
+        self.stats.get_mut(ip).map(|(peer, _)| peer)
+    }
+
+    /// How many addresses are kept.
+    pub fn len(&self) -> usize {
+        self.stats.len()
+    }
+
+    /// How many addresses were forgotten so far.
+    pub fn evicted(&self) -> u64 {
+        self.evicted
+    }
+
+    /// How many addresses are kept at most.
+    pub fn max(&self) -> usize {
+        self.max
+    }
+
+    /// A copy of the connections per address.
+    pub fn to_map(&self) -> HashMap<IpAddr, PeerStats> {
+        self.stats
+            .iter()
+            .map(|(ip, (peer, _))| (*ip, *peer))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn evicts_least_recently_active() {
+        let ip = |n: u8| IpAddr::from([10, 0, 0, n]);
+        let mut peers = Peers::new(2, 2);
+
+        // Addresses with open connections are kept, even past the limit.
+        assert_eq!(peers.opened(ip(1)), 0);
+        assert_eq!(peers.opened(ip(2)), 0);
+        assert_eq!(peers.opened(ip(3)), 0);
+        assert_eq!(peers.len(), 3);
+
+        // Then the one whose connections closed the longest ago goes first, and one that
+        // opened another connection since isn't idle anymore.
+        peers.closed(ip(2), Duration::from_secs(1));
+        peers.closed(ip(1), Duration::from_secs(1));
+        peers.closed(ip(3), Duration::from_secs(1));
+        assert_eq!(peers.opened(ip(2)), 1);
+        assert_eq!(peers.opened(ip(4)), 1);
+        assert_eq!(peers.evicted(), 2);
+
+        let kept = peers.to_map();
+        assert_eq!(kept.len(), 2);
+        assert_eq!(
+            kept[&ip(2)],
+            PeerStats {
+                open: 1,
+                total: 2,
+                closed_with_error: 0,
+                connected: Duration::from_secs(1),
+            }
+        );
+        assert_eq!(kept[&ip(4)].open, 1);
+    }
+}
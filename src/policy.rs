@@ -0,0 +1,210 @@
+use crate::regex::Regex;
+use std::fmt::{Display, Formatter};
+
+/// A rule for connections whose first bytes match a pattern.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Policy {
+    /// What the first bytes are matched against.
+    pub pattern: Pattern,
+
+    /// What happens to matching connections.
+    pub action: Action,
+}
+
+/// A pattern for the first bytes of a connection.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Pattern {
+    /// A regex matching anywhere in the bytes, e.g. `/^GET \/admin/`.
+    Regex(Regex),
+
+    /// A byte sequence appearing anywhere in the bytes, e.g. `0x160301`.
+    Bytes(Vec<u8>),
+}
+
+/// What happens to a connection that matches a policy.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Action {
+    /// Close the connection without forwarding it.
+    Close,
+
+    /// Close the connection with a reset rather than a FIN, without forwarding it.
+    Reset,
+
+    /// Keep the connection open without forwarding it, discarding whatever the client sends.
+    Tarpit,
+
+    /// Forward the connection to another destination.
+    Route(String),
+
+    /// Forward the connection as usual, labeling it with a tag: `key=value`, or just a name
+    /// for the `tag` key.
+    Tag(String),
+}
+
+impl Action {
+    /// The label that a tag adds to the connection, as `(key, value)`.
+    pub fn label(&self) -> Option<(String, String)> {
+        let Action::Tag(tag) = self else {
+            return None;
+        };
+
+        let (key, value) = tag.split_once('=').unwrap_or(("tag", tag));
+        Some((key.trim().to_string(), value.trim().to_string()))
+    }
+}
+
+impl Policy {
+    /// Parses a policy: a pattern (`/regex/` or `0x` and hex digits) followed by an action
+    /// (`close`, `reset`, `tarpit`, `route <address>` or `tag <name>`).
+    fn parse(policy: &str) -> Result<Policy, String> {
+        let invalid = || {
+            format!(
+                "Expected a policy like `/regex/ close` or `0x160301 tag <name>`, got `{}`",
+                policy
+            )
+        };
+
+        let (pattern, action) = match policy.strip_prefix('/') {
+            Some(inner) => {
+                // The regex ends at the first `/` that isn't escaped.
+                let mut escaped = false;
+                let end = inner
+                    .char_indices()
+                    .find(|(_, c)| {
+                        let found = *c == '/' && !escaped;
+                        escaped = *c == '\\' && !escaped;
+                        found
+                    })
+                    .map(|(index, _)| index)
+                    .ok_or_else(invalid)?;
+                let regex = Regex::new(&inner[..end].replace("\\/", "/"))?;
+                (Pattern::Regex(regex), &inner[end + 1..])
+            }
+            None => {
+                let (hex, action) = policy.split_once(' ').ok_or_else(invalid)?;
+                let hex = hex.strip_prefix("0x").ok_or_else(invalid)?;
+                (Pattern::Bytes(parse_hex(hex).ok_or_else(invalid)?), action)
+            }
+        };
+
+        let action = match action.trim().split_once(' ') {
+            Some(("route", dest_addr)) => Action::Route(dest_addr.trim().to_string()),
+            Some(("tag", name)) => Action::Tag(name.trim().to_string()),
+            None if action.trim() == "close" => Action::Close,
+            None if action.trim() == "reset" => Action::Reset,
+            None if action.trim() == "tarpit" => Action::Tarpit,
+            _ => return Err(invalid()),
+        };
+
+        Ok(Policy { pattern, action })
+    }
+
+    /// Whether the first bytes of a connection match the policy.
+    fn matches(&self, bytes: &[u8]) -> bool {
+        match &self.pattern {
+            Pattern::Regex(regex) => regex.is_match(bytes),
+            Pattern::Bytes(signature) => bytes
+                .windows(signature.len())
+                .any(|window| window == signature),
+        }
+    }
+}
+
+impl Display for Policy {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match &self.pattern {
+            Pattern::Regex(regex) => write!(f, "/{}/", regex.as_str().replace('/', "\\/"))?,
+            Pattern::Bytes(bytes) => {
+                write!(f, "0x")?;
+                for byte in bytes {
+                    write!(f, "{:02x}", byte)?;
+                }
+            }
+        }
+        write!(f, " {}", self.action)
+    }
+}
+
+impl Display for Action {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Action::Close => write!(f, "close"),
+            Action::Reset => write!(f, "reset"),
+            Action::Tarpit => write!(f, "tarpit"),
+            Action::Route(dest_addr) => write!(f, "route {}", dest_addr),
+            Action::Tag(name) => write!(f, "tag {}", name),
+        }
+    }
+}
+
+/// Parses policies, separated by semicolons.
+pub fn parse_policies(value: &str) -> Result<Vec<Policy>, String> {
+    value
+        .split(';')
+        .map(str::trim)
+        .filter(|policy| !policy.is_empty())
+        .map(Policy::parse)
+        .collect()
+}
+
+/// Returns the policies that apply to a connection starting with the bytes, in order. Every
+/// matching `tag` applies, up to the first matching policy that decides where the connection
+/// goes (if any), which comes last.
+pub fn evaluate<'a>(policies: &'a [Policy], bytes: &[u8]) -> Vec<&'a Policy> {
+    let mut applied = Vec::new();
+    for policy in policies.iter().filter(|policy| policy.matches(bytes)) {
+        applied.push(policy);
+        if !matches!(policy.action, Action::Tag(_)) {
+            break;
+        }
+    }
+    applied
+}
+
+/// Parses an even number of hex digits.
+fn parse_hex(hex: &str) -> Option<Vec<u8>> {
+    if hex.is_empty() || !hex.len().is_multiple_of(2) {
+        return None;
+    }
+
+    (0..hex.len())
+        .step_by(2)
+        .map(|index| u8::from_str_radix(hex.get(index..index + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn applies_tags_until_a_decision() {
+        let policies = parse_policies(
+            r"0x160301 tag tls1.0; /^GET \/admin/ close; /curl\/\d/ tag curl; /^GET / route 127.0.0.1:9000",
+        )
+        .unwrap();
+        assert_eq!(policies[1].to_string(), r"/^GET \/admin/ close");
+        assert_eq!(policies[0].to_string(), "0x160301 tag tls1.0");
+
+        let applied = |bytes: &[u8]| {
+            evaluate(&policies, bytes)
+                .iter()
+                .map(|policy| policy.action.to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(applied(b"GET /admin HTTP/1.1\r\n"), ["close"]);
+        assert_eq!(
+            applied(b"GET / HTTP/1.1\r\nUser-Agent: curl/8.5.0\r\n"),
+            ["tag curl", "route 127.0.0.1:9000"]
+        );
+        assert_eq!(applied(b"\x16\x03\x01\x02\x00"), ["tag tls1.0"]);
+        assert!(applied(b"SSH-2.0-OpenSSH_9.6\r\n").is_empty());
+
+        let reset = parse_policies("/^QUIT/ reset").unwrap();
+        assert_eq!(reset[0].action, Action::Reset);
+        assert_eq!(reset[0].to_string(), "/^QUIT/ reset");
+
+        assert!(parse_policies("0x1 close").is_err());
+        assert!(parse_policies("/a/ drop").is_err());
+    }
+}
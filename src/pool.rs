@@ -0,0 +1,729 @@
+use crate::format::Elapsed;
+use crate::net;
+use crate::output::print_line;
+use crate::srv;
+use crate::usage;
+use std::collections::btree_map::{BTreeMap, Entry};
+use std::io;
+use std::pin::Pin;
+use std::sync::Mutex;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::TcpStream;
+use tokio::time::Instant;
+
+/// Prefix of pool destinations, e.g. `pool://10.0.0.1:80,10.0.0.2:80` or, with weights,
+/// `pool://10.0.0.1:80=3,10.0.0.2:80=1`.
+pub const PREFIX: &str = "pool://";
+
+/// How often the backends are health checked, unless configured otherwise.
+pub const DEFAULT_HEALTH_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How long a health check may take to connect before the backend counts as unhealthy.
+const HEALTH_CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// The part of its share a backend gets right after recovering, when warming up.
+const MIN_SHARE: f64 = 0.1;
+
+/// How long a slow backend is ejected, unless configured otherwise.
+pub const DEFAULT_EJECTION_TIME: Duration = Duration::from_secs(30);
+
+/// How much each new latency counts in a backend's moving average.
+const LATENCY_WEIGHT: f64 = 0.3;
+
+/// How many latencies a backend needs before its average is compared with the others'.
+const MIN_SAMPLES: u64 = 5;
+
+/// How many backends need enough latencies for a pool median to compare with.
+const MIN_BACKENDS: usize = 3;
+
+/// How the pools check their backends and bring back the ones that recover.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Settings {
+    /// How often to health check the backends.
+    pub health_check_interval: Duration,
+
+    /// How long a recovered backend takes to get its full share of the connections, if it
+    /// doesn't get it right away.
+    pub slow_start: Option<Duration>,
+
+    /// How many times the pool median a backend's latency may be before it's ejected, if slow
+    /// backends are ejected at all.
+    pub outlier_factor: Option<f64>,
+
+    /// How long slow backends are ejected, if not the default.
+    pub ejection_time: Option<Duration>,
+}
+
+// The outlier factor is never NaN, so comparing settings is an equivalence.
+impl Eq for Settings {}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            health_check_interval: DEFAULT_HEALTH_CHECK_INTERVAL,
+            slow_start: None,
+            outlier_factor: None,
+            ejection_time: None,
+        }
+    }
+}
+
+/// The settings of all pools, as set by `set_settings`.
+static SETTINGS: Mutex<Settings> = Mutex::new(Settings {
+    health_check_interval: DEFAULT_HEALTH_CHECK_INTERVAL,
+    slow_start: None,
+    outlier_factor: None,
+    ejection_time: None,
+});
+
+/// The backends of every pool connected to so far, by destination, each kept up to date by a
+/// health check task.
+static POOLS: Mutex<BTreeMap<String, Vec<Backend>>> = Mutex::new(BTreeMap::new());
+
+/// Sets how the pools check and warm up their backends from now on.
+pub fn set_settings(settings: Settings) {
+    *SETTINGS.lock().unwrap() = settings;
+}
+
+/// The settings set last.
+fn settings() -> Settings {
+    *SETTINGS.lock().unwrap()
+}
+
+/// A backend of a pool.
+#[derive(Debug, Clone)]
+struct Backend {
+    /// Its `host:port` address.
+    addr: String,
+
+    /// Whether it passed its last health check.
+    healthy: bool,
+
+    /// When it last recovered from a failed health check or came back from being ejected, if
+    /// it did.
+    recovered: Option<Instant>,
+
+    /// Until when it's ejected for being slow, if it is. It comes back with the next round of
+    /// health checks after that.
+    ejected_until: Option<Instant>,
+
+    /// How long connecting to it took lately.
+    connect_latency: Latency,
+
+    /// How long it took to send its first byte after being connected to, lately.
+    first_byte_latency: Latency,
+
+    /// Its share of the connections relative to the other backends.
+    weight: u32,
+
+    /// How many connections were forwarded to it.
+    connections: u64,
+}
+
+impl Backend {
+    /// A backend that is assumed to be healthy until checked.
+    fn new(addr: &str, weight: u32) -> Self {
+        Self {
+            addr: addr.to_string(),
+            healthy: true,
+            recovered: None,
+            ejected_until: None,
+            connect_latency: Latency::default(),
+            first_byte_latency: Latency::default(),
+            weight,
+            connections: 0,
+        }
+    }
+
+    /// How much of its share of the connections the backend gets: nothing while unhealthy or
+    /// ejected, and after recovering, a part growing linearly from `MIN_SHARE` to all of it over
+    /// the slow start window.
+    fn share(&self, now: Instant, slow_start: Option<Duration>) -> f64 {
+        if !self.healthy || self.ejected_until.is_some() {
+            return 0.0;
+        }
+
+        match (self.recovered, slow_start) {
+            (Some(recovered), Some(window)) if !window.is_zero() => {
+                let progress =
+                    now.saturating_duration_since(recovered).as_secs_f64() / window.as_secs_f64();
+                progress.clamp(MIN_SHARE, 1.0)
+            }
+            _ => 1.0,
+        }
+    }
+
+    /// Its latencies of a kind.
+    fn latency(&mut self, kind: LatencyKind) -> &mut Latency {
+        match kind {
+            LatencyKind::Connect => &mut self.connect_latency,
+            LatencyKind::FirstByte => &mut self.first_byte_latency,
+        }
+    }
+}
+
+/// The kinds of latencies of a backend that are compared with the rest of the pool.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum LatencyKind {
+    /// Connecting to it.
+    Connect,
+
+    /// From being connected to until it sends its first byte.
+    FirstByte,
+}
+
+impl LatencyKind {
+    /// The name used in lines about ejections.
+    fn name(self) -> &'static str {
+        match self {
+            LatencyKind::Connect => "connect",
+            LatencyKind::FirstByte => "first-byte",
+        }
+    }
+}
+
+/// A moving average of latencies.
+#[derive(Debug, Clone, Copy, Default)]
+struct Latency {
+    /// The average, with the latest latencies counting most.
+    average: Duration,
+
+    /// How many latencies were averaged.
+    samples: u64,
+}
+
+impl Latency {
+    /// Averages in a latency.
+    fn record(&mut self, latency: Duration) {
+        self.average = match self.samples {
+            0 => latency,
+            _ => self.average.mul_f64(1.0 - LATENCY_WEIGHT) + latency.mul_f64(LATENCY_WEIGHT),
+        };
+        self.samples += 1;
+    }
+
+    /// The average, once there are enough latencies to go by.
+    fn settled(&self) -> Option<Duration> {
+        (self.samples >= MIN_SAMPLES).then_some(self.average)
+    }
+}
+
+/// Checks that the backends look like `host:port,host:port`, optionally with weights like
+/// `host:port=3`.
+pub fn validate(backends: &str) -> Result<(), String> {
+    parse(backends).map(|_| ())
+}
+
+/// Splits the backends of a pool destination into their addresses and weights, which default
+/// to 1.
+fn parse(backends: &str) -> Result<Vec<(&str, u32)>, String> {
+    let parsed: Vec<(&str, u32)> = backends
+        .split(',')
+        .map(str::trim)
+        .filter(|backend| !backend.is_empty())
+        .map(|backend| {
+            let (addr, weight) = match backend.rsplit_once('=') {
+                Some((addr, weight)) => match weight.trim().parse() {
+                    Ok(weight) => (addr.trim(), weight),
+                    Err(_) => return Err(format!("`{}` has no valid weight", backend)),
+                },
+                None => (backend, 1),
+            };
+            match addr.rsplit_once(':') {
+                Some((host, port)) if !host.is_empty() && port.parse::<u16>().is_ok() => {
+                    Ok((addr, weight))
+                }
+                _ => Err(format!("backend `{}` is not a host:port address", addr)),
+            }
+        })
+        .collect::<Result<_, _>>()
+        .map_err(|err| format!("{} in `{}{}`", err, PREFIX, backends))?;
+
+    if parsed.is_empty() {
+        return Err(format!(
+            "`{}{}` has no backends, expected e.g. {}10.0.0.1:80,10.0.0.2:80",
+            PREFIX, backends, PREFIX
+        ));
+    }
+    Ok(parsed)
+}
+
+/// Connects to one of the healthy backends of a pool, picked at random by their share of the
+/// connections, failing over to the others. The first connection starts the task that health
+/// checks the backends.
+pub async fn connect(backends: &str) -> io::Result<Stream> {
+    let destination = format!("{}{}", PREFIX, backends);
+    let addrs = {
+        let mut pools = POOLS.lock().unwrap();
+        let pool = match pools.entry(destination.clone()) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                let parsed = parse(backends)
+                    .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+                usage::spawn(check_health(destination.clone()));
+                entry.insert(
+                    parsed
+                        .into_iter()
+                        .map(|(addr, weight)| Backend::new(addr, weight))
+                        .collect(),
+                )
+            }
+        };
+        let random = srv::random() as f64 / u64::MAX as f64;
+        pick(pool, Instant::now(), settings().slow_start, random)
+            .into_iter()
+            .map(|i| pool[i].addr.clone())
+            .collect::<Vec<_>>()
+    };
+
+    if addrs.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("{} has no healthy backends", destination),
+        ));
+    }
+
+    let mut last_err = None;
+    for addr in addrs {
+        let start = Instant::now();
+        match net::connect(addr.as_str()).await {
+            Ok(inner) => {
+                let connected = Instant::now();
+                if let Some(backend) = POOLS
+                    .lock()
+                    .unwrap()
+                    .get_mut(&destination)
+                    .and_then(|pool| pool.iter_mut().find(|backend| backend.addr == addr))
+                {
+                    backend.connections += 1;
+                }
+                record_latency(&destination, &addr, LatencyKind::Connect, connected - start);
+                return Ok(Stream {
+                    inner,
+                    destination,
+                    addr,
+                    connected: Some(connected),
+                });
+            }
+            Err(err) => last_err = Some(err),
+        }
+    }
+
+    Err(last_err.expect("at least one backend was tried"))
+}
+
+/// A connection to a backend of a pool, timing how long the backend takes to send its first
+/// byte.
+#[derive(Debug)]
+pub struct Stream {
+    /// The connection.
+    inner: TcpStream,
+
+    /// The pool destination.
+    destination: String,
+
+    /// The backend's address.
+    addr: String,
+
+    /// When it was connected, until the first byte arrived.
+    connected: Option<Instant>,
+}
+
+impl Stream {
+    /// The address of the backend.
+    pub fn addr(&self) -> &str {
+        &self.addr
+    }
+
+    /// The connection to the backend.
+    pub fn tcp(&self) -> &TcpStream {
+        &self.inner
+    }
+}
+
+impl AsyncRead for Stream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let filled = buf.filled().len();
+        let result = Pin::new(&mut this.inner).poll_read(cx, buf);
+        if buf.filled().len() > filled {
+            if let Some(connected) = this.connected.take() {
+                let latency = connected.elapsed();
+                record_latency(
+                    &this.destination,
+                    &this.addr,
+                    LatencyKind::FirstByte,
+                    latency,
+                );
+            }
+        }
+        result
+    }
+}
+
+impl AsyncWrite for Stream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+/// Averages in a latency of a backend, ejecting it if that makes it an outlier.
+fn record_latency(destination: &str, addr: &str, kind: LatencyKind, latency: Duration) {
+    let settings = settings();
+    let mut pools = POOLS.lock().unwrap();
+    let Some(pool) = pools.get_mut(destination) else {
+        return;
+    };
+    let Some(i) = pool.iter().position(|backend| backend.addr == addr) else {
+        return;
+    };
+    pool[i].latency(kind).record(latency);
+
+    let Some(factor) = settings.outlier_factor else {
+        return;
+    };
+    if let Some((latency, median)) = outlier(pool, i, kind, factor) {
+        let ejection_time = settings.ejection_time.unwrap_or(DEFAULT_EJECTION_TIME);
+        pool[i].ejected_until = Some(Instant::now() + ejection_time);
+        print_line!(
+            "⏏️  ejected backend {} of {} for {}: its {} latency of {} is {:.1}× the pool median of {}",
+            addr,
+            destination,
+            Elapsed(ejection_time),
+            kind.name(),
+            Elapsed(latency),
+            latency.as_secs_f64() / median.as_secs_f64(),
+            Elapsed(median)
+        );
+    }
+}
+
+/// Whether a backend's latency of a kind is more than `factor` times the median of the pool,
+/// returning its latency and the median if so. Only backends that aren't ejected and have enough
+/// latencies count, and at most half of the pool is ejected at once.
+fn outlier(
+    pool: &mut [Backend],
+    i: usize,
+    kind: LatencyKind,
+    factor: f64,
+) -> Option<(Duration, Duration)> {
+    if pool[i].ejected_until.is_some() {
+        return None;
+    }
+    let latency = pool[i].latency(kind).settled()?;
+
+    let ejected = pool
+        .iter()
+        .filter(|backend| backend.ejected_until.is_some())
+        .count();
+    let mut latencies: Vec<Duration> = pool
+        .iter_mut()
+        .filter(|backend| backend.ejected_until.is_none())
+        .filter_map(|backend| backend.latency(kind).settled())
+        .collect();
+    if latencies.len() < MIN_BACKENDS || (ejected + 1) * 2 > pool.len() {
+        return None;
+    }
+
+    latencies.sort();
+    let middle = latencies.len() / 2;
+    let median = match latencies.len() % 2 {
+        0 => (latencies[middle - 1] + latencies[middle]) / 2,
+        _ => latencies[middle],
+    };
+    (latency.as_secs_f64() > median.as_secs_f64() * factor).then_some((latency, median))
+}
+
+/// The healthy backends to try, in order: one picked by `random` (from 0 to 1) weighted by
+/// their weights and shares, then the others to fail over to.
+fn pick(
+    backends: &[Backend],
+    now: Instant,
+    slow_start: Option<Duration>,
+    random: f64,
+) -> Vec<usize> {
+    let shares: Vec<f64> = backends
+        .iter()
+        .map(|backend| backend.share(now, slow_start) * backend.weight as f64)
+        .collect();
+    let mut order: Vec<usize> = (0..backends.len()).filter(|&i| shares[i] > 0.0).collect();
+
+    let mut remaining = random * shares.iter().sum::<f64>();
+    let mut picked = order.len().saturating_sub(1);
+    for (position, &i) in order.iter().enumerate() {
+        if remaining < shares[i] {
+            picked = position;
+            break;
+        }
+        remaining -= shares[i];
+    }
+
+    order.rotate_left(picked);
+    order
+}
+
+/// Health checks the backends of a pool by connecting to them, forever.
+async fn check_health(destination: String) {
+    loop {
+        bring_back_ejected(&destination);
+        let addrs: Vec<String> = match POOLS.lock().unwrap().get(&destination) {
+            Some(pool) => pool.iter().map(|backend| backend.addr.clone()).collect(),
+            None => return,
+        };
+
+        let checks: Vec<_> = addrs
+            .into_iter()
+            .map(|addr| {
+                usage::spawn(async move {
+                    let result =
+                        match tokio::time::timeout(HEALTH_CHECK_TIMEOUT, net::connect(&addr)).await
+                        {
+                            Ok(Ok(_)) => Ok(()),
+                            Ok(Err(err)) => Err(err.to_string()),
+                            Err(_) => Err(format!(
+                                "not connected within {}",
+                                Elapsed(HEALTH_CHECK_TIMEOUT)
+                            )),
+                        };
+                    (addr, result)
+                })
+            })
+            .collect();
+        for check in checks {
+            if let Ok((addr, result)) = check.await {
+                record_check(&destination, &addr, result);
+            }
+        }
+
+        tokio::time::sleep(settings().health_check_interval).await;
+    }
+}
+
+/// Brings back the backends of a pool whose ejection is over, starting their latencies afresh.
+fn bring_back_ejected(destination: &str) {
+    let now = Instant::now();
+    let slow_start = settings().slow_start;
+    let mut pools = POOLS.lock().unwrap();
+    let Some(pool) = pools.get_mut(destination) else {
+        return;
+    };
+
+    for backend in pool {
+        if backend.ejected_until.is_none_or(|until| until > now) {
+            continue;
+        }
+
+        backend.ejected_until = None;
+        backend.recovered = Some(now);
+        backend.connect_latency = Latency::default();
+        backend.first_byte_latency = Latency::default();
+        match slow_start {
+            Some(window) => print_line!(
+                "💚 backend {} of {} is back after its ejection, warming up over {}",
+                backend.addr,
+                destination,
+                Elapsed(window)
+            ),
+            None => print_line!(
+                "💚 backend {} of {} is back after its ejection",
+                backend.addr,
+                destination
+            ),
+        }
+    }
+}
+
+/// Marks a backend healthy or unhealthy, printing when that changed.
+fn record_check(destination: &str, addr: &str, result: Result<(), String>) {
+    let mut pools = POOLS.lock().unwrap();
+    let Some(backend) = pools
+        .get_mut(destination)
+        .and_then(|pool| pool.iter_mut().find(|backend| backend.addr == addr))
+    else {
+        return;
+    };
+
+    match (backend.healthy, result) {
+        (true, Err(err)) => {
+            backend.healthy = false;
+            backend.recovered = None;
+            print_line!(
+                "💔 backend {} of {} failed its health check: {}",
+                addr,
+                destination,
+                err
+            );
+        }
+        (false, Ok(())) => {
+            backend.healthy = true;
+            backend.recovered = Some(Instant::now());
+            match settings().slow_start {
+                Some(window) => print_line!(
+                    "💚 backend {} of {} is healthy again, warming up over {}",
+                    addr,
+                    destination,
+                    Elapsed(window)
+                ),
+                None => print_line!("💚 backend {} of {} is healthy again", addr, destination),
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Changes the weight of a backend in every pool it's in, returning in how many.
+pub fn set_weight(addr: &str, weight: u32) -> usize {
+    let mut pools = POOLS.lock().unwrap();
+    let mut changed = 0;
+    for backend in pools.values_mut().flatten() {
+        if backend.addr == addr {
+            backend.weight = weight;
+            changed += 1;
+        }
+    }
+    changed
+}
+
+/// Describes the backends of every pool connected to so far, one per line, e.g.
+/// `pool://10.0.0.1:80,10.0.0.2:80 10.0.0.2:80: healthy, warming up (40%), weight 1, 12
+/// connection(s), connect 1.02ms, first byte 31.2ms`.
+pub fn describe() -> Vec<String> {
+    let now = Instant::now();
+    let slow_start = settings().slow_start;
+    let pools = POOLS.lock().unwrap();
+    pools
+        .iter()
+        .flat_map(|(destination, pool)| {
+            pool.iter().map(move |backend| {
+                let share = backend.share(now, slow_start);
+                let state = match (backend.healthy, backend.ejected_until, share < 1.0) {
+                    (false, _, _) => "unhealthy".to_string(),
+                    (true, Some(until), _) => format!(
+                        "ejected ({} left)",
+                        Elapsed(until.saturating_duration_since(now))
+                    ),
+                    (true, None, true) => format!("healthy, warming up ({:.0}%)", share * 100.0),
+                    (true, None, false) => "healthy".to_string(),
+                };
+                let latency = |latency: Latency| match latency.samples {
+                    0 => "n/a".to_string(),
+                    _ => Elapsed(latency.average).to_string(),
+                };
+                format!(
+                    "{} {}: {}, weight {}, {} connection(s), connect {}, first byte {}",
+                    destination,
+                    backend.addr,
+                    state,
+                    backend.weight,
+                    backend.connections,
+                    latency(backend.connect_latency),
+                    latency(backend.first_byte_latency)
+                )
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn warms_up_recovered_backends() {
+        let mut backends: Vec<Backend> = ["10.0.0.1:80", "10.0.0.2:80", "10.0.0.3:80"]
+            .into_iter()
+            .map(|addr| Backend::new(addr, 1))
+            .collect();
+        let slow_start = Some(Duration::from_secs(100));
+        let now = Instant::now();
+
+        // Unhealthy backends get nothing, the others are picked by their shares.
+        backends[0].healthy = false;
+        assert_eq!(pick(&backends, now, slow_start, 0.0), vec![1, 2]);
+        assert_eq!(pick(&backends, now, slow_start, 0.7), vec![2, 1]);
+
+        // A recovered backend starts with a tenth of its share, growing to all of it.
+        backends[0].healthy = true;
+        backends[0].recovered = Some(now);
+        assert_eq!(backends[0].share(now, slow_start), MIN_SHARE);
+        assert_eq!(pick(&backends, now, slow_start, 0.04), vec![0, 1, 2]);
+        assert_eq!(pick(&backends, now, slow_start, 0.06), vec![1, 2, 0]);
+
+        let later = now + Duration::from_secs(40);
+        assert_eq!(backends[0].share(later, slow_start), 0.4);
+        assert_eq!(
+            backends[0].share(later + Duration::from_secs(60), slow_start),
+            1.0
+        );
+        assert_eq!(backends[0].share(now, None), 1.0);
+
+        for backend in &mut backends {
+            backend.healthy = false;
+        }
+        assert!(pick(&backends, now, slow_start, 0.5).is_empty());
+    }
+
+    #[test]
+    fn ejects_outliers() {
+        let mut pool: Vec<Backend> = (1..=4)
+            .map(|i| Backend::new(&format!("10.0.0.{}:80", i), 1))
+            .collect();
+        for (backend, millis) in pool.iter_mut().zip([10, 12, 20, 100]) {
+            for _ in 0..MIN_SAMPLES {
+                backend
+                    .first_byte_latency
+                    .record(Duration::from_millis(millis));
+            }
+        }
+
+        // The median is 16ms, which the last backend exceeds more than 5 times.
+        let median = Duration::from_millis(16);
+        let slowest = outlier(&mut pool, 3, LatencyKind::FirstByte, 5.0);
+        assert_eq!(slowest, Some((Duration::from_millis(100), median)));
+        assert_eq!(outlier(&mut pool, 2, LatencyKind::FirstByte, 5.0), None);
+        assert_eq!(outlier(&mut pool, 3, LatencyKind::FirstByte, 10.0), None);
+        assert_eq!(outlier(&mut pool, 3, LatencyKind::Connect, 5.0), None);
+
+        // At most half of the pool is ejected.
+        pool[3].ejected_until = Some(Instant::now());
+        pool[2].ejected_until = Some(Instant::now());
+        assert_eq!(outlier(&mut pool, 1, LatencyKind::FirstByte, 1.0), None);
+    }
+
+    #[test]
+    fn weighs_backends() {
+        let parsed = parse("10.0.0.1:80=3, 10.0.0.2:80").unwrap();
+        assert_eq!(parsed, vec![("10.0.0.1:80", 3), ("10.0.0.2:80", 1)]);
+        assert!(parse("10.0.0.1:80=x").is_err());
+        assert!(parse(",").is_err());
+
+        // Three quarters of the connections go to the first backend, none to a backend weighing 0.
+        let now = Instant::now();
+        let mut backends: Vec<Backend> = parsed
+            .into_iter()
+            .map(|(addr, weight)| Backend::new(addr, weight))
+            .collect();
+        assert_eq!(pick(&backends, now, None, 0.74), vec![0, 1]);
+        assert_eq!(pick(&backends, now, None, 0.76), vec![1, 0]);
+        backends[1].weight = 0;
+        assert_eq!(pick(&backends, now, None, 0.99), vec![0]);
+    }
+}
@@ -0,0 +1,367 @@
+use crate::format;
+use crate::protocol::{Parser, Protocol, Stats};
+
+/// How many bytes of a message body are kept, enough for the parameters of a startup message.
+const MAX_KEPT: usize = 1024;
+
+/// The codes of the untyped requests a client may open a connection with, instead of a startup
+/// message.
+const SSL_REQUEST: u32 = 80877103;
+const GSSENC_REQUEST: u32 = 80877104;
+const CANCEL_REQUEST: u32 = 80877102;
+
+/// The longest startup message servers accept.
+const MAX_STARTUP: u32 = 10000;
+
+/// A message, with the first `MAX_KEPT` bytes of its body.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Message {
+    /// The message type, or 0 for the untyped messages a connection starts with.
+    kind: u8,
+
+    /// The start of the body.
+    body: Vec<u8>,
+}
+
+/// Reassembles the messages of one direction from the bytes as they're read: a type byte
+/// (unless untyped), a length that includes itself, and the body.
+#[derive(Debug, Default)]
+struct Frames {
+    /// The header and the kept body of the current message.
+    current: Vec<u8>,
+
+    /// How many body bytes of the current message are still to come, once its header is in.
+    remaining: Option<usize>,
+}
+
+impl Frames {
+    /// Reads at most one message from the front of `bytes`, returning how many bytes it used
+    /// and the message if it's complete. Returns `None` if the length can't be right: too short
+    /// to include itself, or too long for a startup message.
+    fn next(&mut self, bytes: &[u8], typed: bool) -> Option<(usize, Option<Message>)> {
+        let header_len = if typed { 5 } else { 4 };
+        let mut used = 0;
+        let remaining = match self.remaining {
+            Some(remaining) => remaining,
+            None => {
+                used = (header_len - self.current.len()).min(bytes.len());
+                self.current.extend_from_slice(&bytes[..used]);
+                if self.current.len() < header_len {
+                    return Some((used, None));
+                }
+                let len = &self.current[header_len - 4..header_len];
+                let len = u32::from_be_bytes(len.try_into().unwrap());
+                if len < 4 || (!typed && len > MAX_STARTUP) {
+                    return None;
+                }
+                (len - 4) as usize
+            }
+        };
+
+        let take = remaining.min(bytes.len() - used);
+        let room = (header_len + MAX_KEPT).saturating_sub(self.current.len());
+        self.current
+            .extend_from_slice(&bytes[used..used + take.min(room)]);
+        used += take;
+        if remaining > take {
+            self.remaining = Some(remaining - take);
+            return Some((used, None));
+        }
+
+        self.remaining = None;
+        let message = std::mem::take(&mut self.current);
+        let kind = if typed { message[0] } else { 0 };
+        let body = message[header_len..].to_vec();
+        Some((used, Some(Message { kind, body })))
+    }
+}
+
+/// Where a connection is at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+enum State {
+    /// Waiting for the startup message, or a request to encrypt or cancel.
+    #[default]
+    Startup,
+
+    /// The client asked to encrypt the connection, waiting for the server's single-byte answer.
+    AwaitingEncryption,
+
+    /// Exchanging typed messages.
+    Messages,
+
+    /// Encrypted, or not understood, so nothing more is counted.
+    Done,
+}
+
+/// Counts the queries, rows and errors of a PostgreSQL connection, and what it negotiated.
+#[derive(Debug, Default)]
+pub struct Postgres {
+    /// Where the connection is at.
+    state: State,
+
+    /// The messages from the client.
+    client: Frames,
+
+    /// The messages from the server.
+    server: Frames,
+
+    /// How many simple queries (`Query` messages) the client sent.
+    queries: u64,
+
+    /// How many extended queries (`Execute` messages) the client sent.
+    executes: u64,
+
+    /// How many rows the server sent (`DataRow` messages).
+    rows: u64,
+
+    /// How many errors the server sent (`ErrorResponse` messages).
+    errors: u64,
+
+    /// The user and database of the startup message.
+    user: Option<String>,
+    database: Option<String>,
+
+    /// How the client asked to encrypt the connection (`SSL` or `GSSAPI`), if it did, and how
+    /// the server answered.
+    encryption: Option<(&'static str, &'static str)>,
+
+    /// Whether the connection only cancels a query of another one.
+    cancel: bool,
+}
+
+impl Postgres {
+    /// Reads the parameters of a startup message: a protocol version, then pairs of
+    /// null-terminated names and values.
+    fn startup(&mut self, body: &[u8]) {
+        let mut strings = body[4..].split(|&b| b == 0);
+        while let (Some(name), Some(value)) = (strings.next(), strings.next()) {
+            let value = format::sanitize(value);
+            match name {
+                b"user" => self.user = Some(value),
+                b"database" => self.database = Some(value),
+                _ => {}
+            }
+        }
+    }
+}
+
+impl Parser for Postgres {
+    fn client(&mut self, mut bytes: &[u8]) {
+        while !bytes.is_empty() {
+            let typed = match self.state {
+                State::Startup => false,
+                State::Messages => true,
+                State::AwaitingEncryption | State::Done => return,
+            };
+            let Some((used, message)) = self.client.next(bytes, typed) else {
+                self.state = State::Done;
+                return;
+            };
+            bytes = &bytes[used..];
+            let Some(message) = message else {
+                continue;
+            };
+
+            match message.kind {
+                0 if message.body.len() >= 4 => {
+                    match u32::from_be_bytes(message.body[..4].try_into().unwrap()) {
+                        SSL_REQUEST => {
+                            self.encryption = Some(("SSL", "requested"));
+                            self.state = State::AwaitingEncryption;
+                        }
+                        GSSENC_REQUEST => {
+                            self.encryption = Some(("GSSAPI", "requested"));
+                            self.state = State::AwaitingEncryption;
+                        }
+                        CANCEL_REQUEST => {
+                            self.cancel = true;
+                            self.state = State::Done;
+                        }
+                        _ => {
+                            self.startup(&message.body);
+                            self.state = State::Messages;
+                        }
+                    }
+                }
+                0 => self.state = State::Done,
+                b'Q' => self.queries += 1,
+                b'E' => self.executes += 1,
+                _ => {}
+            }
+        }
+    }
+
+    fn server(&mut self, mut bytes: &[u8]) {
+        if self.state == State::AwaitingEncryption {
+            let Some((&answer, rest)) = bytes.split_first() else {
+                return;
+            };
+            // The server agrees with `S` (or `G` for GSSAPI), after which the connection is
+            // encrypted, or declines with `N` and the client goes on in plain text.
+            let accepted = matches!(answer, b'S' | b'G');
+            if let Some((_, outcome)) = &mut self.encryption {
+                *outcome = if accepted { "accepted" } else { "declined" };
+            }
+            self.state = if accepted {
+                State::Done
+            } else {
+                State::Startup
+            };
+            bytes = rest;
+        }
+
+        while !bytes.is_empty() && self.state == State::Messages {
+            let Some((used, message)) = self.server.next(bytes, true) else {
+                self.state = State::Done;
+                return;
+            };
+            bytes = &bytes[used..];
+            match message.map(|message| message.kind) {
+                Some(b'D') => self.rows += 1,
+                Some(b'E') => self.errors += 1,
+                _ => {}
+            }
+        }
+    }
+
+    fn stats(&self) -> Stats {
+        let mut details = Vec::new();
+        if let Some(user) = &self.user {
+            details.push(("user", user.clone()));
+        }
+        if let Some(database) = &self.database {
+            details.push(("database", database.clone()));
+        }
+        if let Some((method, outcome)) = self.encryption {
+            details.push((method, outcome.to_string()));
+        }
+        if self.cancel {
+            details.push(("cancel", "request".to_string()));
+        }
+
+        Stats {
+            protocol: Protocol::Postgres,
+            counts: vec![
+                ("queries", self.queries),
+                ("extended queries", self.executes),
+                ("rows", self.rows),
+                ("errors", self.errors),
+            ],
+            timings: Vec::new(),
+            details,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A typed message.
+    fn message(kind: u8, body: &[u8]) -> Vec<u8> {
+        let mut message = vec![kind];
+        message.extend_from_slice(&(body.len() as u32 + 4).to_be_bytes());
+        message.extend_from_slice(body);
+        message
+    }
+
+    #[test]
+    fn counts_queries() {
+        let mut postgres = Postgres::default();
+
+        // SSL is declined, after which the client starts up in plain text.
+        postgres.client(&[0, 0, 0, 8, 0x04, 0xd2, 0x16, 0x2f]);
+        postgres.server(b"N");
+        let mut startup = 196608u32.to_be_bytes().to_vec();
+        startup.extend_from_slice(b"user\0app\0database\0orders\0\0");
+        let mut startup_message = (startup.len() as u32 + 4).to_be_bytes().to_vec();
+        startup_message.extend_from_slice(&startup);
+        postgres.client(&startup_message);
+
+        // A simple query, split across reads, and an extended one.
+        let query = message(b'Q', b"SELECT 1\0");
+        postgres.client(&query[..3]);
+        postgres.client(&query[3..]);
+        let mut extended = message(b'P', b"\0SELECT $1\0\0\0");
+        extended.extend(message(b'B', &[0; 10]));
+        extended.extend(message(b'E', b"\0\0\0\0\0"));
+        extended.extend(message(b'S', b""));
+        postgres.client(&extended);
+
+        let mut replies = message(b'D', &[0, 1, 0, 0, 0, 1, b'1']);
+        replies.extend(message(b'C', b"SELECT 1\0"));
+        replies.extend(message(b'E', b"SERROR\0\0"));
+        postgres.server(&replies);
+
+        assert_eq!(
+            postgres.stats().to_string(),
+            "1 queries, 1 extended queries, 1 rows, 1 errors (user app, database orders, SSL declined)"
+        );
+
+        // Once SSL is accepted, nothing more can be seen.
+        let mut postgres = Postgres::default();
+        postgres.client(&[0, 0, 0, 8, 0x04, 0xd2, 0x16, 0x2f]);
+        postgres.server(b"S");
+        postgres.client(&[0x16, 0x03, 0x01, 0x00, 0x05]);
+        assert_eq!(
+            postgres.stats().to_string(),
+            "0 queries, 0 extended queries, 0 rows, 0 errors (SSL accepted)"
+        );
+    }
+
+    #[test]
+    fn gives_up_on_garbage() {
+        // A truncated startup message is waited for, and counts nothing.
+        let mut postgres = Postgres::default();
+        postgres.client(&[0, 0, 0]);
+        postgres.client(&[40, 0, 3, 0, 0, b'u', b's']);
+        postgres.server(&message(b'D', b""));
+        assert_eq!(
+            postgres.stats().to_string(),
+            "0 queries, 0 extended queries, 0 rows, 0 errors"
+        );
+
+        // Names that aren't UTF-8 are shown sanitized.
+        let mut postgres = Postgres::default();
+        let mut startup = 196608u32.to_be_bytes().to_vec();
+        startup.extend_from_slice(b"user\0\xff\xfe\x1b\0database\0\0");
+        let mut startup_message = (startup.len() as u32 + 4).to_be_bytes().to_vec();
+        startup_message.extend_from_slice(&startup);
+        postgres.client(&startup_message);
+        let stats = postgres.stats().to_string();
+        assert!(stats.starts_with("0 queries"), "{}", stats);
+        assert!(!stats.contains('\x1b'), "{}", stats);
+
+        // Startup messages that are too long, or lengths too short to include themselves, end
+        // the parsing.
+        for garbage in [
+            &[0xff, 0xff, 0xff, 0xff, 0, 3, 0, 0][..],
+            &[0, 0, 0, 2, 0, 3, 0, 0],
+            b"GET / HTTP/1.1\r\n\r\n",
+        ] {
+            let mut postgres = Postgres::default();
+            postgres.client(garbage);
+            postgres.client(&message(b'Q', b"SELECT 1\0"));
+            assert_eq!(postgres.state, State::Done, "{:?}", garbage);
+            assert_eq!(postgres.queries, 0);
+        }
+
+        // As do messages from the server with a broken length, while a huge one is skipped.
+        let mut postgres = Postgres {
+            state: State::Messages,
+            ..Postgres::default()
+        };
+        postgres.server(&[b'D', 0xff, 0xff, 0xff, 0xff, 1, 2, 3]);
+        postgres.server(&message(b'D', b""));
+        assert_eq!((postgres.rows, postgres.state), (0, State::Messages));
+        let mut postgres = Postgres {
+            state: State::Messages,
+            ..Postgres::default()
+        };
+        postgres.server(&[b'D', 0, 0, 0, 0]);
+        postgres.server(&message(b'D', b""));
+        assert_eq!((postgres.rows, postgres.state), (0, State::Done));
+        postgres.client(&message(b'Q', b"SELECT 1\0"));
+        assert_eq!(postgres.queries, 0);
+    }
+}
@@ -0,0 +1,284 @@
+use crate::exchanges::Exchanges;
+use crate::format::Elapsed;
+use crate::mqtt::Session;
+use crate::mysql::Mysql;
+use crate::postgres::Postgres;
+use crate::resp::Resp;
+use crate::smtp::Smtp;
+use crate::ssh::Ssh;
+use std::fmt::{Display, Formatter};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::io::{AsyncRead, ReadBuf};
+
+/// A protocol whose messages are looked into, to count what connections do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Protocol {
+    /// The PostgreSQL frontend/backend protocol.
+    Postgres,
+
+    /// RESP, Redis's protocol.
+    Redis,
+
+    /// The MySQL client/server protocol.
+    Mysql,
+
+    /// MQTT.
+    Mqtt,
+
+    /// SSH, up to where it's encrypted.
+    Ssh,
+
+    /// SMTP, up to where it's encrypted.
+    Smtp,
+
+    /// Any protocol where the client asks and the server answers, by guessing which bytes are
+    /// requests and which are responses.
+    Generic,
+}
+
+impl Protocol {
+    /// Parses a protocol by its name, e.g. `postgres`.
+    pub fn parse(value: &str) -> Result<Protocol, String> {
+        match value {
+            "postgres" => Ok(Protocol::Postgres),
+            "redis" => Ok(Protocol::Redis),
+            "mysql" => Ok(Protocol::Mysql),
+            "mqtt" => Ok(Protocol::Mqtt),
+            "ssh" => Ok(Protocol::Ssh),
+            "smtp" => Ok(Protocol::Smtp),
+            "generic" => Ok(Protocol::Generic),
+            _ => Err(format!(
+                "Expected a protocol like `postgres`, `redis`, `mysql`, `mqtt`, `ssh`, `smtp` or `generic`, got `{}`",
+                value
+            )),
+        }
+    }
+
+    /// Creates a parser for one connection.
+    pub fn parser(self) -> Box<dyn Parser> {
+        match self {
+            Protocol::Postgres => Box::<Postgres>::default(),
+            Protocol::Redis => Box::<Resp>::default(),
+            Protocol::Mysql => Box::<Mysql>::default(),
+            Protocol::Mqtt => Box::<Session>::default(),
+            Protocol::Ssh => Box::<Ssh>::default(),
+            Protocol::Smtp => Box::<Smtp>::default(),
+            Protocol::Generic => Box::<Exchanges>::default(),
+        }
+    }
+}
+
+impl Display for Protocol {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Protocol::Postgres => write!(f, "postgres"),
+            Protocol::Redis => write!(f, "redis"),
+            Protocol::Mysql => write!(f, "mysql"),
+            Protocol::Mqtt => write!(f, "mqtt"),
+            Protocol::Ssh => write!(f, "ssh"),
+            Protocol::Smtp => write!(f, "smtp"),
+            Protocol::Generic => write!(f, "generic"),
+        }
+    }
+}
+
+/// Follows the messages of a connection as the bytes flow by in either direction. Nothing is
+/// held back, and bytes that aren't understood are given up on.
+pub trait Parser: Send {
+    /// Looks at the next bytes the client sent.
+    fn client(&mut self, bytes: &[u8]);
+
+    /// Looks at the next bytes the server sent.
+    fn server(&mut self, bytes: &[u8]);
+
+    /// What was counted so far.
+    fn stats(&self) -> Stats;
+}
+
+/// What a connection did, as counted by its protocol's parser.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Stats {
+    /// The protocol.
+    pub protocol: Protocol,
+
+    /// The counts, by what was counted in the plural, e.g. `("queries", 12)`.
+    pub counts: Vec<(&'static str, u64)>,
+
+    /// How long phases took, e.g. `("key exchange", 35ms)`.
+    pub timings: Vec<(&'static str, Duration)>,
+
+    /// What was negotiated, e.g. `("database", "orders")`.
+    pub details: Vec<(&'static str, String)>,
+}
+
+impl Display for Stats {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let counts = self
+            .counts
+            .iter()
+            .map(|(name, count)| format!("{} {}", count, name));
+        let timings = self
+            .timings
+            .iter()
+            .map(|(name, duration)| format!("{} {}", name, Elapsed(*duration)));
+        let measured: Vec<String> = counts.chain(timings).collect();
+        let details: Vec<String> = self
+            .details
+            .iter()
+            .map(|(name, value)| format!("{} {}", name, value))
+            .collect();
+        match (measured.is_empty(), details.is_empty()) {
+            (_, true) => write!(f, "{}", measured.join(", ")),
+            (true, false) => write!(f, "{}", details.join(", ")),
+            (false, false) => write!(f, "{} ({})", measured.join(", "), details.join(", ")),
+        }
+    }
+}
+
+/// The details that take few values, like versions and negotiated options, so they can be
+/// counted in the summary. The others, like user names, client IDs and banners, are chosen by
+/// the peers and only shown with each connection.
+const COUNTED_DETAILS: [&str; 10] = [
+    "MQTT",
+    "keep alive",
+    "SSL",
+    "GSSAPI",
+    "cancel",
+    "server",
+    "TLS",
+    "compression",
+    "STARTTLS",
+    "largest batch",
+];
+
+impl Stats {
+    /// The details to count in the summary.
+    pub fn counted_details(&self) -> impl Iterator<Item = &(&'static str, String)> {
+        self.details
+            .iter()
+            .filter(|(name, _)| COUNTED_DETAILS.contains(name))
+    }
+}
+
+/// A parser shared by both directions of a connection.
+pub type SharedParser = Arc<Mutex<Box<dyn Parser>>>;
+
+/// Which side of a connection a reader is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Side {
+    /// What the client sends.
+    Client,
+
+    /// What the server sends.
+    Server,
+}
+
+/// Wraps one side of a connection, passing what's read to its parser.
+pub struct Watch<R> {
+    /// The reader being watched.
+    inner: R,
+
+    /// Which side it is.
+    side: Side,
+
+    /// The parser, if watching at all.
+    parser: Option<SharedParser>,
+}
+
+impl<R> Watch<R> {
+    /// Watches the reader if there's a parser, otherwise just passes the bytes through.
+    pub fn new(inner: R, side: Side, parser: Option<SharedParser>) -> Self {
+        Watch {
+            inner,
+            side,
+            parser,
+        }
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for Watch<R> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let this = self.get_mut();
+        let before = buf.filled().len();
+        let result = Pin::new(&mut this.inner).poll_read(cx, buf);
+        let read = &buf.filled()[before..];
+        if let (Some(parser), false) = (&this.parser, read.is_empty()) {
+            let mut parser = parser.lock().unwrap();
+            match this.side {
+                Side::Client => parser.client(read),
+                Side::Server => parser.server(read),
+            }
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncReadExt;
+
+    /// The protocols there are parsers for.
+    const PROTOCOLS: [Protocol; 7] = [
+        Protocol::Postgres,
+        Protocol::Redis,
+        Protocol::Mysql,
+        Protocol::Mqtt,
+        Protocol::Ssh,
+        Protocol::Smtp,
+        Protocol::Generic,
+    ];
+
+    #[tokio::test]
+    async fn survives_garbage() {
+        // Bytes that look like nothing, with a fixed seed.
+        let mut state = 0x2545_f491_4f6c_dd1du64;
+        let noise: Vec<u8> = (0..64 * 1024)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect();
+        let garbage: [&[u8]; 6] = [
+            &noise,
+            &[0xff; 4096],
+            &[0; 4096],
+            b"\x10\xff\xff\xff\xff\x0f\x00\x04MQ",
+            b"SSH-2.0-x\r\n\x7f\xff\xff\xff\x00\x14",
+            b"*3\r\n$-5\r\n%99999999999999999999\r\n\xff\xfe\r\n",
+        ];
+
+        for protocol in PROTOCOLS {
+            for bytes in garbage {
+                for side in [Side::Client, Side::Server] {
+                    let parser: SharedParser = Arc::new(Mutex::new(protocol.parser()));
+                    let mut watch = Watch::new(bytes, side, Some(Arc::clone(&parser)));
+                    let mut read = Vec::new();
+                    watch.read_to_end(&mut read).await.unwrap();
+                    assert_eq!(read, bytes);
+
+                    // Both directions, in pieces of every size, including truncated ones.
+                    let mut parser = parser.lock().unwrap();
+                    for size in [1, 2, 3, 5, 7, 4096] {
+                        for chunk in bytes.chunks(size).take(200) {
+                            parser.client(chunk);
+                            parser.server(chunk);
+                        }
+                    }
+                    let stats = parser.stats();
+                    assert_eq!(stats.protocol, protocol);
+                    assert!(!stats.to_string().contains('\x1b'), "{}", stats);
+                }
+            }
+        }
+    }
+}
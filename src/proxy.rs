@@ -1,67 +1,1192 @@
-use crate::reporter::{Direction, Event, ReporterHandle, SocketCloseError};
+use crate::admission::{self, Admission, ConnectRate};
+use crate::ban;
+use crate::consul;
+use crate::etcd;
+use crate::format::{Elapsed, Rate};
+use crate::handover;
+use crate::har;
+use crate::http;
+use crate::k8s;
+use crate::mux::{self, Tunnel};
+use crate::net::{self, DisplayAddr};
+use crate::output::{eprint_line, print_line};
+use crate::policy::{self, Action};
+use crate::pool;
+use crate::protocol::{self, Protocol, SharedParser};
+use crate::record;
+use crate::reporter::{
+    Activity, Connection, Contender, Direction, Event, RateRange, ReporterHandle, Side,
+    SocketCloseError, Traffic, DEFAULT_MAPPING,
+};
+use crate::session;
+use crate::srv;
+use crate::starttls;
+use crate::tls;
+use crate::usage;
 use std::error::Error;
+use std::future::Future;
 use std::net::SocketAddr;
-use tokio::io::AsyncWriteExt;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader, ReadBuf};
 use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{mpsc, oneshot, watch};
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
 
-/// Runs the proxy.
+/// Where new connections are forwarded to.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Destination {
+    /// The destination address.
+    pub addr: String,
+
+    /// Whether `addr` is only a host, to be combined with the port the connection came in on.
+    pub same_port: bool,
+
+    /// Incremented to close all connections that were established before.
+    pub drain_generation: u64,
+
+    /// Whether `addr` was set through the admin API, which then takes precedence over the
+    /// configured destination until sockgauge restarts.
+    pub overridden: bool,
+
+    /// How new connections are forwarded, besides where to.
+    pub forwarding: Forwarding,
+}
+
+/// How new connections are forwarded, besides where to: what's inspected, recorded, rewritten
+/// and limited. Reloading the configuration replaces it as a whole when any of it changed.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Forwarding {
+    /// Whether to fingerprint the TLS ClientHello of new connections.
+    pub inspect_tls: bool,
+
+    /// Whether to watch new connections for a STARTTLS-style upgrade to TLS.
+    pub detect_starttls: bool,
+
+    /// Which end of a tunnel this is, if any.
+    pub tunnel: Option<Tunnel>,
+
+    /// Whether new connections carry HTTP/1.x requests.
+    pub http: bool,
+
+    /// The protocol new connections speak, to count what they do, if any.
+    pub protocol: Option<Protocol>,
+
+    /// In HTTP mode, the destinations of requests for particular hosts, as `(host, address)`.
+    pub hosts: Vec<(String, String)>,
+
+    /// In HTTP mode, the rules for rewriting the headers of requests.
+    pub request_headers: Vec<http::Rule>,
+
+    /// In HTTP mode, the rules for rewriting the headers of responses.
+    pub response_headers: Vec<http::Rule>,
+
+    /// In HTTP mode, the directory to record each request and response in, if any.
+    pub record_http: Option<String>,
+
+    /// How many bytes of each body to record (in files and the HAR).
+    pub record_http_bodies: usize,
+
+    /// In HTTP mode, whether to collect each request and response for the HAR.
+    pub har: bool,
+
+    /// The directory to record the traffic of each new connection in, if any.
+    pub record_sessions: Option<String>,
+
+    /// What to do with new connections whose first bytes match a pattern.
+    pub policies: Vec<policy::Policy>,
+
+    /// The labels of new connections, as `(key, value)`.
+    pub labels: Vec<(String, String)>,
+
+    /// Other destinations to race `addr` against, connecting to all of them and forwarding to
+    /// the fastest.
+    pub race: Vec<String>,
+
+    /// How many connections may be proxied at once, if limited. Others wait in a queue.
+    pub max_connections: Option<u64>,
+
+    /// How long connections wait in the queue before giving up, if not the default.
+    pub max_queue_time: Option<std::time::Duration>,
+
+    /// How long to wait for the first bytes of new connections before closing them, without
+    /// connecting to the destination, if at all.
+    pub first_byte_timeout: Option<std::time::Duration>,
+
+    /// Whether to connect to the destination right away despite a first-byte timeout, for
+    /// protocols where the server speaks first, closing connections still silent once it passed.
+    pub first_byte_connect: bool,
+
+    /// How long forwarding may make no progress before it's reported as a stall, if at all.
+    pub stall_threshold: Option<std::time::Duration>,
+
+    /// How fast connections to a destination may be made, if limited. Others wait their turn.
+    pub upstream_connect_rate: Option<ConnectRate>,
+}
+
+impl Destination {
+    /// Creates a destination for the given address.
+    pub fn new(addr: String, same_port: bool) -> Self {
+        Self {
+            addr,
+            same_port,
+            drain_generation: 0,
+            overridden: false,
+            forwarding: Forwarding::default(),
+        }
+    }
+
+    /// The address to forward a connection accepted on the given port to.
+    pub fn addr_for(&self, listener_port: u16) -> String {
+        match self.same_port {
+            true => net::join_host_port(&self.addr, listener_port),
+            false => self.addr.clone(),
+        }
+    }
+}
+
+/// How long to wait for the first bytes of a connection to match the policies against.
+const POLICY_WAIT: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// How often to look for peers that stopped answering keepalive probes, with `keepalive-probes`.
+const UNREACHABLE_CHECK_INTERVAL: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// How often the throughput of connections is sampled.
+const GAUGE_INTERVAL: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// How much of each throughput sample goes into the smoothed throughput, for a time constant of
+/// about a second: 1 - 1/e.
+const GAUGE_SMOOTHING: f64 = 0.632;
+
+/// The open connections, when they were forwarded and their meters, for listing them.
+static OPEN: Mutex<Vec<(Connection, Instant, Arc<Meter>)>> = Mutex::new(Vec::new());
+
+/// How long to wait for the slower destinations of a race, to report how long they took.
+const RACE_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// Prefix of named pipe addresses, e.g. `npipe:////./pipe/foo`.
+const PIPE_PREFIX: &str = "npipe://";
+
+/// A connection to the destination.
+enum Outbound {
+    /// A TCP connection.
+    Tcp(TcpStream),
+
+    /// A stream multiplexed over a tunnel carrier.
+    Mux(tokio::io::DuplexStream),
+
+    /// A connection to a backend of a pool.
+    Pooled(pool::Stream),
+
+    /// A named pipe connection.
+    #[cfg(windows)]
+    Pipe(tokio::net::windows::named_pipe::NamedPipeClient),
+}
+
+/// Checks that the address is something we can forward to: a `host:port` address, an SRV name,
+/// a Kubernetes service, a
+/// Consul service, an etcd key, a pool of backends or, on Windows, a named pipe.
+pub fn validate_destination(addr: &str) -> Result<(), String> {
+    if let Some(name) = addr.strip_prefix(srv::PREFIX) {
+        return srv::validate(name);
+    }
+
+    if let Some(target) = addr.strip_prefix(k8s::PREFIX) {
+        return k8s::validate(target);
+    }
+
+    if let Some(target) = addr.strip_prefix(consul::PREFIX) {
+        return consul::validate(target);
+    }
+
+    if let Some(key) = addr.strip_prefix(etcd::PREFIX) {
+        return etcd::validate(key);
+    }
+
+    if let Some(backends) = addr.strip_prefix(pool::PREFIX) {
+        return pool::validate(backends);
+    }
+
+    if let Some(name) = pipe_name(addr) {
+        return validate_pipe(addr, &name);
+    }
+
+    match addr.rsplit_once(':') {
+        Some((host, port)) if !host.is_empty() && port.parse::<u16>().is_ok() => Ok(()),
+        _ => Err(format!("`{}` is not a host:port address", addr)),
+    }
+}
+
+/// Checks that a bind address that's a named pipe can be listened on. Other addresses are
+/// checked once they're bound.
+pub fn validate_bind(addr: &str) -> Result<(), String> {
+    match pipe_name(addr) {
+        Some(name) => validate_pipe(addr, &name),
+        None => Ok(()),
+    }
+}
+
+/// Whether the address is a named pipe.
+pub fn is_pipe(addr: &str) -> bool {
+    addr.starts_with(PIPE_PREFIX)
+}
+
+/// Checks that the named pipe address has a usable name, on Windows.
+fn validate_pipe(addr: &str, name: &str) -> Result<(), String> {
+    if !cfg!(windows) {
+        return Err(format!(
+            "`{}` is a named pipe, which only works on Windows",
+            addr
+        ));
+    }
+
+    if !name.starts_with(r"\\") {
+        return Err(format!(
+            "`{}` is not a named pipe address like npipe:////./pipe/name",
+            addr
+        ));
+    }
+
+    Ok(())
+}
+
+/// Returns the pipe name (e.g. `\\.\pipe\foo`) if the address refers to a named pipe.
+fn pipe_name(addr: &str) -> Option<String> {
+    addr.strip_prefix(PIPE_PREFIX)
+        .map(|path| path.replace('/', "\\"))
+}
+
+/// Binds the listeners, or takes over the ones handed over by a previous process.
+/// With a port range, the bind addresses are hosts that get a listener for every port.
+/// Named pipes are left to `run_pipes`.
+pub async fn bind(
+    bind_addrs: &[String],
+    ports: Option<(u16, u16)>,
+    v6only: Option<bool>,
+    groups: usize,
+) -> Result<Vec<TcpListener>, std::io::Error> {
+    if let Some(listeners) = handover::inherited_listeners()? {
+        print_line!("🔁 took over the listeners from the previous process");
+        return listeners.into_iter().map(TcpListener::from_std).collect();
+    }
+
+    let bind_addrs = bind_addrs.iter().filter(|addr| !is_pipe(addr));
+    let bind_addrs: Vec<String> = match ports {
+        Some((first, last)) => bind_addrs
+            .flat_map(|host| (first..=last).map(|port| net::join_host_port(host, port)))
+            .collect(),
+        None => bind_addrs.cloned().collect(),
+    };
+
+    // With more than one group, every group has a listener of its own on every address.
+    let mut listeners = Vec::with_capacity(bind_addrs.len() * groups);
+    for _ in 0..groups {
+        for bind_addr in &bind_addrs {
+            let listener = match groups {
+                1 => net::listen(bind_addr, v6only).await?,
+                _ => net::listen_shared(bind_addr, v6only).await?,
+            };
+            net::apply_listener_options(&listener)?;
+            listeners.push(listener);
+        }
+    }
+
+    Ok(listeners)
+}
+
+/// Runs the proxy for the listeners of a mapping, split into groups (as bound by `bind`) that
+/// each accept connections in a task of their own, e.g. one group per core.
+pub async fn run_groups(
+    listeners: Arc<Vec<TcpListener>>,
+    groups: usize,
+    mapping: Arc<str>,
+    destination: watch::Receiver<Destination>,
+    reporter_handle: ReporterHandle,
+) -> Result<(), std::io::Error> {
+    // Inherited listeners may have been grouped differently, in which case they're one group.
+    if groups <= 1 || !listeners.len().is_multiple_of(groups) {
+        return run(&listeners, mapping, destination, reporter_handle).await;
+    }
+
+    let size = listeners.len() / groups;
+    let tasks: Vec<AbortOnDrop> = (0..groups)
+        .map(|group| {
+            let listeners = listeners.clone();
+            let mapping = mapping.clone();
+            let destination = destination.clone();
+            let reporter_handle = reporter_handle.clone();
+            AbortOnDrop(usage::spawn(async move {
+                let group = &listeners[group * size..(group + 1) * size];
+                run(group, mapping, destination, reporter_handle).await
+            }))
+        })
+        .collect();
+
+    for mut task in tasks {
+        match (&mut task.0).await {
+            Ok(result) => result?,
+            Err(err) => return Err(std::io::Error::other(err)),
+        }
+    }
+
+    Ok(())
+}
+
+/// Runs the proxy for the named pipes among the bind addresses of a mapping, each accepting
+/// clients in a task of its own. Without any, it never returns.
+pub async fn run_pipes(
+    bind_addrs: &[String],
+    mapping: Arc<str>,
+    destination: watch::Receiver<Destination>,
+    reporter_handle: ReporterHandle,
+) -> Result<(), std::io::Error> {
+    let names: Vec<String> = bind_addrs
+        .iter()
+        .filter_map(|addr| pipe_name(addr))
+        .collect();
+    if names.is_empty() {
+        return std::future::pending().await;
+    }
+
+    #[cfg(windows)]
+    {
+        let tasks: Vec<AbortOnDrop> = names
+            .into_iter()
+            .map(|name| {
+                AbortOnDrop(usage::spawn(listen_pipe(
+                    name,
+                    mapping.clone(),
+                    destination.clone(),
+                    reporter_handle.clone(),
+                )))
+            })
+            .collect();
+
+        for mut task in tasks {
+            match (&mut task.0).await {
+                Ok(result) => result?,
+                Err(err) => return Err(std::io::Error::other(err)),
+            }
+        }
+
+        Ok(())
+    }
+
+    #[cfg(not(windows))]
+    {
+        let _ = (mapping, destination, reporter_handle);
+        Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            "named pipes only work on Windows",
+        ))
+    }
+}
+
+/// Proxies the clients of a named pipe. Pipe clients have no address, so they're reported as
+/// coming from (and to) `0.0.0.0:0`, and never banned.
+#[cfg(windows)]
+async fn listen_pipe(
+    name: String,
+    mapping: Arc<str>,
+    destination: watch::Receiver<Destination>,
+    reporter_handle: ReporterHandle,
+) -> Result<(), std::io::Error> {
+    use std::net::{IpAddr, Ipv4Addr};
+    use tokio::net::windows::named_pipe::ServerOptions;
+
+    const NO_ADDR: SocketAddr = SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0);
+
+    // A client takes the instance it connected to, so a new one waits for the next client.
+    let mut server = ServerOptions::new()
+        .first_pipe_instance(true)
+        .create(&name)?;
+    loop {
+        server.connect().await?;
+        let incoming = std::mem::replace(&mut server, ServerOptions::new().create(&name)?);
+        let connection = Connection {
+            peer: NO_ADDR,
+            local: NO_ADDR,
+            mapping: mapping.clone(),
+            stream: None,
+            labels: Arc::new([]),
+        };
+
+        // New connections use the destination at the time they are accepted.
+        let mut destination = destination.clone();
+        let accepted = destination.borrow_and_update().clone();
+        spawn_proxy(
+            incoming,
+            connection,
+            accepted,
+            destination,
+            reporter_handle.clone(),
+            ClientSocket::default(),
+        );
+    }
+}
+
+/// Aborts a task when dropped, e.g. so the accepting stops along with the proxy.
+pub struct AbortOnDrop(pub JoinHandle<Result<(), std::io::Error>>);
+
+impl Drop for AbortOnDrop {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+/// Runs the proxy for the listeners of a mapping.
 pub async fn run(
-    bind_addr: String,
-    dest_addr: String,
+    listeners: &[TcpListener],
+    mapping: Arc<str>,
+    destination: watch::Receiver<Destination>,
     reporter_handle: ReporterHandle,
 ) -> Result<(), std::io::Error> {
-    // Bind to the socket.
-    let listener = TcpListener::bind(bind_addr).await?;
+    let mut next = 0;
+    while let Ok((incoming, socket_addr)) = accept(listeners, &mut next).await {
+        let connection = match incoming.local_addr() {
+            Ok(local_addr) => Connection {
+                peer: net::normalize(socket_addr),
+                local: net::normalize(local_addr),
+                mapping: mapping.clone(),
+                stream: None,
+                labels: Arc::new([]),
+            },
+            Err(err) => {
+                eprint_line!("💥️ — proxying for socket {} failed: {}", &socket_addr, err);
+                continue;
+            }
+        };
+        // Banned clients are turned away right away, and only counted.
+        if ban::shared().refuse(connection.peer.ip()) {
+            continue;
+        }
+        if let Err(err) = net::apply_socket_options(&incoming) {
+            eprint_line!("💥️ — proxying for socket {} failed: {}", &socket_addr, err);
+            continue;
+        }
 
-    while let Ok((incoming, socket_addr)) = listener.accept().await {
+        // New connections use the destination at the time they are accepted.
+        let mut destination = destination.clone();
+        let accepted = destination.borrow_and_update().clone();
         let reporter_handle = reporter_handle.clone();
-        let dest_addr = dest_addr.clone();
-        let proxy = async move {
-            let result =
-                handle_connection(incoming, &socket_addr, &dest_addr, reporter_handle).await;
-            if let Err(err) = result {
-                eprintln!("💥️ — proxying for socket {} failed: {}", &socket_addr, err)
+        if accepted.forwarding.tunnel == Some(Tunnel::Server) {
+            usage::spawn(serve_carrier(
+                incoming,
+                connection,
+                accepted,
+                destination,
+                reporter_handle,
+            ));
+            continue;
+        }
+
+        // Connections that a `reset` policy may apply to need a handle to their socket, as it
+        // ends up wrapped by then.
+        let resets = accepted
+            .forwarding
+            .policies
+            .iter()
+            .any(|policy| policy.action == Action::Reset);
+        let client = ClientSocket {
+            handle: (resets || net::inspect_accepted())
+                .then(|| net::SocketHandle::new(&incoming).ok())
+                .flatten(),
+            fast_open: net::used_fast_open(&incoming),
+        };
+        spawn_proxy(
+            incoming,
+            connection,
+            accepted,
+            destination,
+            reporter_handle,
+            client,
+        );
+    }
+
+    Ok(())
+}
+
+/// What's known about the socket of a client, which is wrapped by the time it's proxied.
+/// Streams of a tunnel carrier have no socket of their own, and know nothing.
+#[derive(Debug, Default)]
+struct ClientSocket {
+    /// A handle to the socket, if a `reset` policy may need to close it with a reset, or it's
+    /// to be looked at once the connection is over.
+    handle: Option<net::SocketHandle>,
+
+    /// Whether the client used TCP Fast Open.
+    fast_open: bool,
+}
+
+/// Spawns a task proxying the incoming socket to the destination.
+fn spawn_proxy(
+    incoming: impl AsyncRead + AsyncWrite + Send + 'static,
+    connection: Connection,
+    accepted: Destination,
+    mut destination: watch::Receiver<Destination>,
+    reporter_handle: ReporterHandle,
+    client: ClientSocket,
+) {
+    let proxy = async move {
+        let peer = connection.peer;
+
+        // At capacity, wait in the queue for another connection to close, or give up.
+        let _permit = match accepted.forwarding.max_connections {
+            Some(limit) => {
+                let max_wait = accepted
+                    .forwarding
+                    .max_queue_time
+                    .unwrap_or(admission::DEFAULT_MAX_QUEUE_TIME);
+                match admission::admit(peer.ip(), limit, max_wait).await {
+                    Admission::Admitted(permit, waited) => {
+                        if !waited.is_zero() {
+                            reporter_handle.report(Event::Queued(connection.clone(), waited));
+                        }
+                        Some(permit)
+                    }
+                    Admission::TimedOut(waited) => {
+                        reporter_handle.report(Event::Rejected(connection, waited));
+                        return;
+                    }
+                }
             }
+            None => None,
         };
 
-        tokio::spawn(proxy);
+        let drained = drained(&mut destination, accepted.drain_generation);
+        let result = handle_connection(
+            incoming,
+            connection,
+            &accepted,
+            reporter_handle,
+            drained,
+            client,
+        )
+        .await;
+        if let Err(err) = result {
+            eprint_line!("💥️ — proxying for socket {} failed: {}", &peer, err)
+        }
+    };
+
+    usage::spawn(proxy);
+}
+
+/// Proxies every stream of a tunnel carrier to the destination, as if each were a connection of
+/// its own.
+async fn serve_carrier(
+    incoming: TcpStream,
+    connection: Connection,
+    accepted: Destination,
+    destination: watch::Receiver<Destination>,
+    reporter_handle: ReporterHandle,
+) {
+    let label = format!("from {}", DisplayAddr(connection.peer));
+    let mut streams = match mux::accept_carrier(incoming, label).await {
+        Ok(streams) => streams,
+        Err(err) => {
+            eprint_line!(
+                "💥️ — tunnel carrier from {} failed: {}",
+                &connection.peer,
+                err
+            );
+            return;
+        }
+    };
+
+    while let Some((id, stream)) = streams.recv().await {
+        let connection = Connection {
+            stream: Some(id),
+            ..connection.clone()
+        };
+        spawn_proxy(
+            stream,
+            connection,
+            accepted.clone(),
+            destination.clone(),
+            reporter_handle.clone(),
+            ClientSocket::default(),
+        );
     }
+}
 
-    Ok(())
+/// Accepts the next connection on any of the listeners, starting with the one at `next` and
+/// moving it past the listener that accepted, so a busy listener can't starve the others.
+async fn accept(
+    listeners: &[TcpListener],
+    next: &mut usize,
+) -> Result<(TcpStream, SocketAddr), std::io::Error> {
+    std::future::poll_fn(|cx| {
+        for offset in 0..listeners.len() {
+            let index = (*next + offset) % listeners.len();
+            if let Poll::Ready(result) = listeners[index].poll_accept(cx) {
+                *next = (index + 1) % listeners.len();
+                return Poll::Ready(result);
+            }
+        }
+
+        Poll::Pending
+    })
+    .await
 }
 
 /// Proxies the incoming socket to the destination.
 async fn handle_connection(
-    incoming: TcpStream,
-    socket_addr: &SocketAddr,
-    dest_addr: &String,
+    incoming: impl AsyncRead + AsyncWrite,
+    mut connection: Connection,
+    accepted: &Destination,
     reporter_handle: ReporterHandle,
+    drained: impl Future<Output = ()>,
+    client: ClientSocket,
 ) -> Result<(), Box<dyn Error>> {
-    // Open a connection to the destination.
-    let outbound = TcpStream::connect(dest_addr).await?;
-    reporter_handle.report(Event::Opened(*socket_addr));
+    // Count the bytes the client sends and gets back, for the throughput and the flows.
+    let meter = Arc::new(Meter::default());
+    let incoming = Metered {
+        inner: Box::pin(incoming),
+        reporter_handle: reporter_handle.clone(),
+        meter: meter.clone(),
+    };
 
-    // Wait for the proxying to complete (either socket closes).
-    let transfer_result = transfer(incoming, outbound).await;
+    // Record what the client sends (and how much it gets back) if asked to.
+    let incoming = session::Tap::new(
+        incoming,
+        accepted.forwarding.record_sessions.as_deref(),
+        &connection,
+    );
+
+    // In HTTP mode, the first request picks the destination by its host. Clients keep a
+    // connection to one host, so the rest of its requests follow.
+    let incoming = BufReader::new(incoming);
+    tokio::pin!(incoming);
+
+    // With a first-byte timeout, the destination is only connected to once the client sent
+    // something, so clients that connect and then idle never tie up one of its connections.
+    // Unless the server speaks first, and the connection is only closed if it stays silent.
+    let first_byte_timeout = accepted.forwarding.first_byte_timeout;
+    let silent_timeout = first_byte_timeout.filter(|_| accepted.forwarding.first_byte_connect);
+    if let Some(timeout) = first_byte_timeout.filter(|_| silent_timeout.is_none()) {
+        if tokio::time::timeout(timeout, incoming.fill_buf())
+            .await
+            .is_err()
+        {
+            reporter_handle.report(Event::FirstByteTimeout(connection, timeout));
+            return Ok(());
+        }
+    }
+
+    // Match the first bytes the client sends against the policies, if there are any. Clients
+    // that wait for the server to speak first get nothing matched.
+    let mut action = None;
+    let mut labels = accepted.forwarding.labels.clone();
+    if !accepted.forwarding.policies.is_empty() {
+        if let Ok(Ok(bytes)) = tokio::time::timeout(POLICY_WAIT, incoming.fill_buf()).await {
+            for policy in policy::evaluate(&accepted.forwarding.policies, bytes) {
+                reporter_handle.report(Event::Policy(connection.clone(), policy.action.clone()));
+                labels.extend(policy.action.label());
+                action = Some(&policy.action);
+            }
+        }
+    }
+
+    // Every event from here on carries the labels.
+    if !labels.is_empty() {
+        connection.labels = labels.into();
+    }
+
+    match action {
+        Some(Action::Close) => return Ok(()),
+        Some(Action::Reset) => {
+            // Streams of a tunnel carrier have no socket of their own, so they're just closed.
+            if let Some(handle) = client.handle {
+                handle.reset()?;
+            }
+            return Ok(());
+        }
+        Some(Action::Tarpit) => {
+            // Hold on to the client until it gives up, or the connection is drained.
+            let mut sink = tokio::io::sink();
+            tokio::select! {
+                _ = tokio::io::copy(&mut incoming, &mut sink) => {}
+                _ = drained => {}
+            }
+            return Ok(());
+        }
+        _ => {}
+    }
+
+    let mut dest_addr = accepted.addr_for(connection.local.port());
+    let mut first_head = Vec::new();
+    if accepted.forwarding.http {
+        first_head = http::read_head(&mut incoming).await?;
+        let host = http::Head::parse(&first_head).and_then(|head| head.host());
+        if let Some(route) = host.and_then(|host| http::route(&accepted.forwarding.hosts, &host)) {
+            dest_addr = route.to_string();
+        }
+    }
+
+    // A policy takes precedence over the host.
+    if let Some(Action::Route(route)) = action {
+        dest_addr = route.clone();
+    }
+
+    // Connections to a destination that's paced wait their turn, already accepted, so a burst
+    // of clients reaches it spread out.
+    if let Some(rate) = accepted.forwarding.upstream_connect_rate {
+        let waited = admission::pace(&dest_addr, rate).await;
+        if !waited.is_zero() {
+            reporter_handle.report(Event::Paced(connection.clone(), waited));
+        }
+    }
+
+    // Open a connection to the destination, or a stream to it when tunneling. When racing
+    // (unless a host or policy routed the connection elsewhere), the fastest destination wins.
+    let connecting = Instant::now();
+    let mut outbound = match accepted.forwarding.tunnel {
+        Some(Tunnel::Client) => mux::open_stream(&dest_addr).await.map(Outbound::Mux)?,
+        _ if !accepted.forwarding.race.is_empty() && dest_addr == accepted.addr => {
+            let mut candidates = vec![dest_addr];
+            candidates.extend(accepted.forwarding.race.iter().cloned());
+            race(candidates, &connection, &reporter_handle).await?
+        }
+        _ => match connect(&dest_addr).await {
+            // Out of local ports to connect from, which is reported on its own.
+            Err(err) if err.kind() == std::io::ErrorKind::AddrNotAvailable => {
+                reporter_handle.report(Event::PortsExhausted(connection, err.to_string()));
+                return Ok(());
+            }
+            result => result?,
+        },
+    };
+    reporter_handle.report(Event::Connected(connecting.elapsed()));
+    reporter_handle.report(Event::Opened(connection.clone()));
+    if client.fast_open {
+        reporter_handle.report(Event::FastOpen(connection.clone(), Side::Client));
+    }
+    if let Outbound::Pooled(stream) = &outbound {
+        reporter_handle.report(Event::Backend(
+            connection.clone(),
+            stream.addr().to_string(),
+        ));
+    }
+
+    // In HTTP mode, the requests and responses are forwarded one by one. Otherwise, report the
+    // hello messages when the client and server exchange them, and count what the connection
+    // does if it speaks a known protocol.
+    let parser: Option<SharedParser> = accepted
+        .forwarding
+        .protocol
+        .map(|protocol| Arc::new(Mutex::new(protocol.parser())));
+    let mode = match accepted.forwarding.http {
+        true => Mode::Http {
+            first_head,
+            request_headers: &accepted.forwarding.request_headers,
+            response_headers: &accepted.forwarding.response_headers,
+            capture: match accepted.forwarding.record_http.is_some() || accepted.forwarding.har {
+                true => accepted.forwarding.record_http_bodies,
+                false => 0,
+            },
+            on_exchange: {
+                let reporter_handle = reporter_handle.clone();
+                let connection = connection.clone();
+                let record_http = accepted.forwarding.record_http.clone();
+                let har = accepted.forwarding.har;
+                Box::new(move |exchange| {
+                    if let Some(dir) = &record_http {
+                        record::record(dir.clone(), connection.clone(), &exchange);
+                    }
+                    if har {
+                        har::collect(&connection, &exchange);
+                    }
+                    reporter_handle.report(Event::Exchange(exchange))
+                })
+            },
+        },
+        false => Mode::Raw {
+            on_hellos: accepted.forwarding.inspect_tls.then(|| {
+                let reporter_handle = reporter_handle.clone();
+                let connection = connection.clone();
+                let on_client_hello: tls::OnHello<tls::ClientHello> = {
+                    let reporter_handle = reporter_handle.clone();
+                    let connection = connection.clone();
+                    Box::new(move |hello| {
+                        reporter_handle.report(Event::ClientHello(connection, hello))
+                    })
+                };
+                let on_server_hello: tls::OnHello<tls::ServerHello> = Box::new(move |hello| {
+                    reporter_handle.report(Event::ServerHello(connection, hello))
+                });
+                (on_client_hello, on_server_hello)
+            }),
+            on_upgrade: accepted.forwarding.detect_starttls.then(|| {
+                let reporter_handle = reporter_handle.clone();
+                let connection = connection.clone();
+                let on_upgrade: starttls::OnUpgrade = Box::new(move |at, command| {
+                    reporter_handle.report(Event::Upgraded(connection, at, command))
+                });
+                on_upgrade
+            }),
+            parser: parser.clone(),
+        },
+    };
+
+    // With `keepalive-probes`, both sockets are watched for a peer that stopped answering.
+    let probes = net::unreachable_probes();
+    let outbound_handle = probes
+        .and(outbound.tcp())
+        .and_then(|stream| net::SocketHandle::new(stream).ok());
+    let watched = [
+        (Side::Client, client.handle.as_ref()),
+        (Side::Destination, outbound_handle.as_ref()),
+    ];
+
+    // With a stall threshold, writes to either side that make no progress for that long are
+    // reported.
+    let stall_report = |direction| {
+        accepted
+            .forwarding
+            .stall_threshold
+            .map(|threshold| StallReport {
+                threshold,
+                direction,
+                connection: connection.clone(),
+                reporter_handle: reporter_handle.clone(),
+            })
+    };
+    let incoming = Stalls::new(incoming, stall_report(Direction::ServerToClient));
+    let outbound_stalls = stall_report(Direction::ClientToServer);
+
+    // Wait for the proxying to complete (either socket closes), for the connection to be
+    // drained, for a peer to be unreachable or for a client to stay silent too long, in which
+    // case both sockets are closed by dropping them.
+    let transfer_result = tokio::select! {
+        result = outbound.transfer_from(incoming, mode, outbound_stalls) => result,
+        _ = drained => Ok(()),
+        err = unreachable(watched, probes) => Err(err),
+        _ = gauge(&connection, &meter) => Ok(()),
+        _ = silent(&meter, silent_timeout) => Ok(()),
+    };
+
+    // Whether the destination took the first data in the SYN is only known once it was sent,
+    // and how many subflows the connections ended up with once they're over.
+    let tcp = outbound.tcp();
+    if tcp.is_some_and(net::used_fast_open) {
+        reporter_handle.report(Event::FastOpen(connection.clone(), Side::Destination));
+    }
+    let subflows = [
+        (
+            Side::Client,
+            client.handle.as_ref().and_then(net::SocketHandle::subflows),
+        ),
+        (Side::Destination, tcp.and_then(net::subflows)),
+    ];
+    for (side, subflows) in subflows {
+        if let Some(subflows) = subflows {
+            reporter_handle.report(Event::Multipath(connection.clone(), side, subflows));
+        }
+    }
+
+    if let Some(parser) = parser {
+        let stats = parser.lock().unwrap().stats();
+        reporter_handle.report(Event::Protocol(connection.clone(), stats));
+    }
 
     if let Err(err) = transfer_result {
-        reporter_handle.report(Event::ClosedWithError(*socket_addr, err));
+        reporter_handle.report(Event::ClosedWithError(connection, err, meter.traffic()));
         return Ok(());
     }
 
     // Report that the connection closed.
-    reporter_handle.report(Event::ClosedGracefully(*socket_addr));
+    reporter_handle.report(Event::ClosedGracefully(connection, meter.traffic()));
     Ok(())
 }
 
+/// Called with the ClientHello and ServerHello of a connection.
+type OnHellos = (
+    tls::OnHello<tls::ClientHello>,
+    tls::OnHello<tls::ServerHello>,
+);
+
+/// How the traffic of a connection is forwarded.
+enum Mode<'a> {
+    /// As-is, reporting the TLS hello messages and an upgrade to TLS if asked to.
+    Raw {
+        /// Called with the hello messages, if inspecting TLS.
+        on_hellos: Option<OnHellos>,
+
+        /// Called once the connection upgraded to TLS, if watching for it.
+        on_upgrade: Option<starttls::OnUpgrade>,
+
+        /// Follows the messages of the connection's protocol, if it has one.
+        parser: Option<SharedParser>,
+    },
+
+    /// As HTTP requests and responses.
+    Http {
+        /// The head of the first request, which was already read.
+        first_head: Vec<u8>,
+
+        /// The rules for rewriting the headers of requests.
+        request_headers: &'a [http::Rule],
+
+        /// The rules for rewriting the headers of responses.
+        response_headers: &'a [http::Rule],
+
+        /// How many bytes of each body to keep for `on_exchange`.
+        capture: usize,
+
+        /// Called with each request and its response.
+        on_exchange: Box<dyn FnMut(http::Exchange) + Send>,
+    },
+}
+
+/// Connects to all the candidates at once, returning the first connection made. The others are
+/// closed as they connect, and once all have connected, failed or timed out, how long each took
+/// is reported.
+async fn race(
+    candidates: Vec<String>,
+    connection: &Connection,
+    reporter_handle: &ReporterHandle,
+) -> Result<Outbound, std::io::Error> {
+    let (results, mut receiver) = mpsc::unbounded_channel();
+    let started = Instant::now();
+    for (i, addr) in candidates.iter().enumerate() {
+        let results = results.clone();
+        let addr = addr.clone();
+        usage::spawn(async move {
+            let result = match tokio::time::timeout(RACE_TIMEOUT, connect(&addr)).await {
+                Ok(result) => result,
+                Err(_) => Err(std::io::ErrorKind::TimedOut.into()),
+            };
+            let _ = results.send((i, started.elapsed(), result));
+        });
+    }
+    drop(results);
+
+    let (winner, won) = oneshot::channel();
+    let connection = connection.clone();
+    let reporter_handle = reporter_handle.clone();
+    usage::spawn(async move {
+        let mut winner = Some(winner);
+        let mut contenders: Vec<Contender> = candidates
+            .into_iter()
+            .map(|addr| Contender {
+                addr,
+                latency: Err("timed out".to_string()),
+                won: false,
+            })
+            .collect();
+        let mut last_error = None;
+        while let Some((i, elapsed, result)) = receiver.recv().await {
+            match result {
+                Ok(outbound) => {
+                    contenders[i].latency = Ok(elapsed);
+                    if let Some(winner) = winner.take() {
+                        contenders[i].won = true;
+                        let _ = winner.send(Ok(outbound));
+                    }
+                }
+                Err(err) => {
+                    contenders[i].latency = Err(err.to_string());
+                    last_error = Some(err);
+                }
+            }
+        }
+
+        if let Some(winner) = winner {
+            let err = last_error.unwrap_or_else(|| std::io::ErrorKind::TimedOut.into());
+            let _ = winner.send(Err(err));
+        }
+        reporter_handle.report(Event::Race(connection, contenders));
+    });
+
+    won.await
+        .unwrap_or_else(|_| Err(std::io::Error::other("the race was abandoned")))
+}
+
+/// Connects to the destination.
+async fn connect(dest_addr: &str) -> Result<Outbound, std::io::Error> {
+    #[cfg(windows)]
+    if let Some(name) = pipe_name(dest_addr) {
+        return connect_pipe(&name).await.map(Outbound::Pipe);
+    }
+
+    if let Some(name) = dest_addr.strip_prefix(srv::PREFIX) {
+        return srv::connect(name).await.map(Outbound::Tcp);
+    }
+
+    if let Some(target) = dest_addr.strip_prefix(k8s::PREFIX) {
+        return k8s::connect(target).await.map(Outbound::Tcp);
+    }
+
+    if let Some(target) = dest_addr.strip_prefix(consul::PREFIX) {
+        return consul::connect(target).await.map(Outbound::Tcp);
+    }
+
+    if let Some(key) = dest_addr.strip_prefix(etcd::PREFIX) {
+        return etcd::connect(key).await.map(Outbound::Tcp);
+    }
+
+    if let Some(backends) = dest_addr.strip_prefix(pool::PREFIX) {
+        return pool::connect(backends).await.map(Outbound::Pooled);
+    }
+
+    net::connect(dest_addr).await.map(Outbound::Tcp)
+}
+
+/// Connects to a named pipe, waiting for an instance to become available if they're all busy.
+#[cfg(windows)]
+async fn connect_pipe(
+    name: &str,
+) -> Result<tokio::net::windows::named_pipe::NamedPipeClient, std::io::Error> {
+    use tokio::net::windows::named_pipe::ClientOptions;
+
+    /// Returned by Windows when all instances of the pipe are busy.
+    const ERROR_PIPE_BUSY: i32 = 231;
+
+    loop {
+        match ClientOptions::new().open(name) {
+            Ok(client) => return Ok(client),
+            Err(err) if err.raw_os_error() == Some(ERROR_PIPE_BUSY) => {}
+            Err(err) => return Err(err),
+        }
+
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+    }
+}
+
+impl Outbound {
+    /// The TCP connection to the destination, if it's one.
+    fn tcp(&self) -> Option<&TcpStream> {
+        match self {
+            Outbound::Tcp(stream) => Some(stream),
+            Outbound::Pooled(stream) => Some(stream.tcp()),
+            _ => None,
+        }
+    }
+
+    /// Proxies the incoming socket to this destination connection, reporting stalls of the
+    /// writes to it with `stalls`, if given.
+    async fn transfer_from(
+        &mut self,
+        incoming: impl AsyncRead + AsyncWrite,
+        mode: Mode<'_>,
+        stalls: Option<StallReport>,
+    ) -> Result<(), SocketCloseError> {
+        match self {
+            Outbound::Tcp(outbound) => {
+                transfer(incoming, Stalls::new(outbound, stalls), mode).await
+            }
+            Outbound::Mux(outbound) => {
+                transfer(incoming, Stalls::new(outbound, stalls), mode).await
+            }
+            Outbound::Pooled(outbound) => {
+                transfer(incoming, Stalls::new(outbound, stalls), mode).await
+            }
+            #[cfg(windows)]
+            Outbound::Pipe(outbound) => {
+                transfer(incoming, Stalls::new(outbound, stalls), mode).await
+            }
+        }
+    }
+}
+
+/// Resolves once the client sent nothing in the `timeout` after it was forwarded, if there's a
+/// timeout at all.
+async fn silent(meter: &Meter, timeout: Option<std::time::Duration>) {
+    match timeout {
+        Some(timeout) => tokio::time::sleep(timeout).await,
+        None => std::future::pending().await,
+    }
+    if meter.read.load(Ordering::Relaxed) > 0 {
+        std::future::pending().await
+    }
+}
+
+/// Resolves once the peer of one of the watched sockets left `probes` keepalive probes in a row
+/// unanswered, if watching at all, with the error to close the connection with.
+async fn unreachable(
+    watched: [(Side, Option<&net::SocketHandle>); 2],
+    probes: Option<u8>,
+) -> SocketCloseError {
+    let Some(probes) = probes else {
+        return std::future::pending().await;
+    };
+
+    loop {
+        tokio::time::sleep(UNREACHABLE_CHECK_INTERVAL).await;
+        for (side, handle) in watched {
+            if handle.is_some_and(|handle| handle.unanswered_probes() >= probes) {
+                let (direction, peer) = match side {
+                    Side::Client => (Direction::ServerToClient, "client"),
+                    Side::Destination => (Direction::ClientToServer, "destination"),
+                };
+                return SocketCloseError(
+                    direction,
+                    format!("the {} left {} keepalive probe(s) unanswered", peer, probes),
+                    std::io::ErrorKind::HostUnreachable,
+                );
+            }
+        }
+    }
+}
+
+/// Resolves once connections established at the given drain generation should be closed.
+async fn drained(destination: &mut watch::Receiver<Destination>, generation: u64) {
+    while destination.changed().await.is_ok() {
+        if destination.borrow().drain_generation > generation {
+            return;
+        }
+    }
+
+    // The destination can no longer change, so never drain.
+    std::future::pending().await
+}
+
 /// Runs the actual proxying of a socket.
 async fn transfer(
-    mut incoming: TcpStream,
-    mut outbound: TcpStream,
+    incoming: impl AsyncRead + AsyncWrite,
+    outbound: impl AsyncRead + AsyncWrite,
+    mode: Mode<'_>,
 ) -> Result<(), SocketCloseError> {
-    // Split the streams into read and write halves.
-    let (mut read_inbound, mut write_inbound) = incoming.split();
-    let (mut read_outbound, mut write_outbound) = outbound.split();
+    let (on_hellos, on_upgrade, parser) = match mode {
+        Mode::Raw {
+            on_hellos,
+            on_upgrade,
+            parser,
+        } => (on_hellos, on_upgrade, parser),
+        Mode::Http {
+            first_head,
+            request_headers,
+            response_headers,
+            capture,
+            on_exchange,
+        } => {
+            return transfer_http(
+                incoming,
+                outbound,
+                first_head,
+                (request_headers, response_headers),
+                capture,
+                on_exchange,
+            )
+            .await
+        }
+    };
+
+    // Split the streams into read and write halves, looking at the TLS handshake, for an
+    // upgrade to TLS and at the protocol's messages on the way if asked to.
+    let (on_client_hello, on_server_hello) = on_hellos.unzip();
+    let (read_inbound, mut write_inbound) = tokio::io::split(incoming);
+    let read_inbound = protocol::Watch::new(read_inbound, protocol::Side::Client, parser.clone());
+    let read_inbound = starttls::Watch::new(read_inbound, on_upgrade);
+    let mut read_inbound =
+        tls::Inspect::new(read_inbound, tls::parse_client_hello, on_client_hello);
+    let (read_outbound, mut write_outbound) = tokio::io::split(outbound);
+    let read_outbound = protocol::Watch::new(read_outbound, protocol::Side::Server, parser);
+    let mut read_outbound =
+        tls::Inspect::new(read_outbound, tls::parse_server_hello, on_server_hello);
 
     // Connect the client reader to the server writer.
     // That is, whenever we receive data from the client, we forward it to the server.
@@ -69,10 +1194,7 @@ async fn transfer(
         tokio::io::copy(&mut read_inbound, &mut write_outbound)
             .await
             .map_err(|e| map_io_error(Direction::ClientToServer, e))?;
-        write_outbound
-            .shutdown()
-            .await
-            .map_err(|e| map_io_error(Direction::ClientToServer, e))
+        close_write(&mut write_outbound, Direction::ClientToServer).await
     };
 
     // Connect the server reader to the client writer.
@@ -81,19 +1203,973 @@ async fn transfer(
         tokio::io::copy(&mut read_outbound, &mut write_inbound)
             .await
             .map_err(|e| map_io_error(Direction::ServerToClient, e))?;
-        write_inbound
-            .shutdown()
-            .await
-            .map_err(|e| map_io_error(Direction::ServerToClient, e))
+        close_write(&mut write_inbound, Direction::ServerToClient).await
     };
 
     // Poll both tasks.
-    tokio::try_join!(client_to_server, server_to_client)?;
+    join_directions(client_to_server, server_to_client).await
+}
 
-    Ok(())
+/// Runs the proxying of a socket in HTTP mode, forwarding each request and response,
+/// rewriting their headers by the (request, response) rules and keeping up to `capture` bytes
+/// of their bodies for `on_exchange`.
+async fn transfer_http(
+    incoming: impl AsyncRead + AsyncWrite,
+    outbound: impl AsyncRead + AsyncWrite,
+    first_head: Vec<u8>,
+    (request_headers, response_headers): (&[http::Rule], &[http::Rule]),
+    capture: usize,
+    on_exchange: impl FnMut(http::Exchange),
+) -> Result<(), SocketCloseError> {
+    let (read_inbound, mut write_inbound) = tokio::io::split(incoming);
+    let (read_outbound, mut write_outbound) = tokio::io::split(outbound);
+    let (pending_sender, pending) = mpsc::unbounded_channel();
+
+    let client_to_server = async {
+        let read_inbound = BufReader::new(read_inbound);
+        http::forward_requests(
+            read_inbound,
+            &mut write_outbound,
+            first_head,
+            (request_headers, capture),
+            pending_sender,
+        )
+        .await
+        .map_err(|e| map_io_error(Direction::ClientToServer, e))?;
+        close_write(&mut write_outbound, Direction::ClientToServer).await
+    };
+
+    let server_to_client = async {
+        let read_outbound = BufReader::new(read_outbound);
+        http::forward_responses(
+            read_outbound,
+            &mut write_inbound,
+            (response_headers, capture),
+            pending,
+            on_exchange,
+        )
+        .await
+        .map_err(|e| map_io_error(Direction::ServerToClient, e))?;
+        close_write(&mut write_inbound, Direction::ServerToClient).await
+    };
+
+    join_directions(client_to_server, server_to_client).await
+}
+
+/// Passes on that one side of a connection is done sending, unless connections are closed
+/// abortively: then it's up to `join_directions` to drop both sockets, sending resets.
+async fn close_write(
+    write: &mut (impl AsyncWrite + Unpin),
+    direction: Direction,
+) -> Result<(), SocketCloseError> {
+    match net::abortive_close() {
+        true => Ok(()),
+        false => write
+            .shutdown()
+            .await
+            .map_err(|e| map_io_error(direction, e)),
+    }
+}
+
+/// Forwards both directions of a connection until both are done, or when connections are closed
+/// abortively, until either is.
+async fn join_directions(
+    client_to_server: impl Future<Output = Result<(), SocketCloseError>>,
+    server_to_client: impl Future<Output = Result<(), SocketCloseError>>,
+) -> Result<(), SocketCloseError> {
+    match net::abortive_close() {
+        true => tokio::select! {
+            result = client_to_server => result,
+            result = server_to_client => result,
+        },
+        false => tokio::try_join!(client_to_server, server_to_client).map(|_| ()),
+    }
+}
+
+/// Counts the bytes read from and written to a stream with the reporter, and in a meter.
+struct Metered<S> {
+    /// The stream.
+    inner: Pin<Box<S>>,
+
+    /// Used for counting the bytes.
+    reporter_handle: ReporterHandle,
+
+    /// The counts of this stream alone.
+    meter: Arc<Meter>,
+}
+
+/// Counts what went through a stream, for its `Traffic`.
+#[derive(Debug, Default)]
+struct Meter {
+    /// How many bytes were read.
+    read: AtomicU64,
+
+    /// How many reads returned bytes.
+    reads: AtomicU64,
+
+    /// How many bytes were written.
+    written: AtomicU64,
+
+    /// How many writes there were.
+    writes: AtomicU64,
+
+    /// The throughput of what was read and what was written, sampled every `GAUGE_INTERVAL`.
+    throughput: Mutex<[Throughput; 2]>,
+
+    /// When the bytes flowed.
+    flow: Mutex<Flow>,
+}
+
+impl Meter {
+    /// The traffic so far.
+    fn traffic(&self) -> Traffic {
+        let [read, written] = &*self.throughput.lock().unwrap();
+        Traffic {
+            sent: self.read.load(Ordering::Relaxed),
+            reads: self.reads.load(Ordering::Relaxed),
+            received: self.written.load(Ordering::Relaxed),
+            writes: self.writes.load(Ordering::Relaxed),
+            sent_rates: read.range,
+            received_rates: written.range,
+            activity: self.flow.lock().unwrap().activity(),
+        }
+    }
+
+    /// Samples the throughput, a `GAUGE_INTERVAL` after the last sample.
+    fn sample(&self) {
+        let [read, written] = &mut *self.throughput.lock().unwrap();
+        read.sample(self.read.load(Ordering::Relaxed));
+        written.sample(self.written.load(Ordering::Relaxed));
+    }
+
+    /// The smoothed throughput of what was read and what was written, in bytes per second.
+    fn rates(&self) -> [u64; 2] {
+        let [read, written] = &*self.throughput.lock().unwrap();
+        [read.smoothed as u64, written.smoothed as u64]
+    }
+}
+
+/// When bytes flowed through a stream, for its `Activity`.
+#[derive(Debug)]
+struct Flow {
+    /// When the stream was opened.
+    opened: Instant,
+
+    /// When the first and last bytes were read, and written.
+    read: Option<(Instant, Instant)>,
+    written: Option<(Instant, Instant)>,
+
+    /// When bytes last flowed either way, or the stream was opened.
+    last: Instant,
+
+    /// The longest time between bytes flowing, so far.
+    longest_idle: std::time::Duration,
+}
+
+impl Default for Flow {
+    /// Starts when the stream is opened, which is now.
+    fn default() -> Self {
+        let now = Instant::now();
+        Flow {
+            opened: now,
+            read: None,
+            written: None,
+            last: now,
+            longest_idle: std::time::Duration::ZERO,
+        }
+    }
+}
+
+impl Flow {
+    /// Notes that bytes were read, or written.
+    fn flowed(&mut self, read: bool) {
+        let now = Instant::now();
+        let flowed = if read {
+            &mut self.read
+        } else {
+            &mut self.written
+        };
+        *flowed = Some((flowed.map_or(now, |(first, _)| first), now));
+        self.longest_idle = self.longest_idle.max(now - self.last);
+        self.last = now;
+    }
+
+    /// When the bytes flowed, counting the time since the last as idle too.
+    fn activity(&self) -> Activity {
+        let offsets = |flowed: Option<(Instant, Instant)>| {
+            flowed.map(|(first, last)| (first - self.opened, last - self.opened))
+        };
+        Activity {
+            sent: offsets(self.read),
+            received: offsets(self.written),
+            longest_idle: self.longest_idle.max(self.last.elapsed()),
+        }
+    }
+}
+
+/// The throughput of one direction of a stream.
+#[derive(Debug, Default)]
+struct Throughput {
+    /// How many bytes were counted as of the last sample.
+    counted: u64,
+
+    /// The bytes per second, smoothed by `GAUGE_SMOOTHING`.
+    smoothed: f64,
+
+    /// The lowest and highest bytes per second of a sample, once there is one.
+    range: Option<RateRange>,
+}
+
+impl Throughput {
+    /// Samples the throughput, given how many bytes were counted by now.
+    fn sample(&mut self, counted: u64) {
+        let rate = (counted - self.counted) as f64 / GAUGE_INTERVAL.as_secs_f64();
+        self.counted = counted;
+        self.smoothed += GAUGE_SMOOTHING * (rate - self.smoothed);
+        let rate = rate as u64;
+        self.range = Some(match self.range {
+            Some(range) => RateRange {
+                min: range.min.min(rate),
+                max: range.max.max(rate),
+            },
+            None => RateRange {
+                min: rate,
+                max: rate,
+            },
+        });
+    }
+}
+
+/// Samples the throughput of a connection every `GAUGE_INTERVAL`, listing it among the open
+/// connections meanwhile. Never resolves.
+async fn gauge(connection: &Connection, meter: &Arc<Meter>) {
+    /// Takes a connection off the list when dropped.
+    struct Listed<'a>(&'a Arc<Meter>);
+
+    impl Drop for Listed<'_> {
+        fn drop(&mut self) {
+            OPEN.lock()
+                .unwrap()
+                .retain(|(_, _, meter)| !Arc::ptr_eq(meter, self.0));
+        }
+    }
+
+    OPEN.lock()
+        .unwrap()
+        .push((connection.clone(), Instant::now(), meter.clone()));
+    let _listed = Listed(meter);
+    let mut interval = tokio::time::interval_at(Instant::now() + GAUGE_INTERVAL, GAUGE_INTERVAL);
+    loop {
+        interval.tick().await;
+        meter.sample();
+    }
+}
+
+/// Makes room in the list of open connections for `expected` of them.
+pub fn reserve(expected: usize) {
+    let mut open = OPEN.lock().unwrap();
+    let additional = expected.saturating_sub(open.len());
+    open.reserve(additional);
+}
+
+/// Describes the open connections with their throughput each way, smoothed over about a
+/// second, the fastest first.
+pub fn describe_connections() -> Vec<String> {
+    let mut open: Vec<_> = OPEN
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|(connection, opened, meter)| (connection.clone(), opened.elapsed(), meter.rates()))
+        .collect();
+    open.sort_by_key(|(_, _, [sent, received])| std::cmp::Reverse(sent + received));
+
+    open.into_iter()
+        .map(|(connection, connected, [sent, received])| {
+            let mapping = match &*connection.mapping {
+                DEFAULT_MAPPING => String::new(),
+                mapping => format!(" [{}]", mapping),
+            };
+            format!(
+                "{} on :{}{}: {} from the client, {} to the client, connected for {}",
+                DisplayAddr(connection.peer),
+                connection.local.port(),
+                mapping,
+                Rate(sent),
+                Rate(received),
+                Elapsed(connected)
+            )
+        })
+        .collect()
+}
+
+impl<S: AsyncRead> AsyncRead for Metered<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let this = self.get_mut();
+        let before = buf.filled().len();
+        let result = this.inner.as_mut().poll_read(cx, buf);
+        if let Poll::Ready(Ok(())) = result {
+            let read = buf.filled().len() - before;
+            this.reporter_handle.transferred(read as u64);
+            if read > 0 {
+                this.meter.read.fetch_add(read as u64, Ordering::Relaxed);
+                this.meter.reads.fetch_add(1, Ordering::Relaxed);
+                this.meter.flow.lock().unwrap().flowed(true);
+            }
+        }
+        result
+    }
+}
+
+impl<S: AsyncWrite> AsyncWrite for Metered<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        let this = self.get_mut();
+        let result = this.inner.as_mut().poll_write(cx, buf);
+        if let Poll::Ready(Ok(written)) = result {
+            this.reporter_handle.transferred(written as u64);
+            this.meter
+                .written
+                .fetch_add(written as u64, Ordering::Relaxed);
+            this.meter.writes.fetch_add(1, Ordering::Relaxed);
+            this.meter.flow.lock().unwrap().flowed(false);
+        }
+        result
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        self.get_mut().inner.as_mut().poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        self.get_mut().inner.as_mut().poll_shutdown(cx)
+    }
+}
+
+/// What to report a stall of a stream with, once writing to it made no progress for at least
+/// `threshold`.
+#[derive(Clone)]
+struct StallReport {
+    /// How long writing may make no progress before it's a stall.
+    threshold: std::time::Duration,
+
+    /// The direction of the connection that writes to the stream.
+    direction: Direction,
+
+    /// The connection the stream belongs to.
+    connection: Connection,
+
+    /// Used for reporting the stalls.
+    reporter_handle: ReporterHandle,
+}
+
+/// Watches the writes to a stream for stalls: while the peer accepts none of what's pending, the
+/// writes can't complete. Stalls are reported once they're over, or the stream is dropped.
+struct Stalls<S> {
+    /// The stream.
+    inner: Pin<Box<S>>,
+
+    /// What to report stalls with, if watching at all.
+    report: Option<StallReport>,
+
+    /// Since when writing made no progress, if it's pending.
+    stalled_since: Option<Instant>,
+}
+
+impl<S> Stalls<S> {
+    /// Watches the writes to a stream, if there's something to report stalls with.
+    fn new(inner: S, report: Option<StallReport>) -> Self {
+        Self {
+            inner: Box::pin(inner),
+            report,
+            stalled_since: None,
+        }
+    }
+
+    /// Ends a pending write, reporting it if it took long enough to be a stall.
+    fn progressed(&mut self) {
+        let (Some(since), Some(report)) = (self.stalled_since.take(), &self.report) else {
+            return;
+        };
+        let stalled = since.elapsed();
+        if stalled >= report.threshold {
+            report.reporter_handle.report(Event::Stalled(
+                report.connection.clone(),
+                report.direction,
+                stalled,
+            ));
+        }
+    }
+}
+
+impl<S> Drop for Stalls<S> {
+    fn drop(&mut self) {
+        self.progressed();
+    }
+}
+
+impl<S: AsyncRead> AsyncRead for Stalls<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        self.get_mut().inner.as_mut().poll_read(cx, buf)
+    }
+}
+
+impl<S: AsyncWrite> AsyncWrite for Stalls<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        let this = self.get_mut();
+        let result = this.inner.as_mut().poll_write(cx, buf);
+        match result {
+            Poll::Pending if this.report.is_some() => {
+                this.stalled_since.get_or_insert_with(Instant::now);
+            }
+            Poll::Pending => {}
+            Poll::Ready(_) => this.progressed(),
+        }
+        result
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        self.get_mut().inner.as_mut().poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        self.get_mut().inner.as_mut().poll_shutdown(cx)
+    }
 }
 
 /// Maps IO error to a `SocketCloseError`.
 fn map_io_error(direction: Direction, err: std::io::Error) -> SocketCloseError {
-    SocketCloseError(direction, err.to_string())
+    SocketCloseError(direction, err.to_string(), err.kind())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Harness;
+    use tokio::io::AsyncReadExt;
+
+    #[tokio::test]
+    async fn forwards_and_reports() {
+        let harness = Harness::start().await.unwrap();
+        let mut client = harness.connect().await.unwrap();
+        client.write_all(b"hello").await.unwrap();
+        let mut echoed = [0; 5];
+        client.read_exact(&mut echoed).await.unwrap();
+        assert_eq!(&echoed, b"hello");
+
+        harness
+            .wait_for(|snapshot| snapshot.open == 1)
+            .await
+            .unwrap();
+        drop(client);
+        let snapshot = harness
+            .wait_for(|snapshot| snapshot.open == 0)
+            .await
+            .unwrap();
+        assert_eq!((snapshot.total, snapshot.closed_with_error), (1, 0));
+    }
+
+    #[test]
+    fn validates_named_pipes() {
+        assert_eq!(
+            pipe_name("npipe:////./pipe/sockgauge").as_deref(),
+            Some(r"\\.\pipe\sockgauge")
+        );
+        assert_eq!(pipe_name("localhost:80"), None);
+
+        let pipe = validate_destination("npipe:////./pipe/sockgauge");
+        let not_a_pipe = validate_destination("npipe://sockgauge");
+        if cfg!(windows) {
+            assert_eq!(pipe, Ok(()));
+            assert_eq!(
+                not_a_pipe,
+                Err(
+                    "`npipe://sockgauge` is not a named pipe address like npipe:////./pipe/name"
+                        .into()
+                )
+            );
+        } else {
+            assert_eq!(
+                pipe,
+                Err(
+                    "`npipe:////./pipe/sockgauge` is a named pipe, which only works on Windows"
+                        .into()
+                )
+            );
+            assert!(not_a_pipe.is_err());
+        }
+    }
+
+    #[cfg(windows)]
+    #[tokio::test]
+    async fn forwards_to_named_pipes() {
+        use tokio::net::windows::named_pipe::ServerOptions;
+
+        // An echo server on a named pipe, taking one client at a time.
+        let name = format!(r"\\.\pipe\sockgauge-test-{}", std::process::id());
+        let mut server = ServerOptions::new()
+            .first_pipe_instance(true)
+            .create(&name)
+            .unwrap();
+        let pipe_name = name.clone();
+        tokio::spawn(async move {
+            while server.connect().await.is_ok() {
+                let connected = server;
+                server = ServerOptions::new().create(&pipe_name).unwrap();
+                tokio::spawn(async move {
+                    let (mut read, mut write) = tokio::io::split(connected);
+                    let _ = tokio::io::copy(&mut read, &mut write).await;
+                });
+            }
+        });
+
+        let harness = Harness::start_with(|destination| {
+            destination.addr = format!("npipe://{}", name.replace('\\', "/"));
+        })
+        .await
+        .unwrap();
+        for _ in 0..2 {
+            let mut client = harness.connect().await.unwrap();
+            client.write_all(b"hello").await.unwrap();
+            let mut echoed = [0; 5];
+            client.read_exact(&mut echoed).await.unwrap();
+            assert_eq!(&echoed, b"hello");
+        }
+        harness
+            .wait_for(|snapshot| snapshot.total == 2 && snapshot.open == 0)
+            .await
+            .unwrap();
+    }
+
+    #[cfg(windows)]
+    #[tokio::test]
+    async fn listens_on_named_pipes() {
+        use tokio::net::windows::named_pipe::ClientOptions;
+
+        let harness = Harness::start().await.unwrap();
+        let name = format!(r"\\.\pipe\sockgauge-listen-{}", std::process::id());
+        let bind_addrs = [format!("npipe://{}", name.replace('\\', "/"))];
+        let destination = harness.destination.subscribe();
+        let reporter_handle = harness.reporter_handle.clone();
+        let proxy = tokio::spawn(async move {
+            let mapping = Arc::from(DEFAULT_MAPPING);
+            run_pipes(&bind_addrs, mapping, destination, reporter_handle).await
+        });
+
+        // Clients go through the proxy to the echo server, one after another.
+        for _ in 0..2 {
+            let mut client = loop {
+                match ClientOptions::new().open(&name) {
+                    Ok(client) => break client,
+                    Err(_) => tokio::time::sleep(std::time::Duration::from_millis(10)).await,
+                }
+            };
+            client.write_all(b"hello").await.unwrap();
+            let mut echoed = [0; 5];
+            client.read_exact(&mut echoed).await.unwrap();
+            assert_eq!(&echoed, b"hello");
+        }
+        harness
+            .wait_for(|snapshot| snapshot.total == 2 && snapshot.open == 0)
+            .await
+            .unwrap();
+        proxy.abort();
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn forwards_port_ranges() {
+        // Two ports in a row that are free on two loopback addresses, one for the proxy and one
+        // for the destination. Each port on the destination answers with its number.
+        let (first, servers) = loop {
+            let probe = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+            let first = probe.local_addr().unwrap().port();
+            drop(probe);
+            let bound: Result<Vec<_>, _> = [first, first.wrapping_add(1)]
+                .iter()
+                .flat_map(|port| [("127.0.0.1", *port), ("127.0.0.2", *port)])
+                .map(std::net::TcpListener::bind)
+                .collect();
+            if let Ok(bound) = bound {
+                break (
+                    first,
+                    [bound[1].try_clone().unwrap(), bound[3].try_clone().unwrap()],
+                );
+            }
+        };
+        for server in servers {
+            server.set_nonblocking(true).unwrap();
+            let server = TcpListener::from_std(server).unwrap();
+            tokio::spawn(async move {
+                while let Ok((mut stream, _)) = server.accept().await {
+                    let port = stream.local_addr().unwrap().port();
+                    let _ = stream.write_all(&port.to_be_bytes()).await;
+                }
+            });
+        }
+
+        let listeners = bind(
+            &["127.0.0.1".to_string()],
+            Some((first, first + 1)),
+            None,
+            1,
+        )
+        .await
+        .unwrap();
+        let ports: Vec<_> = listeners
+            .iter()
+            .map(|listener| listener.local_addr().unwrap().port())
+            .collect();
+        assert_eq!(ports, [first, first + 1]);
+
+        let (_destination, receiver) =
+            watch::channel(Destination::new("127.0.0.2".to_string(), true));
+        let (reporter_handle, reporter_actor) = crate::reporter::create(Default::default());
+        tokio::spawn(reporter_actor.run());
+        let proxy = tokio::spawn(async move {
+            let mapping = Arc::from(DEFAULT_MAPPING);
+            run(&listeners, mapping, receiver, reporter_handle).await
+        });
+
+        // Connections go to the port on the destination they came in on.
+        for port in [first + 1, first, first + 1] {
+            let mut client = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+            let mut answer = [0; 2];
+            client.read_exact(&mut answer).await.unwrap();
+            assert_eq!(u16::from_be_bytes(answer), port);
+        }
+        proxy.abort();
+    }
+
+    #[tokio::test]
+    async fn drains() {
+        let harness = Harness::start().await.unwrap();
+        let mut client = harness.connect().await.unwrap();
+        harness
+            .wait_for(|snapshot| snapshot.open == 1)
+            .await
+            .unwrap();
+
+        harness
+            .destination
+            .send_modify(|destination| destination.drain_generation += 1);
+        let mut rest = Vec::new();
+        client.read_to_end(&mut rest).await.unwrap();
+        harness
+            .wait_for(|snapshot| snapshot.open == 0)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn closes_by_policy() {
+        let harness = Harness::start_with(|destination| {
+            destination.forwarding.policies = policy::parse_policies("/^QUIT/ close").unwrap();
+        })
+        .await
+        .unwrap();
+        let mut client = harness.connect().await.unwrap();
+        client.write_all(b"QUIT\r\n").await.unwrap();
+        let mut rest = Vec::new();
+        client.read_to_end(&mut rest).await.unwrap();
+        assert!(rest.is_empty());
+    }
+
+    #[tokio::test]
+    async fn labels_connections() {
+        // A destination that resets every connection once it got something, so it closes with
+        // an error.
+        let resetting = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let resetting_addr = resetting.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = resetting.accept().await {
+                let _ = stream.read(&mut [0; 16]).await;
+                stream.set_linger(Some(std::time::Duration::ZERO)).unwrap();
+            }
+        });
+
+        let harness = Harness::start_with(|destination| {
+            destination.forwarding.labels = vec![("test".into(), "run42".into())];
+            destination.forwarding.policies = policy::parse_policies(&format!(
+                "/curl/ tag tenant=curl; /^fail/ tag scripted; /^fail/ route {}",
+                resetting_addr
+            ))
+            .unwrap();
+        })
+        .await
+        .unwrap();
+        for sent in [&b"curl"[..], b"hello"] {
+            let mut client = harness.connect().await.unwrap();
+            client.write_all(sent).await.unwrap();
+            let mut echoed = vec![0; sent.len()];
+            client.read_exact(&mut echoed).await.unwrap();
+        }
+        let mut client = harness.connect().await.unwrap();
+        client.write_all(b"fail").await.unwrap();
+        let _ = client.read_to_end(&mut Vec::new()).await;
+
+        harness
+            .wait_for(|snapshot| snapshot.total == 3 && snapshot.open == 0)
+            .await
+            .unwrap();
+        let aggregates = harness.reporter_handle.aggregates().await.unwrap();
+        let breakdowns = aggregates
+            .get("aggregates")
+            .and_then(|aggregates| aggregates.get("breakdowns"))
+            .unwrap();
+        let counts = |breakdown: &str| breakdowns.get(breakdown).unwrap().to_string();
+        assert_eq!(
+            counts("labels"),
+            r#"{"tag=scripted":1,"tenant=curl":1,"test=run42":3}"#
+        );
+        assert_eq!(
+            counts("labels of connections closed with an error"),
+            r#"{"tag=scripted":1,"test=run42":1}"#
+        );
+    }
+
+    #[tokio::test]
+    async fn takes_turns_accepting_on_busy_listeners() {
+        let listeners = [
+            TcpListener::bind("127.0.0.1:0").await.unwrap(),
+            TcpListener::bind("127.0.0.1:0").await.unwrap(),
+        ];
+        let addrs: Vec<SocketAddr> = listeners
+            .iter()
+            .map(|listener| listener.local_addr().unwrap())
+            .collect();
+
+        // Both have connections waiting, and neither gets to accept twice in a row.
+        let mut clients = Vec::new();
+        for addr in addrs.iter().chain(&addrs) {
+            clients.push(TcpStream::connect(addr).await.unwrap());
+        }
+        let mut next = 0;
+        let mut accepted = Vec::new();
+        for _ in 0..4 {
+            let (stream, _) = accept(&listeners, &mut next).await.unwrap();
+            accepted.push(stream.local_addr().unwrap());
+        }
+        assert_eq!(accepted, [addrs[0], addrs[1], addrs[0], addrs[1]]);
+    }
+
+    #[tokio::test]
+    async fn closes_silent_connections() {
+        let harness = Harness::start_with(|destination| {
+            destination.forwarding.first_byte_timeout = Some(std::time::Duration::from_millis(100));
+        })
+        .await
+        .unwrap();
+        let mut client = harness.connect().await.unwrap();
+        let mut rest = Vec::new();
+        client.read_to_end(&mut rest).await.unwrap();
+
+        // It's closed before the destination is connected to, so it was never open.
+        let aggregates = harness.reporter_handle.aggregates().await.unwrap();
+        let aggregates = aggregates.get("aggregates").unwrap();
+        assert_eq!(
+            aggregates.get("first_byte_timeouts"),
+            Some(&crate::json::Value::Number(1.0))
+        );
+        assert_eq!(
+            aggregates.get("total"),
+            Some(&crate::json::Value::Number(0.0))
+        );
+    }
+
+    #[tokio::test]
+    async fn forwards_silent_connections_for_servers_that_speak_first() {
+        // A server that greets its clients, like SMTP.
+        let server = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = server.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = server.accept().await {
+                tokio::spawn(async move {
+                    let _ = stream.write_all(b"220 ready\r\n").await;
+                    let _ = stream.read(&mut [0; 16]).await;
+                });
+            }
+        });
+        let harness = Harness::start_with(|destination| {
+            destination.addr = addr;
+            destination.forwarding.first_byte_timeout = Some(std::time::Duration::from_millis(100));
+            destination.forwarding.first_byte_connect = true;
+        })
+        .await
+        .unwrap();
+
+        // The greeting gets through, and the client is closed for not answering it.
+        let mut client = harness.connect().await.unwrap();
+        let mut greeting = Vec::new();
+        client.read_to_end(&mut greeting).await.unwrap();
+        assert_eq!(greeting, b"220 ready\r\n");
+        let snapshot = harness
+            .wait_for(|snapshot| snapshot.total == 1 && snapshot.open == 0)
+            .await
+            .unwrap();
+        assert_eq!(snapshot.closed_with_error, 0);
+
+        let aggregates = harness.reporter_handle.aggregates().await.unwrap();
+        let aggregates = aggregates.get("aggregates").unwrap();
+        assert_eq!(
+            aggregates.get("silent"),
+            Some(&crate::json::Value::Number(1.0))
+        );
+        assert_eq!(
+            aggregates.get("first_byte_timeouts"),
+            Some(&crate::json::Value::Number(0.0))
+        );
+    }
+
+    #[tokio::test]
+    async fn reports_stalls() {
+        let harness = Harness::start_with(|destination| {
+            destination.forwarding.stall_threshold = Some(std::time::Duration::from_millis(100));
+        })
+        .await
+        .unwrap();
+
+        // The client never reads what's echoed back, so forwarding to it stalls.
+        let (read, mut write) = harness.connect().await.unwrap().into_split();
+        let writing = tokio::spawn(async move {
+            let chunk = vec![0; 64 * 1024];
+            while write.write_all(&chunk).await.is_ok() {}
+        });
+        tokio::time::sleep(std::time::Duration::from_millis(300)).await;
+        writing.abort();
+        drop(read);
+        harness
+            .wait_for(|snapshot| snapshot.total == 1 && snapshot.open == 0)
+            .await
+            .unwrap();
+
+        let aggregates = harness.reporter_handle.aggregates().await.unwrap();
+        let stalls = aggregates.get("aggregates").unwrap().get("stalls");
+        assert!(stalls.and_then(crate::json::Value::as_f64).unwrap() >= 1.0);
+    }
+
+    #[tokio::test]
+    async fn paces_connects() {
+        let harness = Harness::start_with(|destination| {
+            destination.forwarding.upstream_connect_rate =
+                Some(ConnectRate::parse("10/s").unwrap());
+        })
+        .await
+        .unwrap();
+
+        // Three clients at once are all accepted, but connected to the destination in turn.
+        let started = Instant::now();
+        for _ in 0..3 {
+            let mut client = harness.connect().await.unwrap();
+            tokio::spawn(async move {
+                client.write_all(b"hello").await.unwrap();
+                let mut echoed = [0; 5];
+                client.read_exact(&mut echoed).await.unwrap();
+            });
+        }
+        harness
+            .wait_for(|snapshot| snapshot.total == 3 && snapshot.open == 0)
+            .await
+            .unwrap();
+        assert!(started.elapsed() >= std::time::Duration::from_millis(200));
+
+        let aggregates = harness.reporter_handle.aggregates().await.unwrap();
+        let paced = aggregates.get("aggregates").unwrap().get("paced");
+        assert_eq!(paced.and_then(crate::json::Value::as_f64), Some(2.0));
+    }
+
+    #[tokio::test]
+    async fn gauges_throughput() {
+        let mut throughput = Throughput::default();
+        throughput.sample(2048);
+        throughput.sample(3072);
+        assert_eq!(
+            throughput.range,
+            Some(RateRange {
+                min: 1024,
+                max: 2048
+            })
+        );
+        assert!(throughput.smoothed > 1024.0 && throughput.smoothed < 2048.0);
+
+        // Open connections are listed until they close.
+        let harness = Harness::start().await.unwrap();
+        let mut client = harness.connect().await.unwrap();
+        client.write_all(b"hello").await.unwrap();
+        let mut echoed = [0; 5];
+        client.read_exact(&mut echoed).await.unwrap();
+        let listed = format!(
+            "{} on :{}: ",
+            DisplayAddr(client.local_addr().unwrap()),
+            harness.proxy_addr.port()
+        );
+        let is_listed = || {
+            describe_connections()
+                .iter()
+                .any(|line| line.starts_with(&listed))
+        };
+        assert!(is_listed());
+
+        drop(client);
+        harness
+            .wait_for(|snapshot| snapshot.open == 0)
+            .await
+            .unwrap();
+        assert!(!is_listed());
+    }
+
+    #[tokio::test]
+    async fn races_destinations() {
+        // Nothing listens on a port that was just freed, so connecting to it is refused.
+        let refused = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let refused_addr = refused.local_addr().unwrap().to_string();
+        drop(refused);
+
+        let harness = Harness::start_with(|destination| {
+            destination.forwarding.race = vec![refused_addr.clone()];
+        })
+        .await
+        .unwrap();
+        let mut client = harness.connect().await.unwrap();
+        client.write_all(b"hello").await.unwrap();
+        let mut echoed = [0; 5];
+        client.read_exact(&mut echoed).await.unwrap();
+        assert_eq!(&echoed, b"hello");
+
+        let echo_addr = harness.destination.borrow().addr.clone();
+        let races = tokio::time::timeout(std::time::Duration::from_secs(5), async {
+            loop {
+                let aggregates = harness.reporter_handle.aggregates().await.unwrap();
+                let races = aggregates.get("aggregates").unwrap().get("races").unwrap();
+                if races.get(&refused_addr).is_some() {
+                    return races.clone();
+                }
+                tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+        assert_eq!(
+            races.get(&echo_addr).unwrap().to_string(),
+            r#"{"wins":1,"failures":0}"#
+        );
+        assert_eq!(
+            races.get(&refused_addr).unwrap().to_string(),
+            r#"{"wins":0,"failures":1}"#
+        );
+    }
 }
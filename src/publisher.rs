@@ -0,0 +1,53 @@
+use crate::output::eprint_line;
+use crate::usage;
+use std::future::Future;
+use std::io;
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+/// How many events may wait to be sent before new ones are dropped.
+const QUEUE: usize = 4096;
+
+/// How long to wait before connecting again after the connection failed.
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+/// Somewhere events are published to over a connection, like Redis or an MQTT broker.
+pub trait Publisher: Send + Sync + 'static {
+    /// An event as it's queued.
+    type Event: Send + 'static;
+
+    /// What's left to send after the connection failed, to send after reconnecting.
+    type Pending: Default + Send;
+
+    /// What publishing to it is called in errors, e.g. `publishing events to Redis at ...`.
+    fn describe(&self) -> String;
+
+    /// Connects and sends the events until there are no more (returning `Ok`) or the
+    /// connection fails.
+    fn send<'a>(
+        &'a self,
+        receiver: &'a mut mpsc::Receiver<Self::Event>,
+        pending: &'a mut Self::Pending,
+    ) -> impl Future<Output = io::Result<()>> + Send + 'a;
+}
+
+/// Starts publishing in the background, returning the queue to send events to.
+pub fn start<P: Publisher>(publisher: P) -> mpsc::Sender<P::Event> {
+    let (sender, receiver) = mpsc::channel(QUEUE);
+    usage::spawn(run(publisher, receiver));
+    sender
+}
+
+/// Sends the events, connecting again whenever the connection fails.
+async fn run<P: Publisher>(publisher: P, mut receiver: mpsc::Receiver<P::Event>) {
+    let mut pending = P::Pending::default();
+    loop {
+        match publisher.send(&mut receiver, &mut pending).await {
+            Ok(()) => return,
+            Err(err) => {
+                eprint_line!("💥️ — {} failed: {}", publisher.describe(), err);
+                tokio::time::sleep(RECONNECT_DELAY).await;
+            }
+        }
+    }
+}
@@ -0,0 +1,194 @@
+use crate::http::{Captured, Exchange, Head};
+use crate::net::DisplayAddr;
+use crate::output::eprint_line;
+use crate::reporter::Connection;
+use std::io::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Headers whose values are left out of records.
+const SENSITIVE_HEADERS: [&str; 4] = [
+    "authorization",
+    "proxy-authorization",
+    "cookie",
+    "set-cookie",
+];
+
+/// What sensitive header values are replaced with.
+const REDACTED: &str = "[redacted]";
+
+/// The number of the last request recorded.
+static RECORDED: AtomicU64 = AtomicU64::new(0);
+
+/// Records a request and its response in a file of its own in the directory, in the background.
+pub fn record(dir: String, connection: Connection, exchange: &Exchange) {
+    let number = RECORDED.fetch_add(1, Ordering::Relaxed) + 1;
+    let name = format!(
+        "{}-{:06}-{}-{}.http",
+        compact(exchange.started),
+        number,
+        exchange
+            .request
+            .method()
+            .chars()
+            .filter(char::is_ascii_alphanumeric)
+            .collect::<String>(),
+        exchange.response.status().unwrap_or_default()
+    );
+
+    let mut contents = format!(
+        "# {} from {} on :{}, responded in {:?}\n",
+        rfc3339(exchange.started),
+        DisplayAddr(connection.peer),
+        connection.local.port(),
+        exchange.latency
+    )
+    .into_bytes();
+    write_message(&mut contents, &exchange.request, &exchange.request_body);
+    write_message(&mut contents, &exchange.response, &exchange.response_body);
+
+    tokio::task::spawn_blocking(move || {
+        let path = std::path::Path::new(&dir).join(&name);
+        let result = std::fs::create_dir_all(&dir).and_then(|_| std::fs::write(&path, contents));
+        if let Err(err) = result {
+            eprint_line!("💥️ — recording {} failed: {}", path.display(), err);
+        }
+    });
+}
+
+/// Writes a message head with sensitive values redacted, followed by the recorded part of its
+/// body.
+fn write_message(contents: &mut Vec<u8>, head: &Head, body: &Captured) {
+    contents.extend_from_slice(&sanitize(head).to_bytes());
+    contents.extend_from_slice(&body.bytes);
+    if body.size > body.bytes.len() as u64 {
+        let _ = write!(
+            contents,
+            "\n# ({} of {} bytes recorded)",
+            body.bytes.len(),
+            body.size
+        );
+    }
+    contents.extend_from_slice(b"\n\n");
+}
+
+/// Returns the head with the values of sensitive headers redacted.
+pub fn sanitize(head: &Head) -> Head {
+    let mut head = head.clone();
+    for (name, value) in &mut head.headers {
+        if SENSITIVE_HEADERS
+            .iter()
+            .any(|sensitive| name.eq_ignore_ascii_case(sensitive))
+        {
+            *value = REDACTED.to_string();
+        }
+    }
+    head
+}
+
+/// Formats a time like `2026-10-15T04:05:34.123Z`.
+pub fn rfc3339(time: SystemTime) -> String {
+    let (date, (hours, minutes, seconds), millis) = civil(time);
+    format!(
+        "{}T{:02}:{:02}:{:02}.{:03}Z",
+        date, hours, minutes, seconds, millis
+    )
+}
+
+/// Formats a time in a time zone `offset` seconds ahead of UTC, like
+/// `2026-10-15T06:05:34.123+02:00`.
+pub fn rfc3339_offset(time: SystemTime, offset: i64) -> String {
+    let shift = Duration::from_secs(offset.unsigned_abs());
+    let shifted = match offset < 0 {
+        true => time.checked_sub(shift),
+        false => time.checked_add(shift),
+    };
+    let (date, (hours, minutes, seconds), millis) = civil(shifted.unwrap_or(time));
+    format!(
+        "{}T{:02}:{:02}:{:02}.{:03}{}{:02}:{:02}",
+        date,
+        hours,
+        minutes,
+        seconds,
+        millis,
+        if offset < 0 { '-' } else { '+' },
+        offset.unsigned_abs() / 3600,
+        offset.unsigned_abs() / 60 % 60
+    )
+}
+
+/// Formats a time like `20261015T040534.123Z`, for file names.
+pub fn compact(time: SystemTime) -> String {
+    let (date, (hours, minutes, seconds), millis) = civil(time);
+    format!(
+        "{}T{:02}{:02}{:02}.{:03}Z",
+        date.replace('-', ""),
+        hours,
+        minutes,
+        seconds,
+        millis
+    )
+}
+
+/// Splits a time into the UTC date (`YYYY-MM-DD`), the time of day and the milliseconds.
+fn civil(time: SystemTime) -> (String, (u64, u64, u64), u32) {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let seconds = since_epoch.as_secs();
+    let (days, second_of_day) = ((seconds / 86400) as i64, seconds % 86400);
+
+    // Converts days since the epoch to a date in the proleptic Gregorian calendar, see
+    // http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + (month <= 2) as i64;
+
+    (
+        format!("{:04}-{:02}-{:02}", year, month, day),
+        (
+            second_of_day / 3600,
+            second_of_day / 60 % 60,
+            second_of_day % 60,
+        ),
+        since_epoch.subsec_millis(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_times_and_redacts_secrets() {
+        let time = UNIX_EPOCH + Duration::from_millis(1_792_037_134_123);
+        assert_eq!(rfc3339(time), "2026-10-15T04:05:34.123Z");
+        assert_eq!(compact(time), "20261015T040534.123Z");
+        assert_eq!(
+            rfc3339(UNIX_EPOCH + Duration::from_secs(951_782_400)),
+            "2000-02-29T00:00:00.000Z"
+        );
+        assert_eq!(
+            rfc3339_offset(time, 2 * 3600),
+            "2026-10-15T06:05:34.123+02:00"
+        );
+        assert_eq!(
+            rfc3339_offset(time, -(4 * 3600 + 30 * 60)),
+            "2026-10-14T23:35:34.123-04:30"
+        );
+
+        let head = Head::parse(b"GET / HTTP/1.1\r\nCookie: a=b\r\nAccept: */*\r\n\r\n").unwrap();
+        assert_eq!(
+            sanitize(&head).headers,
+            [
+                ("Cookie".to_string(), REDACTED.to_string()),
+                ("Accept".to_string(), "*/*".to_string())
+            ]
+        );
+    }
+}
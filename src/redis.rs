@@ -0,0 +1,148 @@
+use crate::output::eprint_line;
+use crate::publisher::{self, Publisher};
+use std::future::Future;
+use std::io;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio::sync::mpsc;
+
+/// Where to publish events in Redis.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Target {
+    /// The address of the Redis server.
+    pub addr: String,
+
+    /// The channel to `PUBLISH` events to, if any.
+    pub channel: Option<String>,
+
+    /// The stream to `XADD` events to, if any.
+    pub stream: Option<String>,
+}
+
+/// Publishes events to Redis in the background.
+#[derive(Debug)]
+pub struct Redis {
+    /// Used for sending events to the background task.
+    sender: mpsc::Sender<String>,
+}
+
+impl Redis {
+    /// Starts publishing to a target.
+    pub fn start(target: Target) -> Self {
+        Self {
+            sender: publisher::start(target),
+        }
+    }
+
+    /// Publishes an event, given as JSON. Events are dropped while Redis can't keep up.
+    pub fn publish(&self, event: String) {
+        let _ = self.sender.try_send(event);
+    }
+}
+
+impl Publisher for Target {
+    type Event = String;
+    type Pending = Option<String>;
+
+    fn describe(&self) -> String {
+        format!("publishing events to Redis at {}", self.addr)
+    }
+
+    fn send<'a>(
+        &'a self,
+        receiver: &'a mut mpsc::Receiver<String>,
+        pending: &'a mut Option<String>,
+    ) -> impl Future<Output = io::Result<()>> + Send + 'a {
+        send(self, receiver, pending)
+    }
+}
+
+/// Connects and sends the events until there are no more (returning `Ok`) or the connection
+/// fails. An event that couldn't be sent is left in `pending`, to send after reconnecting.
+async fn send(
+    target: &Target,
+    receiver: &mut mpsc::Receiver<String>,
+    pending: &mut Option<String>,
+) -> io::Result<()> {
+    let (reader, mut writer) = TcpStream::connect(&target.addr).await?.into_split();
+
+    // The replies are read as they come, as commands are sent without waiting for them.
+    let mut replies = BufReader::new(reader).lines();
+    let errors = async {
+        while let Some(reply) = replies.next_line().await? {
+            if let Some(error) = reply.strip_prefix('-') {
+                eprint_line!("💥️ — Redis at {} refused an event: {}", target.addr, error);
+            }
+        }
+        Err::<(), _>(io::Error::from(io::ErrorKind::UnexpectedEof))
+    };
+
+    let commands = async {
+        loop {
+            let event = match pending.take() {
+                Some(event) => event,
+                None => match receiver.recv().await {
+                    Some(event) => event,
+                    None => return Ok(()),
+                },
+            };
+
+            let mut command = Vec::new();
+            if let Some(channel) = &target.channel {
+                command.extend(encode(&["PUBLISH", channel, &event]));
+            }
+            if let Some(stream) = &target.stream {
+                command.extend(encode(&["XADD", stream, "*", "event", &event]));
+            }
+
+            if let Err(err) = writer.write_all(&command).await {
+                *pending = Some(event);
+                return Err(err);
+            }
+        }
+    };
+
+    tokio::select! {
+        result = commands => result,
+        result = errors => result,
+    }
+}
+
+/// Encodes a command in RESP, Redis's protocol.
+fn encode(args: &[&str]) -> Vec<u8> {
+    let mut command = format!("*{}\r\n", args.len()).into_bytes();
+    for arg in args {
+        command.extend_from_slice(format!("${}\r\n", arg.len()).as_bytes());
+        command.extend_from_slice(arg.as_bytes());
+        command.extend_from_slice(b"\r\n");
+    }
+    command
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncReadExt;
+    use tokio::net::TcpListener;
+
+    #[tokio::test]
+    async fn publishes_events() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let redis = Redis::start(Target {
+            addr: listener.local_addr().unwrap().to_string(),
+            channel: Some("events".to_string()),
+            stream: Some("log".to_string()),
+        });
+        redis.publish(r#"{"event":"opened"}"#.to_string());
+        drop(redis);
+
+        let (mut server, _) = listener.accept().await.unwrap();
+        let mut received = String::new();
+        server.read_to_string(&mut received).await.unwrap();
+        assert_eq!(
+            received,
+            "*3\r\n$7\r\nPUBLISH\r\n$6\r\nevents\r\n$18\r\n{\"event\":\"opened\"}\r\n\
+             *5\r\n$4\r\nXADD\r\n$3\r\nlog\r\n$1\r\n*\r\n$5\r\nevent\r\n$18\r\n{\"event\":\"opened\"}\r\n"
+        );
+    }
+}
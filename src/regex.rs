@@ -0,0 +1,618 @@
+/// A compiled regular expression over bytes.
+///
+/// Supports the common subset: literals, `.`, classes (`[a-z]`, `[^0-9]`, `\d`, `\w`, `\s`),
+/// anchors (`^`, `$`), groups (`(...)`, `(?:...)`), alternation (`|`) and greedy or lazy
+/// quantifiers (`*`, `+`, `?`, `{n,m}`). Matching backtracks, but never tries the same
+/// instruction at the same position twice, so it runs in time proportional to the pattern
+/// size times the input length.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Regex {
+    /// The pattern it was compiled from.
+    pattern: String,
+
+    /// The compiled program.
+    program: Vec<Inst>,
+
+    /// The number of capture groups, including the whole match.
+    groups: usize,
+}
+
+/// An instruction of a compiled pattern.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Inst {
+    /// Matches one byte.
+    Byte(u8),
+
+    /// Matches any byte but a newline.
+    Any,
+
+    /// Matches a byte in (or, when negated, not in) the inclusive ranges.
+    Class(Vec<(u8, u8)>, bool),
+
+    /// Continues at both instructions, preferring the first.
+    Split(usize, usize),
+
+    /// Continues at the instruction.
+    Jump(usize),
+
+    /// Records the position in a capture slot.
+    Save(usize),
+
+    /// Matches at the start of the input.
+    Start,
+
+    /// Matches at the end of the input.
+    End,
+
+    /// The pattern matched.
+    Match,
+}
+
+/// The most a bounded quantifier can repeat, to keep programs small.
+const MAX_REPEAT: usize = 1000;
+
+/// The deepest groups can nest.
+const MAX_DEPTH: usize = 64;
+
+impl Regex {
+    /// Compiles a pattern.
+    pub fn new(pattern: &str) -> Result<Regex, String> {
+        let mut parser = Parser {
+            pattern: pattern.as_bytes(),
+            pos: 0,
+            groups: 1,
+            depth: 0,
+        };
+        let node = parser.alternation()?;
+        if parser.pos < pattern.len() {
+            return Err(format!("Unmatched `)` in the regex `{}`", pattern));
+        }
+
+        let mut program = vec![Inst::Save(0)];
+        compile(&node, &mut program);
+        program.extend([Inst::Save(1), Inst::Match]);
+        Ok(Regex {
+            pattern: pattern.to_string(),
+            program,
+            groups: parser.groups,
+        })
+    }
+
+    /// The pattern it was compiled from.
+    pub fn as_str(&self) -> &str {
+        &self.pattern
+    }
+
+    /// Whether the regex matches anywhere in the input.
+    pub fn is_match(&self, input: &[u8]) -> bool {
+        self.find_at(input, 0).is_some()
+    }
+
+    /// Replaces every match with the replacement, in which `$0` to `$9` stand for the match
+    /// and its groups, and `$$` for a dollar sign.
+    pub fn replace_all(&self, input: &str, replacement: &str) -> String {
+        let bytes = input.as_bytes();
+        let mut replaced = Vec::with_capacity(bytes.len());
+        let mut pos = 0;
+        while pos <= bytes.len() {
+            let Some(slots) = self.find_at(bytes, pos) else {
+                break;
+            };
+            let (start, end) = (slots[0].unwrap_or(pos), slots[1].unwrap_or(pos));
+            replaced.extend_from_slice(&bytes[pos..start]);
+            expand(replacement, bytes, &slots, &mut replaced);
+
+            // Step over empty matches so they aren't found again.
+            pos = end;
+            if start == end {
+                replaced.extend(bytes.get(pos));
+                pos += 1;
+            }
+        }
+        replaced.extend(bytes.get(pos..).unwrap_or_default());
+
+        String::from_utf8_lossy(&replaced).into_owned()
+    }
+
+    /// Finds the leftmost match starting at or after `from`, returning the capture slots.
+    fn find_at(&self, input: &[u8], from: usize) -> Option<Vec<Option<usize>>> {
+        // Pairs that failed from an earlier start fail from later ones too.
+        let mut visited = Visited::new(self, input);
+        (from..=input.len()).find_map(|start| self.run(input, start, &mut visited))
+    }
+
+    /// Runs the program from a position, backtracking through the alternatives in order.
+    fn run(&self, input: &[u8], start: usize, visited: &mut Visited) -> Option<Vec<Option<usize>>> {
+        /// Work left to do when backtracking.
+        enum Job {
+            /// Try an instruction at a position.
+            Step(usize, usize),
+
+            /// Undo a capture.
+            Restore(usize, Option<usize>),
+        }
+
+        let mut slots = vec![None; self.groups * 2];
+        let mut jobs = vec![Job::Step(0, start)];
+        while let Some(job) = jobs.pop() {
+            let (mut pc, mut pos) = match job {
+                Job::Step(pc, pos) => (pc, pos),
+                Job::Restore(slot, value) => {
+                    slots[slot] = value;
+                    continue;
+                }
+            };
+
+            // Follow this thread until it fails or matches.
+            loop {
+                if !visited.insert(pc, pos) {
+                    break;
+                }
+
+                let byte = input.get(pos).copied();
+                match &self.program[pc] {
+                    Inst::Byte(expected) if byte == Some(*expected) => {
+                        (pc, pos) = (pc + 1, pos + 1)
+                    }
+                    Inst::Any if byte.is_some_and(|b| b != b'\n') => (pc, pos) = (pc + 1, pos + 1),
+                    Inst::Class(ranges, negated)
+                        if byte.is_some_and(|b| in_ranges(ranges, b) != *negated) =>
+                    {
+                        (pc, pos) = (pc + 1, pos + 1)
+                    }
+                    Inst::Split(first, second) => {
+                        jobs.push(Job::Step(*second, pos));
+                        pc = *first;
+                    }
+                    Inst::Jump(target) => pc = *target,
+                    Inst::Save(slot) => {
+                        jobs.push(Job::Restore(*slot, slots[*slot]));
+                        slots[*slot] = Some(pos);
+                        pc += 1;
+                    }
+                    Inst::Start if pos == 0 => pc += 1,
+                    Inst::End if pos == input.len() => pc += 1,
+                    Inst::Match => return Some(slots),
+                    _ => break,
+                }
+            }
+        }
+
+        None
+    }
+}
+
+/// The (instruction, position) pairs that were tried already in a search. A pair that failed
+/// once fails again, whatever was captured on the way.
+struct Visited {
+    /// One bit per pair.
+    bits: Vec<u64>,
+
+    /// The number of positions, i.e. the input length plus one.
+    positions: usize,
+}
+
+impl Visited {
+    /// Creates an empty set for running the regex on the input.
+    fn new(regex: &Regex, input: &[u8]) -> Self {
+        let positions = input.len() + 1;
+        Self {
+            bits: vec![0; (regex.program.len() * positions).div_ceil(64)],
+            positions,
+        }
+    }
+
+    /// Adds a pair, returning whether it wasn't tried before.
+    fn insert(&mut self, pc: usize, pos: usize) -> bool {
+        let index = pc * self.positions + pos;
+        let (word, bit) = (index / 64, 1 << (index % 64));
+        let new = self.bits[word] & bit == 0;
+        self.bits[word] |= bit;
+        new
+    }
+}
+
+/// Whether the byte is in one of the inclusive ranges.
+fn in_ranges(ranges: &[(u8, u8)], byte: u8) -> bool {
+    ranges
+        .iter()
+        .any(|(low, high)| (*low..=*high).contains(&byte))
+}
+
+/// Appends the replacement, expanding `$n` references to the captures.
+fn expand(replacement: &str, input: &[u8], slots: &[Option<usize>], out: &mut Vec<u8>) {
+    let mut chars = replacement.bytes().peekable();
+    while let Some(c) = chars.next() {
+        match (c, chars.peek()) {
+            (b'$', Some(b'$')) => {
+                chars.next();
+                out.push(b'$');
+            }
+            (b'$', Some(digit @ b'0'..=b'9')) => {
+                let group = (digit - b'0') as usize;
+                chars.next();
+                if let (Some(Some(start)), Some(Some(end))) =
+                    (slots.get(group * 2), slots.get(group * 2 + 1))
+                {
+                    out.extend_from_slice(&input[*start..*end]);
+                }
+            }
+            _ => out.push(c),
+        }
+    }
+}
+
+/// A parsed pattern.
+enum Node {
+    /// Matches nothing, always succeeding.
+    Empty,
+
+    /// A single-byte instruction (a byte, `.`, a class or an anchor).
+    Inst(Inst),
+
+    /// A capture group.
+    Group(Box<Node>, usize),
+
+    /// Nodes that match one after the other.
+    Concat(Vec<Node>),
+
+    /// Alternatives, tried in order.
+    Alternation(Vec<Node>),
+
+    /// A repetition: the node, the minimum and maximum count, and whether it's greedy.
+    Repeat(Box<Node>, usize, Option<usize>, bool),
+}
+
+/// Parses a pattern.
+struct Parser<'a> {
+    /// The pattern being parsed.
+    pattern: &'a [u8],
+
+    /// The current position.
+    pos: usize,
+
+    /// The number of capture groups so far, including the whole match.
+    groups: usize,
+
+    /// How deep the groups are nested at the current position.
+    depth: usize,
+}
+
+impl Parser<'_> {
+    /// Parses alternatives separated by `|`.
+    fn alternation(&mut self) -> Result<Node, String> {
+        let mut alternatives = vec![self.concat()?];
+        while self.eat(b'|') {
+            alternatives.push(self.concat()?);
+        }
+
+        Ok(match alternatives.len() {
+            1 => alternatives.pop().unwrap(),
+            _ => Node::Alternation(alternatives),
+        })
+    }
+
+    /// Parses a sequence of (possibly repeated) atoms.
+    fn concat(&mut self) -> Result<Node, String> {
+        let mut nodes = Vec::new();
+        while let Some(c) = self.peek() {
+            if c == b'|' || c == b')' {
+                break;
+            }
+
+            let atom = self.atom()?;
+            nodes.push(self.repeat(atom)?);
+        }
+
+        Ok(match nodes.len() {
+            0 => Node::Empty,
+            1 => nodes.pop().unwrap(),
+            _ => Node::Concat(nodes),
+        })
+    }
+
+    /// Parses the quantifiers after an atom, if any.
+    fn repeat(&mut self, mut node: Node) -> Result<Node, String> {
+        loop {
+            let (min, max) = match self.peek() {
+                Some(b'*') => (0, None),
+                Some(b'+') => (1, None),
+                Some(b'?') => (0, Some(1)),
+                Some(b'{') => match self.counts() {
+                    Some(counts) => counts,
+                    None => return Ok(node),
+                },
+                _ => return Ok(node),
+            };
+            if matches!(self.peek(), Some(b'*' | b'+' | b'?')) {
+                self.pos += 1;
+            }
+
+            if min.max(max.unwrap_or(0)) > MAX_REPEAT || max.is_some_and(|max| max < min) {
+                return Err(self.error("invalid repetition count"));
+            }
+
+            let greedy = !self.eat(b'?');
+            node = Node::Repeat(Box::new(node), min, max, greedy);
+        }
+    }
+
+    /// Parses `{n}`, `{n,}` or `{n,m}`, leaving a `{` that isn't one of those as a literal.
+    fn counts(&mut self) -> Option<(usize, Option<usize>)> {
+        let rest = std::str::from_utf8(&self.pattern[self.pos + 1..]).ok()?;
+        let (inside, _) = rest.split_once('}')?;
+        let counts = match inside.split_once(',') {
+            Some((min, "")) => (min.parse().ok()?, None),
+            Some((min, max)) => (min.parse().ok()?, Some(max.parse().ok()?)),
+            None => (inside.parse().ok()?, Some(inside.parse().ok()?)),
+        };
+        self.pos += inside.len() + 2;
+        Some(counts)
+    }
+
+    /// Parses a single atom: a group, a class, an escape or a literal byte.
+    fn atom(&mut self) -> Result<Node, String> {
+        let c = self.next().ok_or_else(|| self.error("unexpected end"))?;
+        Ok(match c {
+            b'(' => {
+                self.depth += 1;
+                if self.depth > MAX_DEPTH {
+                    return Err(self.error("groups are nested too deeply"));
+                }
+
+                let group = match self.pattern[self.pos..].starts_with(b"?:") {
+                    true => {
+                        self.pos += 2;
+                        None
+                    }
+                    false => {
+                        self.groups += 1;
+                        Some(self.groups - 1)
+                    }
+                };
+                let inner = self.alternation()?;
+                if !self.eat(b')') {
+                    return Err(self.error("missing `)`"));
+                }
+                self.depth -= 1;
+
+                match group {
+                    Some(group) => Node::Group(Box::new(inner), group),
+                    None => inner,
+                }
+            }
+            b'[' => self.class()?,
+            b'.' => Node::Inst(Inst::Any),
+            b'^' => Node::Inst(Inst::Start),
+            b'$' => Node::Inst(Inst::End),
+            b'*' | b'+' | b'?' => return Err(self.error("nothing to repeat")),
+            b'\\' => {
+                let (ranges, negated) = self.escape()?;
+                match (ranges.as_slice(), negated) {
+                    ([(low, high)], false) if low == high => Node::Inst(Inst::Byte(*low)),
+                    _ => Node::Inst(Inst::Class(ranges, negated)),
+                }
+            }
+            c => Node::Inst(Inst::Byte(c)),
+        })
+    }
+
+    /// Parses a class like `[a-z_]` or `[^,]`, after the `[`.
+    fn class(&mut self) -> Result<Node, String> {
+        let negated = self.eat(b'^');
+        let mut ranges = Vec::new();
+        let mut first = true;
+        loop {
+            let c = self.next().ok_or_else(|| self.error("missing `]`"))?;
+            if c == b']' && !first {
+                break;
+            }
+            first = false;
+
+            let low = match c {
+                b'\\' => {
+                    let (escaped, escaped_negated) = self.escape()?;
+                    match (escaped.as_slice(), escaped_negated) {
+                        ([(low, high)], false) if low == high => *low,
+                        (_, false) => {
+                            ranges.extend(escaped);
+                            continue;
+                        }
+                        (_, true) => return Err(self.error("negated escapes in classes")),
+                    }
+                }
+                c => c,
+            };
+
+            // A range, unless the `-` is the last character of the class.
+            let high = match (self.peek(), self.pattern.get(self.pos + 1)) {
+                (Some(b'-'), Some(&high)) if high != b']' => {
+                    self.pos += 2;
+                    match high {
+                        b'\\' => match self.escape()? {
+                            (escaped, false) if escaped.len() == 1 => escaped[0].0,
+                            _ => return Err(self.error("invalid class range")),
+                        },
+                        high => high,
+                    }
+                }
+                _ => low,
+            };
+            if high < low {
+                return Err(self.error("invalid class range"));
+            }
+            ranges.push((low, high));
+        }
+
+        Ok(Node::Inst(Inst::Class(ranges, negated)))
+    }
+
+    /// Parses an escape after the `\`, returning the bytes it matches.
+    fn escape(&mut self) -> Result<(Vec<(u8, u8)>, bool), String> {
+        let digits = vec![(b'0', b'9')];
+        let word = vec![(b'0', b'9'), (b'A', b'Z'), (b'_', b'_'), (b'a', b'z')];
+        let space = vec![(b'\t', b'\r'), (b' ', b' ')];
+
+        let c = self.next().ok_or_else(|| self.error("trailing `\\`"))?;
+        Ok(match c {
+            b'd' => (digits, false),
+            b'D' => (digits, true),
+            b'w' => (word, false),
+            b'W' => (word, true),
+            b's' => (space, false),
+            b'S' => (space, true),
+            b'n' => (vec![(b'\n', b'\n')], false),
+            b'r' => (vec![(b'\r', b'\r')], false),
+            b't' => (vec![(b'\t', b'\t')], false),
+            b'x' => {
+                let hex = self.pattern.get(self.pos..self.pos + 2);
+                let byte = hex
+                    .and_then(|hex| std::str::from_utf8(hex).ok())
+                    .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                    .ok_or_else(|| self.error("expected two hex digits after `\\x`"))?;
+                self.pos += 2;
+                (vec![(byte, byte)], false)
+            }
+            c if c.is_ascii_alphanumeric() => return Err(self.error("unknown escape")),
+            c => (vec![(c, c)], false),
+        })
+    }
+
+    /// The current byte.
+    fn peek(&self) -> Option<u8> {
+        self.pattern.get(self.pos).copied()
+    }
+
+    /// Consumes the current byte.
+    fn next(&mut self) -> Option<u8> {
+        let c = self.peek()?;
+        self.pos += 1;
+        Some(c)
+    }
+
+    /// Consumes the current byte if it's the given one.
+    fn eat(&mut self, c: u8) -> bool {
+        let matches = self.peek() == Some(c);
+        self.pos += matches as usize;
+        matches
+    }
+
+    /// Describes a syntax error at the current position.
+    fn error(&self, message: &str) -> String {
+        format!(
+            "Invalid regex `{}` at position {}: {}",
+            String::from_utf8_lossy(self.pattern),
+            self.pos,
+            message
+        )
+    }
+}
+
+/// Appends the instructions for a node.
+fn compile(node: &Node, program: &mut Vec<Inst>) {
+    match node {
+        Node::Empty => {}
+        Node::Inst(inst) => program.push(inst.clone()),
+        Node::Group(inner, group) => {
+            program.push(Inst::Save(group * 2));
+            compile(inner, program);
+            program.push(Inst::Save(group * 2 + 1));
+        }
+        Node::Concat(nodes) => nodes.iter().for_each(|node| compile(node, program)),
+        Node::Alternation(alternatives) => {
+            // Each alternative but the last is tried first, and jumps past the rest when done.
+            let mut jumps = Vec::new();
+            for (index, alternative) in alternatives.iter().enumerate() {
+                if index + 1 == alternatives.len() {
+                    compile(alternative, program);
+                    break;
+                }
+
+                let split = program.len();
+                program.push(Inst::Split(split + 1, 0));
+                compile(alternative, program);
+                jumps.push(program.len());
+                program.push(Inst::Jump(0));
+                program[split] = Inst::Split(split + 1, program.len());
+            }
+
+            let end = program.len();
+            for jump in jumps {
+                program[jump] = Inst::Jump(end);
+            }
+        }
+        Node::Repeat(inner, min, max, greedy) => {
+            for _ in 0..*min {
+                compile(inner, program);
+            }
+
+            match max {
+                // Optional copies, each skipping the rest when not taken.
+                Some(max) => {
+                    let mut splits = Vec::new();
+                    for _ in *min..*max {
+                        splits.push(program.len());
+                        program.push(Inst::Split(0, 0));
+                        compile(inner, program);
+                    }
+
+                    let end = program.len();
+                    for split in splits {
+                        program[split] = choice(split + 1, end, *greedy);
+                    }
+                }
+
+                // A loop.
+                None => {
+                    let split = program.len();
+                    program.push(Inst::Split(0, 0));
+                    compile(inner, program);
+                    program.push(Inst::Jump(split));
+                    program[split] = choice(split + 1, program.len(), *greedy);
+                }
+            }
+        }
+    }
+}
+
+/// A split preferring to take (greedy) or skip (lazy) a repetition.
+fn choice(take: usize, skip: usize, greedy: bool) -> Inst {
+    match greedy {
+        true => Inst::Split(take, skip),
+        false => Inst::Split(skip, take),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_and_replaces() {
+        let bearer = Regex::new(r"^(Bearer|Basic) \S+$").unwrap();
+        assert!(bearer.is_match(b"Bearer abc.def"));
+        assert!(!bearer.is_match(b"Bearer abc def"));
+        assert_eq!(bearer.replace_all("Basic dXNlcg==", "$1 ***"), "Basic ***");
+
+        let host = Regex::new(r"([a-z0-9-]+)\.test(:\d{2,5})?").unwrap();
+        assert_eq!(
+            host.replace_all("api.test:8080, web.test", "$1.internal"),
+            "api.internal, web.internal"
+        );
+        assert_eq!(
+            Regex::new("a*?").unwrap().replace_all("baa", "-"),
+            "-b-a-a-"
+        );
+        assert_eq!(
+            Regex::new("[^,]+").unwrap().replace_all("a,bc", "<$0>"),
+            "<a>,<bc>"
+        );
+
+        // Nested quantifiers don't blow up.
+        let nested = Regex::new("(a*)*b").unwrap();
+        assert!(nested.find_at(&[b'a'; 5000], 0).is_none());
+
+        assert!(Regex::new("(a").is_err());
+        assert!(Regex::new("a{3,1}").is_err());
+    }
+}
@@ -0,0 +1,346 @@
+use crate::format::Elapsed;
+use crate::output::print_line;
+use crate::session::{self, Record, Session};
+use std::error::Error;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::time::Instant;
+
+/// How long to wait for more of a response once everything has been sent.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Runs `sockgauge replay <sessions> <destination> [--speed <factor>]`: replays recorded
+/// sessions (a session file, or a directory of them) against the destination, paced like the
+/// original connections, and reports the sessions whose responses differ in size from the
+/// recorded ones.
+pub async fn run(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let (path, dest_addr, speed) = parse_args(args)?;
+    let mut sessions = Vec::new();
+    for path in session_files(Path::new(&path))? {
+        let session = Session::read(&path)?;
+        sessions.push((path, session));
+    }
+    sessions.sort_by_key(|(_, session)| session.started);
+
+    let Some(first_started) = sessions.first().map(|(_, session)| session.started) else {
+        return Err(format!("No sessions to replay in {}", path).into());
+    };
+    match speed {
+        Some(speed) => print_line!(
+            "⏯️  replaying {} session(s) against {} at {}x speed",
+            sessions.len(),
+            dest_addr,
+            speed
+        ),
+        None => print_line!(
+            "⏯️  replaying {} session(s) against {} as fast as possible",
+            sessions.len(),
+            dest_addr
+        ),
+    }
+
+    let start = Instant::now();
+    let replays: Vec<_> = sessions
+        .into_iter()
+        .map(|(path, session)| {
+            let dest_addr = dest_addr.clone();
+            let delay = match speed {
+                Some(speed) => session
+                    .started
+                    .duration_since(first_started)
+                    .unwrap_or_default()
+                    .div_f64(speed),
+                None => Duration::ZERO,
+            };
+            tokio::spawn(async move {
+                tokio::time::sleep_until(start + delay).await;
+                let result = replay(&session, &dest_addr, speed).await;
+                report(&path, &session, result)
+            })
+        })
+        .collect();
+
+    let (mut matched, mut diverged, mut failed) = (0, 0, 0);
+    for replay in replays {
+        match replay.await? {
+            Outcome::Matched => matched += 1,
+            Outcome::Diverged => diverged += 1,
+            Outcome::Failed => failed += 1,
+        }
+    }
+
+    print_line!(
+        "⏯️  replayed {} session(s) in {}: {} matched, {} diverged, {} failed",
+        matched + diverged + failed,
+        Elapsed(start.elapsed()),
+        matched,
+        diverged,
+        failed
+    );
+    Ok(())
+}
+
+/// How a replayed session compares to its recording.
+enum Outcome {
+    /// The responses were as big as the recorded ones.
+    Matched,
+
+    /// The responses were bigger or smaller.
+    Diverged,
+
+    /// The session couldn't be replayed.
+    Failed,
+}
+
+/// Prints how a replayed session compares to its recording.
+fn report(path: &Path, session: &Session, result: io::Result<(u64, u64)>) -> Outcome {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let recorded: u64 = session
+        .records
+        .iter()
+        .map(|record| match record {
+            Record::Server(_, size) => *size,
+            _ => 0,
+        })
+        .sum();
+
+    match result {
+        Ok((sent, received)) if received == recorded => {
+            print_line!(
+                "🟢 {} (from {}): sent {} bytes, received {} bytes as recorded",
+                name,
+                session.peer,
+                sent,
+                received
+            );
+            Outcome::Matched
+        }
+        Ok((sent, received)) => {
+            print_line!(
+                "⚠️  {} (from {}): sent {} bytes, received {} bytes instead of {} ({:+})",
+                name,
+                session.peer,
+                sent,
+                received,
+                recorded,
+                received as i64 - recorded as i64
+            );
+            Outcome::Diverged
+        }
+        Err(err) => {
+            print_line!("🔴 {} (from {}): {}", name, session.peer, err);
+            Outcome::Failed
+        }
+    }
+}
+
+/// Replays the client's side of a session, returning how many bytes were sent and received.
+/// Unless the speed is `None`, everything is sent as long after connecting as it originally was
+/// after the client connected, divided by the speed.
+async fn replay(session: &Session, dest_addr: &str, speed: Option<f64>) -> io::Result<(u64, u64)> {
+    let stream = TcpStream::connect(dest_addr).await?;
+    let connected = Instant::now();
+    let (mut read, mut write) = stream.into_split();
+    let pace = |offset: Duration| async move {
+        if let Some(speed) = speed {
+            tokio::time::sleep_until(connected + offset.div_f64(speed)).await;
+        }
+    };
+
+    let mut sent = 0;
+    let received = {
+        let send = async {
+            for record in &session.records {
+                match record {
+                    Record::Client(offset, bytes) => {
+                        pace(*offset).await;
+                        write.write_all(bytes).await?;
+                        sent += bytes.len() as u64;
+                    }
+                    Record::ClientClosed(offset) => {
+                        pace(*offset).await;
+                        write.shutdown().await?
+                    }
+                    Record::Server(..) => {}
+                }
+            }
+            Ok::<_, io::Error>(())
+        };
+        tokio::pin!(send);
+
+        // Read responses until the server closes the connection, or goes quiet after
+        // everything was sent.
+        let mut done_sending = false;
+        let mut received = 0;
+        let mut buffer = vec![0; 16384];
+        loop {
+            tokio::select! {
+                result = &mut send, if !done_sending => {
+                    result?;
+                    done_sending = true;
+                }
+                result = read.read(&mut buffer) => match result? {
+                    0 => break,
+                    size => received += size as u64,
+                },
+                _ = tokio::time::sleep(IDLE_TIMEOUT), if done_sending => break,
+            }
+        }
+        received
+    };
+
+    Ok((sent, received))
+}
+
+/// Lists the session files at the path, which is either one or a directory of them.
+fn session_files(path: &Path) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    if !path.is_dir() {
+        return Ok(vec![path.to_path_buf()]);
+    }
+
+    let mut files = Vec::new();
+    for entry in std::fs::read_dir(path)? {
+        let path = entry?.path();
+        if path
+            .extension()
+            .is_some_and(|ext| ext == session::EXTENSION)
+        {
+            files.push(path);
+        }
+    }
+    Ok(files)
+}
+
+/// Parses the arguments of the `replay` subcommand into the sessions, the destination and the
+/// speed, which is `None` for as fast as possible.
+fn parse_args(args: &[String]) -> Result<(String, String, Option<f64>), Box<dyn Error>> {
+    let mut positional = Vec::new();
+    let mut speed = Some(1.0);
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let value = match arg.strip_prefix("--speed") {
+            Some("") => args.next().ok_or("Missing value for --speed")?.as_str(),
+            Some(value) if value.starts_with('=') => &value[1..],
+            _ if arg.starts_with("--") => {
+                return Err(format!("Unknown replay option `{}`", arg).into())
+            }
+            _ => {
+                positional.push(arg.clone());
+                continue;
+            }
+        };
+        speed = parse_speed(value)?;
+    }
+
+    match <[String; 2]>::try_from(positional) {
+        Ok([path, dest_addr]) => Ok((path, dest_addr, speed)),
+        Err(_) => Err(
+            "Usage: sockgauge replay <session file or directory> <destination> [--speed <factor>]"
+                .into(),
+        ),
+    }
+}
+
+/// Parses a replay speed like `2x`, `0.5` or `max` for as fast as possible.
+fn parse_speed(value: &str) -> Result<Option<f64>, Box<dyn Error>> {
+    if value == "max" {
+        return Ok(None);
+    }
+
+    match value.strip_suffix('x').unwrap_or(value).parse::<f64>() {
+        Ok(speed) if speed > 0.0 && speed.is_finite() => Ok(Some(speed)),
+        _ => Err(format!("Expected a speed like `2x` or `max`, got `{}`", value).into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    /// Replays a session at the speed against a server that echoes what it gets, returning
+    /// what was sent and received, and when each read arrived at the server, from when it
+    /// accepted the connection.
+    async fn replay_timed(session: &Session, speed: Option<f64>) -> (u64, u64, Vec<Duration>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let dest_addr = listener.local_addr().unwrap().to_string();
+        let server = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let accepted = Instant::now();
+            let mut arrivals = Vec::new();
+            let mut buffer = [0; 1024];
+            loop {
+                let size = stream.read(&mut buffer).await.unwrap();
+                if size == 0 {
+                    return arrivals;
+                }
+                arrivals.push(accepted.elapsed());
+                stream.write_all(&buffer[..size]).await.unwrap();
+            }
+        });
+
+        let (sent, received) = replay(session, &dest_addr, speed).await.unwrap();
+        (sent, received, server.await.unwrap())
+    }
+
+    #[tokio::test]
+    async fn replays_with_the_recorded_pacing() {
+        let ms = Duration::from_millis;
+        let session = Session {
+            peer: "10.0.0.1:50000".to_string(),
+            started: std::time::SystemTime::now(),
+            records: vec![
+                Record::Client(ms(0), b"first".to_vec()),
+                Record::Server(ms(10), 5),
+                Record::Client(ms(200), b"second".to_vec()),
+                Record::Client(ms(600), b"third".to_vec()),
+                Record::ClientClosed(ms(700)),
+            ],
+        };
+
+        // Twice as fast: everything is sent after half the time it originally was.
+        let (sent, received, arrivals) = replay_timed(&session, Some(2.0)).await;
+        assert_eq!((sent, received), (16, 16));
+        assert_eq!(arrivals.len(), 3, "{:?}", arrivals);
+        for (arrival, expected) in arrivals.iter().zip([ms(0), ms(100), ms(300)]) {
+            assert!(
+                *arrival >= expected && *arrival < expected + ms(80),
+                "{:?}",
+                arrivals
+            );
+        }
+
+        // As fast as possible: no waiting at all.
+        let started = Instant::now();
+        let (sent, received, _) = replay_timed(&session, None).await;
+        assert_eq!((sent, received), (16, 16));
+        assert!(started.elapsed() < ms(100));
+    }
+
+    #[test]
+    fn parses_speeds() {
+        let args =
+            |args: &[&str]| parse_args(&args.iter().map(|a| a.to_string()).collect::<Vec<_>>());
+        let (path, dest_addr, speed) = args(&["sessions/", "db:5432"]).unwrap();
+        assert_eq!(
+            (path.as_str(), dest_addr.as_str(), speed),
+            ("sessions/", "db:5432", Some(1.0))
+        );
+        assert_eq!(args(&["--speed", "2x", "s", "d:1"]).unwrap().2, Some(2.0));
+        assert_eq!(args(&["s", "d:1", "--speed=0.5"]).unwrap().2, Some(0.5));
+        assert_eq!(args(&["s", "d:1", "--speed=max"]).unwrap().2, None);
+        for invalid in [
+            &["s", "d:1", "--speed=0"][..],
+            &["s", "d:1", "--speed=-1x"],
+            &["s", "d:1", "--speed=inf"],
+            &["s", "d:1", "--speed"],
+            &["s", "d:1", "--pace=2"],
+            &["s"],
+        ] {
+            assert!(args(invalid).is_err(), "{:?}", invalid);
+        }
+    }
+}
@@ -0,0 +1,126 @@
+use crate::json::Value;
+use crate::record;
+use crate::sink;
+use std::io;
+use std::time::SystemTime;
+
+/// Builds the run report of a run finishing now: when it ran, the settings it ran with, the
+/// environment and everything the reporter counted (its `aggregates` and `histograms`), for CI
+/// jobs to archive and compare between builds.
+pub fn build(settings: &[(String, String)], started: SystemTime, aggregates: Value) -> Value {
+    report(settings, started, SystemTime::now(), aggregates)
+}
+
+/// Writes a run report.
+pub fn write(path: &str, report: &Value) -> io::Result<()> {
+    std::fs::write(path, format!("{}\n", report))
+}
+
+/// Builds the run report.
+fn report(
+    settings: &[(String, String)],
+    started: SystemTime,
+    finished: SystemTime,
+    aggregates: Value,
+) -> Value {
+    let mut report = vec![
+        (
+            "version".to_string(),
+            Value::String(env!("CARGO_PKG_VERSION").to_string()),
+        ),
+        (
+            "started".to_string(),
+            Value::String(record::rfc3339(started)),
+        ),
+        (
+            "finished".to_string(),
+            Value::String(record::rfc3339(finished)),
+        ),
+        (
+            "duration_s".to_string(),
+            Value::Number(
+                finished
+                    .duration_since(started)
+                    .unwrap_or_default()
+                    .as_secs_f64(),
+            ),
+        ),
+        ("config".to_string(), config(settings)),
+        ("environment".to_string(), environment()),
+    ];
+    if let Value::Object(members) = aggregates {
+        report.extend(members);
+    }
+
+    Value::Object(report)
+}
+
+/// The settings, later ones overriding earlier ones, in the order they were first given.
+fn config(settings: &[(String, String)]) -> Value {
+    let mut config: Vec<(String, Value)> = Vec::new();
+    for (key, value) in settings {
+        let value = Value::String(value.clone());
+        match config.iter_mut().find(|(k, _)| k == key) {
+            Some((_, existing)) => *existing = value,
+            None => config.push((key.clone(), value)),
+        }
+    }
+    Value::Object(config)
+}
+
+/// Where it ran.
+fn environment() -> Value {
+    let cpus = std::thread::available_parallelism().map_or(1, |cpus| cpus.get());
+    Value::Object(vec![
+        (
+            "os".to_string(),
+            Value::String(std::env::consts::OS.to_string()),
+        ),
+        (
+            "arch".to_string(),
+            Value::String(std::env::consts::ARCH.to_string()),
+        ),
+        ("hostname".to_string(), Value::String(sink::hostname())),
+        ("cpus".to_string(), Value::Number(cpus as f64)),
+        ("pid".to_string(), Value::Number(std::process::id() as f64)),
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::json;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    #[test]
+    fn builds_reports() {
+        let settings = [
+            ("bind".to_string(), "127.0.0.1:8080".to_string()),
+            ("sample".to_string(), "1/10".to_string()),
+            ("sample".to_string(), "1/100".to_string()),
+        ];
+        let started = UNIX_EPOCH + Duration::from_secs(1_792_037_134);
+        let aggregates = json::parse(r#"{"aggregates":{"total":3},"histograms":{}}"#).unwrap();
+        let report = report(
+            &settings,
+            started,
+            started + Duration::from_millis(1500),
+            aggregates,
+        );
+
+        assert_eq!(
+            report.get("started").and_then(Value::as_str),
+            Some("2026-10-15T04:05:34.000Z")
+        );
+        assert_eq!(report.get("duration_s"), Some(&Value::Number(1.5)));
+        assert_eq!(
+            report.get("config").unwrap().to_string(),
+            r#"{"bind":"127.0.0.1:8080","sample":"1/100"}"#
+        );
+        assert!(report.get("environment").unwrap().get("cpus").is_some());
+        assert_eq!(
+            report.get("aggregates").unwrap().to_string(),
+            r#"{"total":3}"#
+        );
+    }
+}
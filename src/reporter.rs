@@ -1,23 +1,371 @@
-use std::collections::HashMap;
+use crate::alert::{Alert, Alerts};
+use crate::ban::{self, BanRule, Bans};
+use crate::filter::Filter;
+use crate::format::{Bytes, Elapsed, Rate};
+use crate::histogram::Histogram;
+use crate::http::Exchange;
+use crate::ipfix::{self, Exporter};
+use crate::json::{self, Value};
+use crate::kafka::{self, Kafka};
+use crate::mqtt::{self, Mqtt};
+use crate::net::{self, DisplayAddr};
+use crate::output::{self, eprint_line, print_line};
+use crate::peers::{self, Peers};
+use crate::policy::Action;
+use crate::protocol::Stats;
+use crate::record;
+use crate::redis::{self, Redis};
+use crate::spark::History;
+use crate::storm::Storms;
+use crate::timeline::{self, Timeline};
+use crate::tls::{self, ClientHello, ServerHello};
+use crate::usage::Usage;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashMap};
 use std::fmt::{Display, Formatter};
-use std::net::SocketAddr;
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
 use std::time::{Duration, SystemTime};
-use tokio::sync::mpsc;
+use tokio::sync::{mpsc, oneshot};
+use tokio::time::Instant;
 
 /// Events that can be recorded.
 pub enum Event {
+    /// The destination of a socket was connected to, taking this long.
+    Connected(Duration),
+
     /// A socket was opened.
-    Opened(SocketAddr),
+    Opened(Connection),
 
     /// A socket was closed gracefully.
-    ClosedGracefully(SocketAddr),
+    ClosedGracefully(Connection, Traffic),
 
     /// A socket was closed with an error.
-    ClosedWithError(SocketAddr, SocketCloseError),
+    ClosedWithError(Connection, SocketCloseError, Traffic),
+
+    /// The client of a socket sent a TLS ClientHello.
+    ClientHello(Connection, ClientHello),
+
+    /// The server of a socket answered with a TLS ServerHello.
+    ServerHello(Connection, ServerHello),
+
+    /// What a socket did, as counted by its protocol's parser once it closes.
+    Protocol(Connection, Stats),
+
+    /// The client of a socket upgraded it to TLS with a command like `STARTTLS`, sending its
+    /// first TLS byte at this offset.
+    Upgraded(Connection, u64, &'static str),
+
+    /// A request on a socket in HTTP mode got a response.
+    Exchange(Exchange),
+
+    /// A policy applied to a socket, based on the first bytes its client sent.
+    Policy(Connection, Action),
+
+    /// A socket waited this long in the queue before being admitted.
+    Queued(Connection, Duration),
+
+    /// A socket gave up after waiting this long in the queue, and was closed.
+    Rejected(Connection, Duration),
+
+    /// A socket waited this long for its turn to connect to a destination that's paced.
+    Paced(Connection, Duration),
+
+    /// Destinations were raced for a socket, the winner getting its traffic.
+    Race(Connection, Vec<Contender>),
+
+    /// A socket was forwarded to this backend of a pool. Follows `Opened`.
+    Backend(Connection, String),
+
+    /// A socket used TCP Fast Open on this side, its first data acknowledged in the SYN.
+    FastOpen(Connection, Side),
+
+    /// A socket used Multipath TCP on this side, with this many subflows. Comes before it's
+    /// closed.
+    Multipath(Connection, Side, u8),
+
+    /// A socket couldn't be forwarded for lack of a local port to connect from, and was closed.
+    PortsExhausted(Connection, String),
+
+    /// A socket's client sent nothing for this long, and it was closed before being forwarded.
+    FirstByteTimeout(Connection, Duration),
+
+    /// Forwarding in one direction of a socket stalled for this long, the peer accepting none
+    /// of what was pending. Reported once it's over.
+    Stalled(Connection, Direction, Duration),
+
+    /// Asks for a summary of everything seen so far.
+    Summary(oneshot::Sender<String>),
+
+    /// Asks for a snapshot of the current state.
+    Snapshot(oneshot::Sender<Snapshot>),
+
+    /// Asks for everything counted so far, with histograms, as JSON.
+    Aggregates(oneshot::Sender<Value>),
+
+    /// Asks for the samples of every second of the given length of recent history.
+    Timeline(Duration, oneshot::Sender<Vec<timeline::Sample>>),
 }
 
-/// The direction in which the error was encountered.
-#[derive(Debug)]
+/// A proxied connection, identified by its client and listener addresses.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Connection {
+    /// The client's address.
+    pub peer: SocketAddr,
+
+    /// The address of the listener that accepted the connection.
+    pub local: SocketAddr,
+
+    /// The name of the mapping (bind → destination) the connection belongs to.
+    pub mapping: Arc<str>,
+
+    /// The stream within the tunnel carrier, if the connection came through one.
+    pub stream: Option<u32>,
+
+    /// Labels for slicing the reports, as `(key, value)`, e.g. `("tenant", "abc")`.
+    pub labels: Arc<[(String, String)]>,
+}
+
+/// What the client of a connection sent and got back.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Traffic {
+    /// How many bytes the client sent.
+    pub sent: u64,
+
+    /// How many reads it took to receive them, which is roughly the packets.
+    pub reads: u64,
+
+    /// How many bytes the client got back.
+    pub received: u64,
+
+    /// How many writes it took to send them, which is roughly the packets.
+    pub writes: u64,
+
+    /// The lowest and highest throughput of what the client sent, if it was open for a second.
+    pub sent_rates: Option<RateRange>,
+
+    /// The lowest and highest throughput of what the client got back, likewise.
+    pub received_rates: Option<RateRange>,
+
+    /// When the bytes flowed.
+    pub activity: Activity,
+}
+
+/// When the bytes of a connection flowed, as offsets from when it was accepted.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Activity {
+    /// When the client sent its first and last bytes, if it sent any.
+    pub sent: Option<(Duration, Duration)>,
+
+    /// When the client got its first and last bytes back, if it got any.
+    pub received: Option<(Duration, Duration)>,
+
+    /// The longest time no bytes flowed either way, including before the first and after the
+    /// last.
+    pub longest_idle: Duration,
+}
+
+/// The lowest and highest bytes per second of one direction of a connection, over each second
+/// it was open.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateRange {
+    /// The bytes of the slowest second.
+    pub min: u64,
+
+    /// The bytes of the fastest second.
+    pub max: u64,
+}
+
+/// A destination that was raced against others for a connection.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Contender {
+    /// The address of the destination.
+    pub addr: String,
+
+    /// How long connecting took, or why it failed.
+    pub latency: Result<Duration, String>,
+
+    /// Whether it connected first, so it got the connection's traffic.
+    pub won: bool,
+}
+
+/// The state of the reporter at a point in time, for inspecting the proxy programmatically.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Snapshot {
+    /// How many connections are open.
+    pub open: u64,
+
+    /// How many connections were opened in total.
+    pub total: u64,
+
+    /// How many connections closed with an error.
+    pub closed_with_error: u64,
+
+    /// How many connections are open per mapping.
+    pub mappings: HashMap<String, u64>,
+
+    /// The connections per client address.
+    pub peers: HashMap<IpAddr, PeerStats>,
+
+    /// The alerts that fired so far, each time they did.
+    pub alerts_fired: Vec<String>,
+}
+
+/// How a destination did in races.
+#[derive(Debug, Default)]
+struct RaceStats {
+    /// How many races it won.
+    wins: u64,
+
+    /// How many times connecting to it failed or timed out.
+    failures: u64,
+
+    /// How long connecting to it took, when it succeeded.
+    latencies: Histogram,
+}
+
+/// The connections forwarded to a backend of a pool.
+#[derive(Debug, Default)]
+struct BackendStats {
+    /// How many connections are open.
+    open: u64,
+
+    /// How many connections were forwarded to it in total.
+    total: u64,
+
+    /// How many connections closed with an error.
+    closed_with_error: u64,
+
+    /// How many bytes the clients of the closed connections sent and got back.
+    sent: u64,
+    received: u64,
+
+    /// How long the closed connections were open.
+    durations: Histogram,
+}
+
+/// What the reporter leaves behind once it finishes.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Finished {
+    /// Whether any alerts fired.
+    pub alerts_fired: bool,
+
+    /// Everything counted, as JSON, for the run report.
+    pub aggregates: Value,
+}
+
+/// How the reporter stopped running.
+pub enum Stopped {
+    /// All handles were dropped, so it's done.
+    Finished(Finished),
+
+    /// Handling something panicked. The actor still has its mailbox and everything it counted
+    /// (save for what it was handling), and can be run again.
+    Crashed(Box<ReporterActor>, String),
+}
+
+/// The connections of one client address.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PeerStats {
+    /// How many connections are open.
+    pub open: u64,
+
+    /// How many connections were opened in total.
+    pub total: u64,
+
+    /// How many connections closed with an error.
+    pub closed_with_error: u64,
+
+    /// How long the closed connections were open, added up.
+    pub connected: Duration,
+}
+
+/// The name of the mapping configured at startup, unless it's given one.
+pub const DEFAULT_MAPPING: &str = "default";
+
+/// How many connections the tables of open connections have room for at first, unless
+/// configured otherwise.
+const DEFAULT_EXPECTED_CONNECTIONS: usize = 1024;
+
+/// Checks that a mapping can be named so: its name is shown with every line about its
+/// connections, and a word of admin commands.
+pub fn validate_mapping_name(name: &str) -> Result<(), String> {
+    match name.is_empty() || name.contains(char::is_whitespace) || name == DEFAULT_MAPPING {
+        true => Err(format!(
+            "`{}` can't name a mapping, it should be a word other than `{}`",
+            name, DEFAULT_MAPPING
+        )),
+        false => Ok(()),
+    }
+}
+
+/// The breakdowns in the summary, in the order they are shown.
+const BREAKDOWNS: [&str; 11] = [
+    TLS_VERSIONS_OFFERED,
+    ALPN_OFFERED,
+    TLS_VERSIONS_NEGOTIATED,
+    CIPHERS_NEGOTIATED,
+    ALPN_NEGOTIATED,
+    JA4_FINGERPRINTS,
+    HTTP_STATUSES,
+    POLICIES_APPLIED,
+    LABELS,
+    LABELS_CLOSED_WITH_ERROR,
+    PROTOCOL_STATS,
+];
+const TLS_VERSIONS_OFFERED: &str = "TLS versions offered";
+const ALPN_OFFERED: &str = "ALPN offered";
+const TLS_VERSIONS_NEGOTIATED: &str = "TLS versions negotiated";
+const CIPHERS_NEGOTIATED: &str = "cipher suites negotiated";
+const ALPN_NEGOTIATED: &str = "ALPN negotiated";
+const JA4_FINGERPRINTS: &str = "JA4 fingerprints";
+const HTTP_STATUSES: &str = "HTTP responses";
+const POLICIES_APPLIED: &str = "policies applied";
+const LABELS: &str = "labels";
+const LABELS_CLOSED_WITH_ERROR: &str = "labels of connections closed with an error";
+const PROTOCOL_STATS: &str = "protocol statistics";
+
+/// How many distinct values a breakdown counts, and what the rest are counted as.
+const MAX_BREAKDOWN_VALUES: usize = 100;
+const OTHER: &str = "other";
+
+/// How often to check the alerts and error rates, besides whenever connections open and close.
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The latency percentiles in the summary.
+const PERCENTILES: [f64; 3] = [50.0, 90.0, 99.0];
+
+/// A fraction of connections, like `1/100`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Sample(pub u64, pub u64);
+
+impl Sample {
+    /// Parses a fraction like `1/100`.
+    pub fn parse(value: &str) -> Result<Sample, String> {
+        let invalid = || format!("Expected a fraction like `1/100`, got `{}`", value);
+        let (numerator, denominator) = value.split_once('/').ok_or_else(invalid)?;
+        let numerator = numerator.trim().parse().map_err(|_| invalid())?;
+        let denominator = denominator.trim().parse().map_err(|_| invalid())?;
+        if denominator == 0 || numerator > denominator {
+            return Err(invalid());
+        }
+
+        Ok(Sample(numerator, denominator))
+    }
+}
+
+impl Display for Sample {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}", self.0, self.1)
+    }
+}
+
+/// The direction in which the error was encountered, or forwarding stalled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Direction {
     /// The socket close error was encountered by forwarding client data to the server.
     ClientToServer,
@@ -26,34 +374,175 @@ pub enum Direction {
     ServerToClient,
 }
 
+/// A side of a proxied connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Side {
+    /// The client's connection to the listener.
+    Client,
+
+    /// The connection to the destination.
+    Destination,
+}
+
 /// Errors pertaining to ungraceful socket closure.
 #[derive(Debug)]
-pub struct SocketCloseError(pub Direction, pub String);
+pub struct SocketCloseError(pub Direction, pub String, pub io::ErrorKind);
+
+/// How the reporter reports.
+#[derive(Debug, Default)]
+pub struct Options {
+    /// Which fraction of the connections to print lines about, if not all of them.
+    pub sample: Option<Sample>,
+
+    /// The conditions for printing lines about a connection.
+    pub filter: Filter,
+
+    /// The thresholds to raise alerts for.
+    pub alerts: Vec<Alert>,
+
+    /// The rules to ban client addresses by.
+    pub bans: Vec<BanRule>,
+
+    /// Where to post alerts to when they fire and clear, if anywhere.
+    pub alert_webhook: Option<String>,
+
+    /// How often to print sockgauge's own resource usage, if at all.
+    pub self_metrics: Option<Duration>,
+
+    /// How often to print the summary, if at all besides when exiting.
+    pub summary_interval: Option<Duration>,
+
+    /// How short connections must be to be counted together instead of printed, if at all.
+    pub coalesce_under: Option<Duration>,
+
+    /// How many client addresses to keep the connections of, if not the default.
+    pub max_peers: Option<usize>,
+
+    /// How many connections to make room for up front, if not the default.
+    pub expected_connections: Option<usize>,
+
+    /// Whether to keep a line with the current numbers at the bottom of the terminal.
+    pub status_line: bool,
+
+    /// How much recent history to keep the samples of every second of, if not the default.
+    pub history: Option<Duration>,
+
+    /// Where to publish connection events in Redis, if anywhere.
+    pub redis: Option<redis::Target>,
+
+    /// Where and how to publish connection events over MQTT, if at all.
+    pub mqtt: Option<mqtt::Target>,
+
+    /// Where to produce connection events in Kafka, if anywhere.
+    pub kafka: Option<kafka::Target>,
+
+    /// Exports the flows of connections to an IPFIX collector, if configured.
+    pub ipfix: Option<Exporter>,
+}
 
 /// Creates and returns a reporter actor as well as a handle for sending it messages.
-pub fn create() -> (ReporterHandle, ReporterActor) {
+pub fn create(options: Options) -> (ReporterHandle, ReporterActor) {
     let (sender, receiver) = mpsc::unbounded_channel();
-    let actor = ReporterActor::new(receiver);
-    let handle = ReporterHandle::new(sender);
+    let counters = Arc::new(Counters::default());
+    let actor = ReporterActor::new(receiver, counters.clone(), options);
+    let handle = ReporterHandle::new(sender, counters);
     (handle, actor)
 }
 
+/// Counted by the handles and read by the actor, for things too frequent to send events about.
+#[derive(Debug, Default)]
+struct Counters {
+    /// How many events were sent but not received yet.
+    queued: AtomicUsize,
+
+    /// How many bytes were forwarded, in either direction.
+    transferred: AtomicU64,
+}
+
 /// Used for sending events to the reporter.
 #[derive(Clone)]
 pub struct ReporterHandle {
     /// Used for sending events.
-    sender: mpsc::UnboundedSender<Event>,
+    sender: mpsc::UnboundedSender<(Instant, Event)>,
+
+    /// Shared with the actor.
+    counters: Arc<Counters>,
 }
 
 impl ReporterHandle {
     /// Creates a new handle.
-    fn new(sender: mpsc::UnboundedSender<Event>) -> Self {
-        Self { sender }
+    fn new(sender: mpsc::UnboundedSender<(Instant, Event)>, counters: Arc<Counters>) -> Self {
+        Self { sender, counters }
     }
 
     /// Reports the given event.
     pub fn report(&self, event: Event) {
-        let _ = self.sender.send(event);
+        if self.sender.send((Instant::now(), event)).is_ok() {
+            self.counters.queued.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Counts bytes forwarded in either direction.
+    pub fn transferred(&self, bytes: u64) {
+        self.counters
+            .transferred
+            .fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    /// Asks the reporter for a summary of everything seen so far.
+    pub async fn summary(&self) -> Option<String> {
+        let (sender, receiver) = oneshot::channel();
+        self.report(Event::Summary(sender));
+        receiver.await.ok()
+    }
+
+    /// Asks the reporter for the samples of every second of the given length of recent history,
+    /// oldest first.
+    pub async fn timeline(&self, length: Duration) -> Option<Vec<timeline::Sample>> {
+        let (sender, receiver) = oneshot::channel();
+        self.report(Event::Timeline(length, sender));
+        receiver.await.ok()
+    }
+
+    /// Asks the reporter for a snapshot of the current state.
+    pub async fn snapshot(&self) -> Option<Snapshot> {
+        let (sender, receiver) = oneshot::channel();
+        self.report(Event::Snapshot(sender));
+        receiver.await.ok()
+    }
+
+    /// Asks the reporter for everything counted so far, with histograms, as JSON.
+    pub async fn aggregates(&self) -> Option<Value> {
+        let (sender, receiver) = oneshot::channel();
+        self.report(Event::Aggregates(sender));
+        receiver.await.ok()
+    }
+
+    /// Creates a handle that doesn't keep the reporter running.
+    pub fn downgrade(&self) -> WeakReporterHandle {
+        WeakReporterHandle {
+            sender: self.sender.downgrade(),
+            counters: self.counters.clone(),
+        }
+    }
+}
+
+/// A handle to the reporter that doesn't keep it running; the reporter finishes once all
+/// (strong) handles are dropped.
+#[derive(Clone)]
+pub struct WeakReporterHandle {
+    /// Used for creating a strong handle.
+    sender: mpsc::WeakUnboundedSender<(Instant, Event)>,
+
+    /// Shared with the actor.
+    counters: Arc<Counters>,
+}
+
+impl WeakReporterHandle {
+    /// Returns a handle for sending events, unless the reporter has finished.
+    pub fn upgrade(&self) -> Option<ReporterHandle> {
+        let sender = self.sender.upgrade()?;
+        Some(ReporterHandle::new(sender, self.counters.clone()))
     }
 }
 
@@ -62,81 +551,1593 @@ pub struct ReporterActor {
     /// The running count.
     count: u64,
 
+    /// How many connections were opened in total.
+    total: u64,
+
+    /// How many connections closed with an error.
+    closed_with_error: u64,
+
+    /// How many of those were dropped because their peer stopped acknowledging what was sent.
+    timed_out: u64,
+
+    /// How many of those were closed because their peer was unreachable, e.g. as it stopped
+    /// answering keepalive probes.
+    unreachable: u64,
+
+    /// How many connections closed without their client sending anything, e.g. port scanners
+    /// and broken clients.
+    silent: u64,
+
+    /// How many connections were upgraded to TLS mid-stream.
+    upgraded: u64,
+
     /// The receiver, used to consume the mailbox.
-    receiver: mpsc::UnboundedReceiver<Event>,
+    receiver: mpsc::UnboundedReceiver<(Instant, Event)>,
+
+    /// Shared with the handles.
+    counters: Arc<Counters>,
+
+    /// Map of connections and the time they connected.
+    connected_time: HashMap<Connection, Instant>,
+
+    /// The running count per mapping.
+    mapping_counts: HashMap<Arc<str>, u64>,
+
+    /// The connections per client address, of as many as are kept.
+    peers: Peers,
+
+    /// Connection counts per value, by breakdown (e.g. per cipher suite).
+    breakdowns: HashMap<&'static str, HashMap<String, u64>>,
+
+    /// How long servers took to respond to HTTP requests.
+    latencies: Histogram,
+
+    /// How long connecting to the destination took.
+    connect_latencies: Histogram,
+
+    /// How long the closed connections were open.
+    durations: Histogram,
+
+    /// How many bytes the clients of the closed connections sent and got back.
+    sent: u64,
+    received: u64,
+
+    /// How each destination did in races, by address.
+    races: HashMap<String, RaceStats>,
+
+    /// How long the admitted connections that had to queue waited.
+    queue_waits: Histogram,
+
+    /// How long the stalls of connections lasted.
+    stalls: Histogram,
+
+    /// How long events waited in the mailbox before being handled.
+    lags: Histogram,
+
+    /// The same, since sockgauge's own resource usage was last printed.
+    recent_lags: Histogram,
+
+    /// How long the connections that waited for their turn to connect to the destination waited.
+    paces: Histogram,
+
+    /// The backends the open connections to pools were forwarded to.
+    connection_backends: HashMap<Connection, String>,
+
+    /// Where the open connections that were upgraded to TLS mid-stream upgraded, by the offset
+    /// of the client's first TLS byte.
+    upgrades: HashMap<Connection, u64>,
+
+    /// What the open connections did per their protocol, once counted, to publish when they
+    /// close.
+    protocol_stats: HashMap<Connection, Stats>,
+
+    /// How long the phases the protocol parsers timed took, per connection, by protocol and
+    /// phase, e.g. `ssh key exchange`.
+    protocol_timings: BTreeMap<String, Histogram>,
+
+    /// The connections per backend of the pools, by address.
+    backends: HashMap<String, BackendStats>,
+
+    /// How many connections gave up waiting in the queue.
+    rejected: u64,
+
+    /// How many connections couldn't be forwarded for lack of a local port to connect from.
+    ports_exhausted: u64,
+
+    /// How many connections were closed for their client sending nothing within the first-byte
+    /// timeout.
+    first_byte_timeouts: u64,
+
+    /// How many clients' connections used TCP Fast Open.
+    fast_open_clients: u64,
+
+    /// How many connections to destinations used TCP Fast Open.
+    fast_open_destinations: u64,
+
+    /// How many clients' connections used Multipath TCP.
+    multipath_clients: u64,
 
-    /// Map of socket addresses and the time they connected.
-    connected_time: HashMap<SocketAddr, SystemTime>,
+    /// How many connections to destinations used Multipath TCP.
+    multipath_destinations: u64,
+
+    /// The subflows of all those connections, added up.
+    subflows: u64,
+
+    /// Which fraction of the connections to print lines about, if not all of them.
+    sample: Option<Sample>,
+
+    /// The conditions for printing lines about a connection.
+    filter: Filter,
+
+    /// The alerts, and which of them are firing.
+    alerts: Alerts,
+
+    /// The rates of errors and resets, to spot bursts.
+    storms: Storms,
+
+    /// What clients did that counts towards bans, and the bans.
+    bans: Bans,
+
+    /// How often to print sockgauge's own resource usage, if at all.
+    self_metrics: Option<Duration>,
+
+    /// How often to print the summary, if at all besides when exiting.
+    summary_interval: Option<Duration>,
+
+    /// How short connections must be to be counted together instead of printed, if at all.
+    coalesce_under: Option<Duration>,
+
+    /// The open connections whose opening isn't printed until they've been open for
+    /// `coalesce_under`, with the count as of when they opened.
+    deferred: HashMap<Connection, u64>,
+
+    /// How many connections were coalesced since that was last printed, and how many of them
+    /// closed with an error.
+    coalesced: (u64, u64),
+
+    /// How many connections were coalesced in total.
+    coalesced_total: u64,
+
+    /// Whether to keep a line with the current numbers at the bottom of the terminal.
+    status_line: bool,
+
+    /// How many connections were opened each second of the last minute.
+    opened: History,
+
+    /// How many bytes were forwarded each second of the last minute.
+    throughput: History,
+
+    /// What happened every second of the recent history.
+    timeline: Timeline,
+
+    /// Publishes connection events to Redis, if configured.
+    redis: Option<Redis>,
+
+    /// Publishes connection events over MQTT, if configured.
+    mqtt: Option<Mqtt>,
+
+    /// Produces connection events to Kafka, if configured.
+    kafka: Option<Kafka>,
+
+    /// Exports the flows of connections to an IPFIX collector, if configured.
+    ipfix: Option<Exporter>,
 }
 
 impl ReporterActor {
     /// Creates a new actor.
-    fn new(receiver: mpsc::UnboundedReceiver<Event>) -> Self {
+    fn new(
+        receiver: mpsc::UnboundedReceiver<(Instant, Event)>,
+        counters: Arc<Counters>,
+        options: Options,
+    ) -> Self {
+        let expected = options
+            .expected_connections
+            .unwrap_or(DEFAULT_EXPECTED_CONNECTIONS);
         Self {
             receiver,
+            counters,
             count: 0,
-            connected_time: HashMap::with_capacity(1024),
+            total: 0,
+            closed_with_error: 0,
+            timed_out: 0,
+            unreachable: 0,
+            silent: 0,
+            upgraded: 0,
+            connected_time: HashMap::with_capacity(expected),
+            mapping_counts: HashMap::new(),
+            peers: Peers::new(
+                options.max_peers.unwrap_or(peers::DEFAULT_MAX_PEERS),
+                expected,
+            ),
+            breakdowns: HashMap::new(),
+            latencies: Histogram::default(),
+            connect_latencies: Histogram::default(),
+            durations: Histogram::default(),
+            sent: 0,
+            received: 0,
+            races: HashMap::new(),
+            queue_waits: Histogram::default(),
+            stalls: Histogram::default(),
+            lags: Histogram::default(),
+            recent_lags: Histogram::default(),
+            paces: Histogram::default(),
+            connection_backends: HashMap::new(),
+            upgrades: HashMap::new(),
+            protocol_stats: HashMap::new(),
+            protocol_timings: BTreeMap::new(),
+            backends: HashMap::new(),
+            rejected: 0,
+            ports_exhausted: 0,
+            first_byte_timeouts: 0,
+            fast_open_clients: 0,
+            fast_open_destinations: 0,
+            multipath_clients: 0,
+            multipath_destinations: 0,
+            subflows: 0,
+            sample: options.sample,
+            filter: options.filter,
+            alerts: Alerts::new(options.alerts, options.alert_webhook.as_deref()),
+            storms: Storms::default(),
+            bans: Bans::new(options.bans, ban::shared().clone()),
+            self_metrics: options.self_metrics,
+            summary_interval: options.summary_interval,
+            coalesce_under: options.coalesce_under,
+            deferred: match options.coalesce_under {
+                Some(_) => HashMap::with_capacity(expected),
+                None => HashMap::new(),
+            },
+            coalesced: (0, 0),
+            coalesced_total: 0,
+            status_line: options.status_line,
+            opened: History::default(),
+            throughput: History::default(),
+            timeline: Timeline::new(options.history.unwrap_or(timeline::DEFAULT_LENGTH)),
+            redis: options.redis.map(Redis::start),
+            mqtt: options.mqtt.map(Mqtt::start),
+            kafka: options.kafka.map(Kafka::start),
+            ipfix: options.ipfix,
+        }
+    }
+
+    /// Runs the reporter actor mailbox processing loop, until all handles are dropped. Returns
+    /// whether any alerts fired, and everything counted; or, if handling something panicked, the
+    /// actor with everything counted so far, to be run again.
+    pub async fn run(mut self) -> Stopped {
+        // Rates change as time passes, so they are checked regularly too.
+        let mut check_rates = tokio::time::interval(CHECK_INTERVAL);
+        let mut self_metrics = self.self_metrics.map(tokio::time::interval);
+        let mut summaries = self.summary_interval.map(|period| {
+            // The first tick would be right away, before anything happened.
+            tokio::time::interval_at(Instant::now() + period, period)
+        });
+        loop {
+            let handled = tokio::select! {
+                event = self.receiver.recv() => match event {
+                    Some((sent, event)) => self.guarded(|reporter| {
+                        reporter.counters.queued.fetch_sub(1, Ordering::Relaxed);
+                        let lag = sent.elapsed();
+                        reporter.lags.record(lag);
+                        reporter.recent_lags.record(lag);
+                        reporter.receive(event);
+                    }),
+                    None => break,
+                },
+                _ = check_rates.tick() => self.guarded(Self::check_rates),
+                _ = async { self_metrics.as_mut().unwrap().tick().await }, if self_metrics.is_some() => {
+                    self.guarded(|reporter| {
+                        let lags = std::mem::take(&mut reporter.recent_lags);
+                        let usage = Usage::now(
+                            reporter.counters.queued.load(Ordering::Relaxed),
+                            lags.mean().zip(lags.max()),
+                        );
+                        print_line!("🩺 sockgauge itself — {}", usage);
+                    })
+                }
+                _ = async { summaries.as_mut().unwrap().tick().await }, if summaries.is_some() => {
+                    self.guarded(|reporter| print_line!("{}", reporter.summary()))
+                }
+            };
+            if let Err(reason) = handled {
+                return Stopped::Crashed(Box::new(self), reason);
+            }
+        }
+
+        output::remove_status();
+
+        Stopped::Finished(Finished {
+            alerts_fired: !self.alerts.fired().is_empty(),
+            aggregates: self.aggregates(),
+        })
+    }
+
+    /// Checks the rates, which change as time passes, and records them.
+    fn check_rates(&mut self) {
+        self.alerts.check(self.count);
+        self.storms.check();
+        self.bans.check();
+        self.opened.record(self.total);
+        let transferred = self.counters.transferred.load(Ordering::Relaxed);
+        self.throughput.record(transferred);
+        self.timeline.record(
+            SystemTime::now(),
+            self.count,
+            (self.total, self.closed_with_error, transferred),
+        );
+        self.update_status();
+        self.flush_coalesced();
+    }
+
+    /// Prints that a connection opened, with the count as of then.
+    fn print_opened(&self, connection: &Connection, count: u64) {
+        print_line!(
+            "🟢 {: >5} — new connection from {} on :{}{}",
+            count,
+            DisplayAddr(connection.peer),
+            connection.local.port(),
+            self.mapping_suffix(connection)
+        );
+    }
+
+    /// Takes a connection that closed off the deferred ones. Returns whether it's coalesced, as
+    /// it closed sooner than `coalesce_under`; otherwise its opening is printed now, late.
+    fn coalesce(&mut self, connection: &Connection, duration: Duration, error: bool) -> bool {
+        let Some(count) = self.deferred.remove(connection) else {
+            return false;
+        };
+        if self.coalesce_under.is_some_and(|under| duration < under) {
+            self.coalesced.0 += 1;
+            self.coalesced.1 += error as u64;
+            self.coalesced_total += 1;
+            return true;
         }
+        self.print_opened(connection, count);
+        false
     }
 
-    /// Runs the reporter actor mailbox processing loop. Must only be called once.
-    pub async fn run(mut self) {
-        while let Some(event) = self.receiver.recv().await {
-            self.receive(event)
+    /// Prints the opening of the deferred connections that are open long enough by now, and
+    /// how many connections were coalesced since the last time.
+    fn flush_coalesced(&mut self) {
+        let Some(under) = self.coalesce_under else {
+            return;
+        };
+
+        let mut due: Vec<(Instant, Connection, u64)> = Vec::new();
+        for (connection, count) in &self.deferred {
+            match self.connected_time.get(connection) {
+                Some(connected_at) if connected_at.elapsed() < under => {}
+                connected_at => due.push((
+                    connected_at.copied().unwrap_or_else(Instant::now),
+                    connection.clone(),
+                    *count,
+                )),
+            }
         }
+        due.sort_by_key(|(connected_at, _, _)| *connected_at);
+        for (_, connection, count) in due {
+            self.deferred.remove(&connection);
+            self.print_opened(&connection, count);
+        }
+
+        let (coalesced, errors) = std::mem::take(&mut self.coalesced);
+        if coalesced > 0 {
+            let errors = match errors {
+                0 => String::new(),
+                errors => format!(", {} with an error", errors),
+            };
+            print_line!(
+                "🫧 {: >5} — {} connection(s) opened and closed within {}{}",
+                self.count,
+                coalesced,
+                Elapsed(under),
+                errors
+            );
+        }
+    }
+
+    /// Does something with the reporter, catching a panic so what was counted isn't lost with
+    /// the task. Returns the panic's message if it panicked.
+    fn guarded(&mut self, f: impl FnOnce(&mut Self)) -> Result<(), String> {
+        panic::catch_unwind(AssertUnwindSafe(|| f(self))).map_err(|payload| {
+            match (
+                payload.downcast_ref::<&str>(),
+                payload.downcast_ref::<String>(),
+            ) {
+                (Some(message), _) => message.to_string(),
+                (_, Some(message)) => message.clone(),
+                _ => "unknown panic".to_string(),
+            }
+        })
     }
 
     /// Receives an event and handles it.
     fn receive(&mut self, event: Event) {
         match event {
-            Event::Opened(addr) => {
-                // Increment the count.
+            Event::Connected(latency) => self.connect_latencies.record(latency),
+            Event::Opened(connection) => {
+                // Increment the counts.
                 self.count += 1;
+                self.total += 1;
+                *self
+                    .mapping_counts
+                    .entry(connection.mapping.clone())
+                    .or_default() += 1;
+                self.bans.opened(connection.peer.ip());
+                let evicted = self.peers.opened(connection.peer.ip());
+                if evicted > 0 && evicted == self.peers.evicted() {
+                    eprint_line!(
+                        "⚠️  more than {} client addresses seen, so the least recently active ones are forgotten",
+                        self.peers.max()
+                    );
+                }
+                for (key, value) in connection.labels.iter() {
+                    self.count_in(LABELS, format!("{}={}", key, value));
+                }
 
-                // Record the time that they connected.
-                self.connected_time.insert(addr, SystemTime::now());
+                // Report the new connection, or once it's open long enough not to be coalesced.
+                if self.shown(&connection, None) {
+                    match self.coalesce_under {
+                        Some(_) => {
+                            self.deferred.insert(connection.clone(), self.count);
+                        }
+                        None => self.print_opened(&connection, self.count),
+                    }
+                }
 
-                // Report the new connection.
-                println!("🟢 {: >5} — new connection from {}", &self.count, &addr);
+                self.publish("opened", &connection, String::new());
+
+                // Record the time that they connected.
+                self.connected_time.insert(connection, Instant::now());
+                self.alerts.check(self.count);
+                self.update_status();
             }
-            Event::ClosedGracefully(addr) => {
+            Event::ClosedGracefully(connection, traffic) => {
                 // Handle socket close.
-                let connected_duration = self.on_socket_closed(addr);
+                let connected_duration = self.on_socket_closed(&connection, traffic);
+                self.alerts.closed(false);
+                self.alerts.check(self.count);
+                self.update_status();
 
                 // Report that the connection closed.
-                println!(
-                    "🔴 {: >5} — connection closed from {} (connected for {:?})",
-                    &self.count, &addr, connected_duration
+                let silent = traffic.sent == 0;
+                let upgraded_at = self.upgrades.remove(&connection);
+                let protocol = self.protocol_stats.remove(&connection);
+                let coalesced = self.coalesce(&connection, connected_duration, false);
+                if !coalesced && self.shown(&connection, Some(connected_duration)) {
+                    print_line!(
+                        "🔴 {: >5} — connection closed from {} on :{}{} (connected for {}{}{}{})",
+                        &self.count,
+                        DisplayAddr(connection.peer),
+                        connection.local.port(),
+                        self.mapping_suffix(&connection),
+                        Elapsed(connected_duration),
+                        activity_suffix(traffic.activity),
+                        silent_suffix(silent),
+                        upgraded_suffix(upgraded_at)
+                    );
+                }
+
+                self.publish(
+                    "closed",
+                    &connection,
+                    format!(
+                        r#","connected_ms":{:.3},"silent":{},"upgraded_at":{},"protocol":{},"activity":{}{}"#,
+                        connected_duration.as_secs_f64() * 1000.0,
+                        silent,
+                        upgraded_at.map_or(Value::Null, |at| Value::Number(at as f64)),
+                        protocol_json(protocol),
+                        activity_json(traffic.activity),
+                        throughput_json(traffic, connected_duration)
+                    ),
                 );
+                self.export_flows(&connection, connected_duration, traffic);
+                self.bans
+                    .closed(connection.peer.ip(), traffic.sent == 0, false);
             }
-            Event::ClosedWithError(addr, err) => {
-                // Handle socket close.
-                let connected_duration = self.on_socket_closed(addr);
+            Event::ClosedWithError(connection, err, traffic) => {
+                // Handle socket close, counting the error for its backend first, while it's known.
+                if let Some(addr) = self.connection_backends.get(&connection) {
+                    self.backends
+                        .entry(addr.clone())
+                        .or_default()
+                        .closed_with_error += 1;
+                }
+                let connected_duration = self.on_socket_closed(&connection, traffic);
+                self.closed_with_error += 1;
+                let timed_out = err.2 == io::ErrorKind::TimedOut;
+                if timed_out {
+                    self.timed_out += 1;
+                }
+                let unreachable = err.2 == io::ErrorKind::HostUnreachable;
+                if unreachable {
+                    self.unreachable += 1;
+                }
+                if let Some(peer) = self.peers.get_mut(&connection.peer.ip()) {
+                    peer.closed_with_error += 1;
+                }
+                self.alerts.closed(true);
+                self.alerts.check(self.count);
+                self.storms
+                    .closed_with_error(err.2 == io::ErrorKind::ConnectionReset);
+                self.update_status();
+                for (key, value) in connection.labels.iter() {
+                    self.count_in(LABELS_CLOSED_WITH_ERROR, format!("{}={}", key, value));
+                }
+
+                // Report that the connection closed with an error, telling timeouts and
+                // unreachable peers apart.
+                let reason = match (timed_out, unreachable) {
+                    (true, _) => "⌛ timed out: ",
+                    (_, true) => "📵 unreachable: ",
+                    _ => "⚠️  ",
+                };
+                let silent = traffic.sent == 0;
+                let upgraded_at = self.upgrades.remove(&connection);
+                let protocol = self.protocol_stats.remove(&connection);
+                let coalesced = self.coalesce(&connection, connected_duration, true);
+                if !coalesced && self.shown(&connection, Some(connected_duration)) {
+                    print_line!(
+                        "🔴 {: >5} — connection closed from {} on :{}{}: {}{} (connected for {}{}{}{})",
+                        &self.count,
+                        DisplayAddr(connection.peer),
+                        connection.local.port(),
+                        self.mapping_suffix(&connection),
+                        reason,
+                        err,
+                        Elapsed(connected_duration),
+                        activity_suffix(traffic.activity),
+                        silent_suffix(silent),
+                        upgraded_suffix(upgraded_at)
+                    );
+                }
 
-                // Report that the connection closed with an error.
-                println!(
-                    "🔴 {: >5} — connection closed from {}: ⚠️  {} (connected for {:?})",
-                    &self.count, &addr, err, connected_duration
+                self.publish(
+                    "closed",
+                    &connection,
+                    format!(
+                        r#","connected_ms":{:.3},"error":{},"timed_out":{},"unreachable":{},"silent":{},"upgraded_at":{},"protocol":{},"activity":{}{}"#,
+                        connected_duration.as_secs_f64() * 1000.0,
+                        json::quote(&err.to_string()),
+                        timed_out,
+                        unreachable,
+                        silent,
+                        upgraded_at.map_or(Value::Null, |at| Value::Number(at as f64)),
+                        protocol_json(protocol),
+                        activity_json(traffic.activity),
+                        throughput_json(traffic, connected_duration)
+                    ),
                 );
+                self.export_flows(&connection, connected_duration, traffic);
+                self.bans
+                    .closed(connection.peer.ip(), traffic.sent == 0, true);
+            }
+            Event::Protocol(connection, stats) => {
+                // Count the connection and what it did, and what it negotiated.
+                let protocol = stats.protocol;
+                self.count_in(PROTOCOL_STATS, format!("{} connections", protocol));
+                for (name, count) in &stats.counts {
+                    if *count > 0 {
+                        self.add_in(PROTOCOL_STATS, format!("{} {}", protocol, name), *count);
+                    }
+                }
+                for (name, value) in stats.counted_details() {
+                    self.count_in(PROTOCOL_STATS, format!("{} {} {}", protocol, name, value));
+                }
+                for (name, duration) in &stats.timings {
+                    self.protocol_timings
+                        .entry(format!("{} {}", protocol, name))
+                        .or_default()
+                        .record(*duration);
+                }
+
+                if self.shown(&connection, None) {
+                    print_line!(
+                        "🧮 {: >5} — {} from {} on :{}{}: {}",
+                        &self.count,
+                        protocol,
+                        DisplayAddr(connection.peer),
+                        connection.local.port(),
+                        self.mapping_suffix(&connection),
+                        stats
+                    );
+                }
+                self.protocol_stats.insert(connection, stats);
+            }
+            Event::Upgraded(connection, at, command) => {
+                self.upgraded += 1;
+                if self.shown(&connection, None) {
+                    print_line!(
+                        "🔒 {: >5} — {} on :{}{} upgraded to TLS with {} at byte {}",
+                        &self.count,
+                        DisplayAddr(connection.peer),
+                        connection.local.port(),
+                        self.mapping_suffix(&connection),
+                        command,
+                        at
+                    );
+                }
+                self.upgrades.insert(connection, at);
+            }
+            Event::ClientHello(connection, hello) => {
+                // Count what the client offered.
+                let ja4 = hello.ja4();
+                let seen = self.count_in(JA4_FINGERPRINTS, ja4.clone());
+                self.count_in(TLS_VERSIONS_OFFERED, tls::version_name(hello.max_version()));
+                for protocol in &hello.alpn {
+                    self.count_in(ALPN_OFFERED, protocol.clone());
+                }
+
+                // Report the client's TLS stack.
+                if self.shown(&connection, None) {
+                    print_line!(
+                        "🔏 {: >5} — {} from {} on :{}{} (sni {}, JA3 {}, JA4 {}, seen {}×)",
+                        &self.count,
+                        tls::version_name(hello.max_version()),
+                        DisplayAddr(connection.peer),
+                        connection.local.port(),
+                        self.mapping_suffix(&connection),
+                        hello.server_name.as_deref().unwrap_or("-"),
+                        hello.ja3(),
+                        ja4,
+                        seen
+                    );
+                }
+            }
+            Event::ServerHello(connection, hello) => {
+                // Count what the server chose.
+                let version = tls::version_name(hello.version);
+                let cipher = tls::cipher_name(hello.cipher);
+                self.count_in(TLS_VERSIONS_NEGOTIATED, version.clone());
+                self.count_in(CIPHERS_NEGOTIATED, cipher.clone());
+                if let Some(alpn) = &hello.alpn {
+                    self.count_in(ALPN_NEGOTIATED, alpn.clone());
+                }
+
+                // Report the negotiated parameters.
+                if self.shown(&connection, None) {
+                    print_line!(
+                        "🔐 {: >5} — negotiated {} with {} on :{}{} ({}{})",
+                        &self.count,
+                        version,
+                        DisplayAddr(connection.peer),
+                        connection.local.port(),
+                        self.mapping_suffix(&connection),
+                        cipher,
+                        hello
+                            .alpn
+                            .map(|alpn| format!(", ALPN {}", alpn))
+                            .unwrap_or_default()
+                    );
+                }
             }
+            Event::Exchange(exchange) => {
+                // Count the response by its status class (e.g. 2xx).
+                self.count_in(
+                    HTTP_STATUSES,
+                    format!("{}xx", exchange.response.status().unwrap_or_default() / 100),
+                );
+                self.latencies.record(exchange.latency);
+            }
+            Event::Policy(connection, action) => {
+                self.count_in(POLICIES_APPLIED, action.to_string());
+                if self.shown(&connection, None) {
+                    print_line!(
+                        "🚧 {: >5} — {} for {} on :{}{}",
+                        &self.count,
+                        action,
+                        DisplayAddr(connection.peer),
+                        connection.local.port(),
+                        self.mapping_suffix(&connection)
+                    );
+                }
+            }
+            Event::Queued(connection, waited) => {
+                self.queue_waits.record(waited);
+                if self.shown(&connection, None) {
+                    print_line!(
+                        "⏳ {: >5} — {} on :{}{} waited {} in the queue",
+                        &self.count,
+                        DisplayAddr(connection.peer),
+                        connection.local.port(),
+                        self.mapping_suffix(&connection),
+                        Elapsed(waited)
+                    );
+                }
+            }
+            Event::Rejected(connection, waited) => {
+                self.rejected += 1;
+                if self.shown(&connection, None) {
+                    print_line!(
+                        "⛔ {: >5} — {} on :{}{} gave up after {} in the queue",
+                        &self.count,
+                        DisplayAddr(connection.peer),
+                        connection.local.port(),
+                        self.mapping_suffix(&connection),
+                        Elapsed(waited)
+                    );
+                }
+            }
+            Event::Paced(connection, waited) => {
+                self.paces.record(waited);
+                if self.shown(&connection, None) {
+                    print_line!(
+                        "🚦 {: >5} — {} on :{}{} waited {} for its turn to connect",
+                        &self.count,
+                        DisplayAddr(connection.peer),
+                        connection.local.port(),
+                        self.mapping_suffix(&connection),
+                        Elapsed(waited)
+                    );
+                }
+            }
+            Event::FastOpen(connection, side) => {
+                let (count, side) = match side {
+                    Side::Client => (&mut self.fast_open_clients, "from the client"),
+                    Side::Destination => (&mut self.fast_open_destinations, "to the destination"),
+                };
+                *count += 1;
+                if self.shown(&connection, None) {
+                    print_line!(
+                        "🚀 {: >5} — {} on :{}{} used TCP Fast Open {}",
+                        &self.count,
+                        DisplayAddr(connection.peer),
+                        connection.local.port(),
+                        self.mapping_suffix(&connection),
+                        side
+                    );
+                }
+            }
+            Event::Multipath(connection, side, subflows) => {
+                let (count, side) = match side {
+                    Side::Client => (&mut self.multipath_clients, "from the client"),
+                    Side::Destination => (&mut self.multipath_destinations, "to the destination"),
+                };
+                *count += 1;
+                self.subflows += subflows as u64;
+                if self.shown(&connection, None) {
+                    print_line!(
+                        "🔀 {: >5} — {} on :{}{} used Multipath TCP {} with {} subflow(s)",
+                        &self.count,
+                        DisplayAddr(connection.peer),
+                        connection.local.port(),
+                        self.mapping_suffix(&connection),
+                        side,
+                        subflows
+                    );
+                }
+            }
+            Event::PortsExhausted(connection, err) => {
+                self.ports_exhausted += 1;
+                if self.shown(&connection, None) {
+                    print_line!(
+                        "🚱 {: >5} — {} on :{}{} couldn't be forwarded, out of local ports: {}",
+                        &self.count,
+                        DisplayAddr(connection.peer),
+                        connection.local.port(),
+                        self.mapping_suffix(&connection),
+                        err
+                    );
+                }
+            }
+            Event::FirstByteTimeout(connection, timeout) => {
+                self.first_byte_timeouts += 1;
+                if self.shown(&connection, None) {
+                    print_line!(
+                        "🥱 {: >5} — {} on :{}{} sent nothing in {}, closed before being forwarded",
+                        &self.count,
+                        DisplayAddr(connection.peer),
+                        connection.local.port(),
+                        self.mapping_suffix(&connection),
+                        Elapsed(timeout)
+                    );
+                }
+            }
+            Event::Stalled(connection, direction, stalled) => {
+                self.stalls.record(stalled);
+                if self.shown(&connection, None) {
+                    let to = match direction {
+                        Direction::ClientToServer => "the destination",
+                        Direction::ServerToClient => "the client",
+                    };
+                    print_line!(
+                        "🐌 {: >5} — {} on :{}{} stalled for {}, {} accepting nothing",
+                        &self.count,
+                        DisplayAddr(connection.peer),
+                        connection.local.port(),
+                        self.mapping_suffix(&connection),
+                        Elapsed(stalled),
+                        to
+                    );
+                }
+            }
+            Event::Race(connection, contenders) => {
+                for contender in &contenders {
+                    let stats = self.races.entry(contender.addr.clone()).or_default();
+                    match contender.latency {
+                        Ok(latency) => stats.latencies.record(latency),
+                        Err(_) => stats.failures += 1,
+                    }
+                    if contender.won {
+                        stats.wins += 1;
+                    }
+                }
+
+                if self.shown(&connection, None) {
+                    let contenders: Vec<String> = contenders
+                        .iter()
+                        .map(|contender| match (&contender.latency, contender.won) {
+                            (Ok(latency), true) => {
+                                format!("{} {} (won)", contender.addr, Elapsed(*latency))
+                            }
+                            (Ok(latency), false) => {
+                                format!("{} {}", contender.addr, Elapsed(*latency))
+                            }
+                            (Err(err), _) => format!("{} failed ({})", contender.addr, err),
+                        })
+                        .collect();
+                    print_line!(
+                        "🏎️ {: >5} — raced for {} on :{}{}: {}",
+                        &self.count,
+                        DisplayAddr(connection.peer),
+                        connection.local.port(),
+                        self.mapping_suffix(&connection),
+                        contenders.join(", ")
+                    );
+                }
+            }
+            Event::Backend(connection, addr) => {
+                let stats = self.backends.entry(addr.clone()).or_default();
+                stats.open += 1;
+                stats.total += 1;
+                self.connection_backends.insert(connection, addr);
+            }
+            Event::Summary(sender) => {
+                let _ = sender.send(self.summary());
+            }
+            Event::Snapshot(sender) => {
+                let _ = sender.send(self.snapshot());
+            }
+            Event::Aggregates(sender) => {
+                let _ = sender.send(self.aggregates());
+            }
+            Event::Timeline(length, sender) => {
+                let _ = sender.send(self.timeline.last(length));
+            }
+        }
+    }
+
+    /// Counts a connection for a value of a breakdown, returning the count so far.
+    fn count_in(&mut self, breakdown: &'static str, value: String) -> u64 {
+        self.add_in(breakdown, value, 1)
+    }
+
+    /// Adds to the count of a value of a breakdown, returning the count so far. Past
+    /// `MAX_BREAKDOWN_VALUES` distinct values, new ones are counted as `other`, as many come
+    /// from the peers.
+    fn add_in(&mut self, breakdown: &'static str, value: String, n: u64) -> u64 {
+        let values = self.breakdowns.entry(breakdown).or_default();
+        let value = match values.len() < MAX_BREAKDOWN_VALUES || values.contains_key(&value) {
+            true => value,
+            false => OTHER.to_string(),
+        };
+        let count = values.entry(value).or_default();
+        *count += n;
+        *count
+    }
+
+    /// Shows the current numbers in the status line, if there is one.
+    fn update_status(&self) {
+        if self.status_line {
+            output::set_status(self.status());
         }
     }
 
+    /// The current numbers, for the status line.
+    fn status(&self) -> String {
+        format!(
+            "📶 {} open │ {}/s │ {} │ {} error(s) │ {} total",
+            self.count,
+            self.opened.now(),
+            Rate(self.throughput.now()),
+            self.closed_with_error,
+            self.total
+        )
+    }
+
+    /// Captures the current state.
+    fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            open: self.count,
+            total: self.total,
+            closed_with_error: self.closed_with_error,
+            mappings: self
+                .mapping_counts
+                .iter()
+                .map(|(mapping, count)| (mapping.to_string(), *count))
+                .collect(),
+            peers: self.peers.to_map(),
+            alerts_fired: self.alerts.fired().to_vec(),
+        }
+    }
+
+    /// Everything counted so far as JSON: the counts, the breakdowns and the alerts as
+    /// `aggregates`, and the connect latencies, connection durations, HTTP latencies and
+    /// per-second rates of the last minute as `histograms`.
+    fn aggregates(&self) -> Value {
+        let count = |n: u64| Value::Number(n as f64);
+        let counts = |counts: Vec<(String, u64)>| {
+            let mut counts: Vec<(String, Value)> = counts
+                .into_iter()
+                .map(|(value, n)| (value, count(n)))
+                .collect();
+            counts.sort_by(|a, b| a.0.cmp(&b.0));
+            Value::Object(counts)
+        };
+
+        let breakdowns = BREAKDOWNS
+            .iter()
+            .filter_map(|breakdown| {
+                let values = self.breakdowns.get(breakdown)?;
+                let values = values.iter().map(|(v, n)| (v.clone(), *n)).collect();
+                Some((breakdown.to_string(), counts(values)))
+            })
+            .collect();
+        let mappings = self
+            .mapping_counts
+            .iter()
+            .map(|(mapping, n)| (mapping.to_string(), *n))
+            .collect();
+        let aggregates = Value::Object(vec![
+            ("total".to_string(), count(self.total)),
+            ("open".to_string(), count(self.count)),
+            (
+                "closed_with_error".to_string(),
+                count(self.closed_with_error),
+            ),
+            ("timed_out".to_string(), count(self.timed_out)),
+            ("unreachable".to_string(), count(self.unreachable)),
+            ("silent".to_string(), count(self.silent)),
+            ("upgraded_to_tls".to_string(), count(self.upgraded)),
+            ("coalesced".to_string(), count(self.coalesced_total)),
+            ("bytes_sent".to_string(), count(self.sent)),
+            ("bytes_received".to_string(), count(self.received)),
+            ("peers".to_string(), count(self.peers.len() as u64)),
+            ("peers_evicted".to_string(), count(self.peers.evicted())),
+            ("queued".to_string(), count(self.queue_waits.count())),
+            ("stalls".to_string(), count(self.stalls.count())),
+            ("paced".to_string(), count(self.paces.count())),
+            ("rejected".to_string(), count(self.rejected)),
+            ("banned".to_string(), count(self.bans.banned())),
+            ("refused".to_string(), count(self.bans.refused())),
+            ("ports_exhausted".to_string(), count(self.ports_exhausted)),
+            (
+                "first_byte_timeouts".to_string(),
+                count(self.first_byte_timeouts),
+            ),
+            (
+                "fast_open_from_clients".to_string(),
+                count(self.fast_open_clients),
+            ),
+            (
+                "fast_open_to_destinations".to_string(),
+                count(self.fast_open_destinations),
+            ),
+            (
+                "multipath_from_clients".to_string(),
+                count(self.multipath_clients),
+            ),
+            (
+                "multipath_to_destinations".to_string(),
+                count(self.multipath_destinations),
+            ),
+            ("multipath_subflows".to_string(), count(self.subflows)),
+            ("open_per_mapping".to_string(), counts(mappings)),
+            (
+                "backends".to_string(),
+                Value::Object(
+                    self.backends_by_addr()
+                        .map(|(addr, stats)| {
+                            let stats = Value::Object(vec![
+                                ("total".to_string(), count(stats.total)),
+                                ("open".to_string(), count(stats.open)),
+                                (
+                                    "closed_with_error".to_string(),
+                                    count(stats.closed_with_error),
+                                ),
+                                ("bytes_sent".to_string(), count(stats.sent)),
+                                ("bytes_received".to_string(), count(stats.received)),
+                            ]);
+                            (addr.clone(), stats)
+                        })
+                        .collect(),
+                ),
+            ),
+            ("breakdowns".to_string(), Value::Object(breakdowns)),
+            (
+                "races".to_string(),
+                Value::Object(
+                    self.races_by_addr()
+                        .map(|(addr, stats)| {
+                            let stats = Value::Object(vec![
+                                ("wins".to_string(), count(stats.wins)),
+                                ("failures".to_string(), count(stats.failures)),
+                            ]);
+                            (addr.clone(), stats)
+                        })
+                        .collect(),
+                ),
+            ),
+            (
+                "alerts_fired".to_string(),
+                Value::Array(
+                    self.alerts
+                        .fired()
+                        .iter()
+                        .cloned()
+                        .map(Value::String)
+                        .collect(),
+                ),
+            ),
+        ]);
+
+        let per_second = |history: &History| Value::Array(history.counts().map(count).collect());
+        let histograms = Value::Object(vec![
+            (
+                "connect_latency".to_string(),
+                self.connect_latencies.to_json(),
+            ),
+            ("connection_duration".to_string(), self.durations.to_json()),
+            ("http_latency".to_string(), self.latencies.to_json()),
+            ("queue_wait".to_string(), self.queue_waits.to_json()),
+            ("stall".to_string(), self.stalls.to_json()),
+            ("event_lag".to_string(), self.lags.to_json()),
+            ("connect_pacing".to_string(), self.paces.to_json()),
+            (
+                "protocol_timings".to_string(),
+                Value::Object(
+                    self.protocol_timings
+                        .iter()
+                        .map(|(name, timings)| (name.clone(), timings.to_json()))
+                        .collect(),
+                ),
+            ),
+            (
+                "race_latency".to_string(),
+                Value::Object(
+                    self.races_by_addr()
+                        .map(|(addr, stats)| (addr.clone(), stats.latencies.to_json()))
+                        .collect(),
+                ),
+            ),
+            (
+                "backend_connection_duration".to_string(),
+                Value::Object(
+                    self.backends_by_addr()
+                        .map(|(addr, stats)| (addr.clone(), stats.durations.to_json()))
+                        .collect(),
+                ),
+            ),
+            (
+                "connections_per_second".to_string(),
+                per_second(&self.opened),
+            ),
+            ("bytes_per_second".to_string(), per_second(&self.throughput)),
+        ]);
+
+        Value::Object(vec![
+            ("aggregates".to_string(), aggregates),
+            ("histograms".to_string(), histograms),
+            ("timeline".to_string(), self.timeline.to_json()),
+        ])
+    }
+
+    /// How each destination did in races, by address.
+    fn races_by_addr(&self) -> impl Iterator<Item = (&String, &RaceStats)> {
+        let mut races: Vec<(&String, &RaceStats)> = self.races.iter().collect();
+        races.sort_by(|a, b| a.0.cmp(b.0));
+        races.into_iter()
+    }
+
+    /// The connections per backend of the pools, by address.
+    fn backends_by_addr(&self) -> impl Iterator<Item = (&String, &BackendStats)> {
+        let mut backends: Vec<(&String, &BackendStats)> = self.backends.iter().collect();
+        backends.sort_by(|a, b| a.0.cmp(b.0));
+        backends.into_iter()
+    }
+
+    /// Summarizes everything seen so far.
+    fn summary(&self) -> String {
+        let mut summary = format!(
+            "📊 summary — {} connection(s), {} open",
+            self.total, self.count
+        );
+
+        for breakdown in BREAKDOWNS {
+            let Some(counts) = self.breakdowns.get(breakdown) else {
+                continue;
+            };
+
+            // Most common first.
+            let mut counts: Vec<(&String, &u64)> = counts.iter().collect();
+            counts.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+            let counts: Vec<String> = counts
+                .into_iter()
+                .map(|(value, count)| format!("{} ×{}", value, count))
+                .collect();
+            summary.push_str(&format!("\n   {}: {}", breakdown, counts.join(", ")));
+        }
+
+        if self.total > 0 {
+            summary.push_str(&format!(
+                "\n   connections/s over the last minute: {} now {}/s, peak {}/s",
+                self.opened.sparkline(),
+                self.opened.now(),
+                self.opened.peak()
+            ));
+            summary.push_str(&format!(
+                "\n   throughput over the last minute: {} now {}, peak {}",
+                self.throughput.sparkline(),
+                Rate(self.throughput.now()),
+                Rate(self.throughput.peak())
+            ));
+        }
+
+        if self.queue_waits.count() > 0 || self.rejected > 0 {
+            let mut queue = format!("{} waited", self.queue_waits.count());
+            if let (Some(mean), Some(max)) = (self.queue_waits.mean(), self.queue_waits.max()) {
+                queue.push_str(&format!(" (mean {}, max {})", Elapsed(mean), Elapsed(max)));
+            }
+            summary.push_str(&format!("\n   queue: {}, {} gave up", queue, self.rejected));
+        }
+
+        if let (Some(mean), Some(max)) = (self.stalls.mean(), self.stalls.max()) {
+            summary.push_str(&format!(
+                "\n   stalls: {} (mean {}, max {})",
+                self.stalls.count(),
+                Elapsed(mean),
+                Elapsed(max)
+            ));
+        }
+
+        if let (Some(mean), Some(max)) = (self.paces.mean(), self.paces.max()) {
+            summary.push_str(&format!(
+                "\n   connect pacing: {} waited (mean {}, max {})",
+                self.paces.count(),
+                Elapsed(mean),
+                Elapsed(max)
+            ));
+        }
+
+        for (name, timings) in &self.protocol_timings {
+            if let (Some(mean), Some(max)) = (timings.mean(), timings.max()) {
+                summary.push_str(&format!(
+                    "\n   {}: {} connection(s) (mean {}, max {})",
+                    name,
+                    timings.count(),
+                    Elapsed(mean),
+                    Elapsed(max)
+                ));
+            }
+        }
+
+        if self.fast_open_clients > 0 || self.fast_open_destinations > 0 {
+            summary.push_str(&format!(
+                "\n   fast open: {} of {} connection(s) from clients, {} to destinations",
+                self.fast_open_clients, self.total, self.fast_open_destinations
+            ));
+        }
+
+        let multipath = self.multipath_clients + self.multipath_destinations;
+        if multipath > 0 {
+            summary.push_str(&format!(
+                "\n   multipath: {} of {} connection(s) from clients, {} to destinations, {:.1} subflows on average",
+                self.multipath_clients,
+                self.total,
+                self.multipath_destinations,
+                self.subflows as f64 / multipath as f64
+            ));
+        }
+
+        if self.timed_out > 0 {
+            summary.push_str(&format!(
+                "\n   timed out: {} connection(s) whose peer stopped responding",
+                self.timed_out
+            ));
+        }
+
+        if self.unreachable > 0 {
+            summary.push_str(&format!(
+                "\n   unreachable: {} connection(s) whose peer couldn't be reached",
+                self.unreachable
+            ));
+        }
+
+        if self.silent > 0 {
+            summary.push_str(&format!(
+                "\n   silent: {} of {} connection(s) closed without the client sending anything",
+                self.silent, self.total
+            ));
+        }
+
+        if self.upgraded > 0 {
+            summary.push_str(&format!(
+                "\n   upgraded to TLS: {} of {} connection(s) mid-stream",
+                self.upgraded, self.total
+            ));
+        }
+
+        if self.bans.banned() > 0 {
+            summary.push_str(&format!(
+                "\n   bans: {} client address(es) banned, {} connection(s) refused",
+                self.bans.banned(),
+                self.bans.refused()
+            ));
+        }
+
+        if self.peers.evicted() > 0 {
+            summary.push_str(&format!(
+                "\n   peers evicted: {} client address(es) forgotten to keep at most {}",
+                self.peers.evicted(),
+                self.peers.max()
+            ));
+        }
+
+        if self.coalesced_total > 0 {
+            summary.push_str(&format!(
+                "\n   coalesced: {} of {} connection(s) closed too soon to be printed one by one",
+                self.coalesced_total, self.total
+            ));
+        }
+
+        if self.first_byte_timeouts > 0 {
+            summary.push_str(&format!(
+                "\n   first-byte timeouts: {} connection(s) closed before being forwarded",
+                self.first_byte_timeouts
+            ));
+        }
+
+        if self.ports_exhausted > 0 {
+            summary.push_str(&format!(
+                "\n   out of local ports: {} connection(s) not forwarded",
+                self.ports_exhausted
+            ));
+        }
+
+        if !self.races.is_empty() {
+            // Most wins first.
+            let mut races: Vec<(&String, &RaceStats)> = self.races.iter().collect();
+            races.sort_by(|a, b| b.1.wins.cmp(&a.1.wins).then(a.0.cmp(b.0)));
+            let races: Vec<String> = races
+                .into_iter()
+                .map(|(addr, stats)| {
+                    let mut details = Vec::new();
+                    if let Some(mean) = stats.latencies.mean() {
+                        details.push(format!("mean {}", Elapsed(mean)));
+                    }
+                    if stats.failures > 0 {
+                        details.push(format!("{} failed", stats.failures));
+                    }
+                    format!("{} won {}× ({})", addr, stats.wins, details.join(", "))
+                })
+                .collect();
+            summary.push_str(&format!("\n   connect race: {}", races.join(", ")));
+        }
+
+        if !self.backends.is_empty() {
+            // Most connections first.
+            let mut backends: Vec<(&String, &BackendStats)> = self.backends.iter().collect();
+            backends.sort_by(|a, b| b.1.total.cmp(&a.1.total).then(a.0.cmp(b.0)));
+            let backends: Vec<String> = backends
+                .into_iter()
+                .map(|(addr, stats)| {
+                    let closed = stats.total - stats.open;
+                    let mut details = vec![
+                        format!("{} open", stats.open),
+                        format!(
+                            "{:.1}% errors",
+                            match closed {
+                                0 => 0.0,
+                                _ => stats.closed_with_error as f64 / closed as f64 * 100.0,
+                            }
+                        ),
+                        format!(
+                            "{} sent, {} received",
+                            Bytes(stats.sent),
+                            Bytes(stats.received)
+                        ),
+                    ];
+                    if let Some(mean) = stats.durations.mean() {
+                        details.push(format!("mean {}", Elapsed(mean)));
+                    }
+                    format!("{} ×{} ({})", addr, stats.total, details.join(", "))
+                })
+                .collect();
+            summary.push_str(&format!("\n   backends: {}", backends.join(", ")));
+        }
+
+        if let Some(rates) = self.storms.summary() {
+            summary.push_str(&format!("\n   {}", rates));
+        }
+
+        if !self.alerts.fired().is_empty() {
+            let mut fired: Vec<(&String, u64)> = Vec::new();
+            for alert in self.alerts.fired() {
+                match fired.iter_mut().find(|(fired, _)| *fired == alert) {
+                    Some((_, count)) => *count += 1,
+                    None => fired.push((alert, 1)),
+                }
+            }
+            let fired: Vec<String> = fired
+                .into_iter()
+                .map(|(alert, count)| format!("{} ×{}", alert, count))
+                .collect();
+            summary.push_str(&format!("\n   🚨 alerts fired: {}", fired.join(", ")));
+        }
+
+        if let Some(max) = self.latencies.max() {
+            let percentiles: Vec<String> = PERCENTILES
+                .iter()
+                .filter_map(|p| {
+                    Some(format!(
+                        "p{} {}",
+                        p,
+                        Elapsed(self.latencies.percentile(*p)?)
+                    ))
+                })
+                .collect();
+            summary.push_str(&format!(
+                "\n   HTTP latency: {}, max {}",
+                percentiles.join(", "),
+                Elapsed(max)
+            ));
+        }
+
+        summary
+    }
+
     /// Shared logic for when a socket is closed.
-    fn on_socket_closed(&mut self, addr: SocketAddr) -> Duration {
-        // Decrement the count.
-        self.count -= 1;
+    fn on_socket_closed(&mut self, connection: &Connection, traffic: Traffic) -> Duration {
+        // Retrieve (and remove) the time that it connected so we can print the connection
+        // duration. A connection that was never counted as open is only warned about, as nothing
+        // is to be taken off the counts for it.
+        let Some(connected_at) = self.connected_time.remove(connection) else {
+            eprint_line!(
+                "⚠️  closed connection from {} on :{} was never seen opening, so it's not counted",
+                DisplayAddr(connection.peer),
+                connection.local.port()
+            );
+            return Duration::ZERO;
+        };
+
+        // Count the traffic.
+        self.sent += traffic.sent;
+        self.received += traffic.received;
+        if traffic.sent == 0 {
+            self.silent += 1;
+        }
+
+        // Decrement the counts, without going below zero should they be off anyway.
+        self.count = self.count.saturating_sub(1);
+        if let Some(count) = self.mapping_counts.get_mut(&connection.mapping) {
+            *count = count.saturating_sub(1);
+        }
 
-        // Retrieve (and remove) the time that it connected so we can print the connection duration.
-        let connected_at = self
-            .connected_time
-            .remove(&addr)
-            .expect("No corresponding start time for socket?");
+        let connected_duration = connected_at.elapsed();
+        self.durations.record(connected_duration);
+        if let Some(addr) = self.connection_backends.remove(connection) {
+            let stats = self.backends.entry(addr).or_default();
+            stats.open = stats.open.saturating_sub(1);
+            stats.sent += traffic.sent;
+            stats.received += traffic.received;
+            stats.durations.record(connected_duration);
+        }
+        self.peers.closed(connection.peer.ip(), connected_duration);
 
         // Return the connected duration.
-        connected_at
-            .elapsed()
-            .expect("Error computing elapsed time?")
+        connected_duration
+    }
+
+    /// Publishes an event about a connection as JSON, with `details` as extra members, wherever
+    /// events are published.
+    fn publish(&self, event: &'static str, connection: &Connection, details: String) {
+        if self.redis.is_none() && self.mqtt.is_none() && self.kafka.is_none() {
+            return;
+        }
+
+        let labels: Vec<String> = connection
+            .labels
+            .iter()
+            .map(|(key, value)| format!("{}:{}", json::quote(key), json::quote(value)))
+            .collect();
+        let json = format!(
+            r#"{{"event":{},"time":{},"peer":{},"port":{},"mapping":{},"open":{},"labels":{{{}}}{}}}"#,
+            json::quote(event),
+            json::quote(&record::rfc3339(SystemTime::now())),
+            json::quote(&DisplayAddr(connection.peer).to_string()),
+            connection.local.port(),
+            json::quote(&connection.mapping),
+            self.count,
+            labels.join(","),
+            details
+        );
+
+        if let Some(mqtt) = &self.mqtt {
+            mqtt.publish(event, json.clone());
+        }
+        if let Some(kafka) = &self.kafka {
+            kafka.publish(json.clone());
+        }
+        if let Some(redis) = &self.redis {
+            redis.publish(json);
+        }
+    }
+
+    /// Exports the flows of a connection that closed, one each way, if configured.
+    fn export_flows(&mut self, connection: &Connection, connected: Duration, traffic: Traffic) {
+        let Some(ipfix) = &mut self.ipfix else {
+            return;
+        };
+
+        let end = SystemTime::now();
+        let client = ipfix::Flow {
+            source: net::normalize(connection.peer),
+            destination: net::normalize(connection.local),
+            bytes: traffic.sent,
+            packets: traffic.reads,
+            start: end.checked_sub(connected).unwrap_or(end),
+            end,
+        };
+        let server = ipfix::Flow {
+            source: client.destination,
+            destination: client.source,
+            bytes: traffic.received,
+            packets: traffic.writes,
+            ..client
+        };
+        ipfix.export(&[client, server]);
+    }
+
+    /// Whether to print a line about a connection, which has been open for the duration if it
+    /// closed. Sampling goes by a hash of its addresses, so all lines about a connection are
+    /// printed or none are.
+    fn shown(&self, connection: &Connection, duration: Option<Duration>) -> bool {
+        // Nothing is printed about a connection that may yet be coalesced.
+        if self.deferred.contains_key(connection) || !self.filter.allows(connection, duration) {
+            return false;
+        }
+
+        let Some(Sample(numerator, denominator)) = self.sample else {
+            return true;
+        };
+
+        let mut hasher = DefaultHasher::new();
+        (connection.peer, connection.local, connection.stream).hash(&mut hasher);
+        hasher.finish() % denominator < numerator
+    }
+
+    /// The tunnel stream of the connection, for connections of mappings other than the default
+    /// one the mapping and its count, and the labels of the connection.
+    fn mapping_suffix(&self, connection: &Connection) -> String {
+        let mut suffix = match connection.stream {
+            Some(stream) => format!(" (stream {})", stream),
+            None => String::new(),
+        };
+
+        if &*connection.mapping != DEFAULT_MAPPING {
+            let count = self.mapping_counts.get(&connection.mapping).unwrap_or(&0);
+            suffix.push_str(&format!(" [{}: {}]", connection.mapping, count));
+        }
+
+        if !connection.labels.is_empty() {
+            let labels: Vec<String> = connection
+                .labels
+                .iter()
+                .map(|(key, value)| format!("{}={}", key, value))
+                .collect();
+            suffix.push_str(&format!(" {{{}}}", labels.join(", ")));
+        }
+
+        suffix
+    }
+}
+
+/// The throughput of each direction of a closed connection as an extra JSON member, in bytes
+/// per second: the lowest and highest of a second (`null` if it was open for less) and the
+/// average.
+fn throughput_json(traffic: Traffic, connected: Duration) -> String {
+    let direction = |bytes: u64, rates: Option<RateRange>| {
+        let seconds = connected.as_secs_f64();
+        let avg = match seconds > 0.0 {
+            true => bytes as f64 / seconds,
+            false => 0.0,
+        };
+        match rates {
+            Some(rates) => format!(
+                r#"{{"min":{},"avg":{:.0},"max":{}}}"#,
+                rates.min, avg, rates.max
+            ),
+            None => format!(r#"{{"min":null,"avg":{:.0},"max":null}}"#, avg),
+        }
+    };
+    format!(
+        r#","throughput":{{"sent":{},"received":{}}}"#,
+        direction(traffic.sent, traffic.sent_rates),
+        direction(traffic.received, traffic.received_rates)
+    )
+}
+
+/// Notes on the line of a closed connection when its bytes flowed, if any did, to tell
+/// connections that were busy from those that were mostly idle.
+fn activity_suffix(activity: Activity) -> String {
+    let (first, last) = match (activity.sent, activity.received) {
+        (Some(sent), Some(received)) => (sent.0.min(received.0), sent.1.max(received.1)),
+        (Some(flowed), None) | (None, Some(flowed)) => flowed,
+        (None, None) => return String::new(),
+    };
+    format!(
+        ", active from {} to {}, idle for up to {}",
+        Elapsed(first),
+        Elapsed(last),
+        Elapsed(activity.longest_idle)
+    )
+}
+
+/// The JSON of when the bytes of a connection flowed, with `null`s for a direction without any.
+fn activity_json(activity: Activity) -> Value {
+    let millis = |duration: Duration| Value::Number(duration.as_secs_f64() * 1000.0);
+    let first = |flowed: Option<(Duration, Duration)>| flowed.map_or(Value::Null, |f| millis(f.0));
+    let last = |flowed: Option<(Duration, Duration)>| flowed.map_or(Value::Null, |f| millis(f.1));
+    Value::Object(vec![
+        ("first_sent_ms".to_string(), first(activity.sent)),
+        ("last_sent_ms".to_string(), last(activity.sent)),
+        ("first_received_ms".to_string(), first(activity.received)),
+        ("last_received_ms".to_string(), last(activity.received)),
+        ("longest_idle_ms".to_string(), millis(activity.longest_idle)),
+    ])
+}
+
+/// Notes on the line of a closed connection where it was upgraded to TLS, if it was.
+fn upgraded_suffix(upgraded_at: Option<u64>) -> String {
+    match upgraded_at {
+        Some(at) => format!(", upgraded to TLS at byte {}", at),
+        None => String::new(),
+    }
+}
+
+/// The JSON of what a connection did per its protocol, or `null` if it wasn't looked into.
+fn protocol_json(stats: Option<Stats>) -> Value {
+    let Some(stats) = stats else {
+        return Value::Null;
+    };
+    let counts = stats
+        .counts
+        .iter()
+        .map(|(name, count)| (name.to_string(), Value::Number(*count as f64)));
+    let timings = stats.timings.iter().map(|(name, duration)| {
+        (
+            name.to_string(),
+            Value::Number(duration.as_secs_f64() * 1000.0),
+        )
+    });
+    let details = stats
+        .details
+        .into_iter()
+        .map(|(name, value)| (name.to_string(), Value::String(value)));
+    Value::Object(vec![
+        (
+            "name".to_string(),
+            Value::String(stats.protocol.to_string()),
+        ),
+        ("counts".to_string(), Value::Object(counts.collect())),
+        ("timings_ms".to_string(), Value::Object(timings.collect())),
+        ("details".to_string(), Value::Object(details.collect())),
+    ])
+}
+
+/// Notes on the line of a closed connection that its client sent nothing.
+fn silent_suffix(silent: bool) -> &'static str {
+    match silent {
+        true => ", the client sent nothing",
+        false => "",
     }
 }
 
@@ -165,10 +2166,590 @@ mod tests {
 
     #[test]
     fn error_display() {
-        let error = SocketCloseError(Direction::ClientToServer, "damn".to_string());
+        let error = SocketCloseError(
+            Direction::ClientToServer,
+            "damn".to_string(),
+            io::ErrorKind::Other,
+        );
         assert_eq!(
             format!("{}", error),
             "Error while forwarding client traffic to the server: damn"
         );
     }
+
+    #[test]
+    fn throughput() {
+        let traffic = Traffic {
+            sent: 3000,
+            sent_rates: Some(RateRange {
+                min: 500,
+                max: 2000,
+            }),
+            ..Default::default()
+        };
+        assert_eq!(
+            throughput_json(traffic, Duration::from_secs(2)),
+            r#","throughput":{"sent":{"min":500,"avg":1500,"max":2000},"received":{"min":null,"avg":0,"max":null}}"#
+        );
+    }
+
+    #[test]
+    fn activity() {
+        let activity = Activity {
+            sent: Some((Duration::from_millis(2), Duration::from_millis(1200))),
+            received: Some((Duration::from_millis(3), Duration::from_millis(1201))),
+            longest_idle: Duration::from_secs(598),
+        };
+        assert_eq!(
+            activity_suffix(activity),
+            ", active from 2.00ms to 1.20s, idle for up to 9m 58s"
+        );
+        assert_eq!(
+            activity_json(activity).to_string(),
+            r#"{"first_sent_ms":2,"last_sent_ms":1200,"first_received_ms":3,"last_received_ms":1201,"longest_idle_ms":598000}"#
+        );
+        assert_eq!(activity_suffix(Activity::default()), "");
+    }
+
+    #[tokio::test]
+    async fn survives_panics() {
+        let (reporter_handle, mut reporter_actor) = create(Default::default());
+        reporter_handle.report(Event::Opened(Connection {
+            peer: "10.0.0.1:4000".parse().unwrap(),
+            local: ([127, 0, 0, 1], 8080).into(),
+            mapping: Arc::from(DEFAULT_MAPPING),
+            stream: None,
+            labels: Arc::new([]),
+        }));
+
+        // A panic is caught with its message, and what was counted before is kept.
+        assert_eq!(
+            reporter_actor.guarded(|_| panic!("boom")),
+            Err("boom".to_string())
+        );
+        tokio::spawn(reporter_actor.run());
+        let snapshot = reporter_handle.snapshot().await.unwrap();
+        assert_eq!((snapshot.open, snapshot.total), (1, 1));
+    }
+
+    #[tokio::test]
+    async fn tolerates_unknown_connections() {
+        let (reporter_handle, reporter_actor) = create(Default::default());
+        tokio::spawn(reporter_actor.run());
+
+        let connection = |peer: &str| Connection {
+            peer: peer.parse().unwrap(),
+            local: ([127, 0, 0, 1], 8080).into(),
+            mapping: Arc::from(DEFAULT_MAPPING),
+            stream: None,
+            labels: Arc::new([]),
+        };
+
+        // A connection that was never seen opening doesn't take the counts below zero, nor
+        // count towards the traffic or the silent connections, nor stop the reporter.
+        reporter_handle.report(Event::ClosedGracefully(
+            connection("10.0.0.1:4000"),
+            Traffic {
+                received: 500,
+                ..Default::default()
+            },
+        ));
+        reporter_handle.report(Event::Opened(connection("10.0.0.1:4001")));
+        let snapshot = reporter_handle.snapshot().await.unwrap();
+        assert_eq!((snapshot.open, snapshot.total), (1, 1));
+        let aggregates = reporter_handle.aggregates().await.unwrap();
+        let aggregates = aggregates.get("aggregates").unwrap();
+        assert_eq!(aggregates.get("bytes_received"), Some(&Value::Number(0.0)));
+        assert_eq!(aggregates.get("silent"), Some(&Value::Number(0.0)));
+
+        reporter_handle.report(Event::ClosedGracefully(
+            connection("10.0.0.1:4001"),
+            Traffic::default(),
+        ));
+        let snapshot = reporter_handle.snapshot().await.unwrap();
+        assert_eq!((snapshot.open, snapshot.total), (0, 1));
+        assert_eq!(snapshot.mappings[DEFAULT_MAPPING], 0);
+    }
+
+    #[tokio::test]
+    async fn snapshot() {
+        let (reporter_handle, reporter_actor) = create(Default::default());
+        tokio::spawn(reporter_actor.run());
+
+        let connection = |peer: &str| Connection {
+            peer: peer.parse().unwrap(),
+            local: ([127, 0, 0, 1], 8080).into(),
+            mapping: Arc::from(DEFAULT_MAPPING),
+            stream: None,
+            labels: Arc::new([]),
+        };
+        reporter_handle.report(Event::Opened(connection("10.0.0.1:4000")));
+        reporter_handle.report(Event::Opened(connection("10.0.0.1:4001")));
+        reporter_handle.report(Event::Opened(connection("10.0.0.2:4000")));
+        reporter_handle.report(Event::ClosedWithError(
+            connection("10.0.0.1:4000"),
+            SocketCloseError(
+                Direction::ServerToClient,
+                "reset".to_string(),
+                io::ErrorKind::ConnectionReset,
+            ),
+            Traffic::default(),
+        ));
+
+        let snapshot = reporter_handle.snapshot().await.unwrap();
+        assert_eq!(snapshot.open, 2);
+        assert_eq!(snapshot.total, 3);
+        assert_eq!(snapshot.closed_with_error, 1);
+        assert_eq!(snapshot.mappings[DEFAULT_MAPPING], 2);
+        let peer = snapshot.peers[&"10.0.0.1".parse().unwrap()];
+        assert_eq!((peer.open, peer.total, peer.closed_with_error), (1, 2, 1));
+        assert_eq!(snapshot.peers[&"10.0.0.2".parse().unwrap()].open, 1);
+        assert!(snapshot.alerts_fired.is_empty());
+
+        // Timeouts and unreachable peers are errors, counted on their own too.
+        reporter_handle.report(Event::ClosedWithError(
+            connection("10.0.0.2:4000"),
+            SocketCloseError(
+                Direction::ClientToServer,
+                "timed out".to_string(),
+                io::ErrorKind::TimedOut,
+            ),
+            Traffic::default(),
+        ));
+        reporter_handle.report(Event::ClosedWithError(
+            connection("10.0.0.1:4001"),
+            SocketCloseError(
+                Direction::ServerToClient,
+                "peer unreachable".to_string(),
+                io::ErrorKind::HostUnreachable,
+            ),
+            Traffic::default(),
+        ));
+        let aggregates = reporter_handle.aggregates().await.unwrap();
+        let aggregates = aggregates.get("aggregates").unwrap();
+        assert_eq!(
+            aggregates.get("closed_with_error"),
+            Some(&Value::Number(3.0))
+        );
+        assert_eq!(aggregates.get("timed_out"), Some(&Value::Number(1.0)));
+        assert_eq!(aggregates.get("unreachable"), Some(&Value::Number(1.0)));
+        let summary = reporter_handle.summary().await.unwrap();
+        assert!(summary.contains("\n   timed out: 1 connection(s) whose peer stopped responding"));
+
+        // None of the clients sent anything.
+        assert_eq!(aggregates.get("silent"), Some(&Value::Number(3.0)));
+        assert!(summary.contains(
+            "\n   silent: 3 of 3 connection(s) closed without the client sending anything"
+        ));
+
+        reporter_handle.report(Event::FastOpen(connection("10.0.0.1:4001"), Side::Client));
+        reporter_handle.report(Event::FastOpen(
+            connection("10.0.0.1:4001"),
+            Side::Destination,
+        ));
+        let summary = reporter_handle.summary().await.unwrap();
+        assert!(summary
+            .contains("\n   fast open: 1 of 3 connection(s) from clients, 1 to destinations"));
+
+        reporter_handle.report(Event::Upgraded(connection("10.0.0.2:4000"), 31, "STARTTLS"));
+        let summary = reporter_handle.summary().await.unwrap();
+        assert!(summary.contains("\n   upgraded to TLS: 1 of 3 connection(s) mid-stream"));
+
+        reporter_handle.report(Event::Multipath(
+            connection("10.0.0.1:4001"),
+            Side::Client,
+            1,
+        ));
+        reporter_handle.report(Event::Multipath(
+            connection("10.0.0.1:4001"),
+            Side::Destination,
+            2,
+        ));
+        let summary = reporter_handle.summary().await.unwrap();
+        assert!(summary.contains(
+            "\n   multipath: 1 of 3 connection(s) from clients, 1 to destinations, 1.5 subflows on average"
+        ));
+    }
+
+    #[test]
+    fn samples_connections() {
+        assert_eq!(Sample::parse(" 1 / 100 "), Ok(Sample(1, 100)));
+        for invalid in ["1", "1/0", "3/2", "a/b", "-1/2"] {
+            assert!(Sample::parse(invalid).is_err(), "{}", invalid);
+        }
+
+        let connection = |port: u16| Connection {
+            peer: ([10, 0, 0, 1], port).into(),
+            local: ([127, 0, 0, 1], 8080).into(),
+            mapping: Arc::from(DEFAULT_MAPPING),
+            stream: None,
+            labels: Arc::new([]),
+        };
+        let shown = |sample: Option<Sample>| {
+            let (_, reporter_actor) = create(Options {
+                sample,
+                ..Default::default()
+            });
+            (4000..6000)
+                .filter(|port| {
+                    // All lines about a connection are printed, or none are.
+                    let opened = reporter_actor.shown(&connection(*port), None);
+                    let closed = reporter_actor.shown(&connection(*port), Some(Duration::ZERO));
+                    assert_eq!(opened, closed);
+                    opened
+                })
+                .count()
+        };
+        assert_eq!(shown(None), 2000);
+        assert_eq!(shown(Some(Sample(0, 10))), 0);
+        assert_eq!(shown(Some(Sample(10, 10))), 2000);
+        let tenth = shown(Some(Sample(1, 10)));
+        assert!((100..300).contains(&tenth), "{}", tenth);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn coalesces_short_connections() {
+        let (reporter_handle, reporter_actor) = create(Options {
+            coalesce_under: Some(Duration::from_secs(1)),
+            ..Default::default()
+        });
+        tokio::spawn(reporter_actor.run());
+
+        let connection = |port: u16| Connection {
+            peer: ([10, 0, 0, 1], port).into(),
+            local: ([127, 0, 0, 1], 8080).into(),
+            mapping: Arc::from(DEFAULT_MAPPING),
+            stream: None,
+            labels: Arc::new([]),
+        };
+        for port in 4000..4003 {
+            reporter_handle.report(Event::Opened(connection(port)));
+        }
+        reporter_handle.report(Event::ClosedGracefully(
+            connection(4000),
+            Traffic::default(),
+        ));
+        reporter_handle.report(Event::ClosedWithError(
+            connection(4001),
+            SocketCloseError(
+                Direction::ServerToClient,
+                "reset".to_string(),
+                io::ErrorKind::ConnectionReset,
+            ),
+            Traffic::default(),
+        ));
+
+        // The one that lasts longer is not coalesced, and all of them are still counted.
+        reporter_handle.snapshot().await.unwrap();
+        tokio::time::advance(Duration::from_secs(2)).await;
+        reporter_handle.report(Event::ClosedGracefully(
+            connection(4002),
+            Traffic::default(),
+        ));
+        let snapshot = reporter_handle.snapshot().await.unwrap();
+        assert_eq!((snapshot.open, snapshot.total), (0, 3));
+        let aggregates = reporter_handle.aggregates().await.unwrap();
+        let aggregates = aggregates.get("aggregates").unwrap();
+        assert_eq!(aggregates.get("coalesced"), Some(&Value::Number(2.0)));
+        assert_eq!(
+            aggregates.get("closed_with_error"),
+            Some(&Value::Number(1.0))
+        );
+        assert!(reporter_handle.summary().await.unwrap().contains(
+            "\n   coalesced: 2 of 3 connection(s) closed too soon to be printed one by one"
+        ));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn summarizes_error_rates() {
+        let (reporter_handle, reporter_actor) = create(Default::default());
+        tokio::spawn(reporter_actor.run());
+
+        let connection = |port: u16| Connection {
+            peer: ([10, 0, 0, 1], port).into(),
+            local: ([127, 0, 0, 1], 8080).into(),
+            mapping: Arc::from(DEFAULT_MAPPING),
+            stream: None,
+            labels: Arc::new([]),
+        };
+        for port in 4000..4012 {
+            reporter_handle.report(Event::Opened(connection(port)));
+            let kind = match port % 3 {
+                0 => io::ErrorKind::TimedOut,
+                _ => io::ErrorKind::ConnectionReset,
+            };
+            reporter_handle.report(Event::ClosedWithError(
+                connection(port),
+                SocketCloseError(Direction::ServerToClient, "failed".to_string(), kind),
+                Traffic::default(),
+            ));
+        }
+        assert!(reporter_handle.summary().await.unwrap().contains(
+            "\n   errors: 12 in total, 12/min now, peak 12/min; resets: 8 in total, 8/min now, peak 8/min"
+        ));
+
+        // The rates now are for the last minute.
+        tokio::time::advance(Duration::from_secs(61)).await;
+        assert!(reporter_handle.summary().await.unwrap().contains(
+            "\n   errors: 12 in total, 0/min now, peak 12/min; resets: 8 in total, 0/min now, peak 8/min"
+        ));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn shows_the_current_numbers_in_the_status_line() {
+        let (_, mut reporter_actor) = create(Default::default());
+        let connection = |port: u16| Connection {
+            peer: ([10, 0, 0, 1], port).into(),
+            local: ([127, 0, 0, 1], 8080).into(),
+            mapping: Arc::from(DEFAULT_MAPPING),
+            stream: None,
+            labels: Arc::new([]),
+        };
+        for port in 4000..4003 {
+            reporter_actor.receive(Event::Opened(connection(port)));
+        }
+        reporter_actor.receive(Event::ClosedWithError(
+            connection(4000),
+            SocketCloseError(
+                Direction::ClientToServer,
+                "reset".to_string(),
+                io::ErrorKind::ConnectionReset,
+            ),
+            Traffic::default(),
+        ));
+        tokio::time::advance(CHECK_INTERVAL).await;
+        reporter_actor.check_rates();
+        assert_eq!(
+            reporter_actor.status(),
+            "📶 2 open │ 3/s │ 0 B/s │ 1 error(s) │ 3 total"
+        );
+    }
+
+    #[test]
+    fn makes_room_for_the_expected_connections() {
+        let (_, mut reporter_actor) = create(Options {
+            expected_connections: Some(5000),
+            max_peers: Some(100),
+            coalesce_under: Some(Duration::from_secs(1)),
+            ..Default::default()
+        });
+        let capacities = |reporter: &ReporterActor| {
+            (
+                reporter.connected_time.capacity(),
+                reporter.deferred.capacity(),
+            )
+        };
+        let reserved = capacities(&reporter_actor);
+        assert!(reserved.0 >= 5000 && reserved.1 >= 5000, "{:?}", reserved);
+
+        // The tables don't grow while the expected connections open.
+        for n in 0..5000u32 {
+            let [_, _, high, low] = n.to_be_bytes();
+            reporter_actor.receive(Event::Opened(Connection {
+                peer: ([10, 0, high, low], 4000).into(),
+                local: ([127, 0, 0, 1], 8080).into(),
+                mapping: Arc::from(DEFAULT_MAPPING),
+                stream: None,
+                labels: Arc::new([]),
+            }));
+        }
+        assert_eq!(reporter_actor.total, 5000);
+        assert_eq!(capacities(&reporter_actor), reserved);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn measures_how_long_events_wait() {
+        let (reporter_handle, reporter_actor) = create(Default::default());
+        for port in 4000..4003 {
+            reporter_handle.report(Event::Opened(Connection {
+                peer: ([10, 0, 0, 1], port).into(),
+                local: ([127, 0, 0, 1], 8080).into(),
+                mapping: Arc::from(DEFAULT_MAPPING),
+                stream: None,
+                labels: Arc::new([]),
+            }));
+        }
+        assert_eq!(reporter_handle.counters.queued.load(Ordering::Relaxed), 3);
+
+        // The events wait for the reporter to get to them.
+        tokio::time::advance(Duration::from_secs(2)).await;
+        tokio::spawn(reporter_actor.run());
+        let aggregates = reporter_handle.aggregates().await.unwrap();
+        assert_eq!(reporter_handle.counters.queued.load(Ordering::Relaxed), 0);
+        let lag = aggregates
+            .get("histograms")
+            .unwrap()
+            .get("event_lag")
+            .unwrap();
+        // Asking for the aggregates is an event too, which didn't wait.
+        assert_eq!(lag.get("count").unwrap().to_string(), "4");
+        assert_eq!(lag.get("max_ms").unwrap().to_string(), "2000");
+        assert_eq!(lag.get("min_ms").unwrap().to_string(), "0");
+    }
+
+    #[test]
+    fn bounds_the_breakdowns() {
+        let (_, mut reporter_actor) = create(Options {
+            sample: Some(Sample(0, 1)),
+            ..Default::default()
+        });
+        for port in 0..1000u16 {
+            let connection = Connection {
+                peer: ([10, 0, 0, 1], port).into(),
+                local: ([127, 0, 0, 1], 1883).into(),
+                mapping: Arc::from(DEFAULT_MAPPING),
+                stream: None,
+                labels: Arc::new([]),
+            };
+            reporter_actor.receive(Event::Protocol(
+                connection.clone(),
+                Stats {
+                    protocol: crate::protocol::Protocol::Mqtt,
+                    counts: vec![("publishes", 1)],
+                    timings: Vec::new(),
+                    details: vec![
+                        ("client ID", format!("sensor-{}", port)),
+                        ("MQTT", "3.1.1".to_string()),
+                    ],
+                },
+            ));
+            reporter_actor.receive(Event::ClosedGracefully(connection, Traffic::default()));
+            reporter_actor.count_in(JA4_FINGERPRINTS, format!("t13d{}", port));
+        }
+
+        // Client IDs aren't counted, and values past the limit are counted together.
+        let protocol_stats = &reporter_actor.breakdowns[PROTOCOL_STATS];
+        assert_eq!(protocol_stats.len(), 3);
+        assert_eq!(protocol_stats["mqtt MQTT 3.1.1"], 1000);
+        let fingerprints = &reporter_actor.breakdowns[JA4_FINGERPRINTS];
+        assert_eq!(fingerprints.len(), MAX_BREAKDOWN_VALUES + 1);
+        assert_eq!(fingerprints[OTHER], 900);
+        assert_eq!(reporter_actor.protocol_stats.len(), 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn backend_stats() {
+        let (reporter_handle, reporter_actor) = create(Default::default());
+        tokio::spawn(reporter_actor.run());
+
+        let connection = |port: u16| Connection {
+            peer: ([10, 0, 0, 1], port).into(),
+            local: ([127, 0, 0, 1], 8080).into(),
+            mapping: Arc::from(DEFAULT_MAPPING),
+            stream: None,
+            labels: Arc::new([]),
+        };
+        for (port, backend) in [
+            (4000, "10.0.1.1:80"),
+            (4001, "10.0.1.1:80"),
+            (4002, "10.0.1.2:80"),
+        ] {
+            reporter_handle.report(Event::Opened(connection(port)));
+            reporter_handle.report(Event::Backend(connection(port), backend.to_string()));
+        }
+        reporter_handle.snapshot().await.unwrap();
+        tokio::time::advance(Duration::from_secs(2)).await;
+        let traffic = Traffic {
+            sent: 100,
+            received: 2048,
+            ..Default::default()
+        };
+        reporter_handle.report(Event::ClosedGracefully(connection(4000), traffic));
+        reporter_handle.report(Event::ClosedWithError(
+            connection(4002),
+            SocketCloseError(
+                Direction::ServerToClient,
+                "reset".to_string(),
+                io::ErrorKind::ConnectionReset,
+            ),
+            Traffic::default(),
+        ));
+
+        let aggregates = reporter_handle.aggregates().await.unwrap();
+        assert_eq!(
+            aggregates
+                .get("aggregates")
+                .and_then(|aggregates| aggregates.get("backends"))
+                .unwrap()
+                .to_string(),
+            r#"{"10.0.1.1:80":{"total":2,"open":1,"closed_with_error":0,"bytes_sent":100,"bytes_received":2048},"10.0.1.2:80":{"total":1,"open":0,"closed_with_error":1,"bytes_sent":0,"bytes_received":0}}"#
+        );
+        let summary = reporter_handle.summary().await.unwrap();
+        assert!(summary.contains(
+            "\n   backends: 10.0.1.1:80 ×2 (1 open, 0.0% errors, 100 B sent, 2.00 KiB received, mean 2s), \
+             10.0.1.2:80 ×1 (0 open, 100.0% errors, 0 B sent, 0 B received, mean 2s)"
+        ));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn exact_durations() {
+        let (reporter_handle, reporter_actor) = create(Options {
+            alerts: crate::alert::parse_alerts("concurrent>0").unwrap(),
+            ..Default::default()
+        });
+        tokio::spawn(reporter_actor.run());
+
+        let connection = Connection {
+            peer: "10.0.0.1:4000".parse().unwrap(),
+            local: ([127, 0, 0, 1], 8080).into(),
+            mapping: Arc::from(DEFAULT_MAPPING),
+            stream: None,
+            labels: Arc::new([]),
+        };
+        reporter_handle.report(Event::Opened(connection.clone()));
+        reporter_handle.snapshot().await.unwrap();
+        tokio::time::advance(Duration::from_secs(5)).await;
+        reporter_handle.report(Event::ClosedGracefully(connection, Traffic::default()));
+
+        let snapshot = reporter_handle.snapshot().await.unwrap();
+        let peer = snapshot.peers[&"10.0.0.1".parse().unwrap()];
+        assert_eq!(peer.connected, Duration::from_secs(5));
+        assert_eq!(snapshot.alerts_fired, ["concurrent>0"]);
+        assert_eq!(
+            reporter_handle.summary().await.unwrap(),
+            "📊 summary — 1 connection(s), 0 open\n   \
+             connections/s over the last minute: █▁▁ now 0/s, peak 1/s\n   \
+             throughput over the last minute: ▁▁▁ now 0 B/s, peak 0 B/s\n   \
+             silent: 1 of 1 connection(s) closed without the client sending anything\n   \
+             🚨 alerts fired: concurrent>0 ×1"
+        );
+    }
+
+    #[tokio::test]
+    async fn breaks_down_http_responses() {
+        let (reporter_handle, reporter_actor) = create(Default::default());
+        tokio::spawn(reporter_actor.run());
+
+        let exchange = |status: &str, millis: u64| {
+            let head = |text: &str| crate::http::Head::parse(text.as_bytes()).unwrap();
+            crate::http::Exchange {
+                request: head("GET / HTTP/1.1\r\n\r\n"),
+                request_body: Default::default(),
+                response: head(&format!("HTTP/1.1 {}\r\n\r\n", status)),
+                response_body: Default::default(),
+                started: std::time::SystemTime::now(),
+                latency: Duration::from_millis(millis),
+                duration: Duration::from_millis(millis),
+            }
+        };
+        for millis in 1..=8 {
+            reporter_handle.report(Event::Exchange(exchange("200 OK", millis)));
+        }
+        reporter_handle.report(Event::Exchange(exchange("404 Not Found", 30)));
+        reporter_handle.report(Event::Exchange(exchange("503 Unavailable", 1500)));
+
+        let summary = reporter_handle.summary().await.unwrap();
+        assert!(summary.contains("\n   HTTP responses: 2xx ×8, 4xx ×1, 5xx ×1"));
+        assert!(
+            summary.ends_with("\n   HTTP latency: p50 5.00ms, p90 50.0ms, p99 1.50s, max 1.50s")
+        );
+
+        let aggregates = reporter_handle.aggregates().await.unwrap();
+        let latency = aggregates
+            .get("histograms")
+            .unwrap()
+            .get("http_latency")
+            .unwrap();
+        assert_eq!(latency.get("count").unwrap().to_string(), "10");
+    }
 }
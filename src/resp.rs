@@ -0,0 +1,256 @@
+use crate::protocol::{Parser, Protocol, Stats};
+
+/// The longest line to keep, enough for the header of any value; inline commands can be longer,
+/// but only their end matters.
+const MAX_LINE_LEN: usize = 1024;
+
+/// The longest bulk string, and the most elements of an aggregate, to go along with: as much as
+/// servers accept for a string by default (512 MiB).
+const MAX_LEN: i64 = 512 * 1024 * 1024;
+
+/// The deepest aggregates can nest.
+const MAX_DEPTH: usize = 64;
+
+/// Splits one direction of RESP into values as the bytes are read, keeping track of how deep
+/// into arrays, maps and the like it is.
+#[derive(Debug, Default)]
+struct Values {
+    /// The line being read, up to `MAX_LINE_LEN` bytes.
+    line: Vec<u8>,
+
+    /// How many bytes of the current bulk string are still to come, including its `\r\n`.
+    bulk: usize,
+
+    /// The type of the current bulk string, once its header is in.
+    bulk_kind: u8,
+
+    /// How many elements the enclosing aggregates still have to come, innermost last.
+    nested: Vec<u64>,
+
+    /// Whether a length or nesting came along that RESP can't have, after which nothing more
+    /// is read.
+    broken: bool,
+}
+
+impl Values {
+    /// Reads the next bytes, calling `on_element` with the type of each element that's complete
+    /// (`b'-'` for errors, 0 for inline commands), and whether it completed a top-level value.
+    fn feed(&mut self, mut bytes: &[u8], mut on_element: impl FnMut(u8, bool)) {
+        while !bytes.is_empty() && !self.broken {
+            if self.bulk > 0 {
+                let take = self.bulk.min(bytes.len());
+                self.bulk -= take;
+                bytes = &bytes[take..];
+                if self.bulk == 0 {
+                    let kind = self.bulk_kind;
+                    self.complete(kind, &mut on_element);
+                }
+                continue;
+            }
+
+            let end = bytes.iter().position(|&b| b == b'\n');
+            let (line, rest) = bytes.split_at(end.map_or(bytes.len(), |end| end + 1));
+            let room = MAX_LINE_LEN.saturating_sub(self.line.len());
+            self.line.extend_from_slice(&line[..line.len().min(room)]);
+            bytes = rest;
+            if end.is_none() {
+                continue;
+            }
+
+            let line = std::mem::take(&mut self.line);
+            let Some((&kind, header)) = line.split_first() else {
+                continue;
+            };
+            let len = || {
+                let header = std::str::from_utf8(header).ok()?;
+                let len = header.trim_end().parse::<i64>().ok()?;
+                (-1..=MAX_LEN).contains(&len).then_some(len)
+            };
+            match kind {
+                // Bulk strings, bulk errors and verbatim strings, unless null.
+                b'$' | b'!' | b'=' => match len() {
+                    None => self.broken = true,
+                    Some(len) if len >= 0 => {
+                        self.bulk = len as usize + 2;
+                        self.bulk_kind = kind;
+                    }
+                    Some(_) => self.complete(kind, &mut on_element),
+                },
+
+                // Arrays, sets and pushes, and maps and attributes of pairs, unless empty.
+                b'*' | b'~' | b'>' | b'%' | b'|' => {
+                    let pairs = if matches!(kind, b'%' | b'|') { 2 } else { 1 };
+                    match len() {
+                        None => self.broken = true,
+                        Some(_) if self.nested.len() == MAX_DEPTH => self.broken = true,
+                        Some(len) if len > 0 => self.nested.push(len as u64 * pairs),
+                        Some(_) => self.complete(kind, &mut on_element),
+                    }
+                }
+
+                // Simple values, or an inline command.
+                b'+' | b'-' | b':' | b'_' | b'#' | b',' | b'(' => {
+                    self.complete(kind, &mut on_element)
+                }
+                _ if self.nested.is_empty() && !line.trim_ascii().is_empty() => {
+                    self.complete(0, &mut on_element)
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Completes an element, and the aggregates it completes.
+    fn complete(&mut self, kind: u8, on_element: &mut impl FnMut(u8, bool)) {
+        while let Some(remaining) = self.nested.last_mut() {
+            *remaining -= 1;
+            if *remaining > 0 {
+                on_element(kind, false);
+                return;
+            }
+            self.nested.pop();
+        }
+        on_element(kind, true);
+    }
+}
+
+/// Counts the commands, pipelined batches and error replies of a Redis connection.
+#[derive(Debug, Default)]
+pub struct Resp {
+    /// The commands from the client.
+    client: Values,
+
+    /// The replies from the server.
+    server: Values,
+
+    /// How many commands the client sent.
+    commands: u64,
+
+    /// How many commands the client sent since the server last replied.
+    batch: u64,
+
+    /// How many batches of more than one command the client sent before a reply came back.
+    pipelined: u64,
+
+    /// The most commands the client sent before a reply came back.
+    largest_batch: u64,
+
+    /// How many error replies the server sent, including those in arrays (e.g. of `EXEC`).
+    errors: u64,
+}
+
+impl Parser for Resp {
+    fn client(&mut self, bytes: &[u8]) {
+        let mut commands = 0;
+        self.client.feed(bytes, |_, done| {
+            if done {
+                commands += 1;
+            }
+        });
+        self.commands += commands;
+        self.batch += commands;
+    }
+
+    fn server(&mut self, bytes: &[u8]) {
+        // The commands sent until now were sent before this reply came back, as a batch.
+        if self.batch > 1 {
+            self.pipelined += 1;
+        }
+        self.largest_batch = self.largest_batch.max(self.batch);
+        self.batch = 0;
+
+        let mut errors = 0;
+        self.server.feed(bytes, |kind, _| {
+            if matches!(kind, b'-' | b'!') {
+                errors += 1;
+            }
+        });
+        self.errors += errors;
+    }
+
+    fn stats(&self) -> Stats {
+        let largest_batch = self.largest_batch.max(self.batch);
+        Stats {
+            protocol: Protocol::Redis,
+            counts: vec![
+                ("commands", self.commands),
+                ("pipelined batches", self.pipelined),
+                ("errors", self.errors),
+            ],
+            timings: Vec::new(),
+            details: match largest_batch > 1 {
+                true => vec![("largest batch", largest_batch.to_string())],
+                false => Vec::new(),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_commands() {
+        let mut resp = Resp::default();
+
+        // One command at a time, the first split across reads.
+        resp.client(b"*2\r\n$3\r\nGET\r\n$1");
+        resp.client(b"\r\na\r\n");
+        resp.server(b"$-1\r\n");
+        resp.client(b"PING\r\n");
+        resp.server(b"+PONG\r\n");
+
+        // A pipeline of three, one of which fails, then a transaction with a failed command.
+        resp.client(b"*3\r\n$3\r\nSET\r\n$1\r\na\r\n$1\r\n1\r\n*2\r\n$4\r\nINCR\r\n$1\r\na\r\n");
+        resp.client(b"*1\r\n$4\r\nEXEC\r\n");
+        resp.server(b"+OK\r\n-ERR value is not an integer\r\n");
+        resp.server(b"*2\r\n:1\r\n-ERR wrong kind\r\n");
+
+        assert_eq!(
+            resp.stats().to_string(),
+            "5 commands, 1 pipelined batches, 2 errors (largest batch 3)"
+        );
+    }
+
+    #[test]
+    fn gives_up_on_garbage() {
+        // Truncated values are waited for.
+        let mut resp = Resp::default();
+        resp.client(b"*2\r\n$3\r\nGE");
+        resp.server(b"$5\r\nab");
+        assert_eq!((resp.commands, resp.errors), (0, 0));
+
+        // Lengths that aren't numbers, or are too large or negative, end the parsing.
+        for garbage in [
+            &b"$\xff\xfe\r\n"[..],
+            b"$abc\r\n",
+            b"$99999999999999999999\r\n",
+            b"*9223372036854775807\r\n",
+            b"$-2\r\n",
+            b"%1000000000\r\n",
+        ] {
+            let mut resp = Resp::default();
+            resp.client(garbage);
+            resp.client(b"PING\r\n");
+            resp.server(garbage);
+            resp.server(b"-ERR\r\n");
+            assert!(resp.client.broken && resp.server.broken, "{:?}", garbage);
+            assert_eq!((resp.commands, resp.errors), (0, 0), "{:?}", garbage);
+        }
+
+        // As do aggregates nested too deep.
+        let mut resp = Resp::default();
+        resp.server(&b"*1\r\n".repeat(1000));
+        resp.server(b"-ERR\r\n");
+        assert!(resp.server.broken);
+        assert_eq!((resp.server.nested.len(), resp.errors), (MAX_DEPTH, 0));
+
+        // Inline commands that aren't UTF-8, or are longer than a line is kept, still count.
+        let mut resp = Resp::default();
+        resp.client(b"\xff\xfe \x1b\r\n");
+        resp.client(&b"x".repeat(3 * MAX_LINE_LEN));
+        resp.client(b"\r\n");
+        assert_eq!(resp.commands, 2);
+    }
+}
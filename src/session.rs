@@ -0,0 +1,395 @@
+use crate::net::DisplayAddr;
+use crate::output::eprint_line;
+use crate::record;
+use crate::reporter::Connection;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{mpsc, OnceLock};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+/// The first line of a session file, naming the format and its version.
+const MAGIC: &str = "sockgauge session 1";
+
+/// The extension of session files.
+pub const EXTENSION: &str = "session";
+
+/// The number of the last session recorded.
+static RECORDED: AtomicU64 = AtomicU64::new(0);
+
+/// Sends the sessions to the thread writing them, which is started on first use.
+static WRITER: OnceLock<mpsc::Sender<Message>> = OnceLock::new();
+
+/// Something that happened on a recorded connection, at an offset from when it was accepted.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Record {
+    /// The client sent these bytes.
+    Client(Duration, Vec<u8>),
+
+    /// The client was sent this many bytes. Only the size of responses is kept.
+    Server(Duration, u64),
+
+    /// The client closed its side of the connection.
+    ClientClosed(Duration),
+}
+
+/// A recorded connection, as read back from its file.
+#[derive(Debug)]
+pub struct Session {
+    /// The client's address.
+    pub peer: String,
+
+    /// When the connection was accepted.
+    pub started: SystemTime,
+
+    /// What happened on the connection, in order.
+    pub records: Vec<Record>,
+}
+
+impl Session {
+    /// Reads a session file.
+    pub fn read(path: &Path) -> Result<Session, Box<dyn Error>> {
+        let contents =
+            std::fs::read(path).map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
+        let invalid = || format!("{} is not a sockgauge session file", path.display());
+
+        // A few `key: value` lines, followed by an empty line and the records.
+        let end = contents
+            .windows(2)
+            .position(|window| window == b"\n\n")
+            .ok_or_else(invalid)?;
+        let header = std::str::from_utf8(&contents[..end]).map_err(|_| invalid())?;
+        let mut lines = header.lines();
+        if lines.next() != Some(MAGIC) {
+            return Err(invalid().into());
+        }
+
+        let mut session = Session {
+            peer: String::new(),
+            started: UNIX_EPOCH,
+            records: Vec::new(),
+        };
+        for line in lines {
+            match line.split_once(": ") {
+                Some(("peer", peer)) => session.peer = peer.to_string(),
+                Some(("started", started)) => {
+                    let seconds: f64 = started.parse().map_err(|_| invalid())?;
+                    session.started = Duration::try_from_secs_f64(seconds)
+                        .ok()
+                        .and_then(|since| UNIX_EPOCH.checked_add(since))
+                        .ok_or_else(invalid)?;
+                }
+                _ => {}
+            }
+        }
+
+        let mut rest = &contents[end + 2..];
+        while !rest.is_empty() {
+            let (record, size) = decode(rest).ok_or_else(invalid)?;
+            session.records.push(record);
+            rest = &rest[size..];
+        }
+
+        Ok(session)
+    }
+}
+
+/// Records the traffic of a connection while passing it through.
+pub struct Tap<S> {
+    /// The client's stream.
+    inner: Pin<Box<S>>,
+
+    /// The recording, unless the connection isn't recorded.
+    recording: Option<Recording>,
+}
+
+impl<S> Tap<S> {
+    /// Records the connection in a file in the directory, if there is one, otherwise just passes
+    /// the traffic through.
+    pub fn new(inner: S, dir: Option<&str>, connection: &Connection) -> Self {
+        Tap {
+            inner: Box::pin(inner),
+            recording: dir.map(|dir| Recording::start(dir, connection)),
+        }
+    }
+}
+
+impl<S: AsyncRead> AsyncRead for Tap<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let before = buf.filled().len();
+        let result = this.inner.as_mut().poll_read(cx, buf);
+        if let (Some(recording), Poll::Ready(Ok(()))) = (&mut this.recording, &result) {
+            let offset = recording.started.elapsed();
+            match &buf.filled()[before..] {
+                [] if !recording.client_closed => {
+                    recording.client_closed = true;
+                    recording.send(Record::ClientClosed(offset));
+                }
+                [] => {}
+                bytes => recording.send(Record::Client(offset, bytes.to_vec())),
+            }
+        }
+        result
+    }
+}
+
+impl<S: AsyncWrite> AsyncWrite for Tap<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let result = this.inner.as_mut().poll_write(cx, buf);
+        if let (Some(recording), Poll::Ready(Ok(written))) = (&this.recording, &result) {
+            let offset = recording.started.elapsed();
+            recording.send(Record::Server(offset, *written as u64));
+        }
+        result
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.get_mut().inner.as_mut().poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.get_mut().inner.as_mut().poll_shutdown(cx)
+    }
+}
+
+/// A connection being recorded.
+struct Recording {
+    /// Identifies the session to the writer.
+    number: u64,
+
+    /// When the connection was accepted, which the offsets of records are relative to.
+    started: Instant,
+
+    /// Whether the client closed its side, which is only recorded once.
+    client_closed: bool,
+}
+
+impl Recording {
+    /// Starts recording a connection in a file of its own in the directory.
+    fn start(dir: &str, connection: &Connection) -> Self {
+        let number = RECORDED.fetch_add(1, Ordering::Relaxed) + 1;
+        let now = SystemTime::now();
+        let path = Path::new(dir).join(format!(
+            "{}-{:06}.{}",
+            record::compact(now),
+            number,
+            EXTENSION
+        ));
+        let header = format!(
+            "{}\npeer: {}\nlocal: {}\nstarted: {:.6}\n\n",
+            MAGIC,
+            DisplayAddr(connection.peer),
+            DisplayAddr(connection.local),
+            now.duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs_f64()
+        );
+
+        let recording = Recording {
+            number,
+            started: Instant::now(),
+            client_closed: false,
+        };
+        recording.message(Message::Open(number, path, header.into_bytes()));
+        recording
+    }
+
+    /// Adds a record to the session.
+    fn send(&self, record: Record) {
+        self.message(Message::Write(self.number, encode(&record)));
+    }
+
+    /// Sends a message to the writer thread.
+    fn message(&self, message: Message) {
+        let writer = WRITER.get_or_init(|| {
+            let (sender, receiver) = mpsc::channel();
+            std::thread::spawn(move || write_sessions(receiver));
+            sender
+        });
+        let _ = writer.send(message);
+    }
+}
+
+impl Drop for Recording {
+    fn drop(&mut self) {
+        self.message(Message::Close(self.number));
+    }
+}
+
+/// What the writer thread is asked to do with a session file.
+enum Message {
+    /// Create the file, starting with the header.
+    Open(u64, PathBuf, Vec<u8>),
+
+    /// Append an encoded record.
+    Write(u64, Vec<u8>),
+
+    /// The connection is done, so close the file.
+    Close(u64),
+}
+
+/// Writes the session files, flushing them whenever there is nothing else to do so they're
+/// complete even if the process exits while the connections are open.
+fn write_sessions(receiver: mpsc::Receiver<Message>) {
+    let mut files: HashMap<u64, (PathBuf, BufWriter<File>)> = HashMap::new();
+    let mut unflushed = Vec::new();
+    loop {
+        let message = match receiver.try_recv() {
+            Ok(message) => message,
+            Err(mpsc::TryRecvError::Empty) => {
+                for number in unflushed.drain(..) {
+                    if let Some((path, file)) = files.get_mut(&number) {
+                        if let Err(err) = file.flush() {
+                            eprint_line!("💥️ — recording {} failed: {}", path.display(), err);
+                            files.remove(&number);
+                        }
+                    }
+                }
+                match receiver.recv() {
+                    Ok(message) => message,
+                    Err(_) => return,
+                }
+            }
+            Err(mpsc::TryRecvError::Disconnected) => return,
+        };
+
+        match message {
+            Message::Open(number, path, header) => {
+                let file = path
+                    .parent()
+                    .map_or(Ok(()), std::fs::create_dir_all)
+                    .and_then(|_| File::create(&path))
+                    .map(BufWriter::new)
+                    .and_then(|mut file| file.write_all(&header).map(|_| file));
+                match file {
+                    Ok(file) => {
+                        files.insert(number, (path, file));
+                        unflushed.push(number);
+                    }
+                    Err(err) => eprint_line!("💥️ — recording {} failed: {}", path.display(), err),
+                }
+            }
+            Message::Write(number, record) => {
+                if let Some((path, file)) = files.get_mut(&number) {
+                    match file.write_all(&record) {
+                        Ok(()) => unflushed.push(number),
+                        Err(err) => {
+                            eprint_line!("💥️ — recording {} failed: {}", path.display(), err);
+                            files.remove(&number);
+                        }
+                    }
+                }
+            }
+            Message::Close(number) => {
+                if let Some((path, mut file)) = files.remove(&number) {
+                    if let Err(err) = file.flush() {
+                        eprint_line!("💥️ — recording {} failed: {}", path.display(), err);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Encodes a record as a kind byte (`>` client data, `<` server data, `.` client closed),
+/// the offset in microseconds (8 bytes), a size (4 bytes) and, for client data, the bytes;
+/// big-endian.
+fn encode(record: &Record) -> Vec<u8> {
+    let (kind, offset, size, bytes) = match record {
+        Record::Client(offset, bytes) => (b'>', offset, bytes.len() as u64, &bytes[..]),
+        Record::Server(offset, size) => (b'<', offset, *size, &[][..]),
+        Record::ClientClosed(offset) => (b'.', offset, 0, &[][..]),
+    };
+
+    let mut encoded = Vec::with_capacity(13 + bytes.len());
+    encoded.push(kind);
+    encoded.extend_from_slice(&(offset.as_micros() as u64).to_be_bytes());
+    encoded.extend_from_slice(&(size as u32).to_be_bytes());
+    encoded.extend_from_slice(bytes);
+    encoded
+}
+
+/// Decodes the record at the start of the bytes, returning it and its encoded size.
+fn decode(bytes: &[u8]) -> Option<(Record, usize)> {
+    let kind = *bytes.first()?;
+    let offset = Duration::from_micros(u64::from_be_bytes(bytes.get(1..9)?.try_into().ok()?));
+    let size = u32::from_be_bytes(bytes.get(9..13)?.try_into().ok()?) as usize;
+    match kind {
+        b'>' => Some((
+            Record::Client(offset, bytes.get(13..13 + size)?.to_vec()),
+            13 + size,
+        )),
+        b'<' => Some((Record::Server(offset, size as u64), 13)),
+        b'.' => Some((Record::ClientClosed(offset), 13)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodes_records() {
+        let records = [
+            Record::Client(
+                Duration::from_micros(1500),
+                b"GET / HTTP/1.1\r\n\r\n".to_vec(),
+            ),
+            Record::Server(Duration::from_millis(20), 1024),
+            Record::ClientClosed(Duration::from_secs(3)),
+        ];
+        let encoded: Vec<u8> = records.iter().flat_map(encode).collect();
+
+        let mut rest = &encoded[..];
+        for record in &records {
+            let (decoded, size) = decode(rest).unwrap();
+            assert_eq!(&decoded, record);
+            rest = &rest[size..];
+        }
+        assert!(rest.is_empty());
+        assert_eq!(decode(b"?"), None);
+    }
+
+    #[test]
+    fn reads_the_start_time() {
+        let path = std::env::temp_dir().join(format!("sockgauge-session-{}", std::process::id()));
+        let read = |started: &str| {
+            let header = format!(
+                "{}
+peer: 10.0.0.1:5000
+started: {}
+
+",
+                MAGIC, started
+            );
+            std::fs::write(&path, header).unwrap();
+            Session::read(&path).map(|session| session.started)
+        };
+
+        assert_eq!(
+            read("1.5").unwrap(),
+            UNIX_EPOCH + Duration::from_millis(1500)
+        );
+        for started in ["-1", "NaN", "inf", "1e300", "soon"] {
+            assert!(read(started).is_err(), "{}", started);
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,139 @@
+use std::io;
+
+/// Signals that sockgauge reacts to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Signal {
+    /// Hand the listener over to a freshly started process (`SIGUSR2`).
+    Upgrade,
+
+    /// Reload the config file (`SIGHUP`).
+    Reload,
+
+    /// Print the summary and exit (`SIGINT`, e.g. Ctrl+C).
+    Interrupt,
+
+    /// Print the summary and exit (`SIGTERM`).
+    Terminate,
+}
+
+/// Receives the signals delivered to the process.
+pub struct Signals {
+    /// The read end of the self-pipe that the signal handler writes to.
+    #[cfg(unix)]
+    receiver: tokio::net::UnixStream,
+}
+
+/// Installs handlers for the given signals. Must only be called once.
+#[cfg(unix)]
+pub fn listen(signals: &[Signal]) -> io::Result<Signals> {
+    use std::os::unix::io::IntoRawFd;
+    use std::os::unix::net::UnixStream;
+    use std::sync::atomic::Ordering;
+
+    // The handler may only do async-signal-safe work, so it just writes the signal number
+    // into a socket pair which is read from the runtime.
+    let (sender, receiver) = UnixStream::pair()?;
+    sender.set_nonblocking(true)?;
+    receiver.set_nonblocking(true)?;
+    unix::PIPE_WRITE_FD.store(sender.into_raw_fd(), Ordering::SeqCst);
+
+    for signal in signals {
+        unix::install(signal.number())?;
+    }
+
+    Ok(Signals {
+        receiver: tokio::net::UnixStream::from_std(receiver)?,
+    })
+}
+
+/// Signals are not supported on this platform, so nothing will ever be received.
+#[cfg(not(unix))]
+pub fn listen(_signals: &[Signal]) -> io::Result<Signals> {
+    Ok(Signals {})
+}
+
+impl Signals {
+    /// Waits for the next signal.
+    #[cfg(unix)]
+    pub async fn recv(&mut self) -> Option<Signal> {
+        use tokio::io::AsyncReadExt;
+
+        loop {
+            let number = self.receiver.read_u8().await.ok()?;
+            if let Some(signal) = Signal::from_number(number as libc::c_int) {
+                return Some(signal);
+            }
+        }
+    }
+
+    /// Waits for the next signal, which never arrives on this platform.
+    #[cfg(not(unix))]
+    pub async fn recv(&mut self) -> Option<Signal> {
+        std::future::pending().await
+    }
+}
+
+#[cfg(unix)]
+impl Signal {
+    /// All signals, used for mapping signal numbers back.
+    const ALL: [Signal; 4] = [
+        Signal::Upgrade,
+        Signal::Reload,
+        Signal::Interrupt,
+        Signal::Terminate,
+    ];
+
+    /// The signal number.
+    fn number(self) -> libc::c_int {
+        match self {
+            Signal::Upgrade => libc::SIGUSR2,
+            Signal::Reload => libc::SIGHUP,
+            Signal::Interrupt => libc::SIGINT,
+            Signal::Terminate => libc::SIGTERM,
+        }
+    }
+
+    /// Maps a signal number back to a `Signal`.
+    fn from_number(number: libc::c_int) -> Option<Signal> {
+        Self::ALL.into_iter().find(|s| s.number() == number)
+    }
+}
+
+#[cfg(unix)]
+mod unix {
+    use std::io;
+    use std::sync::atomic::{AtomicI32, Ordering};
+
+    /// The write end of the self-pipe, used by the signal handler.
+    pub static PIPE_WRITE_FD: AtomicI32 = AtomicI32::new(-1);
+
+    /// Installs the handler for the given signal number.
+    pub fn install(number: libc::c_int) -> io::Result<()> {
+        // SAFETY: the action is fully initialized and the handler is async-signal-safe.
+        let result = unsafe {
+            let mut action: libc::sigaction = std::mem::zeroed();
+            action.sa_sigaction = handle as extern "C" fn(libc::c_int) as libc::sighandler_t;
+            action.sa_flags = libc::SA_RESTART;
+            libc::sigemptyset(&mut action.sa_mask);
+            libc::sigaction(number, &action, std::ptr::null_mut())
+        };
+
+        if result != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(())
+    }
+
+    /// The signal handler; forwards the signal number to the self-pipe.
+    extern "C" fn handle(number: libc::c_int) {
+        let fd = PIPE_WRITE_FD.load(Ordering::Relaxed);
+        let byte = number as u8;
+
+        // SAFETY: `write` is async-signal-safe. If the pipe is full the signal is dropped,
+        // which is fine since there is already a pending one to process.
+        unsafe {
+            libc::write(fd, &byte as *const u8 as *const libc::c_void, 1);
+        }
+    }
+}
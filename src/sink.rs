@@ -0,0 +1,291 @@
+use crate::record;
+use std::fmt::{Display, Formatter};
+use std::io;
+use std::net::UdpSocket;
+use std::time::SystemTime;
+
+#[cfg(unix)]
+use std::os::unix::net::UnixDatagram;
+
+/// The syslog socket of the local machine.
+const SYSLOG_SOCKET: &str = "/dev/log";
+
+/// The socket journald accepts native messages on.
+const JOURNALD_SOCKET: &str = "/run/systemd/journal/socket";
+
+/// The syslog facility sockgauge logs as, `daemon`.
+const FACILITY: u8 = 3;
+
+/// The kinds of events, by the symbol their lines start with, to tell them apart in the logs.
+const EVENTS: [(&str, &str); 31] = [
+    ("🟢", "opened"),
+    ("🔴", "closed"),
+    ("🫧", "coalesced"),
+    ("📊", "summary"),
+    ("🚨", "alert"),
+    ("🏎️", "race"),
+    ("⏳", "queued"),
+    ("⛔", "rejected"),
+    ("🚫", "ban"),
+    ("🔓", "unban"),
+    ("🚦", "paced"),
+    ("🚱", "port-exhaustion"),
+    ("🥱", "first-byte-timeout"),
+    ("🐌", "stall"),
+    ("🔒", "tls-upgrade"),
+    ("🧮", "protocol"),
+    ("🚀", "fast-open"),
+    ("🔀", "multipath"),
+    ("💔", "backend-down"),
+    ("💚", "backend-up"),
+    ("⏏️", "ejection"),
+    ("🌩️", "storm"),
+    ("🌤️", "storm-over"),
+    ("🩺", "self-metrics"),
+    ("🔄", "reload"),
+    ("⚡️", "started"),
+    ("🏁", "exiting"),
+    ("📏", "comparison"),
+    ("📉", "regression"),
+    ("💥", "error"),
+    ("⚠️", "warning"),
+];
+
+/// Where the output lines go.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum LogTarget {
+    /// To stdout, and stderr for warnings and errors.
+    #[default]
+    Stdout,
+
+    /// To syslog as RFC 5424 messages, over a Unix socket (a path) or UDP (`host:port`).
+    Syslog(String),
+
+    /// To journald, natively, with the kind of event in a field of its own.
+    Journald,
+}
+
+impl LogTarget {
+    /// Parses `stdout`, `syslog` (on `/dev/log`), `syslog:/path/to/socket`, `syslog:host:port`
+    /// or `journald`.
+    pub fn parse(value: &str) -> Result<LogTarget, String> {
+        match value {
+            "stdout" => Ok(LogTarget::Stdout),
+            "syslog" => Ok(LogTarget::Syslog(SYSLOG_SOCKET.to_string())),
+            "journald" => Ok(LogTarget::Journald),
+            _ => match value.strip_prefix("syslog:") {
+                Some(addr) if !addr.is_empty() => Ok(LogTarget::Syslog(addr.to_string())),
+                _ => Err(format!(
+                    "Expected `stdout`, `syslog`, `syslog:<socket path or host:port>` or `journald` as the log target, got `{}`",
+                    value
+                )),
+            },
+        }
+    }
+
+    /// Connects to the target, unless it's stdout.
+    pub fn connect(&self) -> io::Result<Option<Sink>> {
+        let socket = match self {
+            LogTarget::Stdout => return Ok(None),
+            LogTarget::Syslog(addr) if !addr.starts_with('/') => {
+                let socket = UdpSocket::bind(match addr.starts_with('[') {
+                    true => "[::]:0",
+                    false => "0.0.0.0:0",
+                })?;
+                socket.connect(addr)?;
+                Socket::Udp(socket)
+            }
+            LogTarget::Syslog(path) => unix(path)?,
+            LogTarget::Journald => unix(JOURNALD_SOCKET)?,
+        };
+
+        Ok(Some(Sink {
+            socket,
+            journald: *self == LogTarget::Journald,
+            hostname: hostname(),
+            pid: std::process::id(),
+        }))
+    }
+}
+
+impl Display for LogTarget {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LogTarget::Stdout => write!(f, "stdout"),
+            LogTarget::Syslog(addr) if addr == SYSLOG_SOCKET => write!(f, "syslog"),
+            LogTarget::Syslog(addr) => write!(f, "syslog:{}", addr),
+            LogTarget::Journald => write!(f, "journald"),
+        }
+    }
+}
+
+/// A connected log target.
+pub struct Sink {
+    /// The socket to send messages on.
+    socket: Socket,
+
+    /// Whether to send journald's native messages rather than syslog ones.
+    journald: bool,
+
+    /// The name of this machine, for syslog messages.
+    hostname: String,
+
+    /// The ID of this process.
+    pid: u32,
+}
+
+/// A socket sending datagrams.
+enum Socket {
+    Udp(UdpSocket),
+    #[cfg(unix)]
+    Unix(UnixDatagram),
+}
+
+impl Sink {
+    /// Sends a line, as a warning if it went to stderr and as information otherwise.
+    pub fn send(&self, line: &str, warning: bool) -> io::Result<()> {
+        // The severities of syslog, which journald uses too.
+        let severity = match warning {
+            true => 4,
+            false => 6,
+        };
+        let event = event(line);
+
+        let message = match self.journald {
+            true => journald_message(line, severity, event, self.pid),
+            false => syslog_message(line, severity, event, &self.hostname, self.pid),
+        };
+        match &self.socket {
+            Socket::Udp(socket) => socket.send(&message)?,
+            #[cfg(unix)]
+            Socket::Unix(socket) => socket.send(&message)?,
+        };
+        Ok(())
+    }
+}
+
+/// The kind of event a line is about, if it's one of `EVENTS`.
+pub fn event(line: &str) -> Option<&'static str> {
+    EVENTS
+        .iter()
+        .find(|(symbol, _)| line.starts_with(symbol))
+        .map(|(_, event)| *event)
+}
+
+/// Formats an RFC 5424 syslog message, with the kind of event as the message ID.
+fn syslog_message(
+    line: &str,
+    severity: u8,
+    event: Option<&str>,
+    hostname: &str,
+    pid: u32,
+) -> Vec<u8> {
+    format!(
+        "<{}>1 {} {} sockgauge {} {} - {}",
+        FACILITY * 8 + severity,
+        record::rfc3339(SystemTime::now()),
+        hostname,
+        pid,
+        event.unwrap_or("-"),
+        line
+    )
+    .into_bytes()
+}
+
+/// Formats a journald native message, with the kind of event in `SOCKGAUGE_EVENT`.
+fn journald_message(line: &str, severity: u8, event: Option<&str>, pid: u32) -> Vec<u8> {
+    let mut message = Vec::new();
+    let mut field = |name: &str, value: &str| {
+        message.extend_from_slice(name.as_bytes());
+        // Values spanning lines, like the summary, are sent with their length instead.
+        match value.contains('\n') {
+            true => {
+                message.push(b'\n');
+                message.extend_from_slice(&(value.len() as u64).to_le_bytes());
+            }
+            false => message.push(b'='),
+        }
+        message.extend_from_slice(value.as_bytes());
+        message.push(b'\n');
+    };
+
+    field("MESSAGE", line);
+    field("PRIORITY", &severity.to_string());
+    field("SYSLOG_FACILITY", &FACILITY.to_string());
+    field("SYSLOG_IDENTIFIER", "sockgauge");
+    field("SYSLOG_PID", &pid.to_string());
+    if let Some(event) = event {
+        field("SOCKGAUGE_EVENT", event);
+    }
+    message
+}
+
+/// Connects to a Unix datagram socket.
+#[cfg(unix)]
+fn unix(path: &str) -> io::Result<Socket> {
+    let socket = UnixDatagram::unbound()?;
+    socket.connect(path)?;
+    Ok(Socket::Unix(socket))
+}
+
+/// Unix sockets are only supported on Unix.
+#[cfg(not(unix))]
+fn unix(_path: &str) -> io::Result<Socket> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "logging to a Unix socket is only supported on Unix",
+    ))
+}
+
+/// The name of this machine, or `-` (syslog's nil value) if it can't be told.
+#[cfg(unix)]
+pub fn hostname() -> String {
+    let mut name = [0u8; 256];
+    // SAFETY: `name` is writable for its whole length.
+    if unsafe { libc::gethostname(name.as_mut_ptr().cast(), name.len()) } != 0 {
+        return "-".to_string();
+    }
+
+    let length = name.iter().position(|&b| b == 0).unwrap_or(name.len());
+    match std::str::from_utf8(&name[..length]) {
+        Ok(name) if !name.is_empty() => name.to_string(),
+        _ => "-".to_string(),
+    }
+}
+
+/// The name of this machine isn't looked up on this platform.
+#[cfg(not(unix))]
+pub fn hostname() -> String {
+    "-".to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_messages() {
+        assert_eq!(
+            LogTarget::parse("syslog").unwrap(),
+            LogTarget::Syslog("/dev/log".to_string())
+        );
+        assert_eq!(
+            LogTarget::parse("syslog:logs:514").unwrap().to_string(),
+            "syslog:logs:514"
+        );
+        assert!(LogTarget::parse("syslog:").is_err());
+
+        let message = syslog_message("🟢 1 — new connection", 6, Some("opened"), "box", 42);
+        let message = String::from_utf8(message).unwrap();
+        assert!(message.starts_with("<30>1 "));
+        assert!(message.ends_with(" box sockgauge 42 opened - 🟢 1 — new connection"));
+
+        let message = journald_message("📊 summary\n   more", 6, Some("summary"), 42);
+        let mut expected = b"MESSAGE\n".to_vec();
+        expected.extend_from_slice(&20u64.to_le_bytes());
+        expected.extend_from_slice("📊 summary\n   more\n".as_bytes());
+        expected.extend_from_slice(b"PRIORITY=6\nSYSLOG_FACILITY=3\nSYSLOG_IDENTIFIER=sockgauge\n");
+        expected.extend_from_slice(b"SYSLOG_PID=42\nSOCKGAUGE_EVENT=summary\n");
+        assert_eq!(message, expected);
+    }
+}
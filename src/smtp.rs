@@ -0,0 +1,381 @@
+use crate::format;
+use crate::protocol::{Parser, Protocol, Stats};
+use std::collections::{BTreeMap, VecDeque};
+
+/// The longest line to keep, enough for any command or reply (RFC 5321 allows 512 bytes);
+/// lines of a message can be longer, but only whether they end it matters.
+const MAX_LINE_LEN: usize = 1024;
+
+/// How many commands may wait for their replies before the oldest are forgotten.
+const MAX_PENDING: usize = 1000;
+
+/// The reply codes counted one by one, by what they're counted as; others are counted together.
+const CODES: [(u16, &str); 29] = [
+    (211, "211 replies"),
+    (214, "214 replies"),
+    (220, "220 replies"),
+    (221, "221 replies"),
+    (235, "235 replies"),
+    (250, "250 replies"),
+    (251, "251 replies"),
+    (252, "252 replies"),
+    (334, "334 replies"),
+    (354, "354 replies"),
+    (421, "421 replies"),
+    (432, "432 replies"),
+    (450, "450 replies"),
+    (451, "451 replies"),
+    (452, "452 replies"),
+    (454, "454 replies"),
+    (455, "455 replies"),
+    (500, "500 replies"),
+    (501, "501 replies"),
+    (502, "502 replies"),
+    (503, "503 replies"),
+    (504, "504 replies"),
+    (530, "530 replies"),
+    (535, "535 replies"),
+    (550, "550 replies"),
+    (551, "551 replies"),
+    (552, "552 replies"),
+    (553, "553 replies"),
+    (554, "554 replies"),
+];
+
+/// Splits one direction into lines as the bytes are read.
+#[derive(Debug, Default)]
+struct Lines {
+    /// The line being read, up to `MAX_LINE_LEN` bytes.
+    line: Vec<u8>,
+}
+
+impl Lines {
+    /// Reads at most one line from the front of `bytes`, returning how many bytes it used and
+    /// the line, with its line ending, if it's complete.
+    fn next(&mut self, bytes: &[u8]) -> (usize, Option<Vec<u8>>) {
+        let end = bytes.iter().position(|&b| b == b'\n');
+        let used = end.map_or(bytes.len(), |end| end + 1);
+        let room = MAX_LINE_LEN.saturating_sub(self.line.len());
+        self.line.extend_from_slice(&bytes[..used.min(room)]);
+        match end {
+            Some(_) => (used, Some(std::mem::take(&mut self.line))),
+            None => (used, None),
+        }
+    }
+}
+
+/// A command waiting for its reply, as far as the reply matters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Command {
+    /// `DATA`, after which the message follows if the server agrees.
+    Data,
+
+    /// The end of a message, after `DATA` or with `BDAT ... LAST`.
+    Message,
+
+    /// `STARTTLS`, after which the connection is encrypted if the server agrees.
+    StartTls,
+
+    /// Any other command.
+    Other,
+}
+
+/// What the client is sending.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+enum State {
+    /// Commands.
+    #[default]
+    Commands,
+
+    /// The lines of a message, up to a line with a single dot.
+    Message,
+
+    /// The bytes of a `BDAT` chunk, by how many are still to come.
+    Chunk(u64),
+
+    /// Encrypted, or not SMTP, so nothing more is counted.
+    Done,
+}
+
+/// Counts the mail transactions of an SMTP connection, and the codes the server replied with.
+#[derive(Debug, Default)]
+pub struct Smtp {
+    /// What the client is sending.
+    state: State,
+
+    /// The lines from the client.
+    client: Lines,
+
+    /// The lines from the server.
+    server: Lines,
+
+    /// The commands waiting for their replies, oldest first, as clients may pipeline them.
+    pending: VecDeque<Command>,
+
+    /// How many transactions the client started (`MAIL` commands).
+    transactions: u64,
+
+    /// How many recipients the client gave (`RCPT` commands).
+    recipients: u64,
+
+    /// How many messages the client sent (with `DATA` or `BDAT`).
+    messages: u64,
+
+    /// How many messages the server accepted for delivery.
+    accepted: u64,
+
+    /// How many replies the server sent, by code.
+    replies: BTreeMap<u16, u64>,
+
+    /// The name the client introduced itself with (`EHLO` or `HELO`).
+    hello: Option<String>,
+
+    /// How the server answered `STARTTLS`, if it was asked.
+    starttls: Option<&'static str>,
+}
+
+impl Smtp {
+    /// Notes that the client sent a command, or the end of a message, to wait for its reply.
+    fn sent(&mut self, command: Command) {
+        if self.pending.len() == MAX_PENDING {
+            self.pending.pop_front();
+        }
+        self.pending.push_back(command);
+    }
+
+    /// Reads a command line.
+    fn command(&mut self, line: &[u8]) {
+        let line = format::sanitize(line.trim_ascii_end());
+        let mut words = line.split_ascii_whitespace();
+        let verb = words.next().unwrap_or_default().to_ascii_uppercase();
+        let command = match verb.as_str() {
+            "MAIL" => {
+                self.transactions += 1;
+                Command::Other
+            }
+            "RCPT" => {
+                self.recipients += 1;
+                Command::Other
+            }
+            "DATA" => Command::Data,
+            "BDAT" => {
+                let size = words.next().and_then(|size| size.parse().ok());
+                let last = words
+                    .next()
+                    .is_some_and(|word| word.eq_ignore_ascii_case("LAST"));
+                self.state = State::Chunk(size.unwrap_or_default());
+                if last {
+                    self.messages += 1;
+                    Command::Message
+                } else {
+                    Command::Other
+                }
+            }
+            "EHLO" | "HELO" => {
+                self.hello = words.next().map(str::to_string);
+                Command::Other
+            }
+            "STARTTLS" => Command::StartTls,
+            _ => Command::Other,
+        };
+        self.sent(command);
+    }
+
+    /// Reads the last line of a reply, to the oldest command waiting for one (or the greeting).
+    fn reply(&mut self, code: u16) {
+        *self.replies.entry(code).or_default() += 1;
+        let positive = (200..400).contains(&code);
+        match self.pending.pop_front() {
+            Some(Command::Data) if code == 354 => self.state = State::Message,
+            Some(Command::Message) if positive => self.accepted += 1,
+            Some(Command::StartTls) => {
+                self.starttls = Some(if positive { "accepted" } else { "declined" });
+                if positive {
+                    self.state = State::Done;
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Parser for Smtp {
+    fn client(&mut self, mut bytes: &[u8]) {
+        while !bytes.is_empty() {
+            match self.state {
+                State::Commands => {
+                    let (used, line) = self.client.next(bytes);
+                    bytes = &bytes[used..];
+                    if let Some(line) = line {
+                        self.command(&line);
+                    }
+                }
+                State::Message => {
+                    let (used, line) = self.client.next(bytes);
+                    bytes = &bytes[used..];
+                    if line.is_some_and(|line| line.trim_ascii_end() == b".") {
+                        self.messages += 1;
+                        self.sent(Command::Message);
+                        self.state = State::Commands;
+                    }
+                }
+                State::Chunk(remaining) => {
+                    let take = remaining.min(bytes.len() as u64);
+                    bytes = &bytes[take as usize..];
+                    self.state = match remaining - take {
+                        0 => State::Commands,
+                        remaining => State::Chunk(remaining),
+                    };
+                }
+                State::Done => return,
+            }
+        }
+    }
+
+    fn server(&mut self, mut bytes: &[u8]) {
+        while !bytes.is_empty() && self.state != State::Done {
+            let (used, line) = self.server.next(bytes);
+            bytes = &bytes[used..];
+
+            // Every line is part of a reply, starting with a code from 200 to 599. Multiline
+            // replies have a `-` after the code on all but their last line.
+            let Some(line) = line else {
+                continue;
+            };
+            let code = match line.as_slice() {
+                [first @ b'2'..=b'5', second, third, ..]
+                    if second.is_ascii_digit() && third.is_ascii_digit() =>
+                {
+                    (first - b'0') as u16 * 100
+                        + (second - b'0') as u16 * 10
+                        + (third - b'0') as u16
+                }
+                _ => {
+                    self.state = State::Done;
+                    return;
+                }
+            };
+            match line.get(3) {
+                Some(b'-') => {}
+                None | Some(b' ' | b'\r' | b'\n') => self.reply(code),
+                Some(_) => self.state = State::Done,
+            }
+        }
+    }
+
+    fn stats(&self) -> Stats {
+        let mut counts = vec![
+            ("transactions", self.transactions),
+            ("recipients", self.recipients),
+            ("messages", self.messages),
+            ("accepted messages", self.accepted),
+        ];
+        let mut other = 0;
+        for (code, count) in &self.replies {
+            match CODES.iter().find(|(known, _)| known == code) {
+                Some((_, name)) => counts.push((name, *count)),
+                None => other += count,
+            }
+        }
+        if other > 0 {
+            counts.push(("other replies", other));
+        }
+
+        let mut details = Vec::new();
+        if let Some(hello) = &self.hello {
+            details.push(("hello", hello.clone()));
+        }
+        if let Some(outcome) = self.starttls {
+            details.push(("STARTTLS", outcome.to_string()));
+        }
+
+        Stats {
+            protocol: Protocol::Smtp,
+            counts,
+            timings: Vec::new(),
+            details,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_transactions() {
+        let mut smtp = Smtp::default();
+        smtp.server(b"220 mx.example.com ESMTP\r\n");
+        smtp.client(b"EHLO client.example.com\r\n");
+        smtp.server(b"250-mx.example.com\r\n250-PIPELINING\r\n250 CHUNKING\r\n");
+
+        // A pipelined transaction with a rejected recipient, the message split across reads.
+        smtp.client(b"MAIL FROM:<a@example.com>\r\nRCPT TO:<b@example.com>\r\n");
+        smtp.client(b"RCPT TO:<nobody@example.com>\r\nDATA\r\n");
+        smtp.server(b"250 OK\r\n250 OK\r\n550 No such user\r\n354 Go ahead\r\n");
+        smtp.client(b"Subject: hi\r\n\r\n..not the end\r\n.");
+        smtp.client(b"\r\n");
+        smtp.server(b"250 Queued\r\n");
+
+        // A chunked message that's refused, with a chunk that looks like the end of one.
+        smtp.client(b"MAIL FROM:<a@example.com>\r\nRCPT TO:<b@example.com>\r\n");
+        smtp.client(b"BDAT 3\r\n.\r\nBDAT 0 LAST\r\n");
+        smtp.server(b"250 OK\r\n250 OK\r\n250 3 bytes\r\n552 Too big\r\n");
+
+        // Once STARTTLS is accepted, nothing more can be seen.
+        smtp.client(b"STARTTLS\r\n");
+        smtp.server(b"220 Ready\r\n");
+        smtp.client(&[0x16, 0x03, 0x01, 0x00, 0x05]);
+        smtp.server(b"999 Not a reply\r\n");
+
+        assert_eq!(
+            smtp.stats().to_string(),
+            "2 transactions, 3 recipients, 2 messages, 1 accepted messages, 2 220 replies, \
+             7 250 replies, 1 354 replies, 1 550 replies, 1 552 replies \
+             (hello client.example.com, STARTTLS accepted)"
+        );
+    }
+
+    #[test]
+    fn gives_up_on_garbage() {
+        // Truncated lines are waited for, and names that aren't UTF-8 are shown sanitized.
+        let mut smtp = Smtp::default();
+        smtp.server(b"22");
+        smtp.server(b"0 mx\r\n");
+        smtp.client(b"EHLO \xff\x1b[2J\r\nMAIL FROM:<a@exam");
+        smtp.server(b"250 mx\r\n25");
+        assert_eq!(
+            smtp.stats().to_string(),
+            "0 transactions, 0 recipients, 0 messages, 0 accepted messages, 1 220 replies, \
+             1 250 replies (hello \\xff\\x1b[2J)"
+        );
+
+        // Lines longer than are kept, and chunks larger than any message, are passed over.
+        let mut smtp = Smtp::default();
+        smtp.client(&b"NOOP ".repeat(1000));
+        smtp.client(b"\r\nMAIL FROM:<>\r\nBDAT 18446744073709551615 LAST\r\n");
+        smtp.client(&b"RCPT TO:<b@example.com>\r\n".repeat(100));
+        assert_eq!(
+            (smtp.transactions, smtp.recipients, smtp.messages),
+            (1, 0, 1)
+        );
+        assert_eq!(smtp.state, State::Chunk(18446744073709551615 - 2500));
+
+        // A server that doesn't send replies ends the parsing.
+        for garbage in [
+            &b"SSH-2.0-OpenSSH_9.6\r\n"[..],
+            b"999 Not a reply\r\n",
+            b"+12 Not a reply\r\n",
+            b"220Not a reply\r\n",
+            b"\xff\xfe\xfd\r\n",
+            b"\r\n",
+        ] {
+            let mut smtp = Smtp::default();
+            smtp.server(garbage);
+            smtp.server(b"220 mx\r\n");
+            smtp.client(b"MAIL FROM:<a@example.com>\r\n");
+            assert_eq!(smtp.state, State::Done, "{:?}", garbage);
+            assert_eq!((smtp.transactions, smtp.replies.len()), (0, 0));
+        }
+    }
+}
@@ -0,0 +1,84 @@
+use std::collections::VecDeque;
+
+/// How many seconds of history are kept.
+const SECONDS: usize = 60;
+
+/// How many seconds each character of a sparkline stands for.
+const SECONDS_PER_BAR: usize = 2;
+
+/// The bars of a sparkline, lowest first.
+const BARS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+/// Per-second counts of something over the last minute, e.g. connections opened, drawn as a
+/// sparkline.
+#[derive(Debug, Default)]
+pub struct History {
+    /// The counts, one per second, oldest first.
+    counts: VecDeque<u64>,
+
+    /// The running total when the last second was counted.
+    last_total: u64,
+}
+
+impl History {
+    /// Counts a second, given the running total at its end.
+    pub fn record(&mut self, total: u64) {
+        if self.counts.len() == SECONDS {
+            self.counts.pop_front();
+        }
+        self.counts.push_back(total.saturating_sub(self.last_total));
+        self.last_total = total;
+    }
+
+    /// The counts, one per second, oldest first.
+    pub fn counts(&self) -> impl Iterator<Item = u64> + '_ {
+        self.counts.iter().copied()
+    }
+
+    /// The count of the last second.
+    pub fn now(&self) -> u64 {
+        self.counts.back().copied().unwrap_or_default()
+    }
+
+    /// The highest count of a second in the last minute.
+    pub fn peak(&self) -> u64 {
+        self.counts.iter().copied().max().unwrap_or_default()
+    }
+
+    /// Draws the counts as a sparkline, each bar adding up a few seconds, the highest bar
+    /// standing for the highest sum.
+    pub fn sparkline(&self) -> String {
+        let counts = self.counts.iter().copied().collect::<Vec<_>>();
+        let sums: Vec<u64> = counts
+            .chunks(SECONDS_PER_BAR)
+            .map(|chunk| chunk.iter().sum())
+            .collect();
+        let max = sums.iter().copied().max().unwrap_or_default().max(1);
+        sums.iter()
+            .map(|sum| BARS[(sum * (BARS.len() as u64 - 1)).div_ceil(max) as usize])
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn draws_sparklines() {
+        let mut history = History::default();
+        for total in [0, 0, 1, 3, 10, 24, 24, 24] {
+            history.record(total);
+        }
+        assert_eq!(history.sparkline(), "▁▂█▁");
+        assert_eq!((history.now(), history.peak()), (0, 14));
+
+        for total in 0..100 {
+            history.record(24 + total);
+        }
+        assert_eq!(
+            history.sparkline().chars().count(),
+            SECONDS / SECONDS_PER_BAR
+        );
+    }
+}
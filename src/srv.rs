@@ -0,0 +1,427 @@
+use crate::net;
+use crate::output::print_line;
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::hash::{BuildHasher, Hasher};
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+use tokio::net::{TcpStream, UdpSocket};
+
+/// Prefix of SRV destinations, e.g. `srv://_myservice._tcp.example.com`.
+pub const PREFIX: &str = "srv://";
+
+/// How long to wait for a nameserver to answer.
+const QUERY_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Bounds for how long resolved records are used before resolving again.
+const MIN_TTL: Duration = Duration::from_secs(5);
+const MAX_TTL: Duration = Duration::from_secs(300);
+
+/// The DNS record type of SRV records.
+const TYPE_SRV: u16 = 33;
+
+/// A resolved SRV record.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct SrvRecord {
+    /// Lower priorities are tried first.
+    priority: u16,
+
+    /// Relative weight among records of the same priority.
+    weight: u16,
+
+    /// The port to connect to.
+    port: u16,
+
+    /// The host to connect to.
+    target: String,
+}
+
+/// Records resolved earlier, kept until they expire.
+struct Cached {
+    /// The resolved records.
+    records: Vec<SrvRecord>,
+
+    /// When to resolve again.
+    expires_at: Instant,
+}
+
+/// Resolved records, by name.
+static CACHE: OnceLock<Mutex<HashMap<String, Cached>>> = OnceLock::new();
+
+/// Checks that the SRV destination has a usable name.
+pub fn validate(name: &str) -> Result<(), String> {
+    let valid = !name.is_empty()
+        && name
+            .trim_end_matches('.')
+            .split('.')
+            .all(|label| !label.is_empty() && label.len() <= 63 && !label.contains(':'));
+    match valid {
+        true => Ok(()),
+        false => Err(format!("`{}{}` is not a valid SRV name", PREFIX, name)),
+    }
+}
+
+/// Connects to the targets of the SRV name in priority and weight order, returning the first
+/// connection that succeeds.
+pub async fn connect(name: &str) -> Result<TcpStream, io::Error> {
+    let mut last_err = None;
+    for record in ordered(lookup(name).await?) {
+        match net::connect((record.target.as_str(), record.port)).await {
+            Ok(stream) => return Ok(stream),
+            Err(err) => last_err = Some(err),
+        }
+    }
+
+    Err(last_err.unwrap_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("{} has no SRV records", name),
+        )
+    }))
+}
+
+/// Returns the records for the name, resolving them when they aren't cached (anymore).
+async fn lookup(name: &str) -> Result<Vec<SrvRecord>, io::Error> {
+    let cache = CACHE.get_or_init(Mutex::default);
+    if let Some(cached) = cache.lock().unwrap().get(name) {
+        if cached.expires_at > Instant::now() {
+            return Ok(cached.records.clone());
+        }
+    }
+
+    let (records, ttl) = resolve(name).await?;
+    let ttl = Duration::from_secs(ttl.into()).clamp(MIN_TTL, MAX_TTL);
+
+    // Let the user know when the backends move.
+    let previous = cache.lock().unwrap().insert(
+        name.to_string(),
+        Cached {
+            records: records.clone(),
+            expires_at: Instant::now() + ttl,
+        },
+    );
+    if previous.map(|p| p.records) != Some(records.clone()) {
+        let targets: Vec<String> = records
+            .iter()
+            .map(|r| {
+                format!(
+                    "{}:{} (priority {}, weight {})",
+                    r.target, r.port, r.priority, r.weight
+                )
+            })
+            .collect();
+        print_line!("🔎 {}{} resolved to {}", PREFIX, name, targets.join(", "));
+    }
+
+    Ok(records)
+}
+
+/// Orders the records as described in RFC 2782: by ascending priority and, within the same
+/// priority, randomly with the chance of coming first proportional to the weight. Records with
+/// a weight of 0 start out first in the list, as the RFC has it, but the running sum never
+/// passes the pick on them, so they only come after the others, in random order.
+fn ordered(mut records: Vec<SrvRecord>) -> Vec<SrvRecord> {
+    records.sort_by_key(|r| r.priority);
+
+    let mut ordered = Vec::with_capacity(records.len());
+    while !records.is_empty() {
+        let priority = records[0].priority;
+        let group_len = records
+            .iter()
+            .take_while(|r| r.priority == priority)
+            .count();
+        let mut group: Vec<SrvRecord> = records.drain(..group_len).collect();
+        group.sort_by_key(|r| r.weight != 0);
+
+        while !group.is_empty() {
+            let total: u64 = group.iter().map(|r| u64::from(r.weight)).sum();
+            let index = match total {
+                0 => (random() % group.len() as u64) as usize,
+                _ => {
+                    let pick = random() % total;
+                    let mut sum = 0;
+                    group
+                        .iter()
+                        .position(|r| {
+                            sum += u64::from(r.weight);
+                            sum > pick
+                        })
+                        .unwrap_or(0)
+                }
+            };
+            ordered.push(group.remove(index));
+        }
+    }
+
+    ordered
+}
+
+/// Queries the system's nameservers for the SRV records of the name.
+/// Returns the records and the lowest TTL among them.
+async fn resolve(name: &str) -> Result<(Vec<SrvRecord>, u32), io::Error> {
+    let id = random() as u16;
+    let query = encode_query(id, name)?;
+
+    let mut last_err = None;
+    for nameserver in nameservers() {
+        let result = tokio::time::timeout(QUERY_TIMEOUT, query_nameserver(nameserver, &query))
+            .await
+            .unwrap_or_else(|_| {
+                Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    "DNS query timed out",
+                ))
+            });
+
+        match result.and_then(|response| decode_response(id, &response)) {
+            Ok(resolved) => return Ok(resolved),
+            Err(err) => last_err = Some(err),
+        }
+    }
+
+    Err(last_err.unwrap_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no nameservers")))
+}
+
+/// Sends the query to a nameserver and waits for the response.
+async fn query_nameserver(nameserver: SocketAddr, query: &[u8]) -> Result<Vec<u8>, io::Error> {
+    let local: SocketAddr = match nameserver {
+        SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+        SocketAddr::V6(_) => (std::net::Ipv6Addr::UNSPECIFIED, 0).into(),
+    };
+    let socket = UdpSocket::bind(local).await?;
+    socket.connect(nameserver).await?;
+    socket.send(query).await?;
+
+    let mut buf = vec![0; 4096];
+    let len = socket.recv(&mut buf).await?;
+    buf.truncate(len);
+    Ok(buf)
+}
+
+/// The nameservers from `/etc/resolv.conf`, falling back to the local host.
+fn nameservers() -> Vec<SocketAddr> {
+    let contents = std::fs::read_to_string("/etc/resolv.conf").unwrap_or_default();
+    let nameservers: Vec<SocketAddr> = contents
+        .lines()
+        .filter_map(|line| line.trim().strip_prefix("nameserver"))
+        .filter_map(|ip| ip.trim().parse::<IpAddr>().ok())
+        .map(|ip| SocketAddr::new(ip, 53))
+        .collect();
+
+    match nameservers.is_empty() {
+        true => vec![(Ipv4Addr::LOCALHOST, 53).into()],
+        false => nameservers,
+    }
+}
+
+/// Encodes a recursive SRV query for the name.
+fn encode_query(id: u16, name: &str) -> Result<Vec<u8>, io::Error> {
+    let mut query = Vec::with_capacity(name.len() + 18);
+    query.extend_from_slice(&id.to_be_bytes());
+    // Recursion desired, one question.
+    query.extend_from_slice(&[0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0]);
+
+    for label in name.trim_end_matches('.').split('.') {
+        if label.is_empty() || label.len() > 63 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("invalid name {}", name),
+            ));
+        }
+
+        query.push(label.len() as u8);
+        query.extend_from_slice(label.as_bytes());
+    }
+
+    query.push(0);
+    query.extend_from_slice(&TYPE_SRV.to_be_bytes());
+    // Class IN.
+    query.extend_from_slice(&[0, 1]);
+    Ok(query)
+}
+
+/// Decodes the SRV records from a response, along with the lowest TTL among them.
+fn decode_response(id: u16, response: &[u8]) -> Result<(Vec<SrvRecord>, u32), io::Error> {
+    let invalid =
+        |what: &str| io::Error::new(io::ErrorKind::InvalidData, format!("DNS response {}", what));
+
+    if response.len() < 12 || read_u16(response, 0) != Some(id) {
+        return Err(invalid("does not match the query"));
+    }
+
+    let rcode = response[3] & 0x0f;
+    if rcode != 0 {
+        return Err(invalid(&format!("has error code {}", rcode)));
+    }
+
+    let questions = read_u16(response, 4).unwrap_or(0);
+    let answers = read_u16(response, 6).unwrap_or(0);
+
+    // Skip the questions.
+    let mut pos = 12;
+    for _ in 0..questions {
+        let (_, next) = read_name(response, pos).ok_or_else(|| invalid("is malformed"))?;
+        pos = next + 4;
+    }
+
+    let mut records = Vec::new();
+    let mut min_ttl = u32::MAX;
+    for _ in 0..answers {
+        let (_, next) = read_name(response, pos).ok_or_else(|| invalid("is malformed"))?;
+        let record_type = read_u16(response, next).ok_or_else(|| invalid("is truncated"))?;
+        let ttl = read_u32(response, next + 4).ok_or_else(|| invalid("is truncated"))?;
+        let len = read_u16(response, next + 8).ok_or_else(|| invalid("is truncated"))? as usize;
+        let data = next + 10;
+        pos = data + len;
+
+        // Other answers (e.g. CNAMEs) are skipped.
+        if record_type != TYPE_SRV {
+            continue;
+        }
+
+        let record = (|| {
+            let (target, _) = read_name(response, data + 6)?;
+            Some(SrvRecord {
+                priority: read_u16(response, data)?,
+                weight: read_u16(response, data + 2)?,
+                port: read_u16(response, data + 4)?,
+                target,
+            })
+        })()
+        .ok_or_else(|| invalid("is malformed"))?;
+
+        // A target of `.` means the service is decidedly not available.
+        if !record.target.is_empty() {
+            records.push(record);
+            min_ttl = min_ttl.min(ttl);
+        }
+    }
+
+    if records.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            "no SRV records found",
+        ));
+    }
+
+    Ok((records, min_ttl))
+}
+
+/// Reads a (possibly compressed) name. Returns the name and the position after it.
+fn read_name(message: &[u8], mut pos: usize) -> Option<(String, usize)> {
+    let mut labels = Vec::new();
+    let mut end = None;
+
+    // Bound the number of jumps so malicious pointer loops can't hang us.
+    for _ in 0..128 {
+        let len = *message.get(pos)? as usize;
+        match len {
+            0 => {
+                return Some((labels.join("."), end.unwrap_or(pos + 1)));
+            }
+            len if len & 0xc0 == 0xc0 => {
+                let pointer = (read_u16(message, pos)? & 0x3fff) as usize;
+                end.get_or_insert(pos + 2);
+                pos = pointer;
+            }
+            len => {
+                let label = message.get(pos + 1..pos + 1 + len)?;
+                labels.push(String::from_utf8_lossy(label).into_owned());
+                pos += 1 + len;
+            }
+        }
+    }
+
+    None
+}
+
+/// Reads a big-endian `u16`.
+fn read_u16(message: &[u8], pos: usize) -> Option<u16> {
+    let bytes = message.get(pos..pos + 2)?;
+    Some(u16::from_be_bytes([bytes[0], bytes[1]]))
+}
+
+/// Reads a big-endian `u32`.
+fn read_u32(message: &[u8], pos: usize) -> Option<u32> {
+    let bytes = message.get(pos..pos + 4)?;
+    Some(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+/// A random number, good enough for query IDs and picking weighted records.
+pub fn random() -> u64 {
+    RandomState::new().build_hasher().finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn orders_records_by_priority_and_weight() {
+        let record = |priority, weight, target: &str| SrvRecord {
+            priority,
+            weight,
+            port: 80,
+            target: target.to_string(),
+        };
+
+        const RUNS: usize = 8000;
+        let mut first = HashMap::new();
+        let mut last_weightless = HashMap::new();
+        for _ in 0..RUNS {
+            let records = vec![
+                record(10, 0, "none"),
+                record(10, 1, "light"),
+                record(10, 3, "heavy"),
+                record(10, 0, "nothing"),
+                record(5, 0, "primary"),
+            ];
+            let ordered: Vec<String> = ordered(records).into_iter().map(|r| r.target).collect();
+            assert_eq!(ordered[0], "primary");
+            *first.entry(ordered[1].clone()).or_insert(0) += 1;
+
+            // The records without a weight only come after the others.
+            let mut weighted = ordered[1..3].to_vec();
+            weighted.sort();
+            assert_eq!(weighted, ["heavy", "light"]);
+            *last_weightless.entry(ordered[4].clone()).or_insert(0) += 1;
+        }
+
+        // A record of weight 3 comes first three times as often as one of weight 1.
+        let share = |target: &str| first.get(target).copied().unwrap_or(0) as f64 / RUNS as f64;
+        assert!((share("heavy") - 0.75).abs() < 0.03, "{:?}", first);
+        assert!((share("light") - 0.25).abs() < 0.03, "{:?}", first);
+
+        // Records without a weight come in random order.
+        let share = |target: &str| last_weightless[target] as f64 / RUNS as f64;
+        assert!((share("none") - 0.5).abs() < 0.03, "{:?}", last_weightless);
+    }
+
+    #[test]
+    fn decodes_compressed_srv_records() {
+        let mut response = encode_query(7, "_db._tcp.example.com").unwrap();
+        // One answer, as a response.
+        response[2] = 0x81;
+        response[7] = 1;
+        // Name pointer to the question, type SRV, class IN, TTL 60.
+        response.extend_from_slice(&[0xc0, 12, 0, 33, 0, 1, 0, 0, 0, 60]);
+        // Length, priority 10, weight 5, port 5432, target `db1` + pointer to `example.com`.
+        response.extend_from_slice(&[
+            0, 12, 0, 10, 0, 5, 0x15, 0x38, 3, b'd', b'b', b'1', 0xc0, 21,
+        ]);
+
+        let (records, ttl) = decode_response(7, &response).unwrap();
+        assert_eq!(ttl, 60);
+        assert_eq!(
+            records,
+            [SrvRecord {
+                priority: 10,
+                weight: 5,
+                port: 5432,
+                target: "db1.example.com".to_string(),
+            }]
+        );
+    }
+}
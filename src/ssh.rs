@@ -0,0 +1,247 @@
+use crate::format;
+use crate::protocol::{Parser, Protocol, Stats};
+use tokio::time::Instant;
+
+/// The longest banner line, as limited by RFC 4253.
+const MAX_LINE_LEN: usize = 255;
+
+/// How far into a connection to look for a banner, after which it's not SSH.
+const MAX_BANNER_OFFSET: usize = 8 * 1024;
+
+/// The longest packet implementations accept (OpenSSH's limit), after which it's not SSH.
+const MAX_PACKET_LEN: usize = 256 * 1024;
+
+/// The message types that start and end the key exchange.
+const KEXINIT: u8 = 20;
+const NEWKEYS: u8 = 21;
+
+/// Where one direction of an SSH connection is at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+enum State {
+    /// Reading lines until the banner (servers may send other lines before it).
+    #[default]
+    Banner,
+
+    /// Reading the unencrypted packets of the key exchange.
+    Packets,
+
+    /// Encrypted, or not SSH, so nothing more is looked at.
+    Done,
+}
+
+/// Follows one direction of an SSH connection, up to where it's encrypted.
+#[derive(Debug, Default)]
+struct Direction {
+    /// Where it's at.
+    state: State,
+
+    /// How many bytes were read while looking for the banner.
+    offset: usize,
+
+    /// The line being read, or the first bytes of the packet being read.
+    current: Vec<u8>,
+
+    /// How many bytes of the current packet are still to come, once its length is in.
+    remaining: Option<usize>,
+
+    /// The banner, e.g. `SSH-2.0-OpenSSH_9.6`.
+    banner: Option<String>,
+
+    /// When it started the key exchange, and when it switched to the new keys.
+    kexinit: Option<Instant>,
+    newkeys: Option<Instant>,
+}
+
+impl Direction {
+    /// Reads the next bytes.
+    fn feed(&mut self, mut bytes: &[u8]) {
+        while !bytes.is_empty() {
+            match self.state {
+                State::Banner => {
+                    let end = bytes.iter().position(|&b| b == b'\n');
+                    let (line, rest) = bytes.split_at(end.map_or(bytes.len(), |end| end + 1));
+                    let room = MAX_LINE_LEN.saturating_sub(self.current.len());
+                    self.current
+                        .extend_from_slice(&line[..line.len().min(room)]);
+                    self.offset += line.len();
+                    bytes = rest;
+                    if end.is_some() {
+                        let line = std::mem::take(&mut self.current);
+                        if line.starts_with(b"SSH-") {
+                            self.banner = Some(format::sanitize(line.trim_ascii_end()));
+                            self.state = State::Packets;
+                            continue;
+                        }
+                    }
+                    if self.offset > MAX_BANNER_OFFSET {
+                        self.state = State::Done;
+                    }
+                }
+
+                // A length, then the padding length and the payload, which starts with the
+                // message type. A length too short for those or too long isn't SSH.
+                State::Packets => {
+                    let Some(remaining) = self.remaining else {
+                        let take = (4 - self.current.len()).min(bytes.len());
+                        self.current.extend_from_slice(&bytes[..take]);
+                        bytes = &bytes[take..];
+                        if self.current.len() == 4 {
+                            let len = u32::from_be_bytes(self.current[..4].try_into().unwrap());
+                            if !(2..=MAX_PACKET_LEN).contains(&(len as usize)) {
+                                self.state = State::Done;
+                                return;
+                            }
+                            self.remaining = Some(len as usize);
+                            self.current.clear();
+                        }
+                        continue;
+                    };
+
+                    let take = remaining.min(bytes.len());
+                    let room = 2usize.saturating_sub(self.current.len());
+                    self.current.extend_from_slice(&bytes[..take.min(room)]);
+                    bytes = &bytes[take..];
+                    self.remaining = Some(remaining - take);
+                    if remaining > take {
+                        continue;
+                    }
+
+                    self.remaining = None;
+                    match std::mem::take(&mut self.current).get(1) {
+                        Some(&KEXINIT) => self.kexinit = self.kexinit.or(Some(Instant::now())),
+                        Some(&NEWKEYS) => {
+                            self.newkeys = Some(Instant::now());
+                            self.state = State::Done;
+                        }
+                        _ => {}
+                    }
+                }
+                State::Done => return,
+            }
+        }
+    }
+}
+
+/// Records the banners of an SSH connection, and how long its key exchange took.
+#[derive(Debug, Default)]
+pub struct Ssh {
+    /// What the client sends.
+    client: Direction,
+
+    /// What the server sends.
+    server: Direction,
+}
+
+impl Parser for Ssh {
+    fn client(&mut self, bytes: &[u8]) {
+        self.client.feed(bytes);
+    }
+
+    fn server(&mut self, bytes: &[u8]) {
+        self.server.feed(bytes);
+    }
+
+    fn stats(&self) -> Stats {
+        let mut details = Vec::new();
+        if let Some(banner) = &self.client.banner {
+            details.push(("client", banner.clone()));
+        }
+        if let Some(banner) = &self.server.banner {
+            details.push(("server", banner.clone()));
+        }
+
+        // From the first side starting it to both switching to the new keys.
+        let started = match (self.client.kexinit, self.server.kexinit) {
+            (Some(client), Some(server)) => Some(client.min(server)),
+            (client, server) => client.or(server),
+        };
+        let finished = self.client.newkeys.zip(self.server.newkeys);
+        let key_exchange = started
+            .zip(finished)
+            .map(|(started, (client, server))| client.max(server) - started);
+
+        Stats {
+            protocol: Protocol::Ssh,
+            counts: Vec::new(),
+            timings: key_exchange
+                .map(|duration| vec![("key exchange", duration)])
+                .unwrap_or_default(),
+            details,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    /// An unencrypted packet of the given message type.
+    fn packet(kind: u8, len: usize) -> Vec<u8> {
+        let mut packet = (len as u32 + 2).to_be_bytes().to_vec();
+        packet.extend_from_slice(&[0, kind]);
+        packet.extend(std::iter::repeat_n(0, len));
+        packet
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn times_key_exchanges() {
+        let mut ssh = Ssh::default();
+        ssh.server(b"SSH-2.0-OpenSSH_9.2p1 Debian-2\r\n");
+        ssh.client(b"SSH-2.0-OpenSSH_9.6\r\n");
+
+        // The client's KEXINIT is split across reads.
+        let kexinit = packet(KEXINIT, 1000);
+        ssh.client(&kexinit[..3]);
+        ssh.client(&kexinit[3..]);
+        ssh.server(&packet(KEXINIT, 800));
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        ssh.server(&packet(31, 500));
+        ssh.server(&packet(NEWKEYS, 0));
+        tokio::time::sleep(Duration::from_millis(5)).await;
+        ssh.client(&packet(NEWKEYS, 0));
+        ssh.client(&[0xde, 0xad, 0xbe, 0xef]);
+
+        assert_eq!(
+            ssh.stats().to_string(),
+            "key exchange 35.0ms (client SSH-2.0-OpenSSH_9.6, server SSH-2.0-OpenSSH_9.2p1 Debian-2)"
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn gives_up_on_garbage() {
+        // Banners that aren't UTF-8 are shown sanitized, and truncated packets are waited for.
+        let mut ssh = Ssh::default();
+        ssh.client(b"SSH-2.0-\xff\x1b[2J\r\n");
+        ssh.client(&packet(KEXINIT, 10)[..7]);
+        assert_eq!(ssh.client.banner.as_deref(), Some("SSH-2.0-\\xff\\x1b[2J"));
+        assert_eq!(
+            (ssh.client.state, ssh.client.kexinit),
+            (State::Packets, None)
+        );
+
+        // Packets too long or too short end the parsing.
+        for len in [0, 1, MAX_PACKET_LEN as u32 + 1, u32::MAX] {
+            let mut ssh = Ssh::default();
+            ssh.server(b"SSH-2.0-x\r\n");
+            ssh.server(&len.to_be_bytes());
+            ssh.server(&packet(KEXINIT, 0));
+            assert_eq!((ssh.server.state, ssh.server.kexinit), (State::Done, None));
+        }
+
+        // As does a connection that doesn't start with a banner, in one line or many.
+        for garbage in [
+            vec![b'x'; MAX_BANNER_OFFSET + 1],
+            b"GET / HTTP/1.1\r\n".repeat(600),
+        ] {
+            let mut ssh = Ssh::default();
+            ssh.client(&garbage);
+            ssh.client(b"\nSSH-2.0-late\r\n");
+            assert_eq!(
+                (ssh.client.state, ssh.client.banner.as_deref()),
+                (State::Done, None)
+            );
+        }
+        assert_eq!(Ssh::default().stats().to_string(), "");
+    }
+}
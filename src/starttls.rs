@@ -0,0 +1,214 @@
+use crate::tls::RECORD_HANDSHAKE;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, ReadBuf};
+
+/// How far into a connection to look for an upgrade command, after which it's assumed never to
+/// upgrade.
+const MAX_COMMAND_OFFSET: u64 = 64 * 1024;
+
+/// The longest line to look for an upgrade command in; the rest of longer lines is ignored.
+const MAX_LINE_LEN: usize = 256;
+
+/// The SSLRequest a PostgreSQL client opens a connection with to upgrade it: a length of 8 and
+/// the code 80877103.
+const POSTGRES_SSL_REQUEST: [u8; 8] = [0, 0, 0, 8, 0x04, 0xd2, 0x16, 0x2f];
+
+/// Called with the offset of the first TLS byte the client sent, and the command that upgraded
+/// the connection.
+pub type OnUpgrade = Box<dyn FnOnce(u64, &'static str) + Send>;
+
+/// Where a connection is at in upgrading to TLS.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    /// Looking for an upgrade command in what the client sends.
+    Watching,
+
+    /// The client sent an upgrade command, so what it sends next is a TLS handshake if the
+    /// server agreed.
+    Upgrading(&'static str),
+
+    /// Upgraded, or not looking any further.
+    Done,
+}
+
+/// Watches what a client sends for a STARTTLS-style upgrade to TLS, like SMTP's `STARTTLS`,
+/// POP3's `STLS`, FTP's `AUTH TLS` or PostgreSQL's SSLRequest, followed by a TLS handshake.
+#[derive(Debug)]
+pub struct Watcher {
+    /// Where the connection is at.
+    state: State,
+
+    /// How many bytes the client sent so far.
+    offset: u64,
+
+    /// The line being sent, up to `MAX_LINE_LEN` bytes.
+    line: Vec<u8>,
+}
+
+impl Default for Watcher {
+    fn default() -> Self {
+        Self {
+            state: State::Watching,
+            offset: 0,
+            line: Vec::new(),
+        }
+    }
+}
+
+impl Watcher {
+    /// Looks at the next bytes the client sent, returning the offset of the first TLS byte and
+    /// the upgrade command once it upgraded.
+    pub fn inspect(&mut self, bytes: &[u8]) -> Option<(u64, &'static str)> {
+        let mut upgraded = None;
+        let mut rest = bytes;
+        if self.offset == 0 && rest.starts_with(&POSTGRES_SSL_REQUEST) {
+            self.state = State::Upgrading("SSLRequest");
+            rest = &rest[POSTGRES_SSL_REQUEST.len()..];
+        }
+
+        while !rest.is_empty() {
+            match self.state {
+                // A client sends its ClientHello once the server agreed, otherwise it goes on
+                // in plain text.
+                State::Upgrading(command) => {
+                    let handshake =
+                        rest[0] == RECORD_HANDSHAKE && rest.get(1).is_none_or(|&b| b == 3);
+                    if handshake {
+                        let at = self.offset + (bytes.len() - rest.len()) as u64;
+                        upgraded = Some((at, command));
+                        self.state = State::Done;
+                    } else {
+                        self.state = State::Watching;
+                    }
+                }
+                State::Watching => {
+                    let end = rest.iter().position(|&b| b == b'\n');
+                    let (line, next) = rest.split_at(end.map_or(rest.len(), |end| end + 1));
+                    let room = MAX_LINE_LEN.saturating_sub(self.line.len());
+                    self.line.extend_from_slice(&line[..line.len().min(room)]);
+                    rest = next;
+                    if end.is_some() {
+                        if let Some(command) = upgrade_command(&self.line) {
+                            self.state = State::Upgrading(command);
+                        }
+                        self.line.clear();
+                    }
+                }
+                State::Done => break,
+            }
+        }
+
+        self.offset += bytes.len() as u64;
+        if self.state == State::Watching && self.offset > MAX_COMMAND_OFFSET {
+            self.state = State::Done;
+        }
+        upgraded
+    }
+
+    /// Whether there's nothing more to look for.
+    fn done(&self) -> bool {
+        self.state == State::Done
+    }
+}
+
+/// Returns the upgrade command a line of a text protocol is, if it's one: SMTP's and IMAP's
+/// (tagged) `STARTTLS`, POP3's `STLS`, FTP's `AUTH TLS` or `AUTH SSL`, or XMPP's `<starttls/>`.
+fn upgrade_command(line: &[u8]) -> Option<&'static str> {
+    let line = String::from_utf8_lossy(line).trim().to_ascii_uppercase();
+    let words: Vec<&str> = line.split_whitespace().collect();
+    match words.as_slice() {
+        ["STARTTLS"] | [_, "STARTTLS"] => Some("STARTTLS"),
+        ["STLS"] => Some("STLS"),
+        ["AUTH", "TLS" | "SSL" | "TLS-C"] => Some("AUTH TLS"),
+        _ if line.starts_with("<STARTTLS") => Some("<starttls/>"),
+        _ => None,
+    }
+}
+
+/// Wraps the client side of a connection, watching it for an upgrade to TLS as the bytes flow
+/// by. Nothing is held back.
+pub struct Watch<R> {
+    /// The reader being watched.
+    inner: R,
+
+    /// Where the connection is at in upgrading.
+    watcher: Watcher,
+
+    /// Called once the connection upgraded, if watching at all.
+    on_upgrade: Option<OnUpgrade>,
+}
+
+impl<R> Watch<R> {
+    /// Watches the reader if there's a callback, otherwise just passes the bytes through.
+    pub fn new(inner: R, on_upgrade: Option<OnUpgrade>) -> Self {
+        Watch {
+            inner,
+            watcher: Watcher::default(),
+            on_upgrade,
+        }
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for Watch<R> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let this = self.get_mut();
+        let before = buf.filled().len();
+        let result = Pin::new(&mut this.inner).poll_read(cx, buf);
+        if this.on_upgrade.is_none() {
+            return result;
+        }
+
+        if let Some((at, command)) = this.watcher.inspect(&buf.filled()[before..]) {
+            if let Some(on_upgrade) = this.on_upgrade.take() {
+                on_upgrade(at, command);
+            }
+        }
+        if this.watcher.done() {
+            this.on_upgrade = None;
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_upgrades() {
+        // SMTP, with the command split across reads.
+        let mut watcher = Watcher::default();
+        assert_eq!(watcher.inspect(b"EHLO client.example\r\nSTART"), None);
+        assert_eq!(watcher.inspect(b"TLS\r\n"), None);
+        assert_eq!(
+            watcher.inspect(&[0x16, 0x03, 0x01, 0x02, 0x00]),
+            Some((31, "STARTTLS"))
+        );
+        assert_eq!(watcher.inspect(b"\x17\x03\x03"), None);
+
+        // PostgreSQL, where the server agrees with a single `S`.
+        let mut watcher = Watcher::default();
+        assert_eq!(watcher.inspect(&POSTGRES_SSL_REQUEST), None);
+        assert_eq!(
+            watcher.inspect(&[0x16, 0x03, 0x01]),
+            Some((8, "SSLRequest"))
+        );
+
+        // IMAP, where the server refused, so the client goes on in plain text.
+        let mut watcher = Watcher::default();
+        assert_eq!(watcher.inspect(b"a1 starttls\r\n"), None);
+        assert_eq!(watcher.inspect(b"a2 LOGIN user pass\r\n"), None);
+        assert_eq!(watcher.state, State::Watching);
+
+        assert_eq!(upgrade_command(b"AUTH TLS\r\n"), Some("AUTH TLS"));
+        assert_eq!(
+            upgrade_command(b"MAIL FROM:<starttls@example.com>\r\n"),
+            None
+        );
+    }
+}
@@ -0,0 +1,214 @@
+use crate::output::print_line;
+use std::collections::VecDeque;
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// The window of the rates.
+const WINDOW: Duration = Duration::from_secs(60);
+
+/// The fewest errors in a window that can make a storm.
+const STORM_MIN: usize = 10;
+
+/// How many times the usual rate makes a storm.
+const STORM_FACTOR: f64 = 3.0;
+
+/// Keeps per-minute rates of connections closing with errors and with resets, and announces
+/// when they spike (storms) and calm down again.
+#[derive(Debug)]
+pub struct Storms {
+    /// When the rates started, to work out the usual rate.
+    started: Instant,
+
+    /// Connections that closed with an error.
+    errors: Rate,
+
+    /// Connections that were reset.
+    resets: Rate,
+}
+
+/// The rate of one kind of error.
+#[derive(Debug)]
+struct Rate {
+    /// What the errors are called, e.g. `reset`.
+    name: &'static str,
+
+    /// When the errors in the window happened.
+    recent: VecDeque<Instant>,
+
+    /// How many there were in total.
+    total: u64,
+
+    /// The most in one window.
+    peak: usize,
+
+    /// Whether the rate is spiking.
+    storming: bool,
+}
+
+impl Default for Storms {
+    fn default() -> Self {
+        Storms {
+            started: Instant::now(),
+            errors: Rate::new("error"),
+            resets: Rate::new("reset"),
+        }
+    }
+}
+
+impl Storms {
+    /// Notes that a connection closed with an error, which may have been a reset.
+    pub fn closed_with_error(&mut self, reset: bool) {
+        let now = Instant::now();
+        self.errors.add(now);
+        if reset {
+            self.resets.add(now);
+        }
+        self.check();
+    }
+
+    /// Announces storms that started or ended.
+    pub fn check(&mut self) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.started);
+        self.errors.check(now, elapsed);
+        self.resets.check(now, elapsed);
+    }
+
+    /// Describes the rates for the summary, if there were any errors.
+    pub fn summary(&self) -> Option<String> {
+        if self.errors.total == 0 {
+            return None;
+        }
+
+        let now = Instant::now();
+        Some(format!(
+            "{}; {}",
+            self.errors.summary(now),
+            self.resets.summary(now)
+        ))
+    }
+}
+
+impl Rate {
+    /// Starts with no errors.
+    fn new(name: &'static str) -> Self {
+        Rate {
+            name,
+            recent: VecDeque::new(),
+            total: 0,
+            peak: 0,
+            storming: false,
+        }
+    }
+
+    /// Notes an error.
+    fn add(&mut self, now: Instant) {
+        self.prune(now);
+        self.recent.push_back(now);
+        self.total += 1;
+        self.peak = self.peak.max(self.recent.len());
+    }
+
+    /// Forgets the errors that happened before the window.
+    fn prune(&mut self, now: Instant) {
+        while let Some(error) = self.recent.front() {
+            match now.duration_since(*error) > WINDOW {
+                true => self.recent.pop_front(),
+                false => break,
+            };
+        }
+    }
+
+    /// Announces whether a storm started or ended, `elapsed` after the rates started. A storm
+    /// is a window with at least `STORM_MIN` errors and `STORM_FACTOR` times the usual rate
+    /// before it.
+    fn check(&mut self, now: Instant, elapsed: Duration) {
+        self.prune(now);
+        let current = self.recent.len();
+        let before = self.total - current as u64;
+        let usual =
+            before as f64 / (elapsed.saturating_sub(WINDOW).max(WINDOW).as_secs_f64() / 60.0);
+        let storming = current >= STORM_MIN && current as f64 > usual * STORM_FACTOR;
+        if storming == self.storming {
+            return;
+        }
+
+        self.storming = storming;
+        match storming {
+            true => print_line!(
+                "🌩️  {} storm — {} {}s in the last minute, usually {:.1}/min",
+                self.name,
+                current,
+                self.name,
+                usual
+            ),
+            false => print_line!(
+                "🌤️  {} storm over — {} {}s in the last minute",
+                self.name,
+                current,
+                self.name
+            ),
+        }
+    }
+
+    /// Describes the rate for the summary, counting only the errors in the window, even if
+    /// the older ones weren't pruned yet.
+    fn summary(&self, now: Instant) -> String {
+        let current = self
+            .recent
+            .iter()
+            .filter(|error| now.duration_since(**error) <= WINDOW)
+            .count();
+        format!(
+            "{}s: {} in total, {}/min now, peak {}/min",
+            self.name, self.total, current, self.peak
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn announces_storms() {
+        let mut storms = Storms::default();
+        tokio::time::advance(Duration::from_secs(600)).await;
+        for _ in 0..STORM_MIN {
+            storms.closed_with_error(true);
+        }
+        assert!(storms.errors.storming && storms.resets.storming);
+
+        tokio::time::advance(WINDOW + Duration::from_millis(1)).await;
+        storms.closed_with_error(false);
+        assert!(!storms.errors.storming && !storms.resets.storming);
+        assert_eq!(
+            storms.summary().unwrap(),
+            "errors: 11 in total, 1/min now, peak 10/min; resets: 10 in total, 0/min now, peak 10/min"
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn steady_errors_are_no_storm() {
+        // Twenty errors a minute are a storm at first, with no usual rate yet, but once they
+        // are the usual rate, they are not.
+        let mut storms = Storms::default();
+        for error in 0..200 {
+            tokio::time::advance(Duration::from_secs(3)).await;
+            storms.closed_with_error(false);
+            match error {
+                0..=8 => assert!(!storms.errors.storming),
+                9 => assert!(storms.errors.storming),
+                40.. => assert!(!storms.errors.storming, "{}", error),
+                _ => {}
+            }
+        }
+
+        // Until the rate triples.
+        for _ in 0..60 {
+            tokio::time::advance(Duration::from_millis(500)).await;
+            storms.closed_with_error(false);
+        }
+        assert!(storms.errors.storming && !storms.resets.storming);
+    }
+}
@@ -0,0 +1,177 @@
+use crate::net;
+use crate::proxy::{self, Destination};
+use crate::reporter::{self, ReporterHandle, Snapshot, DEFAULT_MAPPING};
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{mpsc, watch};
+use tokio::task::JoinHandle;
+
+/// How long `Harness::wait_for` waits for the reporter to get to a state.
+const WAIT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How often `Harness::wait_for` asks the reporter for a snapshot.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// An echo server and a proxy in front of it, both on ephemeral ports, for end-to-end tests.
+/// Everything stops when the harness is dropped.
+pub struct Harness {
+    /// The address the proxy listens on.
+    pub proxy_addr: SocketAddr,
+
+    /// Used for changing the proxy's destination (initially the echo server) while it runs.
+    pub destination: watch::Sender<Destination>,
+
+    /// Used for asking the reporter about the connections.
+    pub reporter_handle: ReporterHandle,
+
+    /// The echo server, proxy and reporter tasks.
+    tasks: Vec<JoinHandle<()>>,
+}
+
+impl Harness {
+    /// Starts an echo server and a proxy forwarding to it.
+    pub async fn start() -> io::Result<Harness> {
+        Harness::start_with(|_| {}).await
+    }
+
+    /// Starts an echo server and a proxy forwarding to it, letting `configure` change the
+    /// proxy's destination (e.g. to add policies) first.
+    pub async fn start_with(configure: impl FnOnce(&mut Destination)) -> io::Result<Harness> {
+        let (echo_addr, echo_task) = echo_server().await?;
+
+        let mut destination = Destination::new(echo_addr.to_string(), false);
+        configure(&mut destination);
+        let (destination, receiver) = watch::channel(destination);
+
+        let (reporter_handle, reporter_actor) = reporter::create(Default::default());
+        let reporter_task = tokio::spawn(async move {
+            reporter_actor.run().await;
+        });
+
+        let listener = net::listen("127.0.0.1:0", None).await?;
+        let proxy_addr = listener.local_addr()?;
+        let proxy_handle = reporter_handle.clone();
+        let proxy_task = tokio::spawn(async move {
+            let mapping = Arc::from(DEFAULT_MAPPING);
+            let _ = proxy::run(&[listener], mapping, receiver, proxy_handle).await;
+        });
+
+        Ok(Harness {
+            proxy_addr,
+            destination,
+            reporter_handle,
+            tasks: vec![echo_task, proxy_task, reporter_task],
+        })
+    }
+
+    /// Opens a client connection to the proxy.
+    pub async fn connect(&self) -> io::Result<TcpStream> {
+        TcpStream::connect(self.proxy_addr).await
+    }
+
+    /// Asks the reporter for a snapshot of the connections so far.
+    pub async fn snapshot(&self) -> Snapshot {
+        self.reporter_handle.snapshot().await.unwrap_or_default()
+    }
+
+    /// Waits until the reporter's snapshot meets the condition, returning it. Events are
+    /// reported asynchronously, so tests wait for the state they expect instead of sleeping.
+    pub async fn wait_for(
+        &self,
+        condition: impl Fn(&Snapshot) -> bool,
+    ) -> Result<Snapshot, String> {
+        let mut last = Snapshot::default();
+        let waited = tokio::time::timeout(WAIT_TIMEOUT, async {
+            loop {
+                last = self.snapshot().await;
+                if condition(&last) {
+                    return;
+                }
+
+                tokio::time::sleep(POLL_INTERVAL).await;
+            }
+        })
+        .await;
+
+        match waited {
+            Ok(()) => Ok(last),
+            Err(_) => Err(format!(
+                "The reporter did not get to the expected state within {:?}, last: {:?}",
+                WAIT_TIMEOUT, last
+            )),
+        }
+    }
+}
+
+impl Drop for Harness {
+    fn drop(&mut self) {
+        for task in &self.tasks {
+            task.abort();
+        }
+    }
+}
+
+/// Starts a server on an ephemeral port that sends back whatever its clients send, closing
+/// each connection once its client does.
+pub async fn echo_server() -> io::Result<(SocketAddr, JoinHandle<()>)> {
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let addr = listener.local_addr()?;
+    let task = tokio::spawn(async move {
+        while let Ok((mut stream, _)) = listener.accept().await {
+            tokio::spawn(async move {
+                let (mut read, mut write) = stream.split();
+                let _ = tokio::io::copy(&mut read, &mut write).await;
+            });
+        }
+    });
+
+    Ok((addr, task))
+}
+
+/// Starts an HTTP server on an ephemeral port that answers every request with the same
+/// response and closes the connection, passing the requests on as they came in.
+pub async fn http_server(
+    response: String,
+) -> io::Result<(SocketAddr, mpsc::UnboundedReceiver<String>)> {
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let addr = listener.local_addr()?;
+    let (sender, receiver) = mpsc::unbounded_channel();
+    tokio::spawn(async move {
+        while let Ok((mut stream, _)) = listener.accept().await {
+            // A request ends after as many bytes of body as its `Content-Length` says.
+            let mut request = Vec::new();
+            let mut buffer = [0; 4096];
+            while !is_complete(&request) {
+                match stream.read(&mut buffer).await {
+                    Ok(0) | Err(_) => break,
+                    Ok(read) => request.extend_from_slice(&buffer[..read]),
+                }
+            }
+
+            let _ = sender.send(String::from_utf8_lossy(&request).into_owned());
+            let _ = stream.write_all(response.as_bytes()).await;
+        }
+    });
+
+    Ok((addr, receiver))
+}
+
+/// Whether an HTTP request has been read in full.
+fn is_complete(request: &[u8]) -> bool {
+    let Some(end) = request.windows(4).position(|window| window == b"\r\n\r\n") else {
+        return false;
+    };
+
+    let head = String::from_utf8_lossy(&request[..end]);
+    let length = head
+        .lines()
+        .filter_map(|line| line.split_once(':'))
+        .find(|(name, _)| name.eq_ignore_ascii_case("content-length"))
+        .and_then(|(_, length)| length.trim().parse().ok())
+        .unwrap_or(0);
+    request.len() >= end + 4 + length
+}
@@ -0,0 +1,150 @@
+use crate::format::Rate;
+use crate::json::Value;
+use crate::record;
+use std::collections::VecDeque;
+use std::fmt::{Display, Formatter};
+use std::time::{Duration, SystemTime};
+
+/// How much of the recent history is kept, unless configured otherwise.
+pub const DEFAULT_LENGTH: Duration = Duration::from_secs(10 * 60);
+
+/// What happened in a second.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Sample {
+    /// When the second ended.
+    pub time: SystemTime,
+
+    /// How many connections were open at its end.
+    pub open: u64,
+
+    /// How many connections were opened.
+    pub opened: u64,
+
+    /// How many connections closed with an error.
+    pub errors: u64,
+
+    /// How many bytes were forwarded, in either direction.
+    pub bytes: u64,
+}
+
+impl Display for Sample {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} — {} open, {} opened, {} error(s), {}",
+            record::rfc3339(self.time),
+            self.open,
+            self.opened,
+            self.errors,
+            Rate(self.bytes)
+        )
+    }
+}
+
+/// The samples of every second of the recent history, e.g. the last 10 minutes, so it can be
+/// looked at after the fact.
+#[derive(Debug)]
+pub struct Timeline {
+    /// The samples, oldest first.
+    samples: VecDeque<Sample>,
+
+    /// How many samples are kept.
+    capacity: usize,
+
+    /// The running totals of connections opened, errors and bytes when the last second was
+    /// sampled.
+    last_totals: (u64, u64, u64),
+}
+
+impl Timeline {
+    /// Creates a timeline keeping the samples of the given length of history.
+    pub fn new(length: Duration) -> Self {
+        let capacity = length.as_secs().max(1) as usize;
+        Self {
+            samples: VecDeque::with_capacity(capacity),
+            capacity,
+            last_totals: (0, 0, 0),
+        }
+    }
+
+    /// Samples a second ending at `time`, given the open connections and the running totals of
+    /// connections opened, errors and bytes at its end.
+    pub fn record(&mut self, time: SystemTime, open: u64, totals: (u64, u64, u64)) {
+        if self.samples.len() == self.capacity {
+            self.samples.pop_front();
+        }
+        let (opened, errors, bytes) = self.last_totals;
+        self.samples.push_back(Sample {
+            time,
+            open,
+            opened: totals.0.saturating_sub(opened),
+            errors: totals.1.saturating_sub(errors),
+            bytes: totals.2.saturating_sub(bytes),
+        });
+        self.last_totals = totals;
+    }
+
+    /// The samples of the last `length` of history, oldest first.
+    pub fn last(&self, length: Duration) -> Vec<Sample> {
+        let count = (length.as_secs() as usize).min(self.samples.len());
+        self.samples
+            .iter()
+            .skip(self.samples.len() - count)
+            .copied()
+            .collect()
+    }
+
+    /// Serializes the samples, oldest first.
+    pub fn to_json(&self) -> Value {
+        let count = |n: u64| Value::Number(n as f64);
+        let samples = self
+            .samples
+            .iter()
+            .map(|sample| {
+                Value::Object(vec![
+                    (
+                        "time".to_string(),
+                        Value::String(record::rfc3339(sample.time)),
+                    ),
+                    ("open".to_string(), count(sample.open)),
+                    ("opened".to_string(), count(sample.opened)),
+                    ("errors".to_string(), count(sample.errors)),
+                    ("bytes".to_string(), count(sample.bytes)),
+                ])
+            })
+            .collect();
+        Value::Array(samples)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::UNIX_EPOCH;
+
+    #[test]
+    fn keeps_the_recent_history() {
+        let mut timeline = Timeline::new(Duration::from_secs(3));
+        let second = |n: u64| UNIX_EPOCH + Duration::from_secs(1_792_037_134 + n);
+        for (n, totals) in [(0, 0, 0), (2, 0, 100), (3, 1, 300), (3, 1, 300)]
+            .into_iter()
+            .enumerate()
+        {
+            timeline.record(second(n as u64), totals.0, totals);
+        }
+
+        let samples = timeline.last(Duration::from_secs(60));
+        assert_eq!(samples.len(), 3);
+        assert_eq!((samples[0].opened, samples[0].bytes), (2, 100));
+        assert_eq!((samples[1].opened, samples[1].errors), (1, 1));
+        assert_eq!(
+            samples[2].to_string(),
+            "2026-10-15T04:05:37.000Z — 3 open, 0 opened, 0 error(s), 0 B/s"
+        );
+        assert_eq!(timeline.last(Duration::from_secs(1)), &samples[2..]);
+        assert_eq!(
+            timeline.to_json().as_array().unwrap()[1].to_string(),
+            r#"{"time":"2026-10-15T04:05:36.000Z","open":3,"opened":1,"errors":1,"bytes":200}"#
+        );
+    }
+}
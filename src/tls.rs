@@ -0,0 +1,647 @@
+use crate::digest;
+use crate::format;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, ReadBuf};
+
+/// The most bytes to buffer while waiting for a complete ClientHello.
+const MAX_HELLO_LEN: usize = 16 * 1024;
+
+/// The TLS record type of handshake messages.
+pub const RECORD_HANDSHAKE: u8 = 0x16;
+
+/// The handshake types of ClientHello and ServerHello messages.
+const HANDSHAKE_CLIENT_HELLO: u8 = 0x01;
+const HANDSHAKE_SERVER_HELLO: u8 = 0x02;
+
+/// Extension types we look into.
+const EXT_SERVER_NAME: u16 = 0x0000;
+const EXT_SUPPORTED_GROUPS: u16 = 0x000a;
+const EXT_EC_POINT_FORMATS: u16 = 0x000b;
+const EXT_SIGNATURE_ALGORITHMS: u16 = 0x000d;
+const EXT_ALPN: u16 = 0x0010;
+const EXT_SUPPORTED_VERSIONS: u16 = 0x002b;
+
+/// What a client offered in its ClientHello. GREASE values are left out.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ClientHello {
+    /// The legacy version field of the hello.
+    pub version: u16,
+
+    /// The offered cipher suites, in order.
+    pub ciphers: Vec<u16>,
+
+    /// The extension types, in order.
+    pub extensions: Vec<u16>,
+
+    /// The offered groups (elliptic curves), in order.
+    pub groups: Vec<u16>,
+
+    /// The offered EC point formats, in order.
+    pub point_formats: Vec<u8>,
+
+    /// The offered signature algorithms, in order.
+    pub signature_algorithms: Vec<u16>,
+
+    /// The offered ALPN protocols, in order.
+    pub alpn: Vec<String>,
+
+    /// The offered TLS versions from the supported_versions extension, in order.
+    pub supported_versions: Vec<u16>,
+
+    /// The requested server name.
+    pub server_name: Option<String>,
+}
+
+/// What a server chose in its ServerHello.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServerHello {
+    /// The negotiated TLS version.
+    pub version: u16,
+
+    /// The negotiated cipher suite.
+    pub cipher: u16,
+
+    /// The negotiated ALPN protocol. TLS 1.3 servers send it encrypted, so it's only known for
+    /// older versions.
+    pub alpn: Option<String>,
+}
+
+/// The outcome of parsing the first bytes of one side of a connection.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Parsed<T> {
+    /// More bytes are needed.
+    Incomplete,
+
+    /// The bytes are not the expected TLS handshake message.
+    NotTls,
+
+    /// A complete hello message.
+    Hello(T),
+}
+
+impl ClientHello {
+    /// Returns the JA3 fingerprint: the MD5 of the JA3 string.
+    pub fn ja3(&self) -> String {
+        digest::md5_hex(self.ja3_string().as_bytes())
+    }
+
+    /// Returns the JA3 string: version, ciphers, extensions, groups and point formats.
+    pub fn ja3_string(&self) -> String {
+        format!(
+            "{},{},{},{},{}",
+            self.version,
+            join(&self.ciphers, "-", |c| c.to_string()),
+            join(&self.extensions, "-", |e| e.to_string()),
+            join(&self.groups, "-", |g| g.to_string()),
+            join(&self.point_formats, "-", |p| p.to_string())
+        )
+    }
+
+    /// Returns the JA4 fingerprint, e.g. `t13d1516h2_8daaf6152771_e5627efa2ab1`.
+    pub fn ja4(&self) -> String {
+        let mut ciphers = self.ciphers.clone();
+        ciphers.sort_unstable();
+
+        let mut extensions: Vec<u16> = self
+            .extensions
+            .iter()
+            .copied()
+            .filter(|&e| e != EXT_SERVER_NAME && e != EXT_ALPN)
+            .collect();
+        extensions.sort_unstable();
+
+        let ciphers_hash = match ciphers.is_empty() {
+            true => "000000000000".to_string(),
+            false => truncated_sha256(&join(&ciphers, ",", |c| format!("{:04x}", c))),
+        };
+
+        let extensions_hash = match extensions.is_empty() {
+            true => "000000000000".to_string(),
+            false => {
+                let mut input = join(&extensions, ",", |e| format!("{:04x}", e));
+                if !self.signature_algorithms.is_empty() {
+                    input.push('_');
+                    input.push_str(&join(&self.signature_algorithms, ",", |s| {
+                        format!("{:04x}", s)
+                    }));
+                }
+                truncated_sha256(&input)
+            }
+        };
+
+        format!(
+            "t{}{}{:02}{:02}{}_{}_{}",
+            version_code(self.max_version()),
+            if self.server_name.is_some() { 'd' } else { 'i' },
+            self.ciphers.len().min(99),
+            self.extensions.len().min(99),
+            alpn_code(self.alpn.first().map(String::as_str)),
+            ciphers_hash,
+            extensions_hash
+        )
+    }
+
+    /// Returns the highest TLS version offered.
+    pub fn max_version(&self) -> u16 {
+        self.supported_versions
+            .iter()
+            .copied()
+            .max()
+            .unwrap_or(self.version)
+    }
+}
+
+/// Returns the IANA name of common cipher suites, or the code for others.
+pub fn cipher_name(cipher: u16) -> String {
+    let name = match cipher {
+        0x1301 => "TLS_AES_128_GCM_SHA256",
+        0x1302 => "TLS_AES_256_GCM_SHA384",
+        0x1303 => "TLS_CHACHA20_POLY1305_SHA256",
+        0xc02b => "TLS_ECDHE_ECDSA_WITH_AES_128_GCM_SHA256",
+        0xc02c => "TLS_ECDHE_ECDSA_WITH_AES_256_GCM_SHA384",
+        0xc02f => "TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256",
+        0xc030 => "TLS_ECDHE_RSA_WITH_AES_256_GCM_SHA384",
+        0xcca8 => "TLS_ECDHE_RSA_WITH_CHACHA20_POLY1305_SHA256",
+        0xcca9 => "TLS_ECDHE_ECDSA_WITH_CHACHA20_POLY1305_SHA256",
+        0xc013 => "TLS_ECDHE_RSA_WITH_AES_128_CBC_SHA",
+        0xc014 => "TLS_ECDHE_RSA_WITH_AES_256_CBC_SHA",
+        0x009c => "TLS_RSA_WITH_AES_128_GCM_SHA256",
+        0x009d => "TLS_RSA_WITH_AES_256_GCM_SHA384",
+        0x002f => "TLS_RSA_WITH_AES_128_CBC_SHA",
+        0x0035 => "TLS_RSA_WITH_AES_256_CBC_SHA",
+        _ => return format!("0x{:04x}", cipher),
+    };
+
+    name.to_string()
+}
+
+/// Returns a readable name for a TLS version, e.g. `TLS 1.3`.
+pub fn version_name(version: u16) -> String {
+    match version {
+        0x0300 => "SSL 3.0".to_string(),
+        0x0301 => "TLS 1.0".to_string(),
+        0x0302 => "TLS 1.1".to_string(),
+        0x0303 => "TLS 1.2".to_string(),
+        0x0304 => "TLS 1.3".to_string(),
+        other => format!("0x{:04x}", other),
+    }
+}
+
+/// Parses the first bytes a client sent as a TLS ClientHello.
+pub fn parse_client_hello(bytes: &[u8]) -> Parsed<ClientHello> {
+    match handshake_message(bytes, HANDSHAKE_CLIENT_HELLO) {
+        Parsed::Hello(body) => parse_hello_body(&body).map_or(Parsed::NotTls, Parsed::Hello),
+        Parsed::Incomplete => Parsed::Incomplete,
+        Parsed::NotTls => Parsed::NotTls,
+    }
+}
+
+/// Parses the first bytes a server sent as a TLS ServerHello.
+pub fn parse_server_hello(bytes: &[u8]) -> Parsed<ServerHello> {
+    match handshake_message(bytes, HANDSHAKE_SERVER_HELLO) {
+        Parsed::Hello(body) => parse_server_hello_body(&body).map_or(Parsed::NotTls, Parsed::Hello),
+        Parsed::Incomplete => Parsed::Incomplete,
+        Parsed::NotTls => Parsed::NotTls,
+    }
+}
+
+/// Returns the body of the first handshake message, which may span several records, if it has
+/// the expected type.
+fn handshake_message(bytes: &[u8], handshake_type: u8) -> Parsed<Vec<u8>> {
+    // Gather the handshake bytes from the records received so far.
+    let mut handshake = Vec::new();
+    let mut rest = bytes;
+    loop {
+        if rest.len() < 5 {
+            return Parsed::Incomplete;
+        }
+
+        if rest[0] != RECORD_HANDSHAKE || rest[1] != 0x03 {
+            return Parsed::NotTls;
+        }
+
+        let len = u16::from_be_bytes([rest[3], rest[4]]) as usize;
+        if rest.len() < 5 + len {
+            return Parsed::Incomplete;
+        }
+
+        handshake.extend_from_slice(&rest[5..5 + len]);
+        rest = &rest[5 + len..];
+
+        if handshake.len() >= 4 {
+            if handshake[0] != handshake_type {
+                return Parsed::NotTls;
+            }
+
+            let message_len = u32::from_be_bytes([0, handshake[1], handshake[2], handshake[3]]);
+            if handshake.len() >= 4 + message_len as usize {
+                return Parsed::Hello(handshake[4..4 + message_len as usize].to_vec());
+            }
+        }
+    }
+}
+
+/// Parses the body of a ClientHello handshake message.
+fn parse_hello_body(body: &[u8]) -> Option<ClientHello> {
+    let mut reader = Reader(body);
+    let mut hello = ClientHello {
+        version: reader.u16()?,
+        ..Default::default()
+    };
+
+    // Skip the random and the session ID.
+    reader.take(32)?;
+    let session_id_len = reader.u8()? as usize;
+    reader.take(session_id_len)?;
+
+    let ciphers_len = reader.u16()? as usize;
+    hello.ciphers = u16s(reader.take(ciphers_len)?);
+
+    let compression_len = reader.u8()? as usize;
+    reader.take(compression_len)?;
+
+    // Hellos without extensions are valid, if rare.
+    if reader.0.is_empty() {
+        return Some(hello);
+    }
+
+    let extensions_len = reader.u16()? as usize;
+    let mut extensions = Reader(reader.take(extensions_len)?);
+    while !extensions.0.is_empty() {
+        let extension = extensions.u16()?;
+        let len = extensions.u16()? as usize;
+        let mut data = Reader(extensions.take(len)?);
+        if is_grease(extension) {
+            continue;
+        }
+
+        hello.extensions.push(extension);
+        match extension {
+            EXT_SERVER_NAME => {
+                data.u16()?;
+                if data.u8()? == 0 {
+                    let name_len = data.u16()? as usize;
+                    hello.server_name = Some(format::sanitize(data.take(name_len)?));
+                }
+            }
+            EXT_SUPPORTED_GROUPS => {
+                let len = data.u16()? as usize;
+                hello.groups = u16s(data.take(len)?);
+            }
+            EXT_EC_POINT_FORMATS => {
+                let len = data.u8()? as usize;
+                hello.point_formats = data.take(len)?.to_vec();
+            }
+            EXT_SIGNATURE_ALGORITHMS => {
+                let len = data.u16()? as usize;
+                hello.signature_algorithms = u16s(data.take(len)?);
+            }
+            EXT_ALPN => {
+                let len = data.u16()? as usize;
+                let mut protocols = Reader(data.take(len)?);
+                while !protocols.0.is_empty() {
+                    let len = protocols.u8()? as usize;
+                    let protocol = protocols.take(len)?;
+                    hello.alpn.push(format::sanitize(protocol));
+                }
+            }
+            EXT_SUPPORTED_VERSIONS => {
+                let len = data.u8()? as usize;
+                hello.supported_versions = u16s(data.take(len)?);
+            }
+            _ => {}
+        }
+    }
+
+    Some(hello)
+}
+
+/// Parses the body of a ServerHello handshake message.
+fn parse_server_hello_body(body: &[u8]) -> Option<ServerHello> {
+    let mut reader = Reader(body);
+    let mut hello = ServerHello {
+        version: reader.u16()?,
+        cipher: 0,
+        alpn: None,
+    };
+
+    // Skip the random and the session ID.
+    reader.take(32)?;
+    let session_id_len = reader.u8()? as usize;
+    reader.take(session_id_len)?;
+    hello.cipher = reader.u16()?;
+    reader.u8()?;
+
+    if reader.0.is_empty() {
+        return Some(hello);
+    }
+
+    let extensions_len = reader.u16()? as usize;
+    let mut extensions = Reader(reader.take(extensions_len)?);
+    while !extensions.0.is_empty() {
+        let extension = extensions.u16()?;
+        let len = extensions.u16()? as usize;
+        let mut data = Reader(extensions.take(len)?);
+        match extension {
+            EXT_SUPPORTED_VERSIONS => hello.version = data.u16()?,
+            EXT_ALPN => {
+                data.u16()?;
+                let len = data.u8()? as usize;
+                hello.alpn = Some(format::sanitize(data.take(len)?));
+            }
+            _ => {}
+        }
+    }
+
+    Some(hello)
+}
+
+/// Called with the parsed hello message.
+pub type OnHello<T> = Box<dyn FnOnce(T) + Send>;
+
+/// Wraps one side of a connection, passing its hello message to a callback as the bytes flow by.
+/// Nothing is held back, so non-TLS connections and protocols where the server speaks first are
+/// unaffected.
+pub struct Inspect<R, T> {
+    /// The reader being inspected.
+    inner: R,
+
+    /// The bytes read so far, until inspection is done.
+    buffer: Vec<u8>,
+
+    /// Parses the hello message.
+    parse: fn(&[u8]) -> Parsed<T>,
+
+    /// Called with the hello message, taken once inspection is done.
+    on_hello: Option<OnHello<T>>,
+}
+
+impl<R, T> Inspect<R, T> {
+    /// Inspects the reader if there's a callback, otherwise just passes the bytes through.
+    pub fn new(inner: R, parse: fn(&[u8]) -> Parsed<T>, on_hello: Option<OnHello<T>>) -> Self {
+        Inspect {
+            inner,
+            buffer: Vec::new(),
+            parse,
+            on_hello,
+        }
+    }
+}
+
+impl<R: AsyncRead + Unpin, T> AsyncRead for Inspect<R, T> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let this = self.get_mut();
+        let before = buf.filled().len();
+        let result = Pin::new(&mut this.inner).poll_read(cx, buf);
+        if this.on_hello.is_none() {
+            return result;
+        }
+
+        // Inspect the new bytes, giving up on anything that isn't a hello message.
+        this.buffer.extend_from_slice(&buf.filled()[before..]);
+        let eof = matches!(result, Poll::Ready(Ok(()))) && buf.filled().len() == before;
+        match (this.parse)(&this.buffer) {
+            Parsed::Hello(hello) => {
+                if let Some(on_hello) = this.on_hello.take() {
+                    on_hello(hello);
+                }
+            }
+            Parsed::Incomplete if !eof && this.buffer.len() < MAX_HELLO_LEN => return result,
+            _ => this.on_hello = None,
+        }
+
+        this.buffer = Vec::new();
+        result
+    }
+}
+
+/// Reads big-endian values from the front of a slice.
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    /// Takes the next `len` bytes.
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        if self.0.len() < len {
+            return None;
+        }
+
+        let (taken, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Some(taken)
+    }
+
+    /// Takes a byte.
+    fn u8(&mut self) -> Option<u8> {
+        self.take(1).map(|b| b[0])
+    }
+
+    /// Takes a big-endian u16.
+    fn u16(&mut self) -> Option<u16> {
+        self.take(2).map(|b| u16::from_be_bytes([b[0], b[1]]))
+    }
+}
+
+/// Reads a list of big-endian u16 values, leaving out GREASE values.
+fn u16s(bytes: &[u8]) -> Vec<u16> {
+    bytes
+        .chunks_exact(2)
+        .map(|b| u16::from_be_bytes([b[0], b[1]]))
+        .filter(|&v| !is_grease(v))
+        .collect()
+}
+
+/// Whether the value is one of the reserved GREASE values (RFC 8701) clients add at random.
+fn is_grease(value: u16) -> bool {
+    value & 0x0f0f == 0x0a0a && value >> 8 == value & 0xff
+}
+
+/// Formats the values and joins them with the separator.
+fn join<T>(values: &[T], separator: &str, format: impl Fn(&T) -> String) -> String {
+    values
+        .iter()
+        .map(format)
+        .collect::<Vec<_>>()
+        .join(separator)
+}
+
+/// Returns the first 12 hex characters of the SHA-256 of the input, as used by JA4.
+fn truncated_sha256(input: &str) -> String {
+    digest::sha256_hex(input.as_bytes())[..12].to_string()
+}
+
+/// Returns the JA4 code for a TLS version.
+fn version_code(version: u16) -> &'static str {
+    match version {
+        0x0304 => "13",
+        0x0303 => "12",
+        0x0302 => "11",
+        0x0301 => "10",
+        0x0300 => "s3",
+        _ => "00",
+    }
+}
+
+/// Returns the JA4 code for the first ALPN protocol: its first and last character.
+fn alpn_code(protocol: Option<&str>) -> String {
+    let protocol = match protocol {
+        Some(protocol) if !protocol.is_empty() => protocol,
+        _ => return "00".to_string(),
+    };
+
+    let (first, last) = (protocol.chars().next(), protocol.chars().last());
+    match (first, last) {
+        (Some(first), Some(last))
+            if first.is_ascii_alphanumeric() && last.is_ascii_alphanumeric() =>
+        {
+            format!("{}{}", first, last)
+        }
+        _ => {
+            // Non-alphanumeric protocols use the first and last hex digit instead.
+            let hex: String = protocol.bytes().map(|b| format!("{:02x}", b)).collect();
+            format!("{}{}", &hex[..1], &hex[hex.len() - 1..])
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds a ClientHello record with the given cipher suites and extensions.
+    fn client_hello(ciphers: &[u16], extensions: &[(u16, Vec<u8>)]) -> Vec<u8> {
+        let mut body = vec![0x03, 0x03];
+        body.extend_from_slice(&[0; 32]);
+        body.push(0);
+        body.extend_from_slice(&(ciphers.len() as u16 * 2).to_be_bytes());
+        for cipher in ciphers {
+            body.extend_from_slice(&cipher.to_be_bytes());
+        }
+        body.extend_from_slice(&[1, 0]);
+
+        let mut ext = Vec::new();
+        for (extension, data) in extensions {
+            ext.extend_from_slice(&extension.to_be_bytes());
+            ext.extend_from_slice(&(data.len() as u16).to_be_bytes());
+            ext.extend_from_slice(data);
+        }
+        body.extend_from_slice(&(ext.len() as u16).to_be_bytes());
+        body.extend_from_slice(&ext);
+
+        let mut handshake = vec![HANDSHAKE_CLIENT_HELLO, 0];
+        handshake.extend_from_slice(&(body.len() as u16).to_be_bytes());
+        handshake.extend_from_slice(&body);
+
+        let mut record = vec![RECORD_HANDSHAKE, 0x03, 0x01];
+        record.extend_from_slice(&(handshake.len() as u16).to_be_bytes());
+        record.extend_from_slice(&handshake);
+        record
+    }
+
+    #[test]
+    fn fingerprints_client_hello() {
+        let record = client_hello(
+            &[0x0a0a, 0x1301, 0xc02f],
+            &[
+                (0x1a1a, vec![]),
+                (EXT_SERVER_NAME, b"\x00\x07\x00\x00\x04a.io".to_vec()),
+                (EXT_ALPN, b"\x00\x03\x02h2".to_vec()),
+                (EXT_SUPPORTED_VERSIONS, vec![4, 0x03, 0x04, 0x03, 0x03]),
+                (EXT_SUPPORTED_GROUPS, vec![0, 2, 0x00, 0x1d]),
+                (EXT_EC_POINT_FORMATS, vec![1, 0]),
+                (EXT_SIGNATURE_ALGORITHMS, vec![0, 2, 0x04, 0x03]),
+            ],
+        );
+
+        assert_eq!(parse_client_hello(&record[..20]), Parsed::Incomplete);
+        assert_eq!(parse_client_hello(b"GET / HTTP/1.1\r\n"), Parsed::NotTls);
+
+        let hello = match parse_client_hello(&record) {
+            Parsed::Hello(hello) => hello,
+            other => panic!("expected a ClientHello, got {:?}", other),
+        };
+        assert_eq!(hello.server_name.as_deref(), Some("a.io"));
+        assert_eq!(hello.alpn, ["h2"]);
+        assert_eq!(hello.max_version(), 0x0304);
+        assert_eq!(hello.ja3_string(), "771,4865-49199,0-16-43-10-11-13,29,0");
+        assert!(hello.ja4().starts_with("t13d0206h2_"));
+    }
+
+    #[test]
+    fn fingerprints_like_the_ja4_reference() {
+        // A Chrome ClientHello, with GREASE, as in the example of the JA4 specification:
+        // https://github.com/FoxIO-LLC/ja4/blob/main/technical_details/JA4.md
+        let ciphers = [
+            0x3a3a, 0x1301, 0x1302, 0x1303, 0xc02b, 0xc02f, 0xc02c, 0xc030, 0xcca9, 0xcca8, 0xc013,
+            0xc014, 0x009c, 0x009d, 0x002f, 0x0035,
+        ];
+        let signature_algorithms = [
+            0, 16, 0x04, 0x03, 0x08, 0x04, 0x04, 0x01, 0x05, 0x03, 0x08, 0x05, 0x05, 0x01, 0x08,
+            0x06, 0x06, 0x01,
+        ];
+        let record = client_hello(
+            &ciphers,
+            &[
+                (0x8a8a, vec![]),
+                (EXT_SERVER_NAME, b"\x00\x0e\x00\x00\x0bexample.com".to_vec()),
+                (0x0017, vec![]),
+                (0xff01, vec![0]),
+                (
+                    EXT_SUPPORTED_GROUPS,
+                    vec![0, 6, 0x2a, 0x2a, 0x00, 0x1d, 0x00, 0x17],
+                ),
+                (EXT_EC_POINT_FORMATS, vec![1, 0]),
+                (0x0023, vec![]),
+                (EXT_ALPN, b"\x00\x0c\x02h2\x08http/1.1".to_vec()),
+                (0x0005, vec![1, 0, 0, 0, 0]),
+                (EXT_SIGNATURE_ALGORITHMS, signature_algorithms.to_vec()),
+                (0x0012, vec![]),
+                (0x0033, vec![0, 0]),
+                (0x002d, vec![1, 1]),
+                (
+                    EXT_SUPPORTED_VERSIONS,
+                    vec![6, 0x7a, 0x7a, 0x03, 0x04, 0x03, 0x03],
+                ),
+                (0x001b, vec![2, 0, 2]),
+                (0x4469, vec![0, 3, 2, b'h', b'2']),
+                (0xbaba, vec![0]),
+                (0x0015, vec![0; 16]),
+            ],
+        );
+
+        let Parsed::Hello(hello) = parse_client_hello(&record) else {
+            panic!("expected a ClientHello");
+        };
+        assert_eq!(hello.ja4(), "t13d1516h2_8daaf6152771_e5627efa2ab1");
+        assert_eq!(
+            hello.ja3_string(),
+            "771,4865-4866-4867-49195-49199-49196-49200-52393-52392-49171-49172-156-157-47-53,\
+             0-23-65281-10-11-35-16-5-13-18-51-45-43-27-17513-21,29-23,0"
+        );
+        assert_eq!(hello.ja3(), "56975f70bd0fc8222900179d28f2be23");
+    }
+
+    #[test]
+    fn parses_server_hello() {
+        let mut body = vec![0x03, 0x03];
+        body.extend_from_slice(&[0; 32]);
+        body.extend_from_slice(&[0, 0x13, 0x01, 0]);
+        body.extend_from_slice(&[0, 6, 0x00, 0x2b, 0, 2, 0x03, 0x04]);
+
+        let mut record = vec![RECORD_HANDSHAKE, 0x03, 0x03, 0, body.len() as u8 + 4];
+        record.extend_from_slice(&[HANDSHAKE_SERVER_HELLO, 0, 0, body.len() as u8]);
+        record.extend_from_slice(&body);
+
+        let expected = ServerHello {
+            version: 0x0304,
+            cipher: 0x1301,
+            alpn: None,
+        };
+        assert_eq!(parse_server_hello(&record), Parsed::Hello(expected));
+        assert_eq!(parse_client_hello(&record), Parsed::NotTls);
+    }
+}
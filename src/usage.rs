@@ -0,0 +1,281 @@
+use crate::format::{Bytes, Elapsed};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::fmt::{Display, Formatter};
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, AtomicIsize, AtomicU64, AtomicUsize, Ordering};
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+
+/// Whether allocations are counted, which only `--self-metrics` needs.
+static COUNTING: AtomicBool = AtomicBool::new(false);
+
+/// The bytes allocated on the heap and not freed yet, since the counting started. Blocks
+/// allocated before and freed since take it below what's actually allocated, even below zero.
+static HEAP: AtomicIsize = AtomicIsize::new(0);
+
+/// The allocations not freed yet, since the counting started, like `HEAP`.
+static ALLOCATIONS: AtomicIsize = AtomicIsize::new(0);
+
+/// The allocations made in total.
+static TOTAL_ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+
+/// The tasks spawned through `spawn` that haven't finished yet.
+static TASKS: AtomicUsize = AtomicUsize::new(0);
+
+/// The tasks spawned through `spawn` that started running since the usage was last measured,
+/// and how long they waited to, added up and at most, in nanoseconds.
+static SCHEDULED: AtomicU64 = AtomicU64::new(0);
+static SCHEDULING_DELAY: AtomicU64 = AtomicU64::new(0);
+static MAX_SCHEDULING_DELAY: AtomicU64 = AtomicU64::new(0);
+
+/// The system allocator, counting what is allocated once `count_allocations` was called.
+pub struct Counting;
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc(layout);
+        if !ptr.is_null() {
+            allocated(layout.size());
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout);
+        freed(layout.size());
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc_zeroed(layout);
+        if !ptr.is_null() {
+            allocated(layout.size());
+        }
+        ptr
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_ptr = System.realloc(ptr, layout, new_size);
+        if !new_ptr.is_null() {
+            // Moving counts as freeing the old block and allocating the new one.
+            freed(layout.size());
+            allocated(new_size);
+        }
+        new_ptr
+    }
+}
+
+/// Starts counting allocations. Until then, the allocator only checks that it isn't counting.
+pub fn count_allocations() {
+    COUNTING.store(true, Ordering::Relaxed);
+}
+
+/// Counts an allocation.
+fn allocated(size: usize) {
+    if !COUNTING.load(Ordering::Relaxed) {
+        return;
+    }
+    HEAP.fetch_add(size as isize, Ordering::Relaxed);
+    ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+    TOTAL_ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Counts a deallocation.
+fn freed(size: usize) {
+    if !COUNTING.load(Ordering::Relaxed) {
+        return;
+    }
+    HEAP.fetch_sub(size as isize, Ordering::Relaxed);
+    ALLOCATIONS.fetch_sub(1, Ordering::Relaxed);
+}
+
+/// Counts how long a task waited to start running.
+fn scheduled(delay: Duration) {
+    let nanos = delay.as_nanos().try_into().unwrap_or(u64::MAX);
+    SCHEDULED.fetch_add(1, Ordering::Relaxed);
+    SCHEDULING_DELAY.fetch_add(nanos, Ordering::Relaxed);
+    MAX_SCHEDULING_DELAY.fetch_max(nanos, Ordering::Relaxed);
+}
+
+/// Spawns a task, counting it until it finishes, and how long it waited to start running.
+pub fn spawn<F>(future: F) -> JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    let task = Task::start();
+    let spawned = Instant::now();
+    tokio::spawn(async move {
+        scheduled(spawned.elapsed());
+        let _task = task;
+        future.await
+    })
+}
+
+/// Counts a task while it exists, including when it's aborted.
+struct Task;
+
+impl Task {
+    /// Counts a new task.
+    fn start() -> Self {
+        TASKS.fetch_add(1, Ordering::Relaxed);
+        Task
+    }
+}
+
+impl Drop for Task {
+    fn drop(&mut self) {
+        TASKS.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// sockgauge's own resource usage at a point in time.
+#[derive(Debug, Clone, Copy)]
+pub struct Usage {
+    /// The resident set size in bytes, where the OS tells.
+    pub rss: Option<u64>,
+
+    /// The bytes allocated on the heap.
+    pub heap: usize,
+
+    /// The allocations on the heap.
+    pub allocations: usize,
+
+    /// The allocations made since the counting started.
+    pub total_allocations: u64,
+
+    /// The tasks running, e.g. one per proxied connection.
+    pub tasks: usize,
+
+    /// The events waiting for the reporter.
+    pub reporter_queue: usize,
+
+    /// How long the events the reporter handled since the last measurement waited for it, on
+    /// average and at most, if it handled any.
+    pub reporter_lag: Option<(Duration, Duration)>,
+
+    /// How long the tasks that started running since the last measurement waited to, on average
+    /// and at most, if any did.
+    pub scheduling_delay: Option<(Duration, Duration)>,
+}
+
+impl Usage {
+    /// Measures the usage now, given the reporter's queue depth and lag, and starts measuring
+    /// the scheduling delay anew.
+    pub fn now(reporter_queue: usize, reporter_lag: Option<(Duration, Duration)>) -> Self {
+        let scheduled = SCHEDULED.swap(0, Ordering::Relaxed);
+        let delay = SCHEDULING_DELAY.swap(0, Ordering::Relaxed);
+        let max_delay = MAX_SCHEDULING_DELAY.swap(0, Ordering::Relaxed);
+        Usage {
+            rss: rss(),
+            heap: HEAP.load(Ordering::Relaxed).max(0) as usize,
+            allocations: ALLOCATIONS.load(Ordering::Relaxed).max(0) as usize,
+            total_allocations: TOTAL_ALLOCATIONS.load(Ordering::Relaxed),
+            tasks: TASKS.load(Ordering::Relaxed),
+            reporter_queue,
+            reporter_lag,
+            scheduling_delay: (scheduled > 0).then(|| {
+                (
+                    Duration::from_nanos(delay / scheduled),
+                    Duration::from_nanos(max_delay),
+                )
+            }),
+        }
+    }
+}
+
+impl Display for Usage {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if let Some(rss) = self.rss {
+            write!(f, "rss {}, ", Bytes(rss))?;
+        }
+
+        write!(
+            f,
+            "heap {} in {} allocation(s) ({} since starting), {} task(s), reporter queue {}",
+            Bytes(self.heap as u64),
+            self.allocations,
+            self.total_allocations,
+            self.tasks,
+            self.reporter_queue
+        )?;
+        if let Some((mean, max)) = self.reporter_lag {
+            write!(f, ", reporter lag {} (max {})", Elapsed(mean), Elapsed(max))?;
+        }
+        if let Some((mean, max)) = self.scheduling_delay {
+            write!(
+                f,
+                ", scheduling delay {} (max {})",
+                Elapsed(mean),
+                Elapsed(max)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// The resident set size in bytes, from `/proc/self/statm`.
+#[cfg(target_os = "linux")]
+fn rss() -> Option<u64> {
+    let statm = std::fs::read_to_string("/proc/self/statm").ok()?;
+    let pages: u64 = statm.split_whitespace().nth(1)?.parse().ok()?;
+    let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) };
+    Some(pages * u64::try_from(page_size).ok()?)
+}
+
+/// The resident set size isn't measured on this platform.
+#[cfg(not(target_os = "linux"))]
+fn rss() -> Option<u64> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Other tests run at the same time and allocate and spawn too, so these only check what
+    // they can't undo.
+
+    #[tokio::test]
+    async fn counts_allocations_and_tasks() {
+        count_allocations();
+        let before = Usage::now(0, None);
+        let block = vec![0u8; 1 << 20];
+        let (started, start) = tokio::sync::oneshot::channel::<()>();
+        let task = spawn(async move {
+            let _ = start.await;
+        });
+        tokio::task::yield_now().await;
+
+        let during = Usage::now(3, None);
+        assert!(during.total_allocations > before.total_allocations);
+        assert!(during.heap >= block.len());
+        assert!(during.tasks >= 1);
+        assert!(during.scheduling_delay.is_some());
+        assert_eq!(during.reporter_queue, 3);
+        if cfg!(target_os = "linux") {
+            assert!(during.rss.unwrap() >= block.len() as u64);
+        }
+
+        drop(started);
+        task.await.unwrap();
+    }
+
+    #[test]
+    fn display() {
+        let usage = Usage {
+            rss: Some(30 << 20),
+            heap: 2 << 20,
+            allocations: 1200,
+            total_allocations: 56000,
+            tasks: 7,
+            reporter_queue: 2,
+            reporter_lag: Some((Duration::from_micros(40), Duration::from_millis(3))),
+            scheduling_delay: None,
+        };
+        assert_eq!(
+            usage.to_string(),
+            "rss 30.0 MiB, heap 2.00 MiB in 1200 allocation(s) (56000 since starting), 7 task(s), reporter queue 2, reporter lag 40µs (max 3.00ms)"
+        );
+    }
+}